@@ -0,0 +1,224 @@
+use std::{
+    fmt, fs,
+    io::{self, Error as IoError},
+    mem,
+    os::unix::{
+        ffi::OsStrExt,
+        io::FromRawFd,
+        net::{SocketAddr, UnixDatagram, UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+/// Address a UDS is bound to.
+///
+/// Strings starting with `@` or a nul byte are converted to an abstract address, and every other
+/// string or path to a filesystem one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    /// Socket file on the filesystem, removed before binding and once the UDS is stopped
+    Path(PathBuf),
+    /// Name in the abstract namespace of Linux, without the leading nul byte. It leaves no file
+    /// behind, and its access is not restricted by file permissions
+    Abstract(Vec<u8>),
+}
+
+impl Address {
+    /// Path of the socket file, if bound to the filesystem
+    pub fn as_path(&self) -> Option<&Path> {
+        match self {
+            Address::Path(p) => Some(p.as_path()),
+            Address::Abstract(_) => None,
+        }
+    }
+
+    /// Whether the address is in the abstract namespace
+    pub fn is_abstract(&self) -> bool {
+        matches!(self, Address::Abstract(_))
+    }
+
+    /// Address a socket is bound to, `None` if it is unnamed
+    pub(crate) fn of(addr: &SocketAddr) -> Option<Self> {
+        addr.as_pathname()
+            .map(Address::from)
+            .or_else(|| abstract_name(addr).map(|n| Address::Abstract(n.to_vec())))
+    }
+
+    /// Textual form, converted back with [`From<String>`]. `None` if it isn't valid UTF-8
+    pub(crate) fn to_str(&self) -> Option<String> {
+        match self {
+            Address::Path(p) => p.to_str().map(str::to_owned),
+            Address::Abstract(n) => std::str::from_utf8(n).ok().map(|n| format!("@{}", n)),
+        }
+    }
+
+    /// Bind a listener to the address, which must be available
+    pub(crate) fn bind(&self) -> Result<UnixListener, IoError> {
+        match self {
+            Address::Path(p) => UnixListener::bind(p),
+            Address::Abstract(n) => UnixListener::bind_addr(&abstract_addr(n)?),
+        }
+    }
+
+    /// Bind a `SOCK_SEQPACKET` listener to the address, which must be available. The accepted
+    /// sockets keep the boundaries of the messages
+    pub(crate) fn bind_seqpacket(&self) -> Result<UnixListener, IoError> {
+        let (addr, len) = self.sockaddr()?;
+        let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0) };
+        if fd < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        // Owned right away, so the descriptor is closed on every error
+        let listener = unsafe { UnixListener::from_raw_fd(fd) };
+        let addr = &addr as *const libc::sockaddr_un as *const libc::sockaddr;
+
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0
+            || unsafe { libc::bind(fd, addr, len) } < 0
+            || unsafe { libc::listen(fd, libc::SOMAXCONN) } < 0
+        {
+            return Err(IoError::last_os_error());
+        }
+
+        Ok(listener)
+    }
+
+    /// Bind a datagram socket to the address, which must be available
+    pub(crate) fn bind_datagram(&self) -> Result<UnixDatagram, IoError> {
+        match self {
+            Address::Path(p) => UnixDatagram::bind(p),
+            Address::Abstract(n) => UnixDatagram::bind_addr(&abstract_addr(n)?),
+        }
+    }
+
+    /// Raw socket address, with its length
+    pub(crate) fn sockaddr(&self) -> Result<(libc::sockaddr_un, libc::socklen_t), IoError> {
+        let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+        // Paths are nul terminated, while abstract names start with a nul byte and are not
+        let (bytes, terminator) = match self {
+            Address::Path(p) => (p.as_os_str().as_bytes().to_vec(), 1),
+            Address::Abstract(n) => ([&[0x00u8][..], n].concat(), 0),
+        };
+
+        if bytes.len() + terminator > addr.sun_path.len() {
+            return Err(IoError::new(
+                io::ErrorKind::InvalidInput,
+                format!("The address {} is too long for a socket", self),
+            ));
+        }
+
+        addr.sun_path
+            .iter_mut()
+            .zip(bytes.iter())
+            .for_each(|(d, s)| *d = *s as libc::c_char);
+
+        let len = mem::size_of_val(&addr) - addr.sun_path.len() + bytes.len() + terminator;
+        Ok((addr, len as libc::socklen_t))
+    }
+
+    /// Connect to the listener bound to the address
+    pub(crate) fn connect(&self) -> Result<UnixStream, IoError> {
+        match self {
+            Address::Path(p) => UnixStream::connect(p),
+            Address::Abstract(n) => UnixStream::connect_addr(&abstract_addr(n)?),
+        }
+    }
+
+    /// Set the address as the destination of the datagram socket, and the only sender it receives
+    /// from
+    pub(crate) fn connect_datagram(&self, socket: &UnixDatagram) -> Result<(), IoError> {
+        match self {
+            Address::Path(p) => socket.connect(p),
+            Address::Abstract(n) => socket.connect_addr(&abstract_addr(n)?),
+        }
+    }
+
+    /// Remove the socket file. Abstract addresses are released with their listener
+    pub(crate) fn remove_file(&self) -> Result<(), IoError> {
+        match self {
+            Address::Path(p) => fs::remove_file(p),
+            Address::Abstract(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_name(addr: &SocketAddr) -> Option<&[u8]> {
+    use std::os::linux::net::SocketAddrExt;
+    addr.as_abstract_name()
+}
+
+#[cfg(target_os = "android")]
+fn abstract_name(addr: &SocketAddr) -> Option<&[u8]> {
+    use std::os::android::net::SocketAddrExt;
+    addr.as_abstract_name()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn abstract_name(_addr: &SocketAddr) -> Option<&[u8]> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_addr(name: &[u8]) -> Result<std::os::unix::net::SocketAddr, IoError> {
+    use std::os::linux::net::SocketAddrExt;
+    std::os::unix::net::SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(target_os = "android")]
+fn abstract_addr(name: &[u8]) -> Result<std::os::unix::net::SocketAddr, IoError> {
+    use std::os::android::net::SocketAddrExt;
+    std::os::unix::net::SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn abstract_addr(_name: &[u8]) -> Result<std::os::unix::net::SocketAddr, IoError> {
+    Err(IoError::new(
+        std::io::ErrorKind::Unsupported,
+        "Abstract socket addresses are only supported on Linux",
+    ))
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Path(p) => write!(f, "{}", p.display()),
+            Address::Abstract(n) => write!(f, "@{}", String::from_utf8_lossy(n)),
+        }
+    }
+}
+
+impl From<PathBuf> for Address {
+    fn from(path: PathBuf) -> Self {
+        Address::Path(path)
+    }
+}
+
+impl From<&Path> for Address {
+    fn from(path: &Path) -> Self {
+        Address::Path(path.to_path_buf())
+    }
+}
+
+impl From<&str> for Address {
+    fn from(address: &str) -> Self {
+        match address.strip_prefix(['@', '\0']) {
+            Some(name) => Address::Abstract(name.as_bytes().to_vec()),
+            None => Address::Path(PathBuf::from(address)),
+        }
+    }
+}
+
+impl From<String> for Address {
+    fn from(address: String) -> Self {
+        Address::from(address.as_str())
+    }
+}
+
+impl From<&String> for Address {
+    fn from(address: &String) -> Self {
+        Address::from(address.as_str())
+    }
+}
@@ -0,0 +1,163 @@
+//! Regression tests of the paths meant to run without allocating: small frames and wakes. Also
+//! checks that the lengths announced by a peer are not allocated before its bytes arrive.
+//!
+//! Allocations are counted per thread by the global allocator of the test binary, so the tests
+//! running in parallel don't see the allocations of each other.
+
+use crate::{
+    codec::{self, FrameReader, FrameWriter, MAX_FRAME_SIZE, SMALL_FRAME_SIZE},
+    events::EventBus,
+    queue::{Event, Queue},
+    SystemClock,
+};
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    collections::VecDeque,
+    io::{self, Write},
+    os::unix::net::UnixStream,
+    sync::Arc,
+};
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static ALLOCATED_BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|a| a.set(a.get() + 1));
+        ALLOCATED_BYTES.with(|a| a.set(a.get() + layout.size()));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|a| a.set(a.get() + 1));
+        ALLOCATED_BYTES.with(|a| a.set(a.get() + new_size));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Allocations performed by the closure on the current thread
+fn allocations<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+/// Bytes allocated by the closure on the current thread, counting every reallocation in full
+fn allocated_bytes<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATED_BYTES.with(Cell::get);
+    f();
+    ALLOCATED_BYTES.with(Cell::get) - before
+}
+
+#[test]
+fn small_frames_are_written_without_allocating() {
+    let (mut tx, mut rx) = UnixStream::pair().unwrap();
+    let payload = [0x2au8; SMALL_FRAME_SIZE];
+    let mut writer = FrameWriter::new(tx.try_clone().unwrap());
+    let mut buffer = [0x00u8; SMALL_FRAME_SIZE];
+
+    let count = allocations(|| {
+        for len in [0, 1, SMALL_FRAME_SIZE] {
+            codec::write_frame(&mut tx, &payload[..len]).unwrap();
+            writer.write_frame(&payload[..len]).unwrap();
+
+            for _ in 0..2 {
+                let n = codec::read_frame_into(&mut rx, &mut buffer).unwrap();
+                assert_eq!(n, len);
+            }
+        }
+    });
+
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn frames_are_read_into_a_buffer_without_allocating() {
+    let (mut tx, rx) = UnixStream::pair().unwrap();
+    let mut reader = FrameReader::new(rx, SMALL_FRAME_SIZE);
+    let mut buffer = [0x00u8; SMALL_FRAME_SIZE];
+
+    for _ in 0..16 {
+        codec::write_frame(&mut tx, b"ping").unwrap();
+    }
+
+    let count = allocations(|| {
+        for _ in 0..16 {
+            let n = reader.read_frame_into(&mut buffer).unwrap();
+            assert_eq!(&buffer[..n], b"ping");
+        }
+    });
+
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn announced_lengths_are_not_allocated_up_front() {
+    // Announces the maximum, but sends a few bytes and closes
+    let mut announced = (MAX_FRAME_SIZE as u32).to_be_bytes().to_vec();
+    announced.extend_from_slice(b"truncated");
+
+    let truncated = |e: io::Error| assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    let bytes = allocated_bytes(|| {
+        let mut reader = &announced[..];
+        truncated(codec::read_frame(&mut reader, MAX_FRAME_SIZE).unwrap_err());
+
+        let mut reader = futures::io::Cursor::new(&announced);
+        let read = codec::read_frame_async(&mut reader, MAX_FRAME_SIZE);
+        truncated(futures::executor::block_on(read).unwrap_err());
+
+        let (mut tx, mut rx) = UnixStream::pair().unwrap();
+        tx.write_all(&announced).unwrap();
+        drop(tx);
+        truncated(codec::read_frame_shared(&mut rx, MAX_FRAME_SIZE).unwrap_err());
+    });
+
+    assert!(bytes < MAX_FRAME_SIZE / 16, "{} bytes allocated", bytes);
+}
+
+#[test]
+fn wakes_are_delivered_without_allocating() {
+    let queue = Queue::new(
+        1,
+        (1, 1),
+        &[],
+        None,
+        Arc::new(SystemClock),
+        Arc::new(EventBus::default()),
+    );
+
+    let round_trip = |id: u64| {
+        queue.wake(0, id);
+        queue.wake(0, id + 1);
+
+        match queue.next(0, false, None) {
+            // Converted as by the worker, sorting the ids in place
+            Event::Woken(ids) => {
+                let mut woken = VecDeque::from(ids);
+                woken.make_contiguous().sort_unstable();
+                assert_eq!(woken, [id, id + 1]);
+                queue.recycle(0, woken.into());
+            }
+            _ => panic!("expected a wake"),
+        }
+    };
+
+    // The first wakes grow the buffers of the worker, then reused
+    (0..2).for_each(|i| round_trip(i * 2));
+
+    let count = allocations(|| (0..1000).for_each(|i| round_trip(i * 2)));
+    assert_eq!(count, 0);
+}
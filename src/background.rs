@@ -0,0 +1,127 @@
+use std::{
+    io::{self, Error as IoError},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Background loops of the embedding application, such as cache refreshes or upstream
+/// heartbeats, tied to the lifecycle of the UDS.
+///
+/// Every task runs on its own thread, and receives a [`CancelToken`]. Once the workers are
+/// finished, the tokens are cancelled and the tasks joined before [`crate::ServerHandle::join`]
+/// returns. Cancellation is cooperative: a task must check its token, or sleep with it, to end.
+#[derive(Clone)]
+pub struct TaskGroup {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    token: CancelToken,
+    threads: Mutex<Vec<(String, thread::JoinHandle<()>)>>,
+}
+
+/// Cancellation signal of the tasks of a [`TaskGroup`]
+#[derive(Clone)]
+pub struct CancelToken {
+    state: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl CancelToken {
+    fn new() -> Self {
+        CancelToken {
+            state: Arc::new((Mutex::new(false), Condvar::new())),
+        }
+    }
+
+    /// Whether the task should end
+    pub fn is_cancelled(&self) -> bool {
+        *self.state.0.lock().unwrap()
+    }
+
+    /// Sleep for the provided period, waking up early if the task is cancelled. Returns `true`
+    /// if it was cancelled
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        let mut cancelled = self.state.0.lock().unwrap();
+
+        while !*cancelled {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                break;
+            }
+
+            cancelled = self.state.1.wait_timeout(cancelled, timeout).unwrap().0;
+        }
+
+        *cancelled
+    }
+
+    fn cancel(&self) {
+        *self.state.0.lock().unwrap() = true;
+        self.state.1.notify_all();
+    }
+}
+
+impl TaskGroup {
+    pub(crate) fn new() -> Self {
+        TaskGroup {
+            inner: Arc::new(Inner {
+                token: CancelToken::new(),
+                threads: Mutex::new(vec![]),
+            }),
+        }
+    }
+
+    /// Run the task on a dedicated thread until it returns. Fails if the UDS is already stopped
+    pub fn spawn<N, F>(&self, name: N, task: F) -> Result<(), IoError>
+    where
+        N: Into<String>,
+        F: FnOnce(CancelToken) + Send + 'static,
+    {
+        let name = name.into();
+
+        // Held while spawning, so the task can't be missed by a concurrent shutdown
+        let mut threads = self.inner.threads.lock().unwrap();
+        if self.inner.token.is_cancelled() {
+            return Err(IoError::new(
+                io::ErrorKind::NotConnected,
+                "The UDS is stopped, no task can be added",
+            ));
+        }
+
+        let token = self.inner.token.clone();
+        let thread = thread::Builder::new()
+            .name(name.clone())
+            .spawn(move || task(token))?;
+        threads.push((name, thread));
+
+        Ok(())
+    }
+
+    /// Number of tasks still running
+    pub fn running(&self) -> usize {
+        self.inner
+            .threads
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, t)| !t.is_finished())
+            .count()
+    }
+
+    /// Cancel every task and wait for them to end
+    pub(crate) fn shutdown(&self) {
+        let threads = {
+            let mut threads = self.inner.threads.lock().unwrap();
+            self.inner.token.cancel();
+            std::mem::take(&mut *threads)
+        };
+
+        for (name, thread) in threads {
+            if thread.join().is_err() {
+                error!("Background task {} panicked", name);
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{IpcStream, Job, Message, TaskProvider};
+
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{mpsc, Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+/// Handler of a connection written as plain blocking code, run by [`Blocking`] on a pool of
+/// threads separate from the workers.
+///
+/// Meant for handlers bound to blocking calls, such as ioctls or file IO, that would otherwise
+/// stall every other connection of the worker.
+pub trait BlockingHandler: Send + Sync + 'static {
+    /// Handle the connection to completion. The socket is closed once it is dropped
+    fn handle(&self, stream: IpcStream) -> Message;
+}
+
+/// Provider running a [`BlockingHandler`] on a dedicated pool of threads, created with
+/// [`blocking_handler`].
+///
+/// The future resolves once the handler returns, and the worker is free to poll its other
+/// futures meanwhile. A handler that panics resolves as [`Message::Error`]. If the future is
+/// dropped before, for instance by a drain deadline, the handler still runs to completion.
+pub struct Blocking<H> {
+    handler: Arc<H>,
+    pool: Arc<BlockingPool>,
+    socket: Option<IpcStream>,
+    result: Option<Arc<Mutex<Slot>>>,
+}
+
+/// Result of a handler, with the waker of the future waiting for it
+struct Slot {
+    message: Option<Message>,
+    waker: Option<Waker>,
+}
+
+/// Create a [`Blocking`] provider running the handler on `threads` dedicated threads. The threads
+/// end once every clone of the provider is dropped
+pub fn blocking_handler<H: BlockingHandler>(handler: H, threads: usize) -> Blocking<H> {
+    Blocking {
+        handler: Arc::new(handler),
+        pool: Arc::new(BlockingPool::spawn(threads.max(1))),
+        socket: None,
+        result: None,
+    }
+}
+
+impl<H> Clone for Blocking<H> {
+    fn clone(&self) -> Self {
+        Blocking {
+            handler: Arc::clone(&self.handler),
+            pool: Arc::clone(&self.pool),
+            socket: None,
+            result: None,
+        }
+    }
+}
+
+impl<H: BlockingHandler> TaskProvider for Blocking<H> {
+    fn set_socket(&mut self, socket: IpcStream) {
+        self.socket.replace(socket);
+    }
+}
+
+impl<H: BlockingHandler> Future for Blocking<H> {
+    type Output = Message;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Some(socket) = self.socket.take() {
+            let slot = Arc::new(Mutex::new(Slot {
+                message: None,
+                waker: Some(cx.waker().clone()),
+            }));
+
+            let handler = Arc::clone(&self.handler);
+            let s = Arc::clone(&slot);
+            let submitted = self.pool.submit(Box::new(move || {
+                let message = panic::catch_unwind(AssertUnwindSafe(|| handler.handle(socket)))
+                    .unwrap_or_else(|_| {
+                        error!("Blocking handler panicked");
+                        Message::Error
+                    });
+
+                let mut slot = s.lock().unwrap();
+                slot.message.replace(message);
+                if let Some(w) = slot.waker.take() {
+                    w.wake();
+                }
+            }));
+
+            if !submitted {
+                error!("The blocking pool has no thread left to run the handler");
+                return Poll::Ready(Message::Error);
+            }
+
+            self.result.replace(slot);
+            return Poll::Pending;
+        }
+
+        let slot = match self.result.as_ref() {
+            Some(s) => s,
+            None => return Poll::Ready(Message::Error),
+        };
+
+        let mut slot = slot.lock().unwrap();
+        match slot.message.take() {
+            Some(m) => Poll::Ready(m),
+            None => {
+                slot.waker.replace(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Fixed set of threads taking jobs from a shared channel, ending once the sender is dropped
+struct BlockingPool {
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+impl BlockingPool {
+    fn spawn(threads: usize) -> Self {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+
+        for i in 0..threads {
+            let rx = Arc::clone(&rx);
+
+            let spawned = thread::Builder::new()
+                .name(format!("uds-blocking-{}", i))
+                .spawn(move || loop {
+                    // The lock is released before running the job
+                    let job = rx.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => return,
+                    }
+                });
+
+            if let Err(e) = spawned {
+                error!("Error spawning a blocking pool thread: {}", e);
+            }
+        }
+
+        BlockingPool {
+            jobs: Mutex::new(tx),
+        }
+    }
+
+    /// Queue the job, failing if every thread of the pool is gone
+    fn submit(&self, job: Job) -> bool {
+        self.jobs.lock().unwrap().send(job).is_ok()
+    }
+}
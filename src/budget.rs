@@ -0,0 +1,174 @@
+use crate::{BudgetAction, MemoryBudget};
+
+use std::{
+    fmt,
+    io::{self, Error as IoError},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::Waker,
+};
+
+/// Memory held by a single connection, accounted against [`crate::Options::memory_budget`].
+///
+/// Handed to the provider with [`crate::TaskProvider::set_budget`]. The accounting is cooperative:
+/// the provider reserves the memory of its buffers and queued frames before holding it, for
+/// instance through [`crate::codec::FrameWriter::budget`], and the reservations are released when
+/// dropped.
+#[derive(Clone)]
+pub struct Budget(Arc<State>);
+
+struct State {
+    limit: usize,
+    action: BudgetAction,
+    used: AtomicUsize,
+    exceeded: AtomicBool,
+    /// Waker of the connection, so the worker closes it as soon as the budget is exceeded
+    waker: Mutex<Option<Waker>>,
+}
+
+/// Memory reserved from a [`Budget`], released when dropped
+pub struct Reservation {
+    budget: Budget,
+    bytes: usize,
+}
+
+impl Budget {
+    pub(crate) fn new(budget: &MemoryBudget) -> Self {
+        Budget(Arc::new(State {
+            limit: budget.limit,
+            action: budget.on_exceeded,
+            used: AtomicUsize::new(0),
+            exceeded: AtomicBool::new(false),
+            waker: Mutex::new(None),
+        }))
+    }
+
+    /// Maximum number of bytes the connection may hold
+    pub fn limit(&self) -> usize {
+        self.0.limit
+    }
+
+    /// Bytes currently reserved
+    pub fn used(&self) -> usize {
+        self.0.used.load(Ordering::Acquire)
+    }
+
+    /// Bytes that can still be reserved
+    pub fn available(&self) -> usize {
+        self.0.limit.saturating_sub(self.used())
+    }
+
+    /// Whether a reservation was refused with [`BudgetAction::Close`], so the connection is about
+    /// to be closed
+    pub fn is_exceeded(&self) -> bool {
+        self.0.exceeded.load(Ordering::Acquire)
+    }
+
+    /// Reserve the provided number of bytes.
+    ///
+    /// Over the budget, fails with an error of kind [`io::ErrorKind::WouldBlock`] under
+    /// [`BudgetAction::Backpressure`]: the provider should stop reading until it released memory.
+    /// Under [`BudgetAction::Close`], the error is of kind [`io::ErrorKind::OutOfMemory`] and the
+    /// worker closes the connection.
+    pub fn reserve(&self, bytes: usize) -> Result<Reservation, IoError> {
+        let mut reservation = Reservation {
+            budget: self.clone(),
+            bytes: 0,
+        };
+
+        reservation.resize(bytes)?;
+        Ok(reservation)
+    }
+
+    pub(crate) fn set_waker(&self, waker: &Waker) {
+        self.0.waker.lock().unwrap().replace(waker.clone());
+    }
+
+    /// Whether the budget still holds the waker of the connection, taken once exceeded
+    pub(crate) fn holds_waker(&self) -> bool {
+        self.0.waker.lock().unwrap().is_some()
+    }
+
+    fn grow(&self, bytes: usize) -> Result<(), IoError> {
+        let limit = self.0.limit;
+        let grown = self
+            .0
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(bytes).filter(|u| *u <= limit)
+            });
+
+        if grown.is_ok() {
+            return Ok(());
+        }
+
+        match self.0.action {
+            BudgetAction::Backpressure => Err(IoError::new(
+                io::ErrorKind::WouldBlock,
+                "The connection is over its memory budget",
+            )),
+
+            BudgetAction::Close => {
+                self.0.exceeded.store(true, Ordering::Release);
+                if let Some(w) = self.0.waker.lock().unwrap().take() {
+                    w.wake();
+                }
+
+                Err(IoError::new(
+                    io::ErrorKind::OutOfMemory,
+                    "The connection exceeded its memory budget",
+                ))
+            }
+        }
+    }
+
+    fn shrink(&self, bytes: usize) {
+        self.0.used.fetch_sub(bytes, Ordering::AcqRel);
+    }
+}
+
+impl fmt::Debug for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Budget")
+            .field("limit", &self.limit())
+            .field("used", &self.used())
+            .field("exceeded", &self.is_exceeded())
+            .finish()
+    }
+}
+
+impl Reservation {
+    /// Bytes held by the reservation
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Change the number of bytes held, failing as [`Budget::reserve`] if it grows over the
+    /// budget. The reservation is unchanged on failure
+    pub fn resize(&mut self, bytes: usize) -> Result<(), IoError> {
+        if bytes > self.bytes {
+            self.budget.grow(bytes - self.bytes)?;
+        } else {
+            self.budget.shrink(self.bytes - bytes);
+        }
+
+        self.bytes = bytes;
+        Ok(())
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.shrink(self.bytes);
+    }
+}
+
+impl fmt::Debug for Reservation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reservation")
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
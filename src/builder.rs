@@ -0,0 +1,530 @@
+use crate::{
+    AcceptFilter, Address, BackpressurePolicy, Banner, Clock, CompletionFn, DrainPolicy, Executor,
+    Hardening, LostWaker, MemoryBudget, Options, PeerRevalidation, RequestLog, ScalingPolicy,
+    SocketKind, TakeoverCheck, Validation, WarmUp, WorkerGroup,
+};
+
+use std::{error::Error, fmt, sync::Arc, time::Duration};
+
+/// Builder of [`Options`], returned by [`Options::builder`], refusing to build inconsistent
+/// options.
+///
+/// The builder starts from the defaults, or from the options it was converted from, such as the
+/// ones of [`Options::profile`]. The checks are the errors of the startup validation that don't
+/// depend on the environment, so they are reported before binding whatever the
+/// [`Options::validation`] mode.
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+/// Inconsistency of the options refused by [`OptionsBuilder::build`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionsError {
+    /// No workers are configured, so no socket would be handled
+    NoWorkers,
+    /// The option, a period or timeout, is zero where it must elapse
+    ZeroDuration(&'static str),
+    /// The permission bits go beyond `0o7777`
+    InvalidMode(u32),
+    /// [`Options::max_pending_tasks`] is 0, so every socket would be held back or rejected
+    NoPendingTasks,
+    /// The limit, such as [`Options::max_accept_rate`], is 0, so no socket would be accepted
+    NoConnections(&'static str),
+    /// The worker groups reserve every worker, leaving none for the other listeners
+    NoWorkersLeft {
+        /// Workers reserved by the groups
+        reserved: usize,
+        /// Workers configured
+        workers: usize,
+    },
+    /// [`Options::min_workers`] and [`Options::max_workers`] don't bound [`Options::workers`], or
+    /// the minimum is 0
+    InvalidPoolBounds {
+        /// Minimum of the pool
+        min: usize,
+        /// Workers spawned first
+        workers: usize,
+        /// Maximum of the pool
+        max: usize,
+    },
+    /// The worker group has no workers, so its sockets would never be handled
+    EmptyWorkerGroup(String),
+    /// The listener is routed to more than one worker group
+    ListenerInManyGroups(Address),
+    /// The option doesn't apply to the sockets of the kind
+    ConflictingSocketKind {
+        /// Kind of the sockets served
+        kind: SocketKind,
+        /// Option set that doesn't apply to them
+        option: &'static str,
+    },
+}
+
+impl OptionsBuilder {
+    /// Set [`Options::workers`]
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.options.workers = workers;
+        self
+    }
+
+    /// Set [`Options::accept_filter`]
+    pub fn accept_filter(mut self, accept_filter: AcceptFilter) -> Self {
+        self.options.accept_filter = Some(accept_filter);
+        self
+    }
+
+    /// Set [`Options::max_connection_age`]
+    pub fn max_connection_age(mut self, max_connection_age: Duration) -> Self {
+        self.options.max_connection_age = Some(max_connection_age);
+        self
+    }
+
+    /// Set [`Options::exit_on_idle`]
+    pub fn exit_on_idle(mut self, exit_on_idle: Duration) -> Self {
+        self.options.exit_on_idle = Some(exit_on_idle);
+        self
+    }
+
+    /// Set [`Options::handle_signals`]
+    pub fn handle_signals(mut self, handle_signals: bool) -> Self {
+        self.options.handle_signals = handle_signals;
+        self
+    }
+
+    /// Set [`Options::max_concurrent_per_worker`]
+    pub fn max_concurrent_per_worker(mut self, max_concurrent_per_worker: usize) -> Self {
+        self.options.max_concurrent_per_worker = max_concurrent_per_worker;
+        self
+    }
+
+    /// Set [`Options::max_in_flight`]
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.options.max_in_flight = Some(max_in_flight);
+        self
+    }
+
+    /// Set [`Options::max_pending_tasks`]
+    pub fn max_pending_tasks(mut self, max_pending_tasks: usize) -> Self {
+        self.options.max_pending_tasks = Some(max_pending_tasks);
+        self
+    }
+
+    /// Set [`Options::backpressure`]
+    pub fn backpressure(mut self, backpressure: BackpressurePolicy) -> Self {
+        self.options.backpressure = backpressure;
+        self
+    }
+
+    /// Set [`Options::max_connections_per_uid`]
+    pub fn max_connections_per_uid(mut self, max_connections_per_uid: usize) -> Self {
+        self.options.max_connections_per_uid = Some(max_connections_per_uid);
+        self
+    }
+
+    /// Set [`Options::max_accept_rate`]
+    #[doc(alias = "max_accepts_per_second")]
+    pub fn max_accept_rate(mut self, max_accept_rate: u32) -> Self {
+        self.options.max_accept_rate = Some(max_accept_rate);
+        self
+    }
+
+    /// Set [`Options::accept_burst`]
+    #[doc(alias = "burst")]
+    pub fn accept_burst(mut self, accept_burst: u32) -> Self {
+        self.options.accept_burst = Some(accept_burst);
+        self
+    }
+
+    /// Set [`Options::accept_rate_policy`]
+    pub fn accept_rate_policy(mut self, accept_rate_policy: BackpressurePolicy) -> Self {
+        self.options.accept_rate_policy = accept_rate_policy;
+        self
+    }
+
+    /// Enable the protections of the level, replacing the options they set. See [`Hardening`]
+    pub fn hardening(mut self, hardening: Hardening) -> Self {
+        self.options = hardening.apply(self.options);
+        self
+    }
+
+    /// Add a group to [`Options::worker_groups`]
+    pub fn worker_group(mut self, group: WorkerGroup) -> Self {
+        self.options.worker_groups.push(group);
+        self
+    }
+
+    /// Set [`Options::poll_budget`]
+    pub fn poll_budget(mut self, poll_budget: Duration) -> Self {
+        self.options.poll_budget = Some(poll_budget);
+        self
+    }
+
+    /// Set [`Options::max_workers`]
+    pub fn max_workers(mut self, max_workers: usize) -> Self {
+        self.options.max_workers = Some(max_workers);
+        self
+    }
+
+    /// Set [`Options::min_workers`]
+    pub fn min_workers(mut self, min_workers: usize) -> Self {
+        self.options.min_workers = Some(min_workers);
+        self
+    }
+
+    /// Set [`Options::scaling`]
+    pub fn scaling(mut self, scaling: ScalingPolicy) -> Self {
+        self.options.scaling = scaling;
+        self
+    }
+
+    /// Set [`Options::lost_waker`]
+    pub fn lost_waker(mut self, lost_waker: LostWaker) -> Self {
+        self.options.lost_waker = lost_waker;
+        self
+    }
+
+    /// Set [`Options::respawn_workers`]
+    pub fn respawn_workers(mut self, respawn_workers: bool) -> Self {
+        self.options.respawn_workers = respawn_workers;
+        self
+    }
+
+    /// Set [`Options::check_fd_leaks`]
+    pub fn check_fd_leaks(mut self, check_fd_leaks: bool) -> Self {
+        self.options.check_fd_leaks = check_fd_leaks;
+        self
+    }
+
+    /// Set [`Options::memory_budget`]
+    pub fn memory_budget(mut self, memory_budget: MemoryBudget) -> Self {
+        self.options.memory_budget = Some(memory_budget);
+        self
+    }
+
+    /// Set [`Options::connection_scratch`]
+    pub fn connection_scratch(mut self, connection_scratch: bool) -> Self {
+        self.options.connection_scratch = connection_scratch;
+        self
+    }
+
+    /// Set [`Options::socket_kind`]
+    pub fn socket_kind(mut self, socket_kind: SocketKind) -> Self {
+        self.options.socket_kind = socket_kind;
+        self
+    }
+
+    /// Set [`Options::mode`]
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.options.mode = Some(mode);
+        self
+    }
+
+    /// Set [`Options::owner`]
+    pub fn owner(mut self, owner: libc::uid_t) -> Self {
+        self.options.owner = Some(owner);
+        self
+    }
+
+    /// Set [`Options::group`]
+    pub fn group(mut self, group: libc::gid_t) -> Self {
+        self.options.group = Some(group);
+        self
+    }
+
+    /// Set [`Options::linger`]
+    pub fn linger(mut self, linger: Duration) -> Self {
+        self.options.linger = Some(linger);
+        self
+    }
+
+    /// Set [`Options::read_timeout`]
+    pub fn read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.options.read_timeout = Some(read_timeout);
+        self
+    }
+
+    /// Set [`Options::write_timeout`]
+    pub fn write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.options.write_timeout = Some(write_timeout);
+        self
+    }
+
+    /// Set [`Options::drain_on_close`]
+    pub fn drain_on_close(mut self, drain_on_close: Duration) -> Self {
+        self.options.drain_on_close = Some(drain_on_close);
+        self
+    }
+
+    /// Set [`Options::trace_connections`]
+    pub fn trace_connections(mut self, trace_connections: usize) -> Self {
+        self.options.trace_connections = Some(trace_connections);
+        self
+    }
+
+    /// Set [`Options::account_traffic`]
+    pub fn account_traffic(mut self, account_traffic: bool) -> Self {
+        self.options.account_traffic = account_traffic;
+        self
+    }
+
+    /// Set [`Options::warm_up`]
+    pub fn warm_up(mut self, warm_up: WarmUp) -> Self {
+        self.options.warm_up = Some(warm_up);
+        self
+    }
+
+    /// Set [`Options::warm_up_timeout`]
+    pub fn warm_up_timeout(mut self, warm_up_timeout: Duration) -> Self {
+        self.options.warm_up_timeout = warm_up_timeout;
+        self
+    }
+
+    /// Set [`Options::validation`]
+    pub fn validation(mut self, validation: Validation) -> Self {
+        self.options.validation = Some(validation);
+        self
+    }
+
+    /// Set [`Options::drain_policy`]
+    pub fn drain_policy(mut self, drain_policy: DrainPolicy) -> Self {
+        self.options.drain_policy = Some(drain_policy);
+        self
+    }
+
+    /// Set [`Options::banner`]
+    pub fn banner(mut self, banner: Banner) -> Self {
+        self.options.banner = Some(banner);
+        self
+    }
+
+    /// Set [`Options::request_log`]
+    pub fn request_log(mut self, request_log: RequestLog) -> Self {
+        self.options.request_log = Some(request_log);
+        self
+    }
+
+    /// Set [`Options::on_complete`]
+    pub fn on_complete(mut self, on_complete: CompletionFn) -> Self {
+        self.options.on_complete = Some(on_complete);
+        self
+    }
+
+    /// Set [`Options::peer_revalidation`]
+    pub fn peer_revalidation(mut self, peer_revalidation: PeerRevalidation) -> Self {
+        self.options.peer_revalidation = Some(peer_revalidation);
+        self
+    }
+
+    /// Set [`Options::takeover_check`]
+    pub fn takeover_check(mut self, takeover_check: TakeoverCheck) -> Self {
+        self.options.takeover_check = Some(takeover_check);
+        self
+    }
+
+    /// Set [`Options::accept_shutdown_timeout`]
+    pub fn accept_shutdown_timeout(mut self, accept_shutdown_timeout: Duration) -> Self {
+        self.options.accept_shutdown_timeout = accept_shutdown_timeout;
+        self
+    }
+
+    /// Set [`Options::executor`]
+    pub fn executor(mut self, executor: Arc<dyn Executor>) -> Self {
+        self.options.executor = executor;
+        self
+    }
+
+    /// Set [`Options::clock`]
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.options.clock = clock;
+        self
+    }
+
+    /// Check the options, and return them if they are consistent
+    pub fn build(self) -> Result<Options, OptionsError> {
+        match errors(&self.options).into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(self.options),
+        }
+    }
+}
+
+impl From<Options> for OptionsBuilder {
+    fn from(options: Options) -> Self {
+        OptionsBuilder { options }
+    }
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptionsError::NoWorkers => {
+                write!(
+                    f,
+                    "No workers are configured, so no socket would be handled"
+                )
+            }
+            OptionsError::ZeroDuration(option) => {
+                write!(f, "{} is 0, but must be a positive duration", option)
+            }
+            OptionsError::InvalidMode(mode) => {
+                write!(
+                    f,
+                    "mode {:#o} has bits beyond the permission bits 0o7777",
+                    mode
+                )
+            }
+            OptionsError::NoPendingTasks => write!(
+                f,
+                "max_pending_tasks is 0, so every socket would be held back or rejected"
+            ),
+            OptionsError::NoConnections(option) => {
+                write!(f, "{} is 0, so no socket would be accepted", option)
+            }
+            OptionsError::NoWorkersLeft { reserved, workers } => write!(
+                f,
+                "worker_groups reserve {} of the {} workers, leaving none for the other listeners",
+                reserved, workers
+            ),
+            OptionsError::InvalidPoolBounds { min, workers, max } => write!(
+                f,
+                "The pool of {} to {} workers doesn't bound the {} workers spawned first, or is empty",
+                min, max, workers
+            ),
+            OptionsError::EmptyWorkerGroup(name) => write!(
+                f,
+                "The worker group {} has no workers, so its sockets would never be handled",
+                name
+            ),
+            OptionsError::ListenerInManyGroups(listener) => write!(
+                f,
+                "The listener {} is routed to more than one worker group",
+                listener
+            ),
+            OptionsError::ConflictingSocketKind { kind, option } => {
+                write!(f, "{} doesn't apply to {:?} sockets", option, kind)
+            }
+        }
+    }
+}
+
+impl Error for OptionsError {}
+
+/// Every inconsistency of the options, in the order they are checked
+pub(crate) fn errors(options: &Options) -> Vec<OptionsError> {
+    let mut errors = vec![];
+
+    if options.workers == 0 {
+        errors.push(OptionsError::NoWorkers);
+    }
+
+    let durations = [
+        ("read_timeout", options.read_timeout),
+        ("write_timeout", options.write_timeout),
+        (
+            "warm_up_timeout",
+            options.warm_up.as_ref().map(|_| options.warm_up_timeout),
+        ),
+        (
+            "takeover_check.interval",
+            options.takeover_check.map(|c| c.interval),
+        ),
+        (
+            "peer_revalidation.interval",
+            options.peer_revalidation.map(|r| r.interval),
+        ),
+        (
+            "scaling.scale_up_after",
+            options.max_workers.map(|_| options.scaling.scale_up_after),
+        ),
+        (
+            "scaling.scale_down_after",
+            (options.max_workers.is_some() || options.min_workers.is_some())
+                .then_some(options.scaling.scale_down_after),
+        ),
+    ];
+    durations
+        .iter()
+        .filter(|(_, d)| d.is_some_and(|d| d.is_zero()))
+        .for_each(|(option, _)| errors.push(OptionsError::ZeroDuration(option)));
+
+    if let Some(mode) = options.mode.filter(|m| m & !0o7777 != 0) {
+        errors.push(OptionsError::InvalidMode(mode));
+    }
+
+    if options.max_pending_tasks == Some(0) {
+        errors.push(OptionsError::NoPendingTasks);
+    }
+
+    if options.max_connections_per_uid == Some(0) {
+        errors.push(OptionsError::NoConnections("max_connections_per_uid"));
+    }
+
+    if options.max_accept_rate == Some(0) {
+        errors.push(OptionsError::NoConnections("max_accept_rate"));
+    }
+
+    if options.accept_burst == Some(0) {
+        errors.push(OptionsError::NoConnections("accept_burst"));
+    }
+
+    let reserved: usize = options.worker_groups.iter().map(|g| g.workers).sum();
+    if !options.worker_groups.is_empty() && reserved >= options.workers {
+        errors.push(OptionsError::NoWorkersLeft {
+            reserved,
+            workers: options.workers,
+        });
+    }
+
+    let min = options.min_workers.unwrap_or(options.workers);
+    let max = options.max_workers.unwrap_or(options.workers);
+    if min == 0 || min > options.workers || max < options.workers {
+        errors.push(OptionsError::InvalidPoolBounds {
+            min,
+            workers: options.workers,
+            max,
+        });
+    }
+
+    for (i, group) in options.worker_groups.iter().enumerate() {
+        if group.workers == 0 {
+            errors.push(OptionsError::EmptyWorkerGroup(group.name.clone()));
+        }
+
+        let routed = options.worker_groups[..i]
+            .iter()
+            .flat_map(|g| g.listeners.iter())
+            .find(|l| group.listeners.contains(l));
+        if let Some(listener) = routed {
+            errors.push(OptionsError::ListenerInManyGroups(listener.clone()));
+        }
+    }
+
+    // Datagram sockets are never accepted, so the options of the accept loop don't apply
+    if options.socket_kind == SocketKind::Datagram {
+        let accepting = [
+            ("accept_filter", options.accept_filter.is_some()),
+            ("banner", options.banner.is_some()),
+            ("max_pending_tasks", options.max_pending_tasks.is_some()),
+            (
+                "max_connections_per_uid",
+                options.max_connections_per_uid.is_some(),
+            ),
+            ("max_accept_rate", options.max_accept_rate.is_some()),
+            ("accept_burst", options.accept_burst.is_some()),
+            ("account_traffic", options.account_traffic),
+            ("worker_groups", !options.worker_groups.is_empty()),
+        ];
+
+        accepting
+            .iter()
+            .filter(|(_, set)| *set)
+            .for_each(|(option, _)| {
+                errors.push(OptionsError::ConflictingSocketKind {
+                    kind: options.socket_kind,
+                    option,
+                })
+            });
+    }
+
+    errors
+}
@@ -0,0 +1,176 @@
+use crate::{Clock, SystemClock};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Cache of the responses to identical request frames, so the sockets polled aggressively, such
+/// as status or introspection ones, don't run the handler for every request.
+///
+/// Set on a provider with [`crate::OneShot::cache`], or used directly by a custom provider. A
+/// response is served for its time to live, whoever the peer is, so the handler must answer a
+/// given request the same way for every peer. Past the entry or byte limits, the expired entries
+/// are evicted first, then the oldest ones.
+pub struct ResponseCache {
+    ttl: Duration,
+    max_entries: usize,
+    max_bytes: usize,
+    clock: Arc<dyn Clock>,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<Vec<u8>, Entry>,
+    bytes: usize,
+}
+
+struct Entry {
+    response: Vec<u8>,
+    inserted: Instant,
+}
+
+impl ResponseCache {
+    /// Cache holding every response for `ttl`, with up to `max_entries` entries and `max_bytes`
+    /// bytes of requests and responses
+    pub fn new(ttl: Duration, max_entries: usize, max_bytes: usize) -> Self {
+        ResponseCache {
+            ttl,
+            max_entries,
+            max_bytes,
+            clock: Arc::new(SystemClock),
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Expire the entries with the provided clock instead of the system one
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Response cached for the request, if it is not expired
+    pub fn get(&self, request: &[u8]) -> Option<Vec<u8>> {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+
+        let response = match entries.map.get(request) {
+            Some(e) if now.saturating_duration_since(e.inserted) < self.ttl => {
+                Some(e.response.clone())
+            }
+            Some(_) => {
+                entries.remove(request);
+                None
+            }
+            None => None,
+        };
+
+        let counter = if response.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        response
+    }
+
+    /// Cache the response to the request. Entries larger than the byte limit are not cached
+    pub fn insert(&self, request: Vec<u8>, response: Vec<u8>) {
+        let size = request.len() + response.len();
+        if size > self.max_bytes || self.max_entries == 0 {
+            return;
+        }
+
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&request);
+
+        let ttl = self.ttl;
+        let expired: Vec<Vec<u8>> = entries
+            .map
+            .iter()
+            .filter(|(_, e)| now.saturating_duration_since(e.inserted) >= ttl)
+            .map(|(r, _)| r.clone())
+            .collect();
+        expired.iter().for_each(|r| entries.remove(r));
+
+        while entries.map.len() >= self.max_entries || entries.bytes + size > self.max_bytes {
+            let oldest = entries
+                .map
+                .iter()
+                .min_by_key(|(_, e)| e.inserted)
+                .map(|(r, _)| r.clone());
+
+            match oldest {
+                Some(r) => entries.remove(&r),
+                None => break,
+            }
+        }
+
+        entries.bytes += size;
+        entries.map.insert(
+            request,
+            Entry {
+                response,
+                inserted: now,
+            },
+        );
+    }
+
+    /// Remove every entry
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.map.clear();
+        entries.bytes = 0;
+    }
+
+    /// Number of entries, including the expired ones not evicted yet
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().map.len()
+    }
+
+    /// Whether the cache has no entry
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Requests served from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Requests not found in the cache, or expired
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+impl Entries {
+    fn remove(&mut self, request: &[u8]) {
+        if let Some(e) = self.map.remove(request) {
+            self.bytes -= request.len() + e.response.len();
+        }
+    }
+}
+
+impl fmt::Debug for ResponseCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("ttl", &self.ttl)
+            .field("max_entries", &self.max_entries)
+            .field("max_bytes", &self.max_bytes)
+            .field("len", &self.len())
+            .finish()
+    }
+}
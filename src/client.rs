@@ -0,0 +1,724 @@
+use crate::{codec, reactor::Sleep, watch, Address, IpcStream, MuxClient, SessionClient};
+
+use futures::io::{AsyncRead, AsyncWrite};
+
+use std::{
+    future::{self, Future},
+    io::{self, Error as IoError},
+    mem,
+    ops::{Deref, DerefMut},
+    os::unix::{
+        io::{AsRawFd, FromRawFd},
+        net::UnixStream,
+    },
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Period between two connection attempts while the backlog of the listener is full
+const BACKLOG_RETRY: Duration = Duration::from_millis(1);
+
+/// Period between two connection attempts while the socket exists but is not yet accepting
+const AVAILABLE_RETRY: Duration = Duration::from_millis(10);
+
+/// Client of a UDS speaking the length-prefixed framing of [`crate::codec`], for tests and peer
+/// processes.
+///
+/// The connection is opened on the first call and reused by the next ones. It is dropped on any
+/// error, so the next call connects again. Only the connection is retried: a request failing
+/// after it was written is not sent again, since the server may have handled it.
+///
+/// Clones share the connection, and the calls are serialized over it. The blocking methods wait
+/// for the async call in progress, if any, so they shouldn't be called by the task awaiting it.
+#[derive(Debug, Clone)]
+pub struct UnixDomainClient {
+    address: Address,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    max_frame: usize,
+    stream: Arc<Connection>,
+}
+
+impl UnixDomainClient {
+    /// Client of the socket bound to the provided address, connecting once without timeout
+    pub fn new<A: Into<Address>>(address: A) -> Self {
+        UnixDomainClient {
+            address: address.into(),
+            connect_timeout: None,
+            timeout: None,
+            retries: 0,
+            backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            max_frame: codec::MAX_FRAME_SIZE,
+            stream: Arc::new(Connection::default()),
+        }
+    }
+
+    /// Fail an attempt to connect once the backlog of the listener stayed full for the timeout
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout.replace(timeout);
+        self
+    }
+
+    /// Fail the reads and writes that take longer than the timeout
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout.replace(timeout);
+        self
+    }
+
+    /// Attempt to connect up to `retries` more times while the socket is missing, refuses the
+    /// connection or times out. The wait between two attempts starts at `backoff` and doubles
+    /// up to `max_backoff`
+    pub fn retries(mut self, retries: u32, backoff: Duration, max_backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self.max_backoff = max_backoff.max(backoff);
+        self
+    }
+
+    /// Reject the response frames longer than `max` bytes. Defaults to
+    /// [`codec::MAX_FRAME_SIZE`]
+    pub fn max_frame(mut self, max: usize) -> Self {
+        self.max_frame = max;
+        self
+    }
+
+    /// Address of the socket
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Whether a connection is open
+    pub fn is_connected(&self) -> bool {
+        self.stream.hold().is_some()
+    }
+
+    /// Open the connection if it isn't already
+    pub fn connect(&self) -> Result<(), IoError> {
+        self.with_stream(|_| Ok(()))
+    }
+
+    /// Open the connection, waiting up to the timeout for the server to be available, such as a
+    /// daemon still starting.
+    ///
+    /// A missing socket file is waited for by watching its directory, instead of sleeping between
+    /// attempts. Once created, the connection is attempted again until it's accepted. Abstract
+    /// addresses leave no file, and are only attempted again.
+    pub fn connect_when_available(&self, timeout: Duration) -> Result<(), IoError> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(path) = self.address.as_path() {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                watch::wait_created(path, remaining)?;
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.connect() {
+                Err(e) if is_transient(&e) && !remaining.is_zero() => {
+                    debug!("{} not yet available: {}", self.address, e);
+                    thread::sleep(AVAILABLE_RETRY.min(remaining));
+                }
+
+                result => return result,
+            }
+        }
+    }
+
+    /// Close the connection. The next call opens a new one
+    pub fn close(&self) {
+        self.stream.hold().take();
+    }
+
+    /// Send the payload as a frame, without waiting for a response
+    pub fn send(&self, payload: &[u8]) -> Result<(), IoError> {
+        self.with_stream(|s| codec::write_frame(s, payload))
+    }
+
+    /// Send the payload as a frame and read the response frame. An error frame sent by the
+    /// server is returned as an error, see [`codec::ErrorFrame::from_io`]
+    pub fn request(&self, payload: &[u8]) -> Result<Vec<u8>, IoError> {
+        let max = self.max_frame;
+
+        self.with_stream(|s| {
+            codec::write_frame(s, payload)?;
+            codec::read_frame(s, max)
+        })
+    }
+
+    /// [`UnixDomainClient::send`] driven by the task awaiting the returned future, see
+    /// [`ClientFuture`]. Nothing is sent until it's polled
+    pub fn send_async(&self, payload: &[u8]) -> ClientFuture<()> {
+        let client = self.clone();
+        let payload = payload.to_vec();
+
+        ClientFuture::driven(async move { client.call_async(&payload, false).await.map(|_| ()) })
+    }
+
+    /// [`UnixDomainClient::request`] driven by the task awaiting the returned future, see
+    /// [`ClientFuture`]. Nothing is sent until it's polled
+    pub fn request_async(&self, payload: &[u8]) -> ClientFuture<Vec<u8>> {
+        let client = self.clone();
+        let payload = payload.to_vec();
+
+        ClientFuture::driven(async move {
+            client
+                .call_async(&payload, true)
+                .await
+                .map(Option::unwrap_or_default)
+        })
+    }
+
+    /// Open a connection of its own, with the settings of the client, multiplexing the calls of
+    /// the returned [`MuxClient`]
+    pub fn multiplexed(&self) -> Result<MuxClient, IoError> {
+        MuxClient::new(self.open()?, self.max_frame)
+    }
+
+    /// Client of a resumable session of a [`crate::SessionStore`], connecting with the settings
+    /// of the client and keeping up to `replay` unacknowledged frames
+    pub fn session(&self, replay: usize) -> SessionClient {
+        SessionClient::new(self.clone(), replay, self.max_frame)
+    }
+
+    fn with_stream<T, F>(&self, f: F) -> Result<T, IoError>
+    where
+        F: FnOnce(&mut UnixStream) -> Result<T, IoError>,
+    {
+        let mut stream = self.stream.hold();
+
+        if stream.is_none() {
+            stream.replace(self.open()?);
+        }
+
+        let result = stream.as_mut().map(f).unwrap_or_else(|| {
+            Err(IoError::new(
+                io::ErrorKind::NotConnected,
+                "The client is not connected",
+            ))
+        });
+
+        if result.is_err() {
+            stream.take();
+        }
+
+        result
+    }
+
+    /// Write the payload as a frame, then read the response frame if `respond`, without blocking
+    /// the task
+    async fn call_async(&self, payload: &[u8], respond: bool) -> Result<Option<Vec<u8>>, IoError> {
+        let mut guard = self.stream.hold_async().await;
+
+        let stream = match guard.take() {
+            Some(s) => s,
+            None => self.open_async().await?,
+        };
+
+        let stream = IpcStream::from(stream);
+        let mut timed = Timed::new(&stream, self.timeout);
+
+        codec::write_frame_async(&mut timed, payload).await?;
+        let response = match respond {
+            true => Some(codec::read_frame_async(&mut timed, self.max_frame).await?),
+            false => None,
+        };
+
+        // Only a call that succeeded gives the connection back, as the blocking calls do
+        guard.replace(stream.into_inner());
+        Ok(response)
+    }
+
+    pub(crate) fn open(&self) -> Result<UnixStream, IoError> {
+        let mut attempts = Attempts::new(self.backoff);
+
+        loop {
+            let result = match self.connect_timeout {
+                Some(t) => connect_timeout(&self.address, t),
+                None => self.address.connect(),
+            };
+
+            match result {
+                Ok(stream) => return self.opened(stream),
+                Err(e) => thread::sleep(self.retry_after(&mut attempts, e)?),
+            }
+        }
+    }
+
+    /// [`UnixDomainClient::open`] without blocking the task
+    async fn open_async(&self) -> Result<UnixStream, IoError> {
+        let mut attempts = Attempts::new(self.backoff);
+
+        loop {
+            match connect_async(&self.address, self.connect_timeout).await {
+                Ok(stream) => return self.opened(stream),
+                Err(e) => {
+                    let backoff = self.retry_after(&mut attempts, e)?;
+                    Sleep::until(Instant::now() + backoff).await?;
+                }
+            }
+        }
+    }
+
+    fn opened(&self, stream: UnixStream) -> Result<UnixStream, IoError> {
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+
+        Ok(stream)
+    }
+
+    /// Wait before the next connection attempt after the error, or the error if it's the last
+    fn retry_after(&self, attempts: &mut Attempts, e: IoError) -> Result<Duration, IoError> {
+        if attempts.attempt >= self.retries || !is_transient(&e) {
+            return Err(e);
+        }
+
+        let backoff = attempts.backoff;
+        debug!(
+            "Error connecting to {}, retrying in {:?}: {}",
+            self.address, backoff, e
+        );
+
+        attempts.backoff = (backoff * 2).min(self.max_backoff);
+        attempts.attempt += 1;
+
+        Ok(backoff)
+    }
+}
+
+/// Connection shared by the clones of a client, held by a single call at a time. Unlike a mutex,
+/// it can be held across the awaits of an async call, and waited for without blocking the task
+#[derive(Debug, Default)]
+struct Connection {
+    state: Mutex<Held>,
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Held {
+    stream: Option<UnixStream>,
+    busy: bool,
+    /// Tasks waiting for the connection
+    waiters: Vec<Waker>,
+}
+
+impl Connection {
+    /// Wait for the connection, blocking the thread
+    fn hold(&self) -> Holder<'_> {
+        let mut state = self.state.lock().unwrap();
+        while state.busy {
+            state = self.released.wait(state).unwrap();
+        }
+
+        self.take(&mut state)
+    }
+
+    /// Wait for the connection without blocking the task
+    async fn hold_async(&self) -> Holder<'_> {
+        future::poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            if !state.busy {
+                return Poll::Ready(self.take(&mut state));
+            }
+
+            if !state.waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                state.waiters.push(cx.waker().clone());
+            }
+
+            Poll::Pending
+        })
+        .await
+    }
+
+    fn take(&self, state: &mut Held) -> Holder<'_> {
+        state.busy = true;
+
+        Holder {
+            connection: self,
+            stream: state.stream.take(),
+        }
+    }
+}
+
+/// Connection held by a call, given back once dropped
+struct Holder<'a> {
+    connection: &'a Connection,
+    stream: Option<UnixStream>,
+}
+
+impl Deref for Holder<'_> {
+    type Target = Option<UnixStream>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.stream
+    }
+}
+
+impl DerefMut for Holder<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.stream
+    }
+}
+
+impl Drop for Holder<'_> {
+    fn drop(&mut self) {
+        let waiters = {
+            let mut state = self.connection.state.lock().unwrap();
+            state.stream = self.stream.take();
+            state.busy = false;
+            self.connection.released.notify_one();
+
+            mem::take(&mut state.waiters)
+        };
+
+        waiters.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// Connection attempts of a client, with the wait before the next one
+struct Attempts {
+    attempt: u32,
+    backoff: Duration,
+}
+
+impl Attempts {
+    fn new(backoff: Duration) -> Self {
+        Attempts {
+            attempt: 0,
+            backoff,
+        }
+    }
+}
+
+/// Errors of a connection attempt that may succeed later, such as a server not yet bound
+fn is_transient(e: &IoError) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::NotFound
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock
+    )
+}
+
+/// Non-blocking socket, to be connected with [`connect`]
+fn nonblocking_socket() -> Result<UnixStream, IoError> {
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0) };
+    if fd < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    // Owned right away, so the descriptor is closed on every error
+    let stream = unsafe { UnixStream::from_raw_fd(fd) };
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(IoError::last_os_error());
+    }
+    stream.set_nonblocking(true)?;
+
+    Ok(stream)
+}
+
+/// Attempt to connect the non-blocking socket, resolving to whether the connection is still in
+/// progress. A full backlog of the listener fails with `EAGAIN`
+fn connect(
+    stream: &UnixStream,
+    addr: &libc::sockaddr_un,
+    len: libc::socklen_t,
+) -> Result<bool, IoError> {
+    loop {
+        let ret = unsafe {
+            libc::connect(
+                stream.as_raw_fd(),
+                addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                len,
+            )
+        };
+
+        if ret == 0 {
+            return Ok(false);
+        }
+
+        let e = IoError::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::EINPROGRESS) => return Ok(true),
+            _ => return Err(e),
+        }
+    }
+}
+
+fn connect_timed_out(address: &Address) -> IoError {
+    IoError::new(
+        io::ErrorKind::TimedOut,
+        format!("Timed out connecting to {}", address),
+    )
+}
+
+/// Connect without blocking past the timeout while the backlog of the listener is full
+fn connect_timeout(address: &Address, timeout: Duration) -> Result<UnixStream, IoError> {
+    let (addr, len) = address.sockaddr()?;
+    let stream = nonblocking_socket()?;
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let result = connect(&stream, &addr, len);
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        match result {
+            Ok(false) => break,
+
+            Ok(true) => {
+                wait_writable(&stream, remaining)?;
+                break;
+            }
+
+            Err(e) if e.raw_os_error() == Some(libc::EAGAIN) && !remaining.is_zero() => {
+                thread::sleep(BACKLOG_RETRY.min(remaining));
+            }
+
+            Err(e) if e.raw_os_error() == Some(libc::EAGAIN) => {
+                return Err(connect_timed_out(address));
+            }
+
+            Err(e) => return Err(e),
+        }
+    }
+
+    stream.set_nonblocking(false)?;
+    Ok(stream)
+}
+
+/// Connect without blocking the task, waiting up to the timeout, if any, while the backlog of the
+/// listener is full
+async fn connect_async(
+    address: &Address,
+    timeout: Option<Duration>,
+) -> Result<UnixStream, IoError> {
+    let (addr, len) = address.sockaddr()?;
+    let stream = IpcStream::from(nonblocking_socket()?);
+    let deadline = timeout.map(|t| Instant::now() + t);
+
+    loop {
+        let result = connect(&stream, &addr, len);
+        let now = Instant::now();
+
+        match result {
+            Ok(false) => break,
+
+            Ok(true) => {
+                let remaining = deadline.map(|d| d.saturating_duration_since(now));
+                let mut timed = Timed::new(&stream, remaining);
+                future::poll_fn(|cx| timed.poll_writable(cx)).await?;
+
+                if let Some(e) = stream.take_error()? {
+                    return Err(e);
+                }
+                break;
+            }
+
+            Err(e) if e.raw_os_error() == Some(libc::EAGAIN) => {
+                let retry = now + BACKLOG_RETRY;
+                match deadline {
+                    Some(d) if d <= now => return Err(connect_timed_out(address)),
+                    Some(d) => Sleep::until(retry.min(d)).await?,
+                    None => Sleep::until(retry).await?,
+                }
+            }
+
+            Err(e) => return Err(e),
+        }
+    }
+
+    let stream = stream.into_inner();
+    stream.set_nonblocking(false)?;
+    Ok(stream)
+}
+
+/// Wait for a connection in progress to complete, returning its error if it failed
+fn wait_writable(stream: &UnixStream, timeout: Duration) -> Result<(), IoError> {
+    let mut pollfd = libc::pollfd {
+        fd: stream.as_raw_fd(),
+        events: libc::POLLOUT,
+        revents: 0,
+    };
+
+    let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    let ret = unsafe { libc::poll(&mut pollfd, 1, timeout) };
+
+    if ret < 0 {
+        return Err(IoError::last_os_error());
+    } else if ret == 0 {
+        return Err(IoError::new(
+            io::ErrorKind::TimedOut,
+            "Timed out connecting to the socket",
+        ));
+    }
+
+    stream.take_error()?.map(Err).unwrap_or(Ok(()))
+}
+
+/// Stream of an async call, failing the reads and writes that wait for the socket longer than
+/// the timeout, as the timeouts of the socket do for the blocking calls
+struct Timed<'a> {
+    stream: &'a IpcStream,
+    timeout: Option<Duration>,
+    sleep: Option<Sleep>,
+}
+
+impl<'a> Timed<'a> {
+    fn new(stream: &'a IpcStream, timeout: Option<Duration>) -> Self {
+        Timed {
+            stream,
+            timeout,
+            sleep: None,
+        }
+    }
+
+    fn poll_writable(&mut self, cx: &mut Context) -> Poll<Result<(), IoError>> {
+        let poll = self.stream.poll_writable(cx);
+        self.poll_timeout(cx, poll)
+    }
+
+    /// Fail the pending operation once it waited for the timeout. A completed one starts the
+    /// timeout of the next one over
+    fn poll_timeout<T>(
+        &mut self,
+        cx: &mut Context,
+        poll: Poll<Result<T, IoError>>,
+    ) -> Poll<Result<T, IoError>> {
+        let timeout = match (poll.is_pending(), self.timeout) {
+            (true, Some(t)) => t,
+            (true, None) => return Poll::Pending,
+            (false, _) => {
+                self.sleep.take();
+                return poll;
+            }
+        };
+
+        let sleep = self
+            .sleep
+            .get_or_insert_with(|| Sleep::until(Instant::now() + timeout));
+
+        match Pin::new(sleep).poll(cx) {
+            Poll::Ready(Ok(_)) => {
+                self.sleep.take();
+                Poll::Ready(Err(IoError::new(
+                    io::ErrorKind::TimedOut,
+                    "Timed out waiting for the socket",
+                )))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncRead for Timed<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, IoError>> {
+        let poll = Pin::new(&mut self.stream).poll_read(cx, buf);
+        self.poll_timeout(cx, poll)
+    }
+}
+
+impl AsyncWrite for Timed<'_> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        let poll = Pin::new(&mut self.stream).poll_write(cx, buf);
+        self.poll_timeout(cx, poll)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.stream).poll_close(cx)
+    }
+}
+
+/// Future of a call of a [`UnixDomainClient`] or a [`MuxClient`], to be awaited from any
+/// executor.
+///
+/// The async calls of a [`UnixDomainClient`] are driven by the task awaiting them. Their socket
+/// is read and written without blocking, and a background thread polling the sockets wakes the
+/// task once it's ready, so no thread is spawned per call. The calls of a [`MuxClient`] resolve
+/// once the thread reading the responses of its connection receives theirs.
+pub struct ClientFuture<T>(Call<T>);
+
+enum Call<T> {
+    /// Resolved by another thread through the slot
+    Slot(Arc<Mutex<Slot<T>>>),
+    /// Driven by the task polling it
+    Driven(Pin<Box<dyn Future<Output = Result<T, IoError>> + Send>>),
+}
+
+/// Result of a call, with the waker of the future waiting for it
+pub(crate) struct Slot<T> {
+    result: Option<Result<T, IoError>>,
+    waker: Option<Waker>,
+}
+
+impl<T> Slot<T> {
+    /// Set the result of the call, waking the future
+    pub(crate) fn resolve(&mut self, result: Result<T, IoError>) {
+        self.result.replace(result);
+        if let Some(w) = self.waker.take() {
+            w.wake();
+        }
+    }
+}
+
+impl<T> ClientFuture<T> {
+    /// Future resolved once the result is set in the returned slot
+    pub(crate) fn pending() -> (Self, Arc<Mutex<Slot<T>>>) {
+        let slot = Arc::new(Mutex::new(Slot {
+            result: None,
+            waker: None,
+        }));
+
+        let future = ClientFuture(Call::Slot(Arc::clone(&slot)));
+
+        (future, slot)
+    }
+
+    /// Future of the call, polled by the task awaiting it
+    fn driven<F>(call: F) -> Self
+    where
+        F: Future<Output = Result<T, IoError>> + Send + 'static,
+    {
+        ClientFuture(Call::Driven(Box::pin(call)))
+    }
+}
+
+impl<T> Future for ClientFuture<T> {
+    type Output = Result<T, IoError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let slot = match &mut self.0 {
+            Call::Slot(s) => s,
+            Call::Driven(call) => return call.as_mut().poll(cx),
+        };
+
+        let mut slot = slot.lock().unwrap();
+        match slot.result.take() {
+            Some(r) => Poll::Ready(r),
+            None => {
+                slot.waker.replace(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
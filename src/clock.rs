@@ -0,0 +1,114 @@
+use std::{
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Source of time of the UDS, used for every deadline, timeout and timestamp.
+///
+/// Set with [`crate::Options::clock`]. Blocking waits still elapse in real time, so a clock that
+/// doesn't follow it reports how often they must check it again with [`Clock::resolution`].
+pub trait Clock: Send + Sync {
+    /// Current instant, for deadlines and durations
+    fn now(&self) -> Instant;
+
+    /// Current wall-clock time, for timestamps
+    fn system_time(&self) -> SystemTime;
+
+    /// Longest real time a blocking wait may last before checking the clock again. `None` for
+    /// the clocks following real time
+    fn resolution(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Default clock, reading the time of the OS
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock that only moves when advanced, so deadlines and timeouts can be tested
+/// deterministically.
+///
+/// Blocking waits check it again every [`ManualClock::RESOLUTION`], so an advance is observed
+/// within that period.
+pub struct ManualClock {
+    origin: Instant,
+    system_origin: SystemTime,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    /// Real time between two checks of the clock by a blocking wait
+    pub const RESOLUTION: Duration = Duration::from_millis(10);
+
+    /// Clock starting at the current time
+    pub fn new() -> Self {
+        ManualClock {
+            origin: Instant::now(),
+            system_origin: SystemTime::now(),
+            elapsed: Mutex::new(Duration::from_secs(0)),
+        }
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    /// Time the clock was advanced since it was created
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl fmt::Debug for ManualClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManualClock")
+            .field("elapsed", &self.elapsed())
+            .finish()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.origin + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.system_origin + self.elapsed()
+    }
+
+    fn resolution(&self) -> Option<Duration> {
+        Some(ManualClock::RESOLUTION)
+    }
+}
+
+/// Real time to block for, waiting for the provided clock to reach the deadline. Zero once it is
+/// reached
+pub(crate) fn wait_until(clock: &dyn Clock, deadline: Instant) -> Duration {
+    wait_for(clock, deadline.saturating_duration_since(clock.now()))
+}
+
+/// Real time to block for, waiting for the provided clock to move by `duration`
+pub(crate) fn wait_for(clock: &dyn Clock, duration: Duration) -> Duration {
+    match clock.resolution() {
+        Some(r) if !duration.is_zero() => duration.min(r),
+        _ => duration,
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{clock, fd::FdGuard, stats::Stats, Clock};
+
+use std::{
+    io::{self, Error as IoError, Read},
+    mem,
+    net::Shutdown,
+    os::unix::{io::AsRawFd, net::UnixStream},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Set `SO_LINGER` on the socket, so closing it waits up to `timeout` for unsent data
+pub fn set_linger(stream: &UnixStream, timeout: Duration) -> Result<(), IoError> {
+    let linger = libc::linger {
+        l_onoff: 1,
+        l_linger: timeout.as_secs().min(libc::c_int::MAX as u64) as libc::c_int,
+    };
+
+    let ret = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_LINGER,
+            &linger as *const libc::linger as *const libc::c_void,
+            mem::size_of::<libc::linger>() as libc::socklen_t,
+        )
+    };
+
+    if ret < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Duplicate of a socket, kept to signal end of stream to the peer while the provider still owns
+/// the socket. See [`crate::DrainClass::Notify`]
+pub struct Notify {
+    stream: UnixStream,
+    _fd: FdGuard,
+}
+
+impl Notify {
+    pub fn new(stream: &UnixStream, stats: &Arc<Stats>) -> Option<Self> {
+        let stream = stream
+            .try_clone()
+            .map_err(|e| error!("Error duplicating the socket to notify the peer: {}", e))
+            .ok()?;
+
+        Some(Notify {
+            stream,
+            _fd: FdGuard::new(stats),
+        })
+    }
+
+    /// Half-close the socket, so the peer observes end of stream
+    pub fn notify(self) {
+        self.stream.shutdown(Shutdown::Write).unwrap_or_else(|e| {
+            error!("Error notifying the peer of the shutdown: {}", e);
+        });
+    }
+}
+
+/// Duplicate of a socket, kept to drain its input once the provider is dropped.
+///
+/// Closing a socket with unread input may reset the connection, and the peer might lose the
+/// response still waiting to be read.
+pub struct Drain {
+    stream: UnixStream,
+    clock: Arc<dyn Clock>,
+    _fd: FdGuard,
+}
+
+impl Drain {
+    pub fn new(stream: &UnixStream, stats: &Arc<Stats>) -> Option<Self> {
+        let stream = stream
+            .try_clone()
+            .map_err(|e| error!("Error duplicating the socket to drain it: {}", e))
+            .ok()?;
+
+        Some(Drain {
+            stream,
+            clock: Arc::clone(&stats.clock),
+            _fd: FdGuard::new(stats),
+        })
+    }
+
+    /// Signal end of stream to the peer, and discard its input until it closes or the timeout
+    /// elapses
+    pub fn close(self, timeout: Duration) {
+        self.drain(timeout).unwrap_or_else(|e| match e.kind() {
+            io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::BrokenPipe => (),
+            _ => error!("Error draining a closed connection: {}", e),
+        });
+    }
+
+    fn drain(mut self, timeout: Duration) -> Result<(), IoError> {
+        self.stream.shutdown(Shutdown::Write)?;
+
+        let deadline = self.clock.now() + timeout;
+        let mut buffer = [0x00u8; 4096];
+
+        loop {
+            let remaining = clock::wait_until(&*self.clock, deadline);
+            if remaining == Duration::from_secs(0) {
+                return Ok(());
+            }
+
+            self.stream.set_read_timeout(Some(remaining))?;
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(_) => (),
+                // The deadline is checked again against the clock
+                Err(e)
+                    if e.kind() == io::ErrorKind::Interrupted
+                        || e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
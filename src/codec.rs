@@ -0,0 +1,847 @@
+//! Length-prefixed framing.
+//!
+//! Every frame is a big-endian `u32` with the length of the payload, followed by the payload.
+//!
+//! The most significant bit of the length marks an [`ErrorFrame`], sent before closing the
+//! connection on a protocol violation. Its payload is a big-endian `u16` code, a big-endian `u32`
+//! with the milliseconds the peer should wait before retrying, zero for none, and a UTF-8 message.
+//!
+//! Multiplexed connections, such as the ones of [`crate::MuxClient`], prefix the payload of every
+//! frame with a big-endian `u64` correlation id, the response carrying the id of its request.
+//!
+//! The second most significant bit marks a shared frame, whose payload is passed as a sealed
+//! memfd instead of through the socket, see [`write_frame_shared`]. Its payload is the big-endian
+//! `u64` length of the contents of the memfd, passed with `SCM_RIGHTS` along with the prefix.
+//!
+//! [`LengthDelimitedFrame`] wraps a stream, blocking or asynchronous, so the providers read and
+//! write whole frames without handling the prefix themselves.
+
+use crate::{fdpass, Budget, Reservation, SharedBuffer, SharedPayload};
+
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use std::{
+    convert::TryFrom,
+    error::Error,
+    fmt,
+    io::{self, Error as IoError, Read, Write},
+    os::unix::{
+        io::{AsFd, AsRawFd},
+        net::UnixStream,
+    },
+    time::{Duration, Instant},
+};
+
+/// Default maximum payload length accepted by [`read_frame`]
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Capacity reserved for a payload before its bytes arrive. Longer payloads grow as they are
+/// read, so a peer announcing a large frame and sending nothing doesn't get it allocated
+const PAYLOAD_RESERVE: usize = 64 * 1024;
+
+/// Bytes requested from the socket by every read of a [`FrameReader`] reading ahead
+const READ_AHEAD_CHUNK: usize = 16 * 1024;
+
+/// Payload length up to which frames are encoded on the stack and written with a single call,
+/// without allocating
+pub const SMALL_FRAME_SIZE: usize = 256;
+
+/// Bit of the length prefix set for error frames
+pub(crate) const ERROR_FLAG: u32 = 0x8000_0000;
+
+/// Bit of the length prefix set for the frames passing their payload as a memfd
+const SHARED_FLAG: u32 = 0x4000_0000;
+
+/// Machine-readable reason of an [`ErrorFrame`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ErrorCode(pub u16);
+
+impl ErrorCode {
+    /// The frame was longer than the maximum accepted by the peer
+    pub const FRAME_TOO_LARGE: ErrorCode = ErrorCode(1);
+    /// The frame could not be decoded
+    pub const BAD_MAGIC: ErrorCode = ErrorCode(2);
+    /// The peer is not allowed to perform the request
+    pub const UNAUTHORIZED: ErrorCode = ErrorCode(3);
+    /// The request could not be handled
+    pub const INTERNAL: ErrorCode = ErrorCode(4);
+    /// The server is overloaded, the request may be retried later
+    pub const OVERLOADED: ErrorCode = ErrorCode(5);
+}
+
+/// Diagnostic sent to the peer before closing on a protocol violation.
+///
+/// [`read_frame`] returns a received error frame as an [`IoError`] of kind
+/// [`io::ErrorKind::Other`], which can be recovered with [`ErrorFrame::from_io`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorFrame {
+    /// Reason of the error
+    pub code: ErrorCode,
+    /// Human readable description
+    pub message: String,
+    /// Time the peer should wait before retrying, if at all
+    pub retry_after: Option<Duration>,
+}
+
+impl ErrorFrame {
+    /// Error frame without retry hint
+    pub fn new<M: Into<String>>(code: ErrorCode, message: M) -> Self {
+        ErrorFrame {
+            code,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    /// Error frame received by [`read_frame`], if that is the source of the provided error
+    pub fn from_io(e: &IoError) -> Option<&ErrorFrame> {
+        e.get_ref().and_then(|e| e.downcast_ref())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let retry_after = self
+            .retry_after
+            .map(|d| u32::try_from(d.as_millis()).unwrap_or(u32::MAX).max(1))
+            .unwrap_or(0);
+
+        let mut payload = Vec::with_capacity(6 + self.message.len());
+        payload.extend_from_slice(&self.code.0.to_be_bytes());
+        payload.extend_from_slice(&retry_after.to_be_bytes());
+        payload.extend_from_slice(self.message.as_bytes());
+
+        payload
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, IoError> {
+        if payload.len() < 6 {
+            return Err(IoError::new(
+                io::ErrorKind::UnexpectedEof,
+                "Truncated error frame",
+            ));
+        }
+
+        let code = u16::from_be_bytes([payload[0], payload[1]]);
+        let retry_after = u32::from_be_bytes([payload[2], payload[3], payload[4], payload[5]]);
+
+        Ok(ErrorFrame {
+            code: ErrorCode(code),
+            message: String::from_utf8_lossy(&payload[6..]).into_owned(),
+            retry_after: (retry_after > 0).then(|| Duration::from_millis(retry_after as u64)),
+        })
+    }
+}
+
+impl fmt::Display for ErrorFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error frame {}: {}", self.code.0, self.message)
+    }
+}
+
+impl Error for ErrorFrame {}
+
+/// Read a frame, rejecting payloads longer than `max` bytes with an error of kind
+/// [`io::ErrorKind::InvalidData`]
+pub fn read_frame<R: Read>(reader: &mut R, max: usize) -> Result<Vec<u8>, IoError> {
+    let (len, error) = read_prefix(reader, max)?;
+    let payload = read_payload(reader, len)?;
+
+    if error {
+        return Err(IoError::other(ErrorFrame::decode(&payload)?));
+    }
+
+    Ok(payload)
+}
+
+/// Read a frame into the provided buffer, without allocating, and return the length of its
+/// payload.
+///
+/// Meant for peers with a fixed maximum frame size, decoding the payload in place with a
+/// serialization format such as postcard. Payloads longer than the buffer are rejected as in
+/// [`read_frame`].
+pub fn read_frame_into<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, IoError> {
+    let (len, error) = read_prefix(reader, buffer.len())?;
+
+    let payload = &mut buffer[..len];
+    reader.read_exact(payload)?;
+
+    if error {
+        return Err(IoError::other(ErrorFrame::decode(payload)?));
+    }
+
+    Ok(len)
+}
+
+/// Read the length prefix, returning the payload length and whether it is an error frame
+fn read_prefix<R: Read>(reader: &mut R, max: usize) -> Result<(usize, bool), IoError> {
+    let mut prefix = [0x00u8; 4];
+    reader.read_exact(&mut prefix)?;
+
+    decode_prefix(prefix, max)
+}
+
+fn decode_prefix(prefix: [u8; 4], max: usize) -> Result<(usize, bool), IoError> {
+    let len = u32::from_be_bytes(prefix);
+    let error = len & ERROR_FLAG != 0;
+
+    let len = (len & !ERROR_FLAG) as usize;
+    if len > max {
+        return Err(IoError::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {} bytes exceeds the maximum of {}", len, max),
+        ));
+    }
+
+    Ok((len, error))
+}
+
+/// Read a payload of `len` bytes, allocating as they arrive rather than up front
+fn read_payload<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, IoError> {
+    let mut payload = Vec::with_capacity(len.min(PAYLOAD_RESERVE));
+    reader.take(len as u64).read_to_end(&mut payload)?;
+
+    if payload.len() < len {
+        return Err(truncated());
+    }
+
+    Ok(payload)
+}
+
+fn truncated() -> IoError {
+    IoError::new(
+        io::ErrorKind::UnexpectedEof,
+        "The connection was closed in the middle of a frame",
+    )
+}
+
+/// Write a frame with the provided payload
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> Result<(), IoError> {
+    write_prefixed(writer, payload, 0)
+}
+
+/// Asynchronous [`read_frame`], for sockets driven by an async runtime
+pub async fn read_frame_async<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max: usize,
+) -> Result<Vec<u8>, IoError> {
+    let mut prefix = [0x00u8; 4];
+    reader.read_exact(&mut prefix).await?;
+    let (len, error) = decode_prefix(prefix, max)?;
+
+    let mut payload = Vec::with_capacity(len.min(PAYLOAD_RESERVE));
+    reader.take(len as u64).read_to_end(&mut payload).await?;
+    if payload.len() < len {
+        return Err(truncated());
+    }
+
+    if error {
+        return Err(IoError::other(ErrorFrame::decode(&payload)?));
+    }
+
+    Ok(payload)
+}
+
+/// Asynchronous [`write_frame`], for sockets driven by an async runtime
+pub async fn write_frame_async<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    payload: &[u8],
+) -> Result<(), IoError> {
+    let prefix = encode_prefix(payload, 0)?;
+
+    writer.write_all(&prefix).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await
+}
+
+/// Write a frame of a multiplexed connection, its payload prefixed with the correlation id
+pub fn write_tagged_frame<W: Write>(
+    writer: &mut W,
+    id: u64,
+    payload: &[u8],
+) -> Result<(), IoError> {
+    let mut frame = Vec::with_capacity(8 + payload.len());
+    frame.extend_from_slice(&id.to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    write_frame(writer, &frame)
+}
+
+/// Read a frame of a multiplexed connection, returning its correlation id and payload. Payloads
+/// longer than `max` bytes, the id excluded, are rejected as in [`read_frame`]
+pub fn read_tagged_frame<R: Read>(reader: &mut R, max: usize) -> Result<(u64, Vec<u8>), IoError> {
+    let mut frame = read_frame(reader, max.saturating_add(8))?;
+
+    let id = frame
+        .get(..8)
+        .and_then(|id| <[u8; 8]>::try_from(id).ok())
+        .map(u64::from_be_bytes)
+        .ok_or_else(|| {
+            IoError::new(
+                io::ErrorKind::InvalidData,
+                "Multiplexed frame without correlation id",
+            )
+        })?;
+    frame.drain(..8);
+
+    Ok((id, frame))
+}
+
+/// Write a frame, passing the payload as a sealed memfd if it's at least `threshold` bytes long,
+/// such as [`crate::SHARED_THRESHOLD`], so a jumbo payload is copied once instead of streamed.
+///
+/// The payload is written inline if memfds are not supported. The peer must read the frame with
+/// [`read_frame_shared`]. To write the payload in place, without any copy, see [`write_shared`].
+pub fn write_frame_shared<S: AsRawFd + Write>(
+    stream: &mut S,
+    payload: &[u8],
+    threshold: usize,
+) -> Result<(), IoError> {
+    if payload.len() < threshold.max(1) {
+        return write_frame(stream, payload);
+    }
+
+    let mut buffer = match SharedBuffer::new(payload.len()) {
+        Ok(b) => b,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => return write_frame(stream, payload),
+        Err(e) => return Err(e),
+    };
+    buffer.copy_from_slice(payload);
+
+    write_shared(stream, buffer)
+}
+
+/// Seal the buffer and pass it to the peer as a shared frame, read with [`read_frame_shared`]
+pub fn write_shared<S: AsRawFd + Write>(
+    stream: &mut S,
+    buffer: SharedBuffer,
+) -> Result<(), IoError> {
+    let (fd, len) = buffer.seal()?;
+
+    let mut frame = [0x00u8; 12];
+    frame[..4].copy_from_slice(&(8 | SHARED_FLAG).to_be_bytes());
+    frame[4..].copy_from_slice(&(len as u64).to_be_bytes());
+
+    // The descriptor is passed with the first bytes, the remaining ones are written as usual
+    let sent = fdpass::send_fds(&*stream, &frame, &[fd.as_fd()])?;
+    stream.write_all(&frame[sent..])
+}
+
+/// Read a frame written with [`write_frame_shared`] or [`write_shared`], mapping the memfd of a
+/// shared frame instead of copying it. Payloads longer than `max` bytes, inline or shared, are
+/// rejected as in [`read_frame`].
+///
+/// The prefix is received with `recvmsg`, directly on the descriptor of the stream, so the
+/// stream must not buffer what it reads. A shared frame whose memfd is not sealed against any
+/// change is rejected with an error of kind [`io::ErrorKind::InvalidData`].
+pub fn read_frame_shared<S: AsRawFd + Read>(
+    stream: &mut S,
+    max: usize,
+) -> Result<SharedPayload, IoError> {
+    let mut fds = vec![];
+    let mut prefix = [0x00u8; 4];
+    let mut received = 0;
+
+    while received < prefix.len() {
+        match fdpass::recv_fds(&*stream, &mut prefix[received..], &mut fds)? {
+            0 => {
+                return Err(IoError::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "The peer closed the connection",
+                ))
+            }
+            n => received += n,
+        }
+    }
+
+    let raw = u32::from_be_bytes(prefix);
+    if raw & SHARED_FLAG == 0 || raw & ERROR_FLAG != 0 {
+        let (len, error) = decode_prefix(prefix, max)?;
+        let payload = read_payload(stream, len)?;
+
+        if error {
+            return Err(IoError::other(ErrorFrame::decode(&payload)?));
+        }
+
+        return Ok(SharedPayload::inline(payload));
+    }
+
+    let mut len = [0x00u8; 8];
+    if raw & !SHARED_FLAG != len.len() as u32 {
+        return Err(IoError::new(
+            io::ErrorKind::InvalidData,
+            "Shared frame without the length of its contents",
+        ));
+    }
+    stream.read_exact(&mut len)?;
+
+    let len = u64::from_be_bytes(len);
+    if len > max as u64 {
+        return Err(IoError::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {} bytes exceeds the maximum of {}", len, max),
+        ));
+    }
+
+    let fd = fds.pop().ok_or_else(|| {
+        IoError::new(
+            io::ErrorKind::InvalidData,
+            "Shared frame received without its memfd",
+        )
+    })?;
+
+    SharedPayload::map(fd, len as usize)
+}
+
+/// Write an error frame
+pub fn write_error<W: Write>(writer: &mut W, error: &ErrorFrame) -> Result<(), IoError> {
+    write_prefixed(writer, &error.encode(), ERROR_FLAG)
+}
+
+fn write_prefixed<W: Write>(writer: &mut W, payload: &[u8], flags: u32) -> Result<(), IoError> {
+    let prefix = encode_prefix(payload, flags)?;
+
+    if payload.len() <= SMALL_FRAME_SIZE {
+        let mut frame = [0x00u8; 4 + SMALL_FRAME_SIZE];
+        frame[..4].copy_from_slice(&prefix);
+        frame[4..4 + payload.len()].copy_from_slice(payload);
+
+        return writer.write_all(&frame[..4 + payload.len()]);
+    }
+
+    writer.write_all(&prefix)?;
+    writer.write_all(payload)
+}
+
+fn encode_prefix(payload: &[u8], flags: u32) -> Result<[u8; 4], IoError> {
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|len| len & (ERROR_FLAG | SHARED_FLAG) == 0)
+        .ok_or_else(|| IoError::new(io::ErrorKind::InvalidInput, "Frame payload too long"))?;
+
+    Ok((len | flags).to_be_bytes())
+}
+
+/// Stream read and written in frames, rejecting the frames longer than its maximum.
+///
+/// Wraps a blocking stream, such as a [`UnixStream`] or the [`crate::IpcStream`] of a provider,
+/// or an asynchronous one implementing [`AsyncRead`] and [`AsyncWrite`], with the `_async`
+/// methods. For explicit flush control or read-ahead, see [`FrameWriter`] and [`FrameReader`].
+#[derive(Debug)]
+pub struct LengthDelimitedFrame<S> {
+    stream: S,
+    max: usize,
+}
+
+impl<S> LengthDelimitedFrame<S> {
+    /// Framed stream accepting payloads up to [`MAX_FRAME_SIZE`] bytes
+    pub fn new(stream: S) -> Self {
+        LengthDelimitedFrame {
+            stream,
+            max: MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Reject the payloads longer than `max` bytes with an error of kind
+    /// [`io::ErrorKind::InvalidData`]
+    pub fn max_frame(mut self, max: usize) -> Self {
+        self.max = max;
+        self
+    }
+
+    /// Underlying stream
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Mutable reference to the underlying stream
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Take the underlying stream back
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Read + Write> LengthDelimitedFrame<S> {
+    /// Read the next frame, as [`read_frame`]
+    pub fn read_frame(&mut self) -> Result<Vec<u8>, IoError> {
+        read_frame(&mut self.stream, self.max)
+    }
+
+    /// Write a frame, as [`write_frame`]
+    pub fn write_frame(&mut self, payload: &[u8]) -> Result<(), IoError> {
+        write_frame(&mut self.stream, payload)
+    }
+
+    /// Write an error frame, as [`write_error`]
+    pub fn write_error(&mut self, error: &ErrorFrame) -> Result<(), IoError> {
+        write_error(&mut self.stream, error)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> LengthDelimitedFrame<S> {
+    /// Read the next frame, as [`read_frame_async`]
+    pub async fn read_frame_async(&mut self) -> Result<Vec<u8>, IoError> {
+        read_frame_async(&mut self.stream, self.max).await
+    }
+
+    /// Write a frame, as [`write_frame_async`]
+    pub async fn write_frame_async(&mut self, payload: &[u8]) -> Result<(), IoError> {
+        write_frame_async(&mut self.stream, payload).await
+    }
+}
+
+/// Framed writer with explicit flush control.
+///
+/// Frames are encoded into an internal buffer and written with a single call once
+/// [`FrameWriter::flush`] is called. When coalescing is enabled, the buffer is also flushed
+/// automatically as soon as it holds `max_bytes`, or when a frame is written after the oldest
+/// buffered frame waited for `max_delay`. Without coalescing, every frame is flushed as it is
+/// written, and frames up to [`SMALL_FRAME_SIZE`] bypass the buffer, so they are written without
+/// allocating. The buffer is flushed on drop, ignoring errors.
+pub struct FrameWriter<W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+    coalescing: Option<(usize, Duration)>,
+    oldest: Option<Instant>,
+    reservation: Option<Reservation>,
+}
+
+impl<W: Write> FrameWriter<W> {
+    /// Writer that flushes every frame as it is written
+    pub fn new(writer: W) -> Self {
+        FrameWriter {
+            writer,
+            buffer: vec![],
+            coalescing: None,
+            oldest: None,
+            reservation: None,
+        }
+    }
+
+    /// Writer that coalesces frames until `max_bytes` are buffered or `max_delay` elapsed
+    pub fn coalescing(writer: W, max_bytes: usize, max_delay: Duration) -> Self {
+        FrameWriter {
+            writer,
+            buffer: Vec::with_capacity(max_bytes),
+            coalescing: Some((max_bytes, max_delay)),
+            oldest: None,
+            reservation: None,
+        }
+    }
+
+    /// Account the buffered frames against the memory budget of the connection. Over the
+    /// budget, the buffer is flushed right away, and the error is returned unless it is
+    /// backpressure
+    pub fn budget(mut self, budget: &Budget) -> Self {
+        self.reservation = budget.reserve(0).ok();
+        self
+    }
+
+    /// Encode a frame, flushing the buffer according to the coalescing thresholds
+    pub fn write_frame(&mut self, payload: &[u8]) -> Result<(), IoError> {
+        if self.coalescing.is_none()
+            && self.buffer.is_empty()
+            && payload.len() <= SMALL_FRAME_SIZE
+            && self.reservation.is_none()
+        {
+            write_frame(&mut self.writer, payload)?;
+            return self.writer.flush();
+        }
+
+        write_frame(&mut self.buffer, payload)?;
+        let oldest = *self.oldest.get_or_insert_with(Instant::now);
+
+        let reserved = match self.reservation.as_mut() {
+            Some(r) => r.resize(self.buffer.len()),
+            None => Ok(()),
+        };
+
+        let flush = reserved.is_err()
+            || match self.coalescing {
+                Some((max_bytes, max_delay)) => {
+                    self.buffer.len() >= max_bytes || oldest.elapsed() >= max_delay
+                }
+                None => true,
+            };
+
+        if flush {
+            self.flush()?;
+        }
+
+        reserved.or_else(|e| {
+            if e.kind() == io::ErrorKind::WouldBlock {
+                Ok(())
+            } else {
+                Err(e)
+            }
+        })
+    }
+
+    /// Encode an error frame after the buffered frames, and flush them all
+    pub fn write_error(&mut self, error: &ErrorFrame) -> Result<(), IoError> {
+        write_error(&mut self.buffer, error)?;
+        self.flush()
+    }
+
+    /// Write all the buffered frames to the underlying writer
+    pub fn flush(&mut self) -> Result<(), IoError> {
+        if !self.buffer.is_empty() {
+            self.writer.write_all(&self.buffer)?;
+            self.buffer.clear();
+        }
+
+        if let Some(r) = self.reservation.as_mut() {
+            r.resize(0).unwrap_or_default();
+        }
+
+        self.oldest = None;
+        self.writer.flush()
+    }
+
+    /// Bytes waiting for a flush
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Reference to the underlying writer
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Mutable reference to the underlying writer. Writing to it directly bypasses the buffered
+    /// frames
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+}
+
+impl<W: Write> Drop for FrameWriter<W> {
+    fn drop(&mut self) {
+        self.flush().unwrap_or_default();
+    }
+}
+
+/// Framed reader with optional read-ahead.
+///
+/// With read-ahead, every read first takes what the socket already holds, without blocking, up to
+/// `depth` complete frames ahead of the caller. The requests of a pipelining peer are then
+/// decoded from a buffer instead of costing two reads each. A read blocks only while no complete
+/// frame is buffered. Errors are returned in order, after the frames read before them, and end
+/// the read-ahead. The bytes read ahead are taken from the socket, so reading it by other means
+/// misses them.
+pub struct FrameReader<R: Read> {
+    source: Source<R>,
+    max: usize,
+}
+
+enum Source<R> {
+    Direct(R),
+    ReadAhead(ReadAhead),
+}
+
+/// Bytes of a socket read ahead of the frames requested, on the thread of the caller
+struct ReadAhead {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+    /// Start of the next frame in the buffer
+    start: usize,
+    depth: usize,
+    /// Error that ended the read-ahead, returned once the frames before it are consumed
+    error: Option<IoError>,
+    ended: bool,
+}
+
+impl ReadAhead {
+    fn read_frame(&mut self, max: usize) -> Result<Vec<u8>, IoError> {
+        if self.ended {
+            return Err(IoError::new(
+                io::ErrorKind::UnexpectedEof,
+                "The read-ahead ended after an error",
+            ));
+        }
+
+        let result = self.next_frame(max);
+        self.ended = result.is_err();
+        result
+    }
+
+    fn next_frame(&mut self, max: usize) -> Result<Vec<u8>, IoError> {
+        self.fill(max, libc::MSG_DONTWAIT);
+
+        loop {
+            if let Some((len, error)) = self.frame_at(self.start, max)? {
+                return self.take(len, error);
+            }
+
+            if let Some(e) = self.error.take() {
+                return Err(e);
+            }
+
+            self.fill(max, 0);
+        }
+    }
+
+    /// Payload length of the frame starting at the offset, and whether it is an error frame,
+    /// once it's buffered whole
+    fn frame_at(&self, offset: usize, max: usize) -> Result<Option<(usize, bool)>, IoError> {
+        let buffered = &self.buffer[offset..];
+        let prefix = match buffered.get(..4) {
+            Some(p) => [p[0], p[1], p[2], p[3]],
+            None => return Ok(None),
+        };
+
+        let (len, error) = decode_prefix(prefix, max)?;
+        Ok(Some((len, error)).filter(|_| 4 + len <= buffered.len()))
+    }
+
+    fn take(&mut self, len: usize, error: bool) -> Result<Vec<u8>, IoError> {
+        let payload = self.buffer[self.start + 4..self.start + 4 + len].to_vec();
+        self.start += 4 + len;
+
+        if self.start == self.buffer.len() {
+            self.buffer.clear();
+            self.start = 0;
+        } else if self.start >= READ_AHEAD_CHUNK {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+        }
+
+        if error {
+            return Err(IoError::other(ErrorFrame::decode(&payload)?));
+        }
+
+        Ok(payload)
+    }
+
+    /// Number of frames buffered whole, up to the depth
+    fn buffered_frames(&self, max: usize) -> usize {
+        let mut offset = self.start;
+        let mut frames = 0;
+
+        while frames < self.depth {
+            match self.frame_at(offset, max) {
+                Ok(Some((len, _))) => offset += 4 + len,
+                Ok(None) => break,
+                // Nothing past a malformed frame is read
+                Err(_) => return self.depth,
+            }
+            frames += 1;
+        }
+
+        frames
+    }
+
+    /// Read from the socket with the flags, once when blocking, and until the socket is drained or
+    /// `depth` frames are buffered otherwise. Errors are kept for once the buffered frames are
+    /// consumed
+    fn fill(&mut self, max: usize, flags: libc::c_int) {
+        while self.error.is_none() && self.buffered_frames(max) < self.depth {
+            let filled = self.buffer.len();
+            self.buffer.resize(filled + READ_AHEAD_CHUNK, 0x00);
+
+            let ret = unsafe {
+                libc::recv(
+                    self.stream.as_raw_fd(),
+                    self.buffer[filled..].as_mut_ptr() as *mut libc::c_void,
+                    READ_AHEAD_CHUNK,
+                    flags,
+                )
+            };
+
+            let received = match ret {
+                n if n > 0 => n as usize,
+                0 => 0,
+                _ => {
+                    self.buffer.truncate(filled);
+
+                    let e = IoError::last_os_error();
+                    match e.kind() {
+                        io::ErrorKind::Interrupted => continue,
+                        io::ErrorKind::WouldBlock if flags & libc::MSG_DONTWAIT != 0 => return,
+                        _ => {
+                            self.error.replace(e);
+                            return;
+                        }
+                    }
+                }
+            };
+
+            self.buffer.truncate(filled + received);
+            if received == 0 {
+                self.error.replace(match self.start == self.buffer.len() {
+                    true => io::ErrorKind::UnexpectedEof.into(),
+                    false => truncated(),
+                });
+            }
+
+            if flags & libc::MSG_DONTWAIT == 0 {
+                return;
+            }
+        }
+    }
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Reader that reads every frame when requested, rejecting payloads longer than `max` bytes
+    pub fn new(reader: R, max: usize) -> Self {
+        FrameReader {
+            source: Source::Direct(reader),
+            max,
+        }
+    }
+
+    /// Read the next frame into the provided buffer, as [`read_frame_into`]. Without read-ahead,
+    /// no allocation is performed, so a buffer reused across frames, such as one on the stack
+    /// for frames up to [`SMALL_FRAME_SIZE`], keeps the read path free of allocations
+    pub fn read_frame_into(&mut self, buffer: &mut [u8]) -> Result<usize, IoError> {
+        let frame = match &mut self.source {
+            Source::Direct(reader) => return read_frame_into(reader, buffer),
+            Source::ReadAhead(_) => self.read_frame()?,
+        };
+
+        let max = buffer.len();
+        let payload = buffer.get_mut(..frame.len()).ok_or_else(|| {
+            IoError::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Frame of {} bytes exceeds the maximum of {}",
+                    frame.len(),
+                    max
+                ),
+            )
+        })?;
+        payload.copy_from_slice(&frame);
+
+        Ok(frame.len())
+    }
+
+    /// Read the next frame, as [`read_frame`]
+    pub fn read_frame(&mut self) -> Result<Vec<u8>, IoError> {
+        match &mut self.source {
+            Source::Direct(reader) => read_frame(reader, self.max),
+            Source::ReadAhead(ahead) => ahead.read_frame(self.max),
+        }
+    }
+}
+
+impl FrameReader<UnixStream> {
+    /// Reader decoding up to `depth` frames of the socket ahead of the caller. The socket is
+    /// shared with the caller, and left open once the reader is dropped
+    pub fn read_ahead(stream: &UnixStream, max: usize, depth: usize) -> Result<Self, IoError> {
+        let ahead = ReadAhead {
+            stream: stream.try_clone()?,
+            buffer: vec![],
+            start: 0,
+            depth: depth.max(1),
+            error: None,
+            ended: false,
+        };
+
+        Ok(FrameReader {
+            source: Source::ReadAhead(ahead),
+            max,
+        })
+    }
+}
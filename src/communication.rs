@@ -1,4 +1,4 @@
-use std::os::unix::net::UnixStream;
+use std::os::unix::{io::RawFd, net::UnixStream};
 
 /// Queable tasks
 pub enum Task {
@@ -19,4 +19,34 @@ pub enum Message {
     Error,
     /// Reschedule socket
     Reschedule,
+    /// Application defined message, delivered to [`crate::TaskProvider::handle_message`]
+    Custom(Vec<u8>),
+    /// Hand the socket over to the provider registered with the provided name, taking it back
+    /// with [`crate::TaskProvider::take_socket`]
+    Handoff(String),
+    /// The provider took the socket out of the crate, to keep handling it elsewhere. The worker
+    /// forgets the connection without closing, draining or checking the socket
+    Detached,
+    /// Hand the descriptor of a connected socket, such as one received with
+    /// [`crate::recv_fds`], over to the workers of the group, which serve it as a newly accepted
+    /// connection. The worker takes ownership of the descriptor, and forgets the current
+    /// connection as with [`Message::Detached`]
+    Handover(RawFd),
+}
+
+/// Cause of the shutdown of a UDS, delivered to the providers in progress with
+/// [`crate::TaskProvider::shutting_down`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// The provided signal was received, see [`crate::Options::handle_signals`]
+    Signal(i32),
+    /// Requested by the application, through a [`crate::ShutdownHandle`] or a
+    /// [`Message::ShouldQuit`] sent with a [`crate::TaskSender`]
+    Admin,
+    /// There were no connections for the period of [`crate::Options::exit_on_idle`]
+    IdleExit,
+    /// A provider resolved to [`Message::ShouldQuit`]
+    Provider,
+    /// The UDS can't keep running, with the description of the error
+    Fatal(String),
 }
@@ -0,0 +1,356 @@
+//! Conformance checks of a provider speaking the framing of [`crate::codec`].
+//!
+//! A [`Suite`] binds the provider to a temporary path, as a regular [`crate::UnixDomainSocket`],
+//! and connects scripted clients to it, some of them misbehaving: slow writes, partial frames,
+//! abrupt closes and oversized frames. A conformant provider answers the well-formed requests,
+//! closes or rejects with an [`ErrorFrame`] the connections it can't serve, within the timeout,
+//! and keeps serving the next clients.
+//!
+//! Meant for the tests of downstream providers:
+//!
+//! ```no_run
+//! use dusk_uds::{conformance::Suite, oneshot_handler};
+//!
+//! let report = Suite::new(b"ping")
+//!     .run(oneshot_handler(|request, _| request))
+//!     .unwrap();
+//!
+//! assert!(report.passes(), "{}", report);
+//! ```
+
+use crate::{
+    codec::{self, ErrorFrame, ERROR_FLAG},
+    Message, Options, Task, TaskProvider, UdsError, UnixDomainSocket,
+};
+
+use std::{
+    convert::TryFrom,
+    env, fmt, fs,
+    io::{self, Error as IoError, Read, Write},
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Number of pieces the request is split in by [`Check::SlowWrite`]
+const SLOW_WRITE_PIECES: usize = 8;
+
+/// Pause between two pieces of [`Check::SlowWrite`]
+const SLOW_WRITE_DELAY: Duration = Duration::from_millis(20);
+
+/// Suites run by the process, so each one binds a path of its own
+static SUITES: AtomicUsize = AtomicUsize::new(0);
+
+/// Scripted client behavior
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    /// Send the probe as a single frame, expecting a response frame
+    Request,
+    /// Send the probe in pieces, pausing between them, expecting a response frame
+    SlowWrite,
+    /// Send the length prefix and half of the probe, then end the stream, expecting the connection
+    /// to be closed without a response frame
+    PartialFrame,
+    /// Send the length prefix and half of the probe, then close the connection, expecting the next
+    /// request to be answered
+    AbruptClose,
+    /// Send the length prefix of a frame longer than the maximum, expecting the connection to be
+    /// closed or rejected with an [`ErrorFrame`] without waiting for the payload
+    OversizedFrame,
+    /// Send the probe once every other check ran, expecting the provider to still answer it
+    Recovery,
+}
+
+/// Result of a single [`Check`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// Client behavior played
+    pub check: Check,
+    /// Description of the misbehavior of the provider, if any
+    pub violation: Option<String>,
+}
+
+/// Outcomes of a [`Suite`], in the order the checks ran
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Every check run
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    /// Whether the provider passed every check
+    pub fn passes(&self) -> bool {
+        self.outcomes.iter().all(|o| o.violation.is_none())
+    }
+
+    /// Checks the provider failed
+    pub fn violations(&self) -> impl Iterator<Item = &Outcome> {
+        self.outcomes.iter().filter(|o| o.violation.is_some())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, outcome) in self.violations().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+
+            let violation = outcome.violation.as_deref().unwrap_or_default();
+            write!(f, "{:?}: {}", outcome.check, violation)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Battery of scripted clients run against a provider
+pub struct Suite {
+    probe: Vec<u8>,
+    timeout: Duration,
+    max_frame: usize,
+    options: Option<Options>,
+}
+
+impl Suite {
+    /// Suite sending the provided probe, which must be a request the provider answers with a
+    /// single frame
+    pub fn new(probe: &[u8]) -> Self {
+        Suite {
+            probe: probe.to_vec(),
+            timeout: Duration::from_secs(5),
+            max_frame: codec::MAX_FRAME_SIZE,
+            options: None,
+        }
+    }
+
+    /// Time the provider has to answer or close a connection. Defaults to 5 seconds
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Maximum length of the request frames accepted by the provider, exceeded by
+    /// [`Check::OversizedFrame`]. Defaults to [`codec::MAX_FRAME_SIZE`]
+    pub fn max_frame(mut self, max: usize) -> Self {
+        self.max_frame = max;
+        self
+    }
+
+    /// Serve the provider with the provided options instead of the default ones
+    pub fn options(mut self, options: Options) -> Self {
+        self.options.replace(options);
+        self
+    }
+
+    /// Serve the provider on a temporary path and run every check against it.
+    ///
+    /// Fails only if the provider can't be served. A provider whose workers don't finish within
+    /// the timeout once the checks ran is reported as a violation, and its workers are left
+    /// behind.
+    pub fn run<T: TaskProvider + 'static>(self, provider: T) -> Result<Report, UdsError> {
+        let Suite {
+            probe,
+            timeout,
+            max_frame,
+            options,
+        } = self;
+
+        let path = env::temp_dir().join(format!(
+            "dusk-uds-conformance-{}-{}.sock",
+            process::id(),
+            SUITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let banner = options
+            .as_ref()
+            .and_then(|o| o.banner.as_ref())
+            .map_or(0, |b| b.frame().len());
+
+        let handle = UnixDomainSocket::new(path.as_path(), options, provider).start()?;
+        let client = Client {
+            path: path.clone(),
+            timeout,
+            banner,
+        };
+
+        let checks = [
+            Check::Request,
+            Check::SlowWrite,
+            Check::PartialFrame,
+            Check::AbruptClose,
+            Check::OversizedFrame,
+            Check::Recovery,
+        ];
+        let mut report = Report {
+            outcomes: checks
+                .iter()
+                .map(|check| Outcome {
+                    check: *check,
+                    violation: client
+                        .play(*check, &probe, max_frame)
+                        .err()
+                        .map(|e| e.to_string()),
+                })
+                .collect(),
+        };
+
+        // The workers are joined on a thread of their own, so a provider that never finishes
+        // doesn't block the report
+        let (joined_tx, joined) = mpsc::channel();
+        handle
+            .task_sender()
+            .send(Task::Message(Message::ShouldQuit))?;
+        thread::spawn(move || joined_tx.send(handle.join()).unwrap_or_default());
+
+        match joined.recv_timeout(timeout) {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => report.outcomes.push(Outcome {
+                check: Check::Recovery,
+                violation: Some("The workers didn't finish once asked to quit".to_string()),
+            }),
+        }
+
+        fs::remove_file(path).unwrap_or_default();
+        Ok(report)
+    }
+}
+
+/// Client of the path served by a [`Suite`]
+struct Client {
+    path: PathBuf,
+    timeout: Duration,
+    /// Length of the banner written to every accepted socket, skipped before reading
+    banner: usize,
+}
+
+impl Client {
+    /// Play the check, failing with the violation of the provider
+    fn play(&self, check: Check, probe: &[u8], max_frame: usize) -> Result<(), IoError> {
+        let mut frame = vec![];
+        codec::write_frame(&mut frame, probe)?;
+        let partial = &frame[..4 + probe.len() / 2];
+
+        let mut stream = self.connect()?;
+
+        match check {
+            Check::Request | Check::Recovery => {
+                send(&mut stream, &frame)?;
+                self.expect_response(&mut stream, max_frame)
+            }
+
+            Check::SlowWrite => {
+                let piece = frame.len().div_ceil(SLOW_WRITE_PIECES);
+                for p in frame.chunks(piece) {
+                    send(&mut stream, p)?;
+                    thread::sleep(SLOW_WRITE_DELAY);
+                }
+
+                self.expect_response(&mut stream, max_frame)
+            }
+
+            Check::PartialFrame => {
+                send(&mut stream, partial)?;
+                stream.shutdown(Shutdown::Write)?;
+                self.expect_close(&mut stream)
+            }
+
+            Check::AbruptClose => {
+                send(&mut stream, partial)?;
+                drop(stream);
+
+                let mut next = self.connect()?;
+                send(&mut next, &frame)?;
+                self.expect_response(&mut next, max_frame)
+            }
+
+            Check::OversizedFrame => {
+                let len = u32::try_from(max_frame + 1)
+                    .ok()
+                    .filter(|l| l & ERROR_FLAG == 0)
+                    .ok_or_else(|| {
+                        IoError::new(
+                            io::ErrorKind::InvalidInput,
+                            "The maximum frame length can't be exceeded by a frame",
+                        )
+                    })?;
+
+                send(&mut stream, &len.to_be_bytes())?;
+                self.expect_close(&mut stream)
+            }
+        }
+    }
+
+    fn connect(&self) -> Result<UnixStream, IoError> {
+        let mut stream = UnixStream::connect(Path::new(&self.path))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut banner = vec![0x00u8; self.banner];
+        stream.read_exact(&mut banner)?;
+
+        Ok(stream)
+    }
+
+    fn expect_response(&self, stream: &mut UnixStream, max_frame: usize) -> Result<(), IoError> {
+        codec::read_frame(stream, max_frame)
+            .map(|_| ())
+            .map_err(|e| match ErrorFrame::from_io(&e) {
+                Some(error) => IoError::other(format!("The request was rejected: {}", error)),
+                None => timed_out(e, "The request wasn't answered"),
+            })
+    }
+
+    /// Read until the provider closes the connection, which may be rejected with an error frame
+    fn expect_close(&self, stream: &mut UnixStream) -> Result<(), IoError> {
+        let mut received = vec![];
+
+        let closed = loop {
+            let mut buffer = [0x00u8; 4096];
+
+            match stream.read(&mut buffer) {
+                Ok(0) => break Ok(()),
+                Ok(n) => received.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => break Ok(()),
+                Err(e) => break Err(timed_out(e, "The connection was left open")),
+            }
+        };
+
+        let answered = received
+            .get(..4)
+            .map(|p| u32::from_be_bytes([p[0], p[1], p[2], p[3]]))
+            .is_some_and(|len| len & ERROR_FLAG == 0);
+
+        if answered {
+            return Err(IoError::other("An incomplete request frame was answered"));
+        }
+
+        closed
+    }
+}
+
+/// Describe a read or write timing out as the provided violation
+fn timed_out(e: IoError, violation: &str) -> IoError {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => IoError::new(
+            io::ErrorKind::TimedOut,
+            format!("{} within the timeout", violation),
+        ),
+        _ => IoError::new(e.kind(), format!("{}: {}", violation, e)),
+    }
+}
+
+/// Write the bytes, describing the error as a violation
+fn send(stream: &mut UnixStream, bytes: &[u8]) -> Result<(), IoError> {
+    stream
+        .write_all(bytes)
+        .map_err(|e| IoError::new(e.kind(), format!("Error sending the request: {}", e)))
+}
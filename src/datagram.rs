@@ -0,0 +1,408 @@
+use crate::{
+    listener::{self, BindSettings},
+    validate, warmup, Address, Options, UdsError,
+};
+
+use std::{
+    io::{self, Error as IoError},
+    net::Shutdown,
+    os::unix::io::AsRawFd,
+    os::unix::net::{SocketAddr, UnixDatagram},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Default maximum length of a packet accepted by a [`DatagramSocket`]
+pub const MAX_PACKET_SIZE: usize = 64 * 1024;
+
+/// Handler of the packets received by a [`DatagramSocket`]
+pub trait DatagramProvider: Send + Sync + 'static {
+    /// Handle a packet, returning the reply to send back to its sender, if any
+    fn handle(&self, packet: Vec<u8>, from: &SocketAddr) -> Option<Vec<u8>>;
+}
+
+/// Server of a `SOCK_DGRAM` socket, for message-oriented local protocols.
+///
+/// Every worker thread receives packets from the same socket and hands them to the provider. The
+/// reply is sent back to the address of the sender, so the handlers don't deal with addressing.
+/// A sender that didn't bind its own socket has no address to reply to, and its replies are
+/// dropped. A [`DatagramClient`] binds one of its own.
+///
+/// Of the [`Options`], only the workers, the validation, the warm-up and the permissions of the
+/// socket file apply.
+pub struct DatagramSocket<D: DatagramProvider> {
+    address: Address,
+    options: Options,
+    provider: Arc<D>,
+    max_packet: usize,
+}
+
+impl<D: DatagramProvider> DatagramSocket<D> {
+    /// Default constructor.
+    ///
+    /// The address is a filesystem path, or a name in the abstract namespace of Linux, written
+    /// as `@name`. See [`Address`].
+    pub fn new<A: Into<Address>>(address: A, options: Option<Options>, provider: D) -> Self {
+        DatagramSocket {
+            address: address.into(),
+            options: options.unwrap_or_default(),
+            provider: Arc::new(provider),
+            max_packet: MAX_PACKET_SIZE,
+        }
+    }
+
+    /// Drop the packets longer than `max` bytes. Defaults to [`MAX_PACKET_SIZE`]
+    pub fn max_packet(mut self, max: usize) -> Self {
+        self.max_packet = max;
+        self
+    }
+
+    /// Will remove the path, if it exists, bind it and block until the socket is shut down
+    pub fn bind(self) -> Result<(), UdsError> {
+        self.start()?.join()
+    }
+
+    /// Same as [`DatagramSocket::bind`], but will return as soon as the workers are spawned
+    pub fn start(self) -> Result<DatagramHandle, UdsError> {
+        if let Some(validation) = self.options.validation {
+            validate::enforce(
+                validate::validate(self.address.as_path(), &self.options),
+                validation,
+            )?;
+        }
+
+        let socket = listener::bind_with(
+            &self.address,
+            &BindSettings::of(&self.options),
+            Address::bind_datagram,
+        )?;
+
+        if let Some(warm_up) = self.options.warm_up.as_ref() {
+            warmup::run(warm_up, self.options.warm_up_timeout)?;
+            info!("UDS warm-up finished");
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut threads = vec![];
+
+        for id in 0..self.options.workers.max(1) {
+            let socket = socket.try_clone()?;
+            let provider = Arc::clone(&self.provider);
+            let stop = Arc::clone(&stop);
+            let max_packet = self.max_packet;
+
+            threads.push(
+                thread::Builder::new()
+                    .name(format!("uds-datagram-{}", id))
+                    .spawn(move || receive(&socket, provider.as_ref(), &stop, max_packet))?,
+            );
+        }
+
+        Ok(DatagramHandle {
+            address: self.address,
+            socket,
+            stop,
+            threads,
+        })
+    }
+}
+
+/// Handle to a running [`DatagramSocket`], returned by [`DatagramSocket::start`]
+pub struct DatagramHandle {
+    address: Address,
+    socket: UnixDatagram,
+    stop: Arc<AtomicBool>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl DatagramHandle {
+    /// Stop receiving packets. The packets being handled are still replied to
+    pub fn shutdown(&self) {
+        self.stop.store(true, Ordering::Release);
+
+        // Wakes up the workers blocked receiving
+        self.socket
+            .shutdown(Shutdown::Read)
+            .unwrap_or_else(|e| error!("Error shutting the datagram socket down: {}", e));
+    }
+
+    /// Block until the socket is shut down and the workers are finished, then remove the path.
+    /// Fails with [`UdsError::WorkerPanicked`] once done if any worker panicked
+    pub fn join(self) -> Result<(), UdsError> {
+        let mut panicked = 0;
+        for t in self.threads {
+            if t.join().is_err() {
+                error!("Error ending the datagram worker gracefully: the worker panicked");
+                panicked += 1;
+            }
+        }
+
+        info!("Unbinding UDS");
+        drop(self.socket);
+        self.address.remove_file()?;
+
+        match panicked {
+            0 => Ok(()),
+            workers => Err(UdsError::WorkerPanicked { workers }),
+        }
+    }
+}
+
+fn receive<D: DatagramProvider>(
+    socket: &UnixDatagram,
+    provider: &D,
+    stop: &AtomicBool,
+    max_packet: usize,
+) {
+    // One more byte than accepted, so a longer packet is detected instead of truncated
+    let mut buffer = vec![0x00u8; max_packet + 1];
+
+    loop {
+        let (n, from) = match socket.recv_from(&mut buffer) {
+            _ if stop.load(Ordering::Acquire) => return,
+            Ok(r) => r,
+            Err(e) => {
+                error!("Error receiving a UDS datagram: {}", e);
+                continue;
+            }
+        };
+
+        if n > max_packet {
+            warn!("UDS datagram longer than {} bytes dropped", max_packet);
+            continue;
+        }
+
+        let packet = buffer[..n].to_vec();
+        let reply = match panic::catch_unwind(AssertUnwindSafe(|| provider.handle(packet, &from))) {
+            Ok(r) => r,
+            Err(_) => {
+                error!("Datagram provider panicked");
+                continue;
+            }
+        };
+
+        let reply = match reply {
+            Some(r) => r,
+            None => continue,
+        };
+
+        if from.is_unnamed() {
+            warn!("UDS datagram sender is not bound to an address, the reply is dropped");
+            continue;
+        }
+
+        if let Err(e) = socket.send_to_addr(&reply, &from) {
+            warn!("Error replying to a UDS datagram: {}", e);
+        }
+    }
+}
+
+/// Client of a [`DatagramSocket`], bound to an address of its own so the replies can reach it.
+///
+/// Unless provided with [`DatagramClient::connect_from`], the address is picked by the crate: on
+/// Linux the socket is autobound to a name of the abstract namespace chosen by the kernel, which
+/// leaves no file behind. Elsewhere it's bound to a file of the temporary directory. A socket file
+/// bound by the client is removed once it's dropped.
+///
+/// The socket only receives from the server, so a reply is the one of the last request unless it
+/// arrived after its request timed out. Such late replies are discarded before every request.
+#[derive(Debug)]
+pub struct DatagramClient {
+    socket: UnixDatagram,
+    address: Address,
+    /// Address of the client, if bound to a socket file to remove once dropped
+    bound: Option<Address>,
+    max_reply: usize,
+}
+
+impl DatagramClient {
+    /// Client of the socket bound to the provided address, bound to an address picked by the
+    /// crate
+    pub fn connect<A: Into<Address>>(address: A) -> Result<Self, IoError> {
+        let socket = autobind()?;
+        let bound = socket
+            .local_addr()?
+            .as_pathname()
+            .map(|p| Address::Path(p.to_path_buf()));
+
+        DatagramClient::connected(socket, address.into(), bound)
+    }
+
+    /// Client of the socket bound to the provided address, bound to the provided return address,
+    /// which must be available
+    pub fn connect_from<A: Into<Address>, L: Into<Address>>(
+        address: A,
+        local: L,
+    ) -> Result<Self, IoError> {
+        let local = local.into();
+        let socket = local.bind_datagram()?;
+        let bound = local.as_path().map(|_| local.clone());
+
+        DatagramClient::connected(socket, address.into(), bound)
+    }
+
+    fn connected(
+        socket: UnixDatagram,
+        address: Address,
+        bound: Option<Address>,
+    ) -> Result<Self, IoError> {
+        let client = DatagramClient {
+            socket,
+            address,
+            bound,
+            max_reply: MAX_PACKET_SIZE,
+        };
+
+        // Dropped on failure, removing the socket file
+        client.address.connect_datagram(&client.socket)?;
+        Ok(client)
+    }
+
+    /// Drop the replies longer than `max` bytes. Defaults to [`MAX_PACKET_SIZE`]
+    pub fn max_reply(mut self, max: usize) -> Self {
+        self.max_reply = max;
+        self
+    }
+
+    /// Fail the sends and the waits for a reply that take longer than the timeout. `None` blocks
+    /// indefinitely
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), IoError> {
+        self.socket.set_read_timeout(timeout)?;
+        self.socket.set_write_timeout(timeout)
+    }
+
+    /// Address of the server
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Address the replies are sent to
+    pub fn local_addr(&self) -> Result<SocketAddr, IoError> {
+        self.socket.local_addr()
+    }
+
+    /// Send the packet, without waiting for a reply
+    pub fn send(&self, packet: &[u8]) -> Result<(), IoError> {
+        self.socket.send(packet).map(|_| ())
+    }
+
+    /// Send the packet and wait for its reply. Fails with `TimedOut` if no reply is received
+    /// within the timeout, such as when the provider doesn't reply to the packet
+    pub fn request(&self, packet: &[u8]) -> Result<Vec<u8>, IoError> {
+        self.discard_late_replies()?;
+        self.send(packet)?;
+
+        // One more byte than accepted, so a longer reply is detected instead of truncated
+        let mut buffer = vec![0x00u8; self.max_reply + 1];
+        loop {
+            match self.socket.recv(&mut buffer) {
+                Ok(n) if n > self.max_reply => {
+                    return Err(IoError::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Reply longer than {} bytes", self.max_reply),
+                    ))
+                }
+
+                Ok(n) => {
+                    buffer.truncate(n);
+                    return Ok(buffer);
+                }
+
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Err(IoError::new(
+                        io::ErrorKind::TimedOut,
+                        format!("No reply from {} within the timeout", self.address),
+                    ))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn discard_late_replies(&self) -> Result<(), IoError> {
+        let mut buffer = [0x00u8; 1];
+
+        loop {
+            let ret = unsafe {
+                libc::recv(
+                    self.socket.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+
+            if ret >= 0 {
+                debug!("Late reply from {} discarded", self.address);
+                continue;
+            }
+
+            let e = IoError::last_os_error();
+            match e.kind() {
+                io::ErrorKind::Interrupted => (),
+                io::ErrorKind::WouldBlock => return Ok(()),
+                _ => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for DatagramClient {
+    fn drop(&mut self) {
+        if let Some(bound) = self.bound.as_ref() {
+            bound
+                .remove_file()
+                .unwrap_or_else(|e| warn!("Error removing the client socket {}: {}", bound, e));
+        }
+    }
+}
+
+/// Datagram socket bound to an abstract name picked by the kernel, by binding it to an address
+/// holding only the family
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn autobind() -> Result<UnixDatagram, IoError> {
+    use std::mem;
+
+    let socket = UnixDatagram::unbound()?;
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+    let ret = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            mem::size_of::<libc::sa_family_t>() as libc::socklen_t,
+        )
+    };
+
+    if ret < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    Ok(socket)
+}
+
+/// Datagram socket bound to a file of the temporary directory, named after the process
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn autobind() -> Result<UnixDatagram, IoError> {
+    use std::{process, sync::atomic::AtomicUsize};
+
+    // Numbered, so the clients of the process don't collide
+    static CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+    let path = std::env::temp_dir().join(format!(
+        "dusk-uds-client-{}-{}.sock",
+        process::id(),
+        CLIENTS.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    // Left behind by a previous process with the same identifier
+    std::fs::remove_file(path.as_path()).unwrap_or_default();
+    UnixDatagram::bind(path)
+}
@@ -0,0 +1,142 @@
+use crate::{Address, ValidationReport};
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Error as IoError},
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Failure of a UDS, by category, so the callers can react to each of them.
+///
+/// Converts from and into [`std::io::Error`], so it can be propagated with `?` from and to
+/// functions returning either.
+#[derive(Debug)]
+pub enum UdsError {
+    /// The startup validation refused the path or the options. See [`crate::Options::validation`]
+    ValidationFailed(ValidationReport),
+    /// The path of the address is not valid UTF-8. Abstract names may be any bytes
+    PathNotUtf8(Address),
+    /// The file left at the path by a previous instance couldn't be removed before binding
+    RemoveStale {
+        /// Path of the file
+        path: PathBuf,
+        /// Error removing it
+        source: IoError,
+    },
+    /// The socket couldn't be bound to the address, or the permissions of its file set
+    BindFailed {
+        /// Address to bind
+        address: Address,
+        /// Error binding it
+        source: IoError,
+    },
+    /// The warm-up failed or didn't finish in time. See [`crate::Options::warm_up`]
+    WarmUpFailed(IoError),
+    /// Workers panicked. The other workers were still finished and the UDS stopped
+    WorkerPanicked {
+        /// Number of workers that panicked
+        workers: usize,
+    },
+    /// The task queue is closed, since the workers are finished
+    ChannelClosed,
+    /// The accept thread didn't finish within the timeout, and was left detached. See
+    /// [`crate::Options::accept_shutdown_timeout`]
+    ShutdownTimeout(Duration),
+    /// Any other IO error
+    Io(IoError),
+}
+
+impl UdsError {
+    /// Copy of the error, for the callers sharing an outcome. The IO errors are re-created from
+    /// their kind and message
+    pub(crate) fn duplicate(&self) -> UdsError {
+        let io = |e: &IoError| IoError::new(e.kind(), e.to_string());
+
+        match self {
+            UdsError::ValidationFailed(report) => UdsError::ValidationFailed(report.clone()),
+            UdsError::PathNotUtf8(address) => UdsError::PathNotUtf8(address.clone()),
+            UdsError::RemoveStale { path, source } => UdsError::RemoveStale {
+                path: path.clone(),
+                source: io(source),
+            },
+            UdsError::BindFailed { address, source } => UdsError::BindFailed {
+                address: address.clone(),
+                source: io(source),
+            },
+            UdsError::WarmUpFailed(e) => UdsError::WarmUpFailed(io(e)),
+            UdsError::WorkerPanicked { workers } => UdsError::WorkerPanicked { workers: *workers },
+            UdsError::ChannelClosed => UdsError::ChannelClosed,
+            UdsError::ShutdownTimeout(timeout) => UdsError::ShutdownTimeout(*timeout),
+            UdsError::Io(e) => UdsError::Io(io(e)),
+        }
+    }
+
+    /// Kind of the [`std::io::Error`] the error converts to
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            UdsError::ValidationFailed(_) | UdsError::PathNotUtf8(_) => io::ErrorKind::InvalidInput,
+            UdsError::RemoveStale { source, .. } | UdsError::BindFailed { source, .. } => {
+                source.kind()
+            }
+            UdsError::WarmUpFailed(e) | UdsError::Io(e) => e.kind(),
+            UdsError::WorkerPanicked { .. } | UdsError::ChannelClosed => io::ErrorKind::Other,
+            UdsError::ShutdownTimeout(_) => io::ErrorKind::TimedOut,
+        }
+    }
+}
+
+impl fmt::Display for UdsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UdsError::ValidationFailed(report) => {
+                write!(f, "Startup validation failed: {}", report)
+            }
+            UdsError::PathNotUtf8(address) => write!(f, "The path {} is not UTF-8", address),
+            UdsError::RemoveStale { path, source } => write!(
+                f,
+                "Error removing the stale file {}: {}",
+                path.display(),
+                source
+            ),
+            UdsError::BindFailed { address, source } => {
+                write!(f, "Error binding {}: {}", address, source)
+            }
+            UdsError::WarmUpFailed(e) => write!(f, "The warm-up failed: {}", e),
+            UdsError::WorkerPanicked { workers } => write!(f, "{} workers panicked", workers),
+            UdsError::ChannelClosed => write!(f, "The task queue is closed"),
+            UdsError::ShutdownTimeout(timeout) => {
+                write!(f, "The accept thread didn't finish within {:?}", timeout)
+            }
+            UdsError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for UdsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UdsError::RemoveStale { source, .. } | UdsError::BindFailed { source, .. } => {
+                Some(source)
+            }
+            UdsError::WarmUpFailed(e) | UdsError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<IoError> for UdsError {
+    fn from(e: IoError) -> Self {
+        UdsError::Io(e)
+    }
+}
+
+impl From<UdsError> for IoError {
+    fn from(e: UdsError) -> Self {
+        match e {
+            UdsError::Io(e) => e,
+            e => IoError::new(e.kind(), e),
+        }
+    }
+}
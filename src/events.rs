@@ -0,0 +1,172 @@
+use crate::{Address, Message, PeerCreds, ShutdownReason};
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Lifecycle event of a UDS, received from [`crate::ServerHandle::events`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerEvent {
+    /// A listener is accepting on the address
+    Bound(Address),
+    /// A socket was accepted and queued to the workers
+    Accepted {
+        /// Credentials of the peer, if they could be fetched
+        peer: Option<PeerCreds>,
+    },
+    /// A socket was closed by the accept loop instead of being queued
+    Rejected(RejectReason),
+    /// A provider future resolved
+    HandlerFinished {
+        /// Output of the future
+        outcome: Message,
+        /// Time from the socket taken by a worker to the future resolved
+        elapsed: Duration,
+    },
+    /// A provider future panicked while polled, and its socket was closed
+    ProviderPanicked {
+        /// Index of the worker polling the future
+        worker: usize,
+        /// Message of the panic, if it was a string
+        message: Option<String>,
+    },
+    /// A worker panicked and was started again, see [`crate::Options::respawn_workers`]
+    WorkerRespawned {
+        /// Index of the worker
+        worker: usize,
+    },
+    /// A worker was spawned to serve a deep queue, see [`crate::Options::max_workers`]
+    WorkerSpawned {
+        /// Index of the worker
+        worker: usize,
+    },
+    /// An idle worker was retired, see [`crate::Options::min_workers`]
+    WorkerRetired {
+        /// Index of the worker
+        worker: usize,
+    },
+    /// The workers were asked to quit, and finish the sockets in progress
+    Draining(ShutdownReason),
+    /// The workers are finished and the listener closed. Last event of the stream
+    Stopped,
+}
+
+/// Cause of a [`ServerEvent::Rejected`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// Too many sockets were waiting for a worker, see [`crate::Options::max_pending_tasks`]
+    Backpressure,
+    /// Refused by [`crate::Options::accept_filter`]
+    Filter,
+    /// The peer user reached [`crate::Options::max_connections_per_uid`]
+    Quota,
+    /// The listener reached [`crate::Options::max_accept_rate`]
+    RateLimited,
+}
+
+/// Events of a UDS, in the order they happened.
+///
+/// Iterating blocks until the next event, and ends after [`ServerEvent::Stopped`]. Events are
+/// queued without bound, so a subscriber should keep receiving them or be dropped.
+pub struct Events {
+    receiver: mpsc::Receiver<ServerEvent>,
+}
+
+impl Events {
+    /// Next event, without blocking. `None` if there is none yet, or the stream ended
+    pub fn try_next(&self) -> Option<ServerEvent> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Next event, blocking up to the timeout
+    pub fn next_timeout(&self, timeout: Duration) -> Option<ServerEvent> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+impl Iterator for Events {
+    type Item = ServerEvent;
+
+    fn next(&mut self) -> Option<ServerEvent> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// Subscribers of the events of a UDS
+#[derive(Default)]
+pub struct EventBus {
+    /// Set while there is at least one subscriber, so the hot paths skip building the events
+    subscribed: AtomicBool,
+    senders: Mutex<Vec<mpsc::Sender<ServerEvent>>>,
+    /// Addresses of the running accept loops, replayed to the new subscribers
+    bound: Mutex<Vec<Address>>,
+}
+
+impl EventBus {
+    /// Add a subscriber, receiving [`ServerEvent::Bound`] for every running accept loop first
+    pub fn subscribe(&self) -> Events {
+        let (sender, receiver) = mpsc::channel();
+
+        // Held while registering, so an accept loop starting meanwhile is reported once
+        let bound = self.bound.lock().unwrap();
+        bound.iter().for_each(|a| {
+            sender
+                .send(ServerEvent::Bound(a.clone()))
+                .unwrap_or_default()
+        });
+
+        self.senders.lock().unwrap().push(sender);
+        self.subscribed.store(true, Ordering::Release);
+
+        Events { receiver }
+    }
+
+    /// Record an accept loop started on the address
+    pub fn bound(&self, address: &Address) {
+        let mut bound = self.bound.lock().unwrap();
+        bound.push(address.clone());
+
+        self.emit(|| ServerEvent::Bound(address.clone()));
+    }
+
+    /// Record an accept loop finished
+    pub fn unbound(&self, address: &Address) {
+        let mut bound = self.bound.lock().unwrap();
+        if let Some(i) = bound.iter().position(|a| a == address) {
+            bound.remove(i);
+        }
+    }
+
+    /// Whether there is any subscriber
+    pub fn is_subscribed(&self) -> bool {
+        self.subscribed.load(Ordering::Acquire)
+    }
+
+    /// Send the event built by the closure to every subscriber, if any, forgetting the dropped
+    /// ones
+    pub fn emit<F: FnOnce() -> ServerEvent>(&self, event: F) {
+        if !self.is_subscribed() {
+            return;
+        }
+
+        let event = event();
+        let mut senders = self.senders.lock().unwrap();
+        senders.retain(|s| s.send(event.clone()).is_ok());
+
+        if senders.is_empty() {
+            self.subscribed.store(false, Ordering::Release);
+        }
+    }
+
+    /// Send [`ServerEvent::Stopped`] and end the streams
+    pub fn stop(&self) {
+        self.emit(|| ServerEvent::Stopped);
+
+        self.senders.lock().unwrap().clear();
+        self.subscribed.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,30 @@
+use std::thread;
+
+/// Unit of work handed to an [`Executor`]
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Runtime used by the UDS to run its worker loops.
+///
+/// Every job is a long-lived worker loop that will only return after the UDS is finished, so the
+/// implementation must be able to run [`crate::Options::workers`] jobs concurrently.
+pub trait Executor: Send + Sync {
+    /// Run the provided job
+    fn spawn(&self, job: Job);
+}
+
+/// Any closure receiving a [`Job`] can be used as executor, as a bridge to user-supplied runtimes
+impl<F: Fn(Job) + Send + Sync> Executor for F {
+    fn spawn(&self, job: Job) {
+        self(job)
+    }
+}
+
+/// Default executor, spawning one dedicated thread per worker
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ThreadExecutor;
+
+impl Executor for ThreadExecutor {
+    fn spawn(&self, job: Job) {
+        thread::spawn(job);
+    }
+}
@@ -0,0 +1,55 @@
+use crate::stats::Stats;
+
+use std::{
+    mem,
+    os::unix::io::{AsRawFd, RawFd},
+    sync::{atomic::Ordering, Arc},
+};
+
+/// Account a descriptor owned by the crate for as long as the guard lives
+pub struct FdGuard(Arc<Stats>);
+
+impl FdGuard {
+    pub fn new(stats: &Arc<Stats>) -> Self {
+        stats.open_fds.fetch_add(1, Ordering::Relaxed);
+        FdGuard(Arc::clone(stats))
+    }
+}
+
+impl Drop for FdGuard {
+    fn drop(&mut self) {
+        self.0.open_fds.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Identity of an open socket, used to check it was closed once the provider is dropped.
+///
+/// The inode is recorded together with the descriptor, so a descriptor number reused by another
+/// socket is not reported as a leak.
+#[derive(Debug, Clone, Copy)]
+pub struct SocketId {
+    fd: RawFd,
+    ino: libc::ino_t,
+}
+
+impl SocketId {
+    pub fn of<S: AsRawFd>(socket: &S) -> Option<Self> {
+        let fd = socket.as_raw_fd();
+        inode(fd).map(|ino| SocketId { fd, ino })
+    }
+
+    /// Whether the descriptor still refers to the same socket
+    pub fn is_open(&self) -> bool {
+        inode(self.fd) == Some(self.ino)
+    }
+}
+
+pub fn inode(fd: RawFd) -> Option<libc::ino_t> {
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+
+    if unsafe { libc::fstat(fd, &mut stat) } == 0 {
+        Some(stat.st_ino)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,318 @@
+use crate::{
+    codec, queue::Queue, stats::Stats, stream::SEND_FLAGS, Budget, ConnectionState, IpcStream,
+    Message, PeerCreds, Task, TaskProvider,
+};
+
+use std::{
+    future::Future,
+    io::{self, Error as IoError, Read},
+    mem,
+    os::unix::{
+        io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        net::UnixStream,
+    },
+    pin::Pin,
+    ptr,
+    sync::{atomic::Ordering, Arc},
+    task::{Context, Poll},
+};
+
+/// Maximum number of descriptors the kernel passes in a single message
+const MAX_FDS: usize = 253;
+
+/// Received descriptors are not inherited by spawned processes
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+const RECV_FLAGS: libc::c_int = libc::MSG_CMSG_CLOEXEC;
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+const RECV_FLAGS: libc::c_int = 0;
+
+/// Function handling a metadata frame together with the descriptors received with it. The
+/// returned payload, if any, is sent back as a frame.
+///
+/// Descriptors not kept by the handler are closed when it returns.
+pub type FdHandlerFn = fn(Vec<u8>, Vec<OwnedFd>, &PeerCreds) -> Option<Vec<u8>>;
+
+/// Provider for descriptor brokering daemons, receiving descriptors passed with `SCM_RIGHTS`.
+///
+/// Reads [`crate::codec`] frames until the peer closes the connection, and hands every frame to
+/// the handler with the descriptors received while reading it. Received descriptors are
+/// close-on-exec, and are closed if the connection fails before reaching the handler. With
+/// [`crate::Options::memory_budget`], the frames longer than the budget left to the connection
+/// are rejected as well.
+pub struct FdReceiver {
+    handler: FdHandlerFn,
+    max_frame: usize,
+    budget: Option<Budget>,
+    peer: Option<PeerCreds>,
+    socket: Option<IpcStream>,
+}
+
+/// Create a [`FdReceiver`] provider from the handler function
+pub fn fd_handler(handler: FdHandlerFn) -> FdReceiver {
+    FdReceiver {
+        handler,
+        max_frame: codec::MAX_FRAME_SIZE,
+        budget: None,
+        peer: None,
+        socket: None,
+    }
+}
+
+impl Clone for FdReceiver {
+    fn clone(&self) -> Self {
+        FdReceiver {
+            handler: self.handler,
+            max_frame: self.max_frame,
+            budget: None,
+            peer: None,
+            socket: None,
+        }
+    }
+}
+
+impl FdReceiver {
+    /// Reject metadata frames longer than `max` bytes. Defaults to [`codec::MAX_FRAME_SIZE`]
+    pub fn max_frame(mut self, max: usize) -> Self {
+        self.max_frame = max;
+        self
+    }
+
+    fn serve(&self, socket: &IpcStream) -> Result<(), IoError> {
+        let creds = match self.peer {
+            Some(c) => c,
+            None => PeerCreds::from_stream(socket)?,
+        };
+
+        loop {
+            let mut reader = FdReader {
+                socket,
+                received: 0,
+                fds: vec![],
+            };
+
+            let max = match self.budget.as_ref() {
+                Some(b) => self.max_frame.min(b.available()),
+                None => self.max_frame,
+            };
+
+            let frame = match codec::read_frame(&mut reader, max) {
+                Ok(f) => f,
+                // The peer closed the connection between frames
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && reader.received == 0 => {
+                    return Ok(())
+                }
+                Err(e) => return Err(e),
+            };
+
+            if let Some(response) = (self.handler)(frame, reader.fds, &creds) {
+                codec::write_frame(&mut &*socket, &response)?;
+            }
+        }
+    }
+}
+
+impl TaskProvider for FdReceiver {
+    fn set_budget(&mut self, budget: Budget) {
+        self.budget.replace(budget);
+    }
+
+    fn set_peer(&mut self, peer: PeerCreds) {
+        self.peer.replace(peer);
+    }
+
+    fn set_socket(&mut self, socket: IpcStream) {
+        self.socket.replace(socket);
+    }
+}
+
+impl Future for FdReceiver {
+    type Output = Message;
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Self::Output> {
+        let socket = match self.socket.take() {
+            Some(s) => s,
+            None => return Poll::Ready(Message::Error),
+        };
+
+        match self.serve(&socket) {
+            Ok(_) => Poll::Ready(Message::Success),
+            Err(e) => {
+                error!("Error receiving descriptors: {}", e);
+                Poll::Ready(Message::Error)
+            }
+        }
+    }
+}
+
+/// Reader collecting the descriptors passed along with the bytes read
+struct FdReader<'a> {
+    socket: &'a IpcStream,
+    received: usize,
+    fds: Vec<OwnedFd>,
+}
+
+impl Read for FdReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = recv_fds(self.socket, buf, &mut self.fds)?;
+        self.received += n;
+
+        Ok(n)
+    }
+}
+
+/// Send the bytes along with the descriptors, passed with `SCM_RIGHTS`.
+///
+/// The descriptors are passed with the first byte, and stay open in this process. Returns the
+/// number of bytes sent, which may be fewer than provided as with [`std::io::Write::write`].
+pub fn send_fds<S: AsRawFd>(socket: &S, bytes: &[u8], fds: &[BorrowedFd]) -> io::Result<usize> {
+    if bytes.is_empty() || fds.len() > MAX_FDS {
+        return Err(IoError::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Descriptors are passed with at least one byte, and at most {} at once",
+                MAX_FDS
+            ),
+        ));
+    }
+
+    let payload = (fds.len() * mem::size_of::<RawFd>()) as u32;
+    let space = unsafe { libc::CMSG_SPACE(payload) } as usize;
+
+    // u64 elements keep the buffer aligned for the control message headers
+    let mut control = vec![0u64; space.div_ceil(mem::size_of::<u64>())];
+
+    let mut iov = libc::iovec {
+        iov_base: bytes.as_ptr() as *mut libc::c_void,
+        iov_len: bytes.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    if !fds.is_empty() {
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = space as _;
+
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(payload) as _;
+
+            let data = libc::CMSG_DATA(cmsg) as *mut RawFd;
+            for (i, fd) in fds.iter().enumerate() {
+                ptr::write_unaligned(data.add(i), fd.as_raw_fd());
+            }
+        }
+    }
+
+    let n = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, SEND_FLAGS) };
+    if n < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    Ok(n as usize)
+}
+
+/// Receive bytes, appending the descriptors passed with them.
+///
+/// The received descriptors are close-on-exec where the platform supports it. If more
+/// descriptors were passed than a single message carries, the call fails after appending the
+/// ones received.
+pub fn recv_fds<S: AsRawFd>(
+    socket: &S,
+    buf: &mut [u8],
+    fds: &mut Vec<OwnedFd>,
+) -> io::Result<usize> {
+    let space = unsafe { libc::CMSG_SPACE((MAX_FDS * mem::size_of::<RawFd>()) as u32) } as usize;
+
+    // u64 elements keep the buffer aligned for the control message headers
+    let mut control = vec![0u64; space.div_ceil(mem::size_of::<u64>())];
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+
+    let n = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, RECV_FLAGS) };
+    if n < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    // Take ownership of every received descriptor first, so they are closed on any error
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { ptr::read_unaligned(cmsg) };
+
+        if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_RIGHTS {
+            let data = unsafe { libc::CMSG_DATA(cmsg) } as *const RawFd;
+            let len = header.cmsg_len as usize - unsafe { libc::CMSG_LEN(0) } as usize;
+
+            for i in 0..len / mem::size_of::<RawFd>() {
+                let fd = unsafe { ptr::read_unaligned(data.add(i)) };
+                fds.push(unsafe { OwnedFd::from_raw_fd(fd) });
+            }
+        }
+
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(IoError::new(
+            io::ErrorKind::InvalidData,
+            "Descriptors were discarded, more than the maximum were passed",
+        ));
+    }
+
+    Ok(n as usize)
+}
+
+/// Queue the socket handed over with [`Message::Handover`] to the workers of the group, as if it
+/// was accepted
+pub(crate) fn handover(
+    fd: RawFd,
+    group: usize,
+    stats: &Arc<Stats>,
+    queue: &Queue,
+) -> Result<(), IoError> {
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    // Owned right away, so the descriptor is closed on every error
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    if stat.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return Err(IoError::new(
+            io::ErrorKind::InvalidInput,
+            "Only sockets can be handed over to the workers",
+        ));
+    }
+
+    let socket = UnixStream::from(fd);
+    let traced = stats.traced(&socket);
+
+    stats.accepted.fetch_add(1, Ordering::Relaxed);
+    stats.touch();
+    stats.record(traced, ConnectionState::Accepted);
+    stats.record(traced, ConnectionState::Queued);
+    queue
+        .push_to(group, Task::Socket(socket))
+        .map_err(IoError::from)
+        .inspect_err(|_| {
+            stats.accepted.fetch_sub(1, Ordering::Relaxed);
+            stats.record(traced, ConnectionState::Cancelled);
+        })
+}
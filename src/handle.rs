@@ -0,0 +1,499 @@
+use crate::{
+    background::TaskGroup,
+    listener::{self, AcceptContext, AcceptSettings, Acceptor, BindSettings, ServerInfo},
+    queue::Queue,
+    reaper::Reaper,
+    stats::Stats,
+    Address, BoundListener, ConnectionEvent, ConnectionState, Events, Message, ServerStats,
+    ShutdownReason, TakeoverAction, TakeoverCheck, Task, UdsError,
+};
+
+use std::{
+    io::{self, Error as IoError, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Result of [`ServerHandle::self_test`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTest {
+    /// Time from connecting to the first bytes of the response
+    pub rtt: Duration,
+    /// First bytes of the response
+    pub response: Vec<u8>,
+}
+
+/// Handle to a running [`crate::UnixDomainSocket`], returned by
+/// [`crate::UnixDomainSocket::start`].
+pub struct ServerHandle {
+    pub(crate) queue: Arc<Queue>,
+    pub(crate) stats: Arc<Stats>,
+    done: mpsc::Receiver<bool>,
+    /// Workers that panicked, counted once they are finished
+    panicked: AtomicUsize,
+    reaper: Option<Arc<Reaper>>,
+    accept: AcceptSettings,
+    acceptor: Arc<Mutex<Option<Acceptor>>>,
+    paused: Arc<AtomicBool>,
+    server: ServerInfo,
+    tasks: TaskGroup,
+}
+
+impl ServerHandle {
+    pub(crate) fn new(
+        queue: Arc<Queue>,
+        done: mpsc::Receiver<bool>,
+        reaper: Option<Arc<Reaper>>,
+        stats: Arc<Stats>,
+        accept: AcceptSettings,
+        server: ServerInfo,
+    ) -> Self {
+        ServerHandle {
+            queue,
+            done,
+            panicked: AtomicUsize::new(0),
+            reaper,
+            stats,
+            accept,
+            acceptor: Arc::new(Mutex::new(None)),
+            paused: Arc::new(AtomicBool::new(false)),
+            server,
+            tasks: TaskGroup::new(),
+        }
+    }
+
+    /// State shared by the accept loops queueing sockets to the workers of this handle
+    pub(crate) fn accept_context(&self) -> AcceptContext {
+        AcceptContext {
+            queue: Arc::clone(&self.queue),
+            stats: Arc::clone(&self.stats),
+            settings: self.accept.clone(),
+            paused: Arc::clone(&self.paused),
+            server: self.server,
+            group: 0,
+        }
+    }
+
+    /// Maximum time to wait for an accept thread to finish
+    pub(crate) fn accept_shutdown_timeout(&self) -> Duration {
+        self.accept.shutdown_timeout
+    }
+
+    /// Mode and ownership of the socket files bound for the workers
+    pub(crate) fn bind_settings(&self) -> BindSettings {
+        self.accept.bind
+    }
+
+    /// Start accepting sockets from the listener, replacing the current accept loop, if any.
+    /// Returns the replaced accept loop, still running.
+    pub(crate) fn listen(
+        &self,
+        listener: UnixListener,
+        address: Address,
+    ) -> Result<Option<Acceptor>, IoError> {
+        let acceptor = Acceptor::spawn(listener, address, self.accept_context())?;
+        Ok(self.acceptor.lock().unwrap().replace(acceptor))
+    }
+
+    /// Check the socket file of the listener periodically from a task of the
+    /// [`ServerHandle::task_group`], reacting to a takeover according to the check
+    pub(crate) fn watch_takeover(&self, check: TakeoverCheck) -> Result<(), IoError> {
+        let acceptor = Arc::clone(&self.acceptor);
+        let context = self.accept_context();
+        let shutdown = self.shutdown_handle();
+        let mut reported = false;
+
+        self.tasks.spawn("uds-takeover", move |token| {
+            while !token.sleep(check.interval) {
+                let mut slot = acceptor.lock().unwrap();
+                let (address, takeover) = match slot
+                    .as_ref()
+                    .and_then(|a| a.takeover().map(|t| (a.address().clone(), t)))
+                {
+                    Some(t) => t,
+                    None => {
+                        reported = false;
+                        continue;
+                    }
+                };
+
+                if reported {
+                    continue;
+                }
+
+                error!(
+                    "The socket file {} was taken over ({:?}), clients may not reach the UDS",
+                    address, takeover
+                );
+                if let (Some(f), Some(path)) = (check.on_takeover, address.as_path()) {
+                    f(path, takeover);
+                }
+
+                match check.action {
+                    TakeoverAction::Report => reported = true,
+
+                    TakeoverAction::Rebind => {
+                        let rebound =
+                            listener::bind(&address, &context.settings.bind).and_then(|l| {
+                                Acceptor::spawn(l, address.clone(), context.clone())
+                                    .map_err(UdsError::from)
+                            });
+
+                        match rebound {
+                            Ok(a) => {
+                                if let Some(previous) = slot.replace(a) {
+                                    previous.stop();
+                                }
+                                info!("UDS listener of {} bound again", address);
+                            }
+
+                            Err(e) => {
+                                error!("Error binding {} again: {}", address, e);
+                                reported = true;
+                            }
+                        }
+                    }
+
+                    TakeoverAction::Shutdown => {
+                        drop(slot);
+                        shutdown.request();
+                        return;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Stop the accept loop and take its listener, to hand it over to another process, with its
+    /// descriptor. The sockets already queued or in progress are still served, and the workers
+    /// run until asked to quit. The path is not removed. `None` if the listener was already taken
+    pub fn take_listener(&self) -> Option<BoundListener> {
+        self.detach_listener()
+            .map(|(address, listener)| BoundListener::from_parts(address, listener))
+    }
+
+    /// Stop the accept loop and take its listener, to hand it over to another process
+    pub(crate) fn detach_listener(&self) -> Option<(Address, UnixListener)> {
+        self.acceptor
+            .lock()
+            .unwrap()
+            .take()
+            .and_then(Acceptor::detach)
+    }
+
+    /// Move the listener to a new path without downtime.
+    ///
+    /// Will bind the new path and start accepting there, then stop the previous listener, queue
+    /// the sockets still waiting in its backlog, and finally remove the previous path.
+    pub fn rebind<A: Into<Address>>(&self, address: A) -> Result<(), IoError> {
+        let address = address.into();
+        let listener = listener::bind(&address, &self.accept.bind)?;
+
+        if let Some(previous) = self.listen(listener, address)? {
+            let previous = previous.stop();
+            previous.remove_file()?;
+            info!("UDS listener moved from {}", previous);
+        }
+
+        Ok(())
+    }
+
+    /// Stop taking new sockets from the listener. Sockets already queued or in progress are not
+    /// affected, and new clients wait in the listener backlog until
+    /// [`ServerHandle::resume_accepting`] is called.
+    pub fn pause_accepting(&self) {
+        self.set_paused(true);
+    }
+
+    /// Resume taking new sockets from the listener, after [`ServerHandle::pause_accepting`].
+    pub fn resume_accepting(&self) {
+        self.set_paused(false);
+    }
+
+    fn set_paused(&self, paused: bool) {
+        set_paused(&self.paused, &self.acceptor, paused);
+    }
+
+    /// Subscribe to the lifecycle events of the UDS, starting with [`crate::ServerEvent::Bound`] for
+    /// every listener accepting. Events are only built while there is a subscriber
+    pub fn events(&self) -> Events {
+        self.stats.events.subscribe()
+    }
+
+    /// Snapshot of the counters of the running UDS
+    pub fn stats(&self) -> ServerStats {
+        self.stats.snapshot(self.queue.queued())
+    }
+
+    /// Most recent lifecycle transitions of the connections, from the oldest to the most recent.
+    /// Empty unless [`crate::Options::trace_connections`] is set.
+    pub fn connection_trace(&self) -> Vec<ConnectionEvent> {
+        self.stats
+            .trace
+            .as_ref()
+            .map(|t| t.events())
+            .unwrap_or_default()
+    }
+
+    /// Connect to the workers over an in-memory socket pair, bypassing the listener, the accept
+    /// filter and the banner. Returns the client end of the pair.
+    pub fn connect_pair(&self) -> Result<UnixStream, IoError> {
+        let (client, server) = UnixStream::pair()?;
+        let traced = self.stats.traced(&server);
+
+        self.stats.accepted.fetch_add(1, Ordering::Relaxed);
+        self.stats.touch();
+        self.stats.record(traced, ConnectionState::Accepted);
+        self.stats.record(traced, ConnectionState::Queued);
+        self.queue.push(Task::Socket(server)).inspect_err(|_| {
+            self.stats.accepted.fetch_sub(1, Ordering::Relaxed);
+            self.stats.record(traced, ConnectionState::Cancelled);
+        })?;
+
+        Ok(client)
+    }
+
+    /// Check the whole pipeline is healthy, sending a probe as a regular client.
+    ///
+    /// Connects to the path of the listener, or over a socket pair if there is none, writes the
+    /// probe and waits up to the timeout for the first bytes of the response. The probe is handled
+    /// by the providers as any other connection, so it must be a request they answer. The banner,
+    /// if any, is skipped.
+    pub fn self_test(&self, probe: &[u8], timeout: Duration) -> Result<SelfTest, IoError> {
+        let address = self
+            .acceptor
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|a| a.address().clone());
+
+        // Sockets accepted from the listener receive the banner first
+        let banner = self.accept.banner.as_ref().filter(|_| address.is_some());
+
+        let started = self.stats.clock.now();
+        let mut client = match address {
+            Some(a) => a.connect()?,
+            None => self.connect_pair()?,
+        };
+
+        client.set_read_timeout(Some(timeout))?;
+        client.set_write_timeout(Some(timeout))?;
+
+        if let Some(banner) = banner {
+            let mut skipped = vec![0x00u8; banner.len()];
+            client.read_exact(&mut skipped)?;
+        }
+
+        client.write_all(probe)?;
+
+        let mut response = vec![0x00u8; 4096];
+        let n = client.read(&mut response).map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock => IoError::new(
+                io::ErrorKind::TimedOut,
+                "Timeout waiting for the response to the probe",
+            ),
+            _ => e,
+        })?;
+        let rtt = self.stats.clock.now().saturating_duration_since(started);
+
+        if n == 0 {
+            return Err(IoError::new(
+                io::ErrorKind::UnexpectedEof,
+                "The connection was closed without a response to the probe",
+            ));
+        }
+
+        response.truncate(n);
+        Ok(SelfTest { rtt, response })
+    }
+
+    /// Cloneable sender to push tasks into the worker loop
+    pub fn task_sender(&self) -> TaskSender {
+        TaskSender {
+            queue: Arc::clone(&self.queue),
+        }
+    }
+
+    /// Background tasks cancelled and joined once the workers are finished
+    pub fn task_group(&self) -> TaskGroup {
+        self.tasks.clone()
+    }
+
+    /// Handle to stop the UDS from another thread, finished with [`ShutdownHandle::finish`]
+    pub(crate) fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            queue: Arc::clone(&self.queue),
+            paused: Arc::clone(&self.paused),
+            acceptor: Arc::clone(&self.acceptor),
+            tasks: self.tasks.clone(),
+            finished: Arc::new(Finished {
+                outcome: Mutex::new(None),
+                cond: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Block until all the workers are finished, without touching the accept loop
+    pub(crate) fn wait_workers(&self) {
+        for panicked in self.done.iter() {
+            if panicked {
+                error!("Error ending the worker thread gracefully: the worker panicked");
+                self.panicked.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Block until all the workers are finished.
+    ///
+    /// Then the tasks of the [`ServerHandle::task_group`] are cancelled and joined, and the accept
+    /// loop is finished and the listener closed, waiting up to
+    /// [`crate::Options::accept_shutdown_timeout`] for the accept thread. Fails with
+    /// [`UdsError::WorkerPanicked`] once done if any worker panicked without being respawned, see
+    /// [`crate::Options::respawn_workers`].
+    pub fn join(self) -> Result<(), UdsError> {
+        self.wait_workers();
+        self.tasks.shutdown();
+
+        let acceptor = self
+            .acceptor
+            .lock()
+            .unwrap()
+            .take()
+            .map(|a| a.shutdown(self.accept.shutdown_timeout))
+            .unwrap_or(Ok(()));
+
+        self.queue.close();
+
+        if let Some(r) = self.reaper {
+            r.close();
+        }
+
+        info!("Unbinding UDS");
+        self.stats.events.stop();
+        acceptor?;
+
+        match self.panicked.load(Ordering::Relaxed) {
+            0 => Ok(()),
+            workers => Err(UdsError::WorkerPanicked { workers }),
+        }
+    }
+}
+
+/// Cloneable handle to push tasks into the worker loop from application code.
+///
+/// Messages other than [`Message::ShouldQuit`] are delivered to
+/// [`crate::TaskProvider::handle_message`] of the worker that receives them.
+#[derive(Clone)]
+pub struct TaskSender {
+    queue: Arc<Queue>,
+}
+
+impl TaskSender {
+    /// Enqueue a task, to be taken by the first available worker
+    pub fn send(&self, task: Task) -> Result<(), UdsError> {
+        self.queue.push(task)
+    }
+
+    /// Deliver a message to every worker
+    pub fn broadcast(&self, message: Message) -> Result<(), UdsError> {
+        self.queue.broadcast(message)
+    }
+}
+
+/// Cloneable handle to stop a UDS started with [`crate::UnixDomainSocket::bind_with_handle`]
+/// from any thread, such as the one handling `SIGTERM`.
+///
+/// Stopping pauses the accept loop, so new clients wait in the backlog until the listener is
+/// closed, and asks the workers to quit. The sockets in progress are drained according to
+/// [`crate::Options::drain_policy`]. Once the workers are finished, the background tasks of the
+/// [`ShutdownHandle::task_group`] are cancelled and joined, the listener is closed and the path
+/// removed.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    queue: Arc<Queue>,
+    paused: Arc<AtomicBool>,
+    acceptor: Arc<Mutex<Option<Acceptor>>>,
+    tasks: TaskGroup,
+    finished: Arc<Finished>,
+}
+
+/// Outcome of the UDS, set once it is stopped
+struct Finished {
+    outcome: Mutex<Option<Result<(), UdsError>>>,
+    cond: Condvar,
+}
+
+impl ShutdownHandle {
+    /// Ask the UDS to stop, without waiting for it. Calling it more than once has no effect
+    pub fn request(&self) {
+        self.request_for(ShutdownReason::Admin);
+    }
+
+    /// Same as [`ShutdownHandle::request`], delivering the provided reason to the providers in
+    /// progress. Only the reason of the first request is delivered
+    pub fn request_for(&self, reason: ShutdownReason) {
+        set_paused(&self.paused, &self.acceptor, true);
+
+        // Fails only if the UDS is already stopped
+        self.queue.quit(reason).unwrap_or_default();
+    }
+
+    /// Block until the UDS is stopped, either by [`ShutdownHandle::request`] or by a provider
+    /// resolving to [`Message::ShouldQuit`]. Returns the error of joining the workers, closing
+    /// the listener or removing the path, if any. Every caller gets its own copy of the error
+    pub fn wait(&self) -> Result<(), UdsError> {
+        let mut outcome = self.finished.outcome.lock().unwrap();
+        while outcome.is_none() {
+            outcome = self.finished.cond.wait(outcome).unwrap();
+        }
+
+        match outcome.as_ref() {
+            Some(Err(e)) => Err(e.duplicate()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ask the UDS to stop and block until it is stopped
+    pub fn shutdown(&self) -> Result<(), UdsError> {
+        self.request();
+        self.wait()
+    }
+
+    /// Background tasks cancelled and joined as part of the shutdown
+    pub fn task_group(&self) -> TaskGroup {
+        self.tasks.clone()
+    }
+
+    /// Whether the UDS is stopped
+    pub fn is_finished(&self) -> bool {
+        self.finished.outcome.lock().unwrap().is_some()
+    }
+
+    /// Record the outcome of the UDS, releasing the waiting threads
+    pub(crate) fn finish(&self, outcome: Result<(), UdsError>) {
+        self.finished.outcome.lock().unwrap().replace(outcome);
+        self.finished.cond.notify_all();
+    }
+}
+
+fn set_paused(paused: &AtomicBool, acceptor: &Mutex<Option<Acceptor>>, value: bool) {
+    paused.store(value, Ordering::Release);
+
+    if let Some(acceptor) = acceptor.lock().unwrap().as_ref() {
+        acceptor.refresh();
+    }
+}
+
+/// Notify the end of a worker loop, even if it unwinds
+pub(crate) struct WorkerDone(pub mpsc::Sender<bool>);
+
+impl Drop for WorkerDone {
+    fn drop(&mut self) {
+        self.0.send(thread::panicking()).unwrap_or_default();
+    }
+}
@@ -0,0 +1,287 @@
+//! Minimal HTTP/1.1 client over a unix domain socket.
+//!
+//! Covers scripting against the HTTP APIs served on sockets, such as the one of Docker, without an
+//! HTTP stack. Every request opens its own connection, sent with `Connection: close`. Responses
+//! with a `Content-Length`, a chunked body or a body delimited by the end of the connection are
+//! supported.
+
+use std::{
+    io::{self, BufRead, BufReader, Error as IoError, Read, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    time::Duration,
+};
+
+/// Maximum length of the status line and of every header line of a response
+const MAX_LINE: usize = 64 * 1024;
+
+/// HTTP request, built with [`Request::get`] or [`Request::post`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    timeout: Option<Duration>,
+}
+
+impl Request {
+    /// Request with the provided method and target, such as `/v1.43/containers/json`
+    pub fn new<M: Into<String>, T: Into<String>>(method: M, target: T) -> Self {
+        Request {
+            method: method.into(),
+            target: target.into(),
+            headers: vec![],
+            body: vec![],
+            timeout: None,
+        }
+    }
+
+    /// `GET` request to the target
+    pub fn get<T: Into<String>>(target: T) -> Self {
+        Request::new("GET", target)
+    }
+
+    /// `POST` request to the target, with the provided body
+    pub fn post<T: Into<String>, B: Into<Vec<u8>>>(target: T, body: B) -> Self {
+        Request::new("POST", target).body(body)
+    }
+
+    /// Add a header. `Host`, `Connection` and `Content-Length` are set by the client
+    pub fn header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Replace the body of the request
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Fail the reads and writes of the request that take longer than the timeout
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout.replace(timeout);
+        self
+    }
+
+    /// Connect to the socket at the provided path, send the request and read the response
+    pub fn send<P: AsRef<Path>>(&self, path: P) -> Result<Response, IoError> {
+        let mut stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+
+        self.write(&mut stream)?;
+        Response::read(&mut BufReader::new(stream), self.method == "HEAD")
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> Result<(), IoError> {
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n",
+            self.method, self.target
+        );
+
+        for (name, value) in self.headers.iter() {
+            if name.contains(['\r', '\n', ':']) || value.contains(['\r', '\n']) {
+                return Err(IoError::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid header {}", name),
+                ));
+            }
+
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        if !self.body.is_empty() || self.method == "POST" || self.method == "PUT" {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+
+        head.push_str("\r\n");
+
+        writer.write_all(head.as_bytes())?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+}
+
+/// HTTP response, returned by [`Request::send`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    /// Status code
+    pub status: u16,
+    /// Reason phrase of the status line
+    pub reason: String,
+    /// Headers, in the order they were received
+    pub headers: Vec<(String, String)>,
+    /// Body, with the chunked transfer encoding removed
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Value of the first header with the provided name, compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Whether the status is in the 2xx range
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    fn read<R: BufRead>(reader: &mut R, head: bool) -> Result<Self, IoError> {
+        // Interim responses, such as 100 Continue, precede the final one
+        let (status, reason) = loop {
+            let line = read_line(reader)?;
+            let status = parse_status(&line)?;
+
+            if !(100..200).contains(&status.0) {
+                break status;
+            }
+
+            while !read_line(reader)?.is_empty() {}
+        };
+
+        let mut headers = vec![];
+        loop {
+            let line = read_line(reader)?;
+            if line.is_empty() {
+                break;
+            }
+
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("Malformed header line: {}", line)))?;
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+
+        let mut response = Response {
+            status,
+            reason,
+            headers,
+            body: vec![],
+        };
+
+        if head || status == 204 || status == 304 {
+            return Ok(response);
+        }
+
+        let chunked = response
+            .header("Transfer-Encoding")
+            .is_some_and(|v| v.to_ascii_lowercase().contains("chunked"));
+        let length = response
+            .header("Content-Length")
+            .map(|v| {
+                v.parse::<u64>()
+                    .map_err(|_| invalid(format!("Invalid Content-Length: {}", v)))
+            })
+            .transpose()?;
+
+        response.body = match (chunked, length) {
+            (true, _) => read_chunked(reader)?,
+            (false, Some(length)) => {
+                let mut body = vec![];
+                reader.take(length).read_to_end(&mut body)?;
+
+                if (body.len() as u64) < length {
+                    return Err(IoError::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "The connection was closed before the end of the body",
+                    ));
+                }
+
+                body
+            }
+            (false, None) => {
+                let mut body = vec![];
+                reader.read_to_end(&mut body)?;
+                body
+            }
+        };
+
+        Ok(response)
+    }
+}
+
+fn parse_status(line: &str) -> Result<(u16, String), IoError> {
+    let mut parts = line.splitn(3, ' ');
+
+    let version = parts.next().unwrap_or_default();
+    if !version.starts_with("HTTP/1.") {
+        return Err(invalid(format!("Malformed status line: {}", line)));
+    }
+
+    let status = parts
+        .next()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid(format!("Malformed status line: {}", line)))?;
+    let reason = parts.next().unwrap_or_default().to_owned();
+
+    Ok((status, reason))
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R) -> Result<Vec<u8>, IoError> {
+    let mut body = vec![];
+
+    loop {
+        let line = read_line(reader)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| invalid(format!("Invalid chunk size: {}", line)))?;
+
+        if size == 0 {
+            // Skip the trailers
+            while !read_line(reader)?.is_empty() {}
+            return Ok(body);
+        }
+
+        // The size comes from the server, so the body grows with the bytes actually received
+        let end = (body.len() as u64)
+            .checked_add(size)
+            .ok_or_else(|| invalid("Chunked body too long"))?;
+        reader.take(size).read_to_end(&mut body)?;
+
+        if (body.len() as u64) < end {
+            return Err(IoError::new(
+                io::ErrorKind::UnexpectedEof,
+                "The connection was closed before the end of a chunk",
+            ));
+        }
+
+        if !read_line(reader)?.is_empty() {
+            return Err(invalid("Missing the end of a chunk"));
+        }
+    }
+}
+
+/// Read a line without its terminator, failing on the end of the connection
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, IoError> {
+    let mut line = vec![];
+    reader
+        .take(MAX_LINE as u64 + 2)
+        .read_until(b'\n', &mut line)?;
+
+    if line.last() != Some(&b'\n') {
+        if line.len() > MAX_LINE {
+            return Err(invalid("Response line too long"));
+        }
+
+        return Err(IoError::new(
+            io::ErrorKind::UnexpectedEof,
+            "The connection was closed before the end of the response",
+        ));
+    }
+
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    String::from_utf8(line).map_err(|_| invalid("Response line is not valid UTF-8"))
+}
+
+fn invalid<M: Into<String>>(message: M) -> IoError {
+    IoError::new(io::ErrorKind::InvalidData, message.into())
+}
@@ -0,0 +1,38 @@
+use crate::{clock, queue::Queue, stats::Stats, ShutdownReason};
+
+use std::{sync::Arc, thread, time::Duration};
+
+/// Spawn a thread that requests the workers to quit once there were no connections for the
+/// provided period. See [`crate::Options::exit_on_idle`].
+pub fn spawn(idle: Duration, queue: Arc<Queue>, stats: Arc<Stats>) {
+    thread::spawn(move || loop {
+        let elapsed = stats.idle_for();
+
+        if elapsed < idle {
+            thread::sleep(clock::wait_for(&*stats.clock, idle - elapsed));
+            continue;
+        }
+
+        if queue.is_closed() {
+            return;
+        }
+
+        let busy = queue.queued() > 0 || stats.workers.iter().any(|w| w.active() > 0);
+
+        // Connections still in progress count as activity
+        if busy {
+            stats.touch();
+            continue;
+        }
+
+        info!("UDS idle for {:?}, quitting", elapsed);
+        queue.quit(ShutdownReason::IdleExit).unwrap_or_else(|e| {
+            error!(
+                "Error trying to send a ShouldQuit message to the task queue: {}",
+                e
+            );
+        });
+
+        return;
+    });
+}
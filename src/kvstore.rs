@@ -0,0 +1,485 @@
+//! Reference key-value control socket, enabled by the `kvstore` feature.
+//!
+//! Meant as a starting point for the control socket of a daemon: [`KvStore`] serves the
+//! requests of every connection with a [`crate::BlockingHandler`], framed by [`crate::codec`],
+//! authorizes the writes by the credentials of the peer, and counts the requests in the
+//! Prometheus format of [`crate::ServerStats::to_prometheus`]. [`KvClient`] speaks the protocol
+//! over a [`crate::UnixDomainClient`].
+//!
+//! Every request is a frame starting with an opcode byte:
+//!
+//! * `GET` (1): the key, answered with the value
+//! * `SET` (2): the key, then the value
+//! * `DELETE` (3): the key
+//! * `LIST` (4): a prefix, answered with every key starting with it
+//! * `STATS` (5): answered with the metrics of the store
+//!
+//! Keys are prefixed by their length, a big-endian `u16`, and a value or prefix runs to the end of
+//! the frame. Every response starts with a status byte, `0` if found and `1` if not, followed by
+//! the value, the keys prefixed by their length, or the metrics. A malformed request is answered
+//! with an [`ErrorFrame`] of code [`BAD_REQUEST`], and a write refused with one of code
+//! [`ErrorCode::UNAUTHORIZED`], before closing the connection.
+//!
+//! A connection is served until the peer closes it, so the workers should own as many of them as
+//! the pool has threads, see [`crate::Options::max_concurrent_per_worker`].
+//!
+//! ```rust,no_run
+//! use dusk_uds::{kvstore::KvStore, Options, UnixDomainSocket};
+//!
+//! let store = KvStore::new().writers(vec![0]);
+//! store.set(b"version", b"1.0");
+//!
+//! let options = Options {
+//!     max_concurrent_per_worker: 4,
+//!     ..Default::default()
+//! };
+//!
+//! UnixDomainSocket::new("/tmp/dusk-kvstore", Some(options), store.provider(4))
+//!     .bind()
+//!     .unwrap();
+//! ```
+
+use crate::{
+    blocking_handler,
+    codec::{self, ErrorCode, ErrorFrame},
+    Blocking, BlockingHandler, IpcStream, Message, PeerCreds, UnixDomainClient,
+};
+
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    io::{self, Error as IoError},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Code of the [`ErrorFrame`] answering a malformed request
+pub const BAD_REQUEST: ErrorCode = ErrorCode(0x100);
+
+/// Default maximum length of a request frame
+pub const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+const GET: u8 = 1;
+const SET: u8 = 2;
+const DELETE: u8 = 3;
+const LIST: u8 = 4;
+const STATS: u8 = 5;
+
+const FOUND: u8 = 0;
+const NOT_FOUND: u8 = 1;
+
+/// Decoded request frame
+enum Request<'a> {
+    Get(&'a [u8]),
+    Set(&'a [u8], &'a [u8]),
+    Delete(&'a [u8]),
+    List(&'a [u8]),
+    Stats,
+}
+
+impl<'a> Request<'a> {
+    fn decode(frame: &'a [u8]) -> Result<Self, ErrorFrame> {
+        let (op, body) = frame
+            .split_first()
+            .ok_or_else(|| ErrorFrame::new(BAD_REQUEST, "Empty request"))?;
+
+        let request = match *op {
+            GET => Request::Get(exact_key(body)?),
+            SET => {
+                let (key, value) = split_key(body)?;
+                Request::Set(key, value)
+            }
+            DELETE => Request::Delete(exact_key(body)?),
+            LIST => Request::List(body),
+            STATS if body.is_empty() => Request::Stats,
+            _ => {
+                return Err(ErrorFrame::new(
+                    BAD_REQUEST,
+                    format!("Unknown request {}", op),
+                ))
+            }
+        };
+
+        Ok(request)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Request::Get(_) => "get",
+            Request::Set(..) => "set",
+            Request::Delete(_) => "delete",
+            Request::List(_) => "list",
+            Request::Stats => "stats",
+        }
+    }
+
+    fn is_write(&self) -> bool {
+        matches!(self, Request::Set(..) | Request::Delete(_))
+    }
+}
+
+fn split_key(body: &[u8]) -> Result<(&[u8], &[u8]), ErrorFrame> {
+    if body.len() < 2 {
+        return Err(ErrorFrame::new(BAD_REQUEST, "Truncated key length"));
+    }
+
+    let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+    let body = &body[2..];
+    if body.len() < len {
+        return Err(ErrorFrame::new(BAD_REQUEST, "Truncated key"));
+    }
+
+    Ok(body.split_at(len))
+}
+
+fn exact_key(body: &[u8]) -> Result<&[u8], ErrorFrame> {
+    match split_key(body)? {
+        (key, []) => Ok(key),
+        _ => Err(ErrorFrame::new(BAD_REQUEST, "Trailing bytes after the key")),
+    }
+}
+
+fn push_key(out: &mut Vec<u8>, key: &[u8]) -> Result<(), IoError> {
+    let len = u16::try_from(key.len())
+        .map_err(|_| IoError::new(io::ErrorKind::InvalidInput, "Key too long"))?;
+
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(key);
+
+    Ok(())
+}
+
+/// Requests counted by a [`KvStore`], shared by its clones
+#[derive(Default)]
+struct Counters {
+    gets: AtomicU64,
+    sets: AtomicU64,
+    deletes: AtomicU64,
+    lists: AtomicU64,
+    stats: AtomicU64,
+    misses: AtomicU64,
+    unauthorized: AtomicU64,
+    bad_requests: AtomicU64,
+}
+
+impl Counters {
+    fn bump(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn of(&self, request: &Request) -> &AtomicU64 {
+        match request {
+            Request::Get(_) => &self.gets,
+            Request::Set(..) => &self.sets,
+            Request::Delete(_) => &self.deletes,
+            Request::List(_) => &self.lists,
+            Request::Stats => &self.stats,
+        }
+    }
+}
+
+/// In-memory key-value store served over a UDS, see the [module](self) documentation.
+///
+/// Clones share the entries and the counters, so the daemon reads and writes the store while it
+/// is served.
+#[derive(Clone)]
+pub struct KvStore {
+    entries: Arc<Mutex<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    counters: Arc<Counters>,
+    writers: Option<Vec<libc::uid_t>>,
+    max_request: usize,
+}
+
+impl KvStore {
+    /// Empty store, writable by any peer
+    pub fn new() -> Self {
+        KvStore {
+            entries: Arc::new(Mutex::new(BTreeMap::new())),
+            counters: Arc::new(Counters::default()),
+            writers: None,
+            max_request: MAX_REQUEST_SIZE,
+        }
+    }
+
+    /// Only accept `SET` and `DELETE` from the peers running as one of the users. The others can
+    /// still read
+    pub fn writers(mut self, uids: Vec<libc::uid_t>) -> Self {
+        self.writers.replace(uids);
+        self
+    }
+
+    /// Maximum length of a request frame, closing the connections sending a longer one
+    pub fn max_request(mut self, max: usize) -> Self {
+        self.max_request = max;
+        self
+    }
+
+    /// Provider serving the store on `threads` dedicated threads
+    pub fn provider(&self, threads: usize) -> Blocking<KvStore> {
+        blocking_handler(self.clone(), threads)
+    }
+
+    /// Value of the key
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    /// Set the value of the key, returning the previous one
+    pub fn set(&self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_vec(), value.to_vec())
+    }
+
+    /// Remove the key, returning its value
+    pub fn delete(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.entries.lock().unwrap().remove(key)
+    }
+
+    /// Number of keys
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Whether the store has no key
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Counters of the store in the Prometheus text exposition format, prefixed with
+    /// `dusk_uds_kv_`, to append to the metrics of [`crate::ServerStats::to_prometheus`]
+    pub fn to_prometheus(&self) -> String {
+        let c = &self.counters;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        let mut out = String::new();
+        out.push_str("# HELP dusk_uds_kv_requests_total Requests handled, by operation\n");
+        out.push_str("# TYPE dusk_uds_kv_requests_total counter\n");
+        for (op, counter) in [
+            ("get", &c.gets),
+            ("set", &c.sets),
+            ("delete", &c.deletes),
+            ("list", &c.lists),
+            ("stats", &c.stats),
+        ]
+        .iter()
+        {
+            out.push_str(&format!(
+                "dusk_uds_kv_requests_total{{op=\"{}\"}} {}\n",
+                op,
+                load(counter)
+            ));
+        }
+
+        #[rustfmt::skip]
+        let metrics = [
+            ("misses_total", "counter", "GET and DELETE requests of a missing key",
+                load(&c.misses)),
+            ("unauthorized_total", "counter", "Writes refused to peers that are not writers",
+                load(&c.unauthorized)),
+            ("bad_requests_total", "counter", "Malformed requests",
+                load(&c.bad_requests)),
+            ("keys", "gauge", "Keys in the store", self.len() as u64),
+        ];
+
+        for (name, kind, help, value) in metrics.iter() {
+            out.push_str(&format!("# HELP dusk_uds_kv_{} {}\n", name, help));
+            out.push_str(&format!("# TYPE dusk_uds_kv_{} {}\n", name, kind));
+            out.push_str(&format!("dusk_uds_kv_{} {}\n", name, value));
+        }
+
+        out
+    }
+
+    fn is_writer(&self, peer: Option<&PeerCreds>) -> bool {
+        match (&self.writers, peer) {
+            (None, _) => true,
+            (Some(uids), Some(peer)) => uids.contains(&peer.uid),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Response to the request frame, or the error frame closing the connection
+    fn answer(&self, frame: &[u8], peer: Option<&PeerCreds>) -> Result<Vec<u8>, ErrorFrame> {
+        let request =
+            Request::decode(frame).inspect_err(|_| Counters::bump(&self.counters.bad_requests))?;
+
+        if request.is_write() && !self.is_writer(peer) {
+            Counters::bump(&self.counters.unauthorized);
+            return Err(ErrorFrame::new(
+                ErrorCode::UNAUTHORIZED,
+                format!("The peer is not allowed to {}", request.name()),
+            ));
+        }
+
+        Counters::bump(self.counters.of(&request));
+
+        let found = |value: Option<Vec<u8>>| match value {
+            Some(mut value) => {
+                value.insert(0, FOUND);
+                value
+            }
+            None => {
+                Counters::bump(&self.counters.misses);
+                vec![NOT_FOUND]
+            }
+        };
+
+        let response = match request {
+            Request::Get(key) => found(self.get(key)),
+            Request::Set(key, value) => {
+                self.set(key, value);
+                vec![FOUND]
+            }
+            Request::Delete(key) => found(self.delete(key).map(|_| vec![])),
+            Request::List(prefix) => {
+                let entries = self.entries.lock().unwrap();
+                let mut response = vec![FOUND];
+
+                // Keys are at most u16::MAX long, checked when decoded
+                entries
+                    .range(prefix.to_vec()..)
+                    .take_while(|(k, _)| k.starts_with(prefix))
+                    .for_each(|(k, _)| push_key(&mut response, k).unwrap_or_default());
+
+                response
+            }
+            Request::Stats => {
+                let mut response = vec![FOUND];
+                response.extend_from_slice(self.to_prometheus().as_bytes());
+                response
+            }
+        };
+
+        Ok(response)
+    }
+}
+
+impl Default for KvStore {
+    fn default() -> Self {
+        KvStore::new()
+    }
+}
+
+impl BlockingHandler for KvStore {
+    fn handle(&self, mut stream: IpcStream) -> Message {
+        let peer = PeerCreds::from_stream(&stream)
+            .map_err(|e| warn!("Error fetching the credentials of a kvstore peer: {}", e))
+            .ok();
+
+        loop {
+            let frame = match codec::read_frame(&mut stream, self.max_request) {
+                Ok(f) => f,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Message::Success,
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    Counters::bump(&self.counters.bad_requests);
+                    let error = ErrorFrame::new(ErrorCode::FRAME_TOO_LARGE, e.to_string());
+                    codec::write_error(&mut stream, &error).unwrap_or_default();
+                    return Message::Error;
+                }
+                Err(e) => {
+                    error!("Error reading a kvstore request: {}", e);
+                    return Message::Error;
+                }
+            };
+
+            let written = match self.answer(&frame, peer.as_ref()) {
+                Ok(response) => codec::write_frame(&mut stream, &response),
+                Err(error) => {
+                    debug!("Refused kvstore request: {}", error);
+                    codec::write_error(&mut stream, &error).unwrap_or_default();
+                    return Message::Error;
+                }
+            };
+
+            if let Err(e) = written {
+                error!("Error writing a kvstore response: {}", e);
+                return Message::Error;
+            }
+        }
+    }
+}
+
+/// Client of a [`KvStore`]. A refused request fails with the [`ErrorFrame`] of the server, see
+/// [`ErrorFrame::from_io`]
+#[derive(Debug, Clone)]
+pub struct KvClient {
+    client: UnixDomainClient,
+}
+
+impl KvClient {
+    /// Client sending the requests with the provided client
+    pub fn new(client: UnixDomainClient) -> Self {
+        KvClient { client }
+    }
+
+    /// Value of the key
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, IoError> {
+        let mut request = vec![GET];
+        push_key(&mut request, key)?;
+
+        self.request(&request)
+            .map(|(found, value)| found.then_some(value))
+    }
+
+    /// Set the value of the key
+    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<(), IoError> {
+        let mut request = vec![SET];
+        push_key(&mut request, key)?;
+        request.extend_from_slice(value);
+
+        self.request(&request).map(|_| ())
+    }
+
+    /// Remove the key, returning whether it existed
+    pub fn delete(&self, key: &[u8]) -> Result<bool, IoError> {
+        let mut request = vec![DELETE];
+        push_key(&mut request, key)?;
+
+        self.request(&request).map(|(found, _)| found)
+    }
+
+    /// Keys starting with the prefix, in order
+    pub fn list(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>, IoError> {
+        let mut request = vec![LIST];
+        request.extend_from_slice(prefix);
+
+        let (_, body) = self.request(&request)?;
+        let mut body = &body[..];
+        let mut keys = vec![];
+        while !body.is_empty() {
+            let (key, rest) =
+                split_key(body).map_err(|e| IoError::new(io::ErrorKind::InvalidData, e.message))?;
+            keys.push(key.to_vec());
+            body = rest;
+        }
+
+        Ok(keys)
+    }
+
+    /// Metrics of the store, see [`KvStore::to_prometheus`]
+    pub fn stats(&self) -> Result<String, IoError> {
+        let (_, body) = self.request(&[STATS])?;
+
+        String::from_utf8(body).map_err(|e| IoError::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Send the request, returning whether the key was found, and the body of the response
+    fn request(&self, request: &[u8]) -> Result<(bool, Vec<u8>), IoError> {
+        let mut response = self.client.request(request)?;
+
+        match response.first().copied() {
+            Some(status @ FOUND) | Some(status @ NOT_FOUND) => {
+                response.remove(0);
+                Ok((status == FOUND, response))
+            }
+            _ => Err(IoError::new(
+                io::ErrorKind::InvalidData,
+                "Invalid kvstore response status",
+            )),
+        }
+    }
+}
@@ -1,23 +1,139 @@
-#![feature(external_doc)]
 #![deny(missing_docs)]
-#![doc(include = "../README.md")]
+#![doc = include_str!("../README.md")]
 
 #[macro_use]
 extern crate log;
 
-use std::{future::Future, os::unix::net::UnixStream};
+use std::{future::Future, pin::Pin};
 
-pub use communication::{Message, Task};
-pub use options::Options;
+pub use address::Address;
+pub use background::{CancelToken, TaskGroup};
+pub use blocking::{blocking_handler, Blocking, BlockingHandler};
+pub use budget::{Budget, Reservation};
+pub use builder::{OptionsBuilder, OptionsError};
+pub use cache::ResponseCache;
+pub use client::{ClientFuture, UnixDomainClient};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use communication::{Message, ShutdownReason, Task};
+pub use datagram::{
+    DatagramClient, DatagramHandle, DatagramProvider, DatagramSocket, MAX_PACKET_SIZE,
+};
+pub use error::UdsError;
+pub use events::{Events, RejectReason, ServerEvent};
+pub use executor::{Executor, Job, ThreadExecutor};
+pub use fdpass::{fd_handler, recv_fds, send_fds, FdHandlerFn, FdReceiver};
+pub use handle::{SelfTest, ServerHandle, ShutdownHandle, TaskSender};
+pub use listener::{BoundListener, ConnectionInfo, ListenerInfo, ServerInfo};
+pub use mux::MuxClient;
+pub use oneshot::{oneshot_handler, OneShot, OneShotFn};
+pub use options::{
+    AcceptFilter, BackpressurePolicy, Banner, BudgetAction, CompletionFn, DrainClass,
+    DrainClassifier, DrainPolicy, Hardening, LostWaker, MemoryBudget, Options, PeerChange,
+    PeerChangeFn, PeerRevalidation, Profile, RequestLog, ScalingPolicy, SocketKind, Takeover,
+    TakeoverAction, TakeoverCheck, TakeoverFn, WarmUp, WorkerGroup,
+};
+pub use outbox::{Fanout, Outbox, SlowConsumer};
+pub use peer::PeerCreds;
+pub use scratch::Scratch;
+pub use server::{Server, ServerBuilder};
+pub use session::{Session, SessionClient, SessionStore, REPLAY_CAPACITY};
+pub use shared::{SharedBuffer, SharedPayload, SHARED_THRESHOLD};
+pub use stats::{ConnectionTraffic, HandlerTimes, Load, PeerTraffic, ServerStats, WorkerStats};
+pub use stream::IpcStream;
+pub use tenants::TenantManager;
+pub use trace::{ConnectionEvent, ConnectionState};
 pub use uds::UnixDomainSocket;
+pub use validate::{Finding, Severity, Validation, ValidationReport};
 
+mod address;
+#[cfg(test)]
+mod allocations;
+mod background;
+mod blocking;
+mod budget;
+mod builder;
+mod cache;
+mod client;
+mod clock;
+mod close;
+pub mod codec;
 mod communication;
+pub mod conformance;
+mod datagram;
+mod error;
+mod events;
+mod executor;
+mod fd;
+mod fdpass;
+mod handle;
+pub mod http;
+mod idle;
+#[cfg(feature = "kvstore")]
+pub mod kvstore;
+mod listener;
+mod mux;
+mod oneshot;
 mod options;
+mod outbox;
+mod peer;
+mod queue;
+mod quota;
+mod reactor;
+mod reaper;
+mod reqlog;
+pub mod restart;
+mod revalidate;
+mod routes;
+mod scale;
+mod scratch;
+mod server;
+mod session;
+mod shared;
+mod signals;
+mod stats;
+mod stream;
+mod systemd;
+mod tenants;
+mod trace;
 mod uds;
+mod validate;
+mod warmup;
+mod watch;
 mod worker;
 
 /// Future provider to the UDS implementation
 pub trait TaskProvider: Send + Sync + Clone + Future<Output = Message> {
     /// Receive a socket to handle it during the future poll call
-    fn set_socket(&mut self, socket: UnixStream);
+    fn set_socket(&mut self, socket: IpcStream);
+
+    /// Receive the resources of the connection, before its socket. Called only when
+    /// [`Options::connection_scratch`] is set.
+    fn set_scratch(&mut self, _scratch: Scratch) {}
+
+    /// Receive the memory budget of the connection, before its socket. Called only when
+    /// [`Options::memory_budget`] is set.
+    fn set_budget(&mut self, _budget: Budget) {}
+
+    /// Receive the information of the listener that accepted the connection, before its socket.
+    /// Allows a provider serving several listeners to tell which one the client connected to.
+    fn set_listener(&mut self, _listener: ListenerInfo) {}
+
+    /// Receive the credentials of the peer, before its socket. Fetched once per connection, so
+    /// the provider doesn't need to query them again. Not called if they can't be fetched.
+    fn set_peer(&mut self, _peer: PeerCreds) {}
+
+    /// Receive a message pushed through a [`TaskSender`]. Called on the instance owned by the
+    /// worker, which is cloned for every new socket.
+    fn handle_message(&mut self, _message: &Message) {}
+
+    /// Give the socket back once the future resolved to [`Message::Handoff`], so it can be handed
+    /// to the target provider. Without it, the handoff fails and the connection is closed.
+    fn take_socket(self: Pin<&mut Self>) -> Option<IpcStream> {
+        None
+    }
+
+    /// Receive the reason of the shutdown, once the worker is asked to quit while the future is
+    /// in progress. The future is polled right after, so it can answer its client before the
+    /// drain policy applies.
+    fn shutting_down(self: Pin<&mut Self>, _reason: &ShutdownReason) {}
 }
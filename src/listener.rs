@@ -0,0 +1,708 @@
+use crate::{
+    codec::{self, ErrorCode, ErrorFrame},
+    fd::{self, FdGuard},
+    queue::Queue,
+    stats::Stats,
+    stream, AcceptFilter, Address, BackpressurePolicy, ConnectionState, Options, PeerCreds,
+    RejectReason, ServerEvent, SocketKind, Takeover, Task, UdsError,
+};
+
+use std::{
+    fs,
+    io::{self, Error as IoError, Read, Write},
+    net::Shutdown,
+    os::unix::{
+        fs::{self as unix_fs, MetadataExt, PermissionsExt},
+        io::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Information of the listener that accepted a socket
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenerInfo {
+    /// Path the listener is bound to. Empty for the sockets created with
+    /// [`crate::ServerHandle::connect_pair`] and for abstract addresses
+    pub path: PathBuf,
+    /// Workers serving the listener
+    pub server: ServerInfo,
+}
+
+/// Information of a finished connection, passed to [`crate::Options::on_complete`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionInfo {
+    /// Listener that accepted the socket
+    pub listener: ListenerInfo,
+    /// Credentials of the peer, if they could be fetched
+    pub peer: Option<PeerCreds>,
+}
+
+/// Summary of the workers serving a listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// Time the workers were spawned
+    pub started: SystemTime,
+    /// Number of worker threads spawned first, from [`crate::Options::workers`]
+    pub workers: usize,
+    /// Maximum in-progress sockets per worker, from [`crate::Options::max_concurrent_per_worker`]
+    pub max_concurrent_per_worker: usize,
+}
+
+impl ListenerInfo {
+    /// Information of the listener that accepted the socket, taken from its local address
+    pub(crate) fn of(stream: &UnixStream, server: ServerInfo) -> Self {
+        let path = stream
+            .local_addr()
+            .ok()
+            .and_then(|a| a.as_pathname().map(Path::to_path_buf))
+            .unwrap_or_default();
+
+        ListenerInfo { path, server }
+    }
+}
+
+/// Behavior of the accept loops taken from [`crate::Options`]
+#[derive(Clone)]
+pub struct AcceptSettings {
+    pub filter: Option<AcceptFilter>,
+    /// Encoded identification frame, written to every accepted socket
+    pub banner: Option<Arc<[u8]>>,
+    pub shutdown_timeout: Duration,
+    pub max_pending: Option<usize>,
+    pub backpressure: BackpressurePolicy,
+    /// Sockets accepted per second by each listener
+    pub accept_rate: Option<u32>,
+    /// Capacity of the token bucket of the accept rate
+    pub accept_burst: Option<u32>,
+    /// Treatment of the sockets past the accept rate
+    pub rate_policy: BackpressurePolicy,
+    pub bind: BindSettings,
+}
+
+/// Type of the sockets, mode and ownership of the socket files, taken from [`crate::Options`]
+#[derive(Debug, Clone, Copy)]
+pub struct BindSettings {
+    pub kind: SocketKind,
+    pub mode: Option<u32>,
+    pub owner: Option<libc::uid_t>,
+    pub group: Option<libc::gid_t>,
+}
+
+impl BindSettings {
+    pub fn of(options: &Options) -> Self {
+        BindSettings {
+            kind: options.socket_kind,
+            mode: options.mode,
+            owner: options.owner,
+            group: options.group,
+        }
+    }
+
+    fn apply_permissions(&self, path: &Path) -> Result<(), IoError> {
+        if self.owner.is_some() || self.group.is_some() {
+            unix_fs::chown(path, self.owner, self.group)?;
+        }
+
+        if let Some(mode) = self.mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// State shared by every accept loop of the UDS
+#[derive(Clone)]
+pub struct AcceptContext {
+    pub queue: Arc<Queue>,
+    pub stats: Arc<Stats>,
+    pub settings: AcceptSettings,
+    pub paused: Arc<AtomicBool>,
+    pub server: ServerInfo,
+    /// Worker group the sockets are queued to, set from the address once spawned
+    pub group: usize,
+}
+
+/// Command sent through the wake socket of an accept loop
+const WAKE_STOP: u8 = 0x00;
+const WAKE_REFRESH: u8 = 0x01;
+const WAKE_DETACH: u8 = 0x02;
+
+/// Period between two checks of the queue while [`BackpressurePolicy::Block`] holds the sockets
+/// in the backlog
+const BACKPRESSURE_RETRY: Duration = Duration::from_millis(5);
+
+/// Token bucket of [`crate::Options::max_accept_rate`], holding up to
+/// [`crate::Options::accept_burst`] sockets
+struct AcceptRate {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl AcceptRate {
+    fn new(rate: u32, burst: Option<u32>, now: Instant) -> Self {
+        let burst = burst.unwrap_or(rate) as f64;
+
+        AcceptRate {
+            rate: rate as f64,
+            burst,
+            tokens: burst,
+            refilled: now,
+        }
+    }
+
+    /// Whether a socket can be accepted now
+    fn available(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled = now;
+
+        self.tokens >= 1.0
+    }
+
+    /// Time until a socket can be accepted again
+    fn retry_after(&self) -> Duration {
+        Duration::from_secs_f64((1.0 - self.tokens).max(0.0) / self.rate)
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+/// Will remove the provided path, if it exists, and bind a listener of the provided type to it
+/// with the provided permissions. Sockets are accepted only once they are set. Abstract addresses
+/// have no file to remove or set permissions on
+pub fn bind(address: &Address, settings: &BindSettings) -> Result<UnixListener, UdsError> {
+    bind_with(address, settings, |a| match settings.kind {
+        SocketKind::Stream => a.bind(),
+        SocketKind::SeqPacket => a.bind_seqpacket(),
+        SocketKind::Datagram => Err(IoError::new(
+            io::ErrorKind::InvalidInput,
+            "Datagram sockets are served by a DatagramSocket",
+        )),
+    })
+}
+
+/// Remove the provided path, if it exists, bind the socket with the provided function and set
+/// its permissions
+pub fn bind_with<S, F>(address: &Address, settings: &BindSettings, bind: F) -> Result<S, UdsError>
+where
+    F: FnOnce(&Address) -> Result<S, IoError>,
+{
+    // Abstract names are raw bytes, only the paths are required to be UTF-8
+    if address.as_path().is_some_and(|p| p.to_str().is_none()) {
+        return Err(UdsError::PathNotUtf8(address.clone()));
+    }
+
+    // Grant the provided path is available to the process
+    if let Some(path) = address.as_path().filter(|p| p.exists()) {
+        fs::remove_file(path).map_err(|source| UdsError::RemoveStale {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    }
+
+    // Perform the bind
+    let failed = |source| UdsError::BindFailed {
+        address: address.clone(),
+        source,
+    };
+    let listener = bind(address).map_err(failed)?;
+    if let Some(path) = address.as_path() {
+        if let Err(e) = settings.apply_permissions(path) {
+            fs::remove_file(path).unwrap_or_default();
+            return Err(failed(e));
+        }
+    }
+
+    info!("UnixDomainSocket bound on {}", address);
+
+    Ok(listener)
+}
+
+/// Listener bound to an address, to be served by a [`crate::UnixDomainSocket`] or handed over to
+/// another process.
+///
+/// Exported as a raw descriptor with [`AsRawFd`] and [`IntoRawFd`], and re-created from one with
+/// [`BoundListener::from_raw_fd`], for instance in a new version of the daemon spawned with the
+/// descriptor inherited. See [`crate::restart`] for a handover doing so.
+#[derive(Debug)]
+pub struct BoundListener {
+    address: Address,
+    listener: UnixListener,
+}
+
+impl BoundListener {
+    /// Bind the address as [`crate::UnixDomainSocket::bind`] would with the provided options,
+    /// removing the path if it exists
+    pub fn bind<A: Into<Address>>(address: A, options: &Options) -> Result<Self, UdsError> {
+        let address = address.into();
+        let listener = bind(&address, &BindSettings::of(options))?;
+
+        Ok(BoundListener { address, listener })
+    }
+
+    /// Listener already bound, taking its address from the socket. Fails if it is unnamed
+    pub fn from_listener(listener: UnixListener) -> Result<Self, IoError> {
+        let address = Address::of(&listener.local_addr()?).ok_or_else(|| {
+            IoError::new(io::ErrorKind::InvalidInput, "The listener is not bound")
+        })?;
+
+        Ok(BoundListener { address, listener })
+    }
+
+    /// Listener of the provided descriptor, as [`BoundListener::from_listener`].
+    ///
+    /// # Safety
+    ///
+    /// The descriptor must be a listening unix socket owned by the caller, which gives up its
+    /// ownership.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> Result<Self, IoError> {
+        BoundListener::from_listener(UnixListener::from_raw_fd(fd))
+    }
+
+    pub(crate) fn from_parts(address: Address, listener: UnixListener) -> Self {
+        BoundListener { address, listener }
+    }
+
+    /// Address the listener is bound to
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Take the address and the listener
+    pub fn into_parts(self) -> (Address, UnixListener) {
+        (self.address, self.listener)
+    }
+}
+
+impl AsRawFd for BoundListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for BoundListener {
+    fn into_raw_fd(self) -> RawFd {
+        self.listener.into_raw_fd()
+    }
+}
+
+/// Accept loop running on its own thread.
+///
+/// The thread polls the listener together with one end of a socket pair, so it can be woken up
+/// and finished from any other thread.
+pub struct Acceptor {
+    address: Address,
+    /// Device and inode of the socket file, recorded when the accept loop starts
+    file: Option<(u64, u64)>,
+    wake: UnixStream,
+    finished: mpsc::Receiver<()>,
+    thread: thread::JoinHandle<UnixListener>,
+}
+
+impl Acceptor {
+    /// Spawn a thread to perform the actual listening.
+    ///
+    /// When there is an incoming socket, transform it to a Task and send to the queue
+    pub fn spawn(
+        listener: UnixListener,
+        address: Address,
+        context: AcceptContext,
+    ) -> Result<Self, IoError> {
+        let (wake, wake_rx) = UnixStream::pair()?;
+        listener.set_nonblocking(true)?;
+        wake_rx.set_nonblocking(true)?;
+
+        let info = ListenerInfo {
+            path: address.as_path().map(Path::to_path_buf).unwrap_or_default(),
+            server: context.server,
+        };
+        let file = address
+            .as_path()
+            .and_then(|p| fs::symlink_metadata(p).ok())
+            .map(|m| (m.dev(), m.ino()));
+        let name = address.to_string();
+        let context = AcceptContext {
+            group: context.queue.listener_group(&address),
+            ..context
+        };
+        let (finished_tx, finished) = mpsc::channel();
+        let bound = address.clone();
+        context.stats.events.bound(&bound);
+        let thread = thread::spawn(move || {
+            let events = Arc::clone(&context.stats.events);
+            let listener = run(listener, wake_rx, &name, info, context);
+            events.unbound(&bound);
+            finished_tx.send(()).unwrap_or_default();
+            listener
+        });
+
+        Ok(Acceptor {
+            address,
+            file,
+            wake,
+            finished,
+            thread,
+        })
+    }
+
+    /// Address the listener is bound to
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Whether the socket file at the path is no longer the one bound by the listener
+    pub fn takeover(&self) -> Option<Takeover> {
+        let (path, file) = self.address.as_path().zip(self.file)?;
+
+        match fs::symlink_metadata(path) {
+            Ok(m) if (m.dev(), m.ino()) != file => Some(Takeover::Replaced),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Some(Takeover::Removed),
+            _ => None,
+        }
+    }
+
+    /// Wake up the accept thread so it reloads [`AcceptContext::paused`]
+    pub fn refresh(&self) {
+        self.wake(WAKE_REFRESH);
+    }
+
+    /// Stop accepting new sockets and wait for the accept thread to finish. The sockets already
+    /// waiting in the listener backlog are still queued to the workers.
+    pub fn stop(self) -> Address {
+        let address = self.address.clone();
+
+        if let Err(e) = self.finish(WAKE_STOP, None) {
+            error!("Error ending the accept thread gracefully: {}", e);
+        }
+
+        address
+    }
+
+    /// Stop accepting new sockets and take the listener back, leaving its backlog untouched
+    pub fn detach(self) -> Option<(Address, UnixListener)> {
+        self.finish(WAKE_DETACH, None)
+            .inspect_err(|e| error!("Error ending the accept thread gracefully: {}", e))
+            .ok()
+    }
+
+    /// Stop accepting new sockets and close the listener, without queueing its backlog. Will fail
+    /// if the accept thread doesn't finish within the timeout, leaving it detached.
+    pub fn shutdown(self, timeout: Duration) -> Result<(), UdsError> {
+        self.finish(WAKE_DETACH, Some(timeout)).map(|_| ())
+    }
+
+    fn finish(
+        self,
+        command: u8,
+        timeout: Option<Duration>,
+    ) -> Result<(Address, UnixListener), UdsError> {
+        self.wake(command);
+
+        if let Some(timeout) = timeout {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = self.finished.recv_timeout(timeout) {
+                return Err(UdsError::ShutdownTimeout(timeout));
+            }
+        }
+
+        let address = self.address;
+        self.thread
+            .join()
+            .map(|listener| (address, listener))
+            .map_err(|e| IoError::other(format!("The accept thread panicked: {:?}", e)).into())
+    }
+
+    fn wake(&self, command: u8) {
+        (&self.wake).write_all(&[command]).unwrap_or_else(|e| {
+            error!("Error waking up the accept thread: {}", e);
+        });
+    }
+}
+
+fn run(
+    listener: UnixListener,
+    wake: UnixStream,
+    name: &str,
+    info: ListenerInfo,
+    context: AcceptContext,
+) -> UnixListener {
+    let _fd = FdGuard::new(&context.stats);
+    let mut blocked = false;
+    let mut rate = context
+        .settings
+        .accept_rate
+        .map(|r| AcceptRate::new(r, context.settings.accept_burst, context.stats.clock.now()));
+
+    loop {
+        // While paused, or blocked by the backpressure policy or the accept rate, the incoming
+        // sockets are left in the listener backlog
+        let paused = context.paused.load(Ordering::Acquire);
+        let timeout = if blocked {
+            BACKPRESSURE_RETRY.as_millis() as libc::c_int
+        } else {
+            -1
+        };
+
+        let mut fds = [
+            libc::pollfd {
+                fd: if paused || blocked {
+                    -1
+                } else {
+                    listener.as_raw_fd()
+                },
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: wake.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } < 0 {
+            let e = IoError::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+
+            error!("Error polling the UDS listener: {}", e);
+            return listener;
+        }
+
+        let commands = if fds[1].revents != 0 {
+            read_commands(&wake)
+        } else {
+            vec![]
+        };
+
+        if commands.contains(&WAKE_DETACH) {
+            debug!("Accept loop of {} detached", name);
+            return listener;
+        }
+
+        let stop = commands.contains(&WAKE_STOP);
+
+        // Drain the backlog also before finishing, so no connected client is left behind, even
+        // past the limit of pending sockets. The flag is loaded again, since it might have changed
+        // during the poll
+        blocked = (!context.paused.load(Ordering::Acquire) || stop)
+            && accept_pending(&listener, &info, &context, &mut rate, !stop);
+
+        if stop {
+            debug!("Accept loop of {} finished", name);
+            return listener;
+        }
+    }
+}
+
+fn read_commands(mut wake: &UnixStream) -> Vec<u8> {
+    let mut commands = vec![];
+    let mut buffer = [0x00u8; 16];
+
+    loop {
+        match wake.read(&mut buffer) {
+            Ok(n) if n > 0 => commands.extend_from_slice(&buffer[..n]),
+            // The acceptor was dropped, so there is no one left to stop the loop
+            Ok(_) => return vec![WAKE_STOP],
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return commands,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => {
+                error!("Error reading the accept thread wake socket: {}", e);
+                return vec![WAKE_STOP];
+            }
+        }
+    }
+}
+
+/// Accept every socket of the backlog. Returns whether the backpressure policy or the accept rate
+/// left some of them in the backlog
+fn accept_pending(
+    listener: &UnixListener,
+    info: &ListenerInfo,
+    context: &AcceptContext,
+    rate: &mut Option<AcceptRate>,
+    bounded: bool,
+) -> bool {
+    let settings = &context.settings;
+
+    loop {
+        let full = bounded
+            && settings
+                .max_pending
+                .is_some_and(|m| context.queue.queued_in(context.group) >= m);
+
+        if full && settings.backpressure == BackpressurePolicy::Block {
+            return true;
+        }
+
+        let now = context.stats.clock.now();
+        let limited = bounded && rate.as_mut().is_some_and(|r| !r.available(now));
+
+        if limited && settings.rate_policy == BackpressurePolicy::Block {
+            return true;
+        }
+
+        let socket = match listener.accept() {
+            Ok((s, _)) => s,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return false,
+            Err(e) => {
+                context.stats.accept_errors.fetch_add(1, Ordering::Relaxed);
+                error!("Error receiving the UDS socket: {}", e);
+                return false;
+            }
+        };
+
+        if limited {
+            context.stats.rejected.fetch_add(1, Ordering::Relaxed);
+            context
+                .stats
+                .events
+                .emit(|| ServerEvent::Rejected(RejectReason::RateLimited));
+
+            let error = ErrorFrame {
+                code: ErrorCode::OVERLOADED,
+                message: "Too many connections per second".to_string(),
+                retry_after: rate.as_ref().map(AcceptRate::retry_after),
+            };
+            reject(socket, settings.rate_policy, error);
+            continue;
+        }
+
+        if let Some(r) = rate.as_mut().filter(|_| bounded) {
+            r.take();
+        }
+
+        if full {
+            context.stats.rejected.fetch_add(1, Ordering::Relaxed);
+            context
+                .stats
+                .events
+                .emit(|| ServerEvent::Rejected(RejectReason::Backpressure));
+
+            let error = ErrorFrame::new(ErrorCode::OVERLOADED, "Too many connections are pending");
+            reject(socket, settings.backpressure, error);
+            continue;
+        }
+
+        dispatch(socket, info, context).unwrap_or_else(|e| {
+            context.stats.accept_errors.fetch_add(1, Ordering::Relaxed);
+            error!("Error receiving the UDS socket: {}", e);
+        });
+    }
+}
+
+/// Close a socket accepted past the limit of pending sockets or the accept rate, writing the
+/// error frame first if the policy asks for it
+fn reject(socket: UnixStream, policy: BackpressurePolicy, error: ErrorFrame) {
+    debug!("UDS socket rejected: {}", error.message);
+
+    if policy != BackpressurePolicy::RejectWithShutdown {
+        return;
+    }
+
+    let mut frame = vec![];
+
+    // The frame fits in the socket buffer, so it is written without blocking the accept loop
+    let result = codec::write_error(&mut frame, &error)
+        .and_then(|_| socket.set_nonblocking(true))
+        .and_then(|_| stream::send_all(&socket, &frame))
+        .and_then(|_| socket.shutdown(Shutdown::Both));
+
+    if let Err(e) = result {
+        debug!("Error rejecting the UDS socket: {}", e);
+    }
+}
+
+fn dispatch(
+    socket: UnixStream,
+    info: &ListenerInfo,
+    context: &AcceptContext,
+) -> Result<(), IoError> {
+    // Some platforms propagate the flag of the listener to the accepted sockets
+    socket.set_nonblocking(false)?;
+
+    let stats = &context.stats;
+    let creds = if context.settings.filter.is_some() || stats.quota.is_some() {
+        Some(PeerCreds::from_stream(&socket)?)
+    } else {
+        None
+    };
+
+    if let Some((filter, creds)) = context.settings.filter.zip(creds.as_ref()) {
+        let load = stats.load(context.queue.queued());
+
+        if !filter(creds, info, &load) {
+            debug!("UDS socket rejected by the accept filter: {:?}", creds);
+            stats.rejected.fetch_add(1, Ordering::Relaxed);
+            stats
+                .events
+                .emit(|| ServerEvent::Rejected(RejectReason::Filter));
+            return Ok(());
+        }
+    }
+
+    // Counted until the worker finishes the connection
+    if let Some((quota, creds)) = stats.quota.as_ref().zip(creds.as_ref()) {
+        if !quota.acquire(&socket, creds.uid) {
+            debug!(
+                "UDS socket rejected, its user reached its quota: {:?}",
+                creds
+            );
+            stats.rejected.fetch_add(1, Ordering::Relaxed);
+            stats
+                .events
+                .emit(|| ServerEvent::Rejected(RejectReason::Quota));
+            return Ok(());
+        }
+    }
+    let ino = stats
+        .quota
+        .as_ref()
+        .and_then(|_| fd::inode(socket.as_raw_fd()));
+
+    if let Some(banner) = context.settings.banner.as_ref() {
+        if let Err(e) = stream::send_all(&socket, banner) {
+            debug!("Error writing the banner, dropping the UDS socket: {}", e);
+            stats.release_quota(ino);
+            return Ok(());
+        }
+    }
+
+    // Fetched before the socket is moved to the queue, only for the subscribers
+    let peer = stats
+        .events
+        .is_subscribed()
+        .then(|| creds.or_else(|| PeerCreds::from_stream(&socket).ok()))
+        .flatten();
+
+    // Count before pushing, so a snapshot never sees more handled than accepted sockets
+    let traced = stats.traced(&socket);
+    stats.accepted.fetch_add(1, Ordering::Relaxed);
+    stats.touch();
+    stats.record(traced, ConnectionState::Accepted);
+    stats.record(traced, ConnectionState::Queued);
+    context
+        .queue
+        .push_to(context.group, Task::Socket(socket))
+        .map_err(IoError::from)
+        .inspect_err(|_| {
+            stats.release_quota(ino);
+            stats.accepted.fetch_sub(1, Ordering::Relaxed);
+            stats.record(traced, ConnectionState::Cancelled);
+        })?;
+
+    stats.events.emit(|| ServerEvent::Accepted { peer });
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use crate::{client::Slot, codec, ClientFuture};
+
+use std::{
+    collections::HashMap,
+    io::{self, Error as IoError},
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+/// Client sending concurrent calls over a single connection, created with
+/// [`crate::UnixDomainClient::multiplexed`].
+///
+/// Every request is written as a frame tagged with a correlation id, see
+/// [`codec::write_tagged_frame`], and the server answers with a frame of the same id, in any
+/// order. A slow call then doesn't hold back the ones sent after it. The responses are read by
+/// a thread of the client, which resolves the future of each call.
+///
+/// The connection is not opened again: once it fails, or an error frame is received, every
+/// pending call fails and so do the next ones. Clones share the connection, which is closed once
+/// the last one is dropped.
+#[derive(Clone)]
+pub struct MuxClient(Arc<Mux>);
+
+struct Mux {
+    stream: Mutex<UnixStream>,
+    calls: Arc<Mutex<Calls>>,
+    next_id: AtomicU64,
+}
+
+#[derive(Default)]
+struct Calls {
+    pending: HashMap<u64, Arc<Mutex<Slot<Vec<u8>>>>>,
+    /// Error that ended the connection, returned to every later call
+    closed: Option<(io::ErrorKind, String)>,
+}
+
+impl MuxClient {
+    pub(crate) fn new(stream: UnixStream, max_frame: usize) -> Result<Self, IoError> {
+        let mut reader = stream.try_clone()?;
+        let calls = Arc::new(Mutex::new(Calls::default()));
+
+        let c = Arc::clone(&calls);
+        thread::Builder::new()
+            .name("uds-mux".to_string())
+            .spawn(move || read_responses(&mut reader, max_frame, &c))?;
+
+        Ok(MuxClient(Arc::new(Mux {
+            stream: Mutex::new(stream),
+            calls,
+            next_id: AtomicU64::new(0),
+        })))
+    }
+
+    /// Send the payload as a request, resolving to its response. The request is written before
+    /// returning, while the response is awaited through the future
+    pub fn call(&self, payload: &[u8]) -> ClientFuture<Vec<u8>> {
+        let (future, slot) = ClientFuture::pending();
+        let id = self.0.next_id.fetch_add(1, Ordering::Relaxed);
+
+        {
+            let mut calls = self.0.calls.lock().unwrap();
+            if let Some((kind, message)) = calls.closed.as_ref() {
+                slot.lock()
+                    .unwrap()
+                    .resolve(Err(IoError::new(*kind, message.clone())));
+                return future;
+            }
+
+            calls.pending.insert(id, Arc::clone(&slot));
+        }
+
+        let mut stream = self.0.stream.lock().unwrap();
+        if let Err(e) = codec::write_tagged_frame(&mut *stream, id, payload) {
+            // A partial frame can't be recovered, so the reader fails every pending call
+            stream.shutdown(Shutdown::Both).unwrap_or_default();
+
+            if self.0.calls.lock().unwrap().pending.remove(&id).is_some() {
+                slot.lock().unwrap().resolve(Err(e));
+            }
+        }
+
+        future
+    }
+
+    /// Number of calls waiting for their response
+    pub fn pending(&self) -> usize {
+        self.0.calls.lock().unwrap().pending.len()
+    }
+
+    /// Whether the connection ended, so every call fails
+    pub fn is_closed(&self) -> bool {
+        self.0.calls.lock().unwrap().closed.is_some()
+    }
+}
+
+impl Drop for Mux {
+    fn drop(&mut self) {
+        // Unblocks the reader thread, failing the calls still pending
+        if let Ok(stream) = self.stream.lock() {
+            stream.shutdown(Shutdown::Both).unwrap_or_default();
+        }
+    }
+}
+
+fn read_responses(reader: &mut UnixStream, max_frame: usize, calls: &Mutex<Calls>) {
+    let e = loop {
+        let (id, response) = match codec::read_tagged_frame(reader, max_frame) {
+            Ok(r) => r,
+            Err(e) => break e,
+        };
+
+        match calls.lock().unwrap().pending.remove(&id) {
+            Some(slot) => slot.lock().unwrap().resolve(Ok(response)),
+            None => warn!("Response to an unknown multiplexed call {} dropped", id),
+        }
+    };
+
+    debug!("Multiplexed connection ended: {}", e);
+
+    let pending = {
+        let mut calls = calls.lock().unwrap();
+        calls.closed.replace((e.kind(), e.to_string()));
+        std::mem::take(&mut calls.pending)
+    };
+
+    for slot in pending.into_values() {
+        slot.lock()
+            .unwrap()
+            .resolve(Err(IoError::new(e.kind(), e.to_string())));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{
+    codec::{self, ErrorCode, ErrorFrame, FrameWriter},
+    Budget, IpcStream, Message, PeerCreds, ResponseCache, TaskProvider,
+};
+
+use std::{
+    future::Future,
+    io::{self, Error as IoError},
+    net::Shutdown,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// Function answering a single request frame
+pub type OneShotFn = fn(Vec<u8>, &PeerCreds) -> Vec<u8>;
+
+/// Provider that reads one frame, answers it with the handler, writes one frame and closes.
+///
+/// Covers the tiny query sockets, that need no state between requests. Frames are read and
+/// written with [`crate::codec`]. Request frames that can't be read due to a protocol violation are
+/// answered with an [`ErrorFrame`]. With [`crate::Options::memory_budget`], the request frames
+/// longer than the budget left to the connection are rejected as well.
+pub struct OneShot {
+    handler: OneShotFn,
+    max_frame: usize,
+    cache: Option<Arc<ResponseCache>>,
+    budget: Option<Budget>,
+    peer: Option<PeerCreds>,
+    socket: Option<IpcStream>,
+}
+
+/// Create a [`OneShot`] provider from the handler function
+pub fn oneshot_handler(handler: OneShotFn) -> OneShot {
+    OneShot {
+        handler,
+        max_frame: codec::MAX_FRAME_SIZE,
+        cache: None,
+        budget: None,
+        peer: None,
+        socket: None,
+    }
+}
+
+impl Clone for OneShot {
+    fn clone(&self) -> Self {
+        OneShot {
+            handler: self.handler,
+            max_frame: self.max_frame,
+            cache: self.cache.clone(),
+            budget: None,
+            peer: None,
+            socket: None,
+        }
+    }
+}
+
+impl OneShot {
+    /// Reject request frames longer than `max` bytes. Defaults to [`codec::MAX_FRAME_SIZE`]
+    pub fn max_frame(mut self, max: usize) -> Self {
+        self.max_frame = max;
+        self
+    }
+
+    /// Answer the repeated requests from the cache, without calling the handler. The cache is
+    /// shared by every clone of the provider
+    pub fn cache(mut self, cache: ResponseCache) -> Self {
+        self.cache.replace(Arc::new(cache));
+        self
+    }
+
+    fn answer(&self, socket: &mut IpcStream) -> Result<(), IoError> {
+        let creds = match self.peer {
+            Some(c) => c,
+            None => PeerCreds::from_stream(socket)?,
+        };
+        let mut writer = FrameWriter::new(&mut *socket);
+
+        let max = match self.budget.as_ref() {
+            Some(b) => self.max_frame.min(b.available()),
+            None => self.max_frame,
+        };
+
+        let request = match codec::read_frame(writer.get_mut(), max) {
+            Ok(r) => r,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                let code = ErrorCode::FRAME_TOO_LARGE;
+                writer.write_error(&ErrorFrame::new(code, e.to_string()))?;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let response = match self.cache.as_ref() {
+            Some(cache) => match cache.get(&request) {
+                Some(response) => response,
+                None => {
+                    let response = (self.handler)(request.clone(), &creds);
+                    cache.insert(request, response.clone());
+                    response
+                }
+            },
+            None => (self.handler)(request, &creds),
+        };
+
+        writer.write_frame(&response)?;
+        drop(writer);
+
+        socket.shutdown(Shutdown::Both)
+    }
+}
+
+impl TaskProvider for OneShot {
+    fn set_budget(&mut self, budget: Budget) {
+        self.budget.replace(budget);
+    }
+
+    fn set_peer(&mut self, peer: PeerCreds) {
+        self.peer.replace(peer);
+    }
+
+    fn set_socket(&mut self, socket: IpcStream) {
+        self.socket.replace(socket);
+    }
+}
+
+impl Future for OneShot {
+    type Output = Message;
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Self::Output> {
+        let mut socket = match self.socket.take() {
+            Some(s) => s,
+            None => return Poll::Ready(Message::Error),
+        };
+
+        match self.answer(&mut socket) {
+            Ok(_) => Poll::Ready(Message::Success),
+            Err(e) => {
+                error!("Error answering the one-shot request: {}", e);
+                Poll::Ready(Message::Error)
+            }
+        }
+    }
+}
@@ -1,13 +1,614 @@
+use crate::{
+    codec, Address, Clock, ConnectionInfo, Executor, ListenerInfo, Load, Message, OptionsBuilder,
+    PeerCreds, SystemClock, ThreadExecutor, Validation,
+};
+
+use std::{io::Error as IoError, os::unix::net::UnixStream, path::Path, sync::Arc, time::Duration};
+
+/// Predicate evaluated in the accept thread before an incoming socket is queued.
+///
+/// Receives the current [`Load`] of the workers, for admission control. If it returns `false`,
+/// the socket is dropped without reaching the workers.
+pub type AcceptFilter = fn(&PeerCreds, &ListenerInfo, &Load) -> bool;
+
+/// Callback invoked by the worker once a connection finished, with the outcome of its provider
+/// and the time it took.
+///
+/// Meant for alerting and SLO tracking. Connections cut by the drain policy or the memory budget
+/// finish as [`crate::Message::Error`]. It runs on the worker, so it must return quickly.
+pub type CompletionFn = fn(&ConnectionInfo, &Message, Duration);
+
+/// Blocking callback run once the path is bound, before the first socket is accepted.
+///
+/// Async initialization can be run to completion inside it, for instance with
+/// [`futures::executor::block_on`].
+pub type WarmUp = Arc<dyn Fn() -> Result<(), IoError> + Send + Sync>;
+
+/// Preset of [`Options`] for a class of workload, created with [`Options::profile`].
+///
+/// | Option                                 | `LowLatency`         | `Throughput`   | `Minimal` |
+/// |----------------------------------------|----------------------|----------------|-----------|
+/// | [`Options::workers`]                   | CPUs                 | CPUs           | 1         |
+/// | [`Options::max_concurrent_per_worker`] | 1                    | 64             | 1         |
+/// | [`Options::max_pending_tasks`]         | 4 per worker         | 4096           | 64        |
+/// | [`Options::backpressure`]              | `RejectWithShutdown` | `Block`        | `Block`   |
+/// | [`Options::poll_budget`]               | 1 ms                 | 10 ms          | none      |
+/// | [`Options::max_in_flight`]             | none                 | none           | 1         |
+/// | [`Options::check_fd_leaks`]            | debug builds         | debug builds   | no        |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// A worker per CPU, each handling a single socket at a time, so no socket waits for another
+    /// one to yield. A short queue, rejecting the sockets past it so the clients fail fast
+    /// instead of waiting
+    LowLatency,
+    /// A worker per CPU, each multiplexing many sockets, for providers that yield while waiting
+    /// on IO. A deep queue, holding the clients in the backlog past it
+    Throughput,
+    /// A single worker handling a single socket at a time, for low-traffic daemons
+    Minimal,
+}
+
+/// Set of protections against local clients exhausting the UDS, enabled together with
+/// [`Options::hardening`] or [`crate::OptionsBuilder::hardening`].
+///
+/// | Protection                           | `Standard`        | `Strict`                        |
+/// |--------------------------------------|-------------------|---------------------------------|
+/// | [`Options::max_in_flight`]           | 4096              | 1024                            |
+/// | [`Options::max_pending_tasks`]       | 1024, `Block`     | 256, `RejectWithShutdown`       |
+/// | [`Options::max_connections_per_uid`] | 256               | 32                              |
+/// | [`Options::max_accept_rate`]         | 5000 per second   | 500 per second                  |
+/// | [`Options::read_timeout`]            | 60 seconds        | 10 seconds                      |
+/// | [`Options::write_timeout`]           | 60 seconds        | 10 seconds                      |
+/// | [`Options::memory_budget`]           | 16 MiB, `Close`   | 1 MiB, `Close`                  |
+///
+/// The memory budget also caps the frames read by [`crate::OneShot`] and [`crate::FdReceiver`].
+/// The timeouts bound the time a peer can take to send its request, and to read its response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hardening {
+    /// Limits unlikely to affect legitimate clients, against runaway or misbehaving ones
+    Standard,
+    /// Tight limits, for sockets exposed to untrusted local users
+    Strict,
+}
+
+impl Hardening {
+    /// Set the protections of the level, keeping the other options
+    pub(crate) fn apply(self, options: Options) -> Options {
+        match self {
+            Hardening::Standard => Options {
+                max_in_flight: Some(4096),
+                max_pending_tasks: Some(1024),
+                backpressure: BackpressurePolicy::Block,
+                max_connections_per_uid: Some(256),
+                max_accept_rate: Some(5000),
+                read_timeout: Some(Duration::from_secs(60)),
+                write_timeout: Some(Duration::from_secs(60)),
+                memory_budget: Some(MemoryBudget {
+                    limit: codec::MAX_FRAME_SIZE,
+                    on_exceeded: BudgetAction::Close,
+                }),
+                ..options
+            },
+
+            Hardening::Strict => Options {
+                max_in_flight: Some(1024),
+                max_pending_tasks: Some(256),
+                backpressure: BackpressurePolicy::RejectWithShutdown,
+                max_connections_per_uid: Some(32),
+                max_accept_rate: Some(500),
+                read_timeout: Some(Duration::from_secs(10)),
+                write_timeout: Some(Duration::from_secs(10)),
+                memory_budget: Some(MemoryBudget {
+                    limit: 1024 * 1024,
+                    on_exceeded: BudgetAction::Close,
+                }),
+                ..options
+            },
+        }
+    }
+}
+
+/// Handling of a provider future that returned [`std::task::Poll::Pending`] without keeping its
+/// waker, so it can never be woken. Reported in the log in any case
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LostWaker {
+    /// Keep the future parked, holding its socket and a slot of the worker, which can never quit
+    Park,
+    /// Drop the future, finishing the connection as [`crate::Message::Error`]
+    Cancel,
+}
+
+/// Treatment of an in-progress connection once the workers are asked to quit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrainClass {
+    /// Let the provider finish, up to [`DrainPolicy::finish_deadline`]
+    Finish,
+    /// Half-close the socket, so the peer observes end of stream, and let the provider finish up
+    /// to [`DrainPolicy::notify_deadline`]
+    Notify,
+    /// Drop the provider at once
+    Cut,
+}
+
+/// Classify a connection when it is taken by a worker, to apply the class if the workers are
+/// asked to quit while it is in progress
+pub type DrainClassifier = fn(&PeerCreds, &ListenerInfo) -> DrainClass;
+
+/// Treatment of the connections still in progress when the workers are asked to quit.
+///
+/// Connections whose deadline elapses are dropped and finished as [`crate::Message::Error`].
+/// Connections whose peer credentials can't be fetched are classified as [`DrainClass::Finish`].
+#[derive(Debug, Clone, Copy)]
+pub struct DrainPolicy {
+    /// Classifier of the connections
+    pub classify: DrainClassifier,
+    /// Deadline of the [`DrainClass::Finish`] connections. `None` waits for them indefinitely
+    pub finish_deadline: Option<Duration>,
+    /// Deadline of the [`DrainClass::Notify`] connections
+    pub notify_deadline: Duration,
+}
+
+impl DrainPolicy {
+    /// Time the connections of the provided class have to finish, once the workers are asked to
+    /// quit
+    pub fn deadline(&self, class: DrainClass) -> Option<Duration> {
+        match class {
+            DrainClass::Finish => self.finish_deadline,
+            DrainClass::Notify => Some(self.notify_deadline),
+            DrainClass::Cut => Some(Duration::from_secs(0)),
+        }
+    }
+}
+
+/// Change of the peer process of a long-lived connection, found by [`PeerRevalidation`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerChange {
+    /// The peer process exited, or its pid now belongs to another process
+    Exited,
+    /// The peer process replaced its executable, such as with `execve`
+    ExeChanged,
+}
+
+/// Callback invoked from the revalidation thread when the peer of a connection changed. Receives
+/// a duplicate of the socket, so it can be shut down while the provider still owns it.
+pub type PeerChangeFn = fn(&UnixStream, &PeerCreds, PeerChange);
+
+/// Periodic check of the peer processes of the connections in progress.
+///
+/// The identity of a peer is its pid, plus its start time and executable where the platform
+/// reports them. Every connection is reported at most once, and is not checked afterwards.
+/// Connections whose peer pid can't be fetched are not checked.
+#[derive(Debug, Clone, Copy)]
+pub struct PeerRevalidation {
+    /// Period between two checks
+    pub interval: Duration,
+    /// Callback for the peers that changed
+    pub on_change: PeerChangeFn,
+}
+
+/// Logging of every connection once it finishes, at the info level.
+///
+/// The line holds the kind of request, told from its first bytes, such as the HTTP method or
+/// `FRAME` for [`crate::codec`] frames, the bytes received and sent, the duration, the outcome and
+/// the start of the payload. Only the traffic through the [`crate::IpcStream`] of the connection
+/// is recorded.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestLog {
+    /// Fields whose values are replaced in the logged payload, written either as
+    /// `"field": value` or as `field=value`
+    pub redact: &'static [&'static str],
+    /// Maximum number of bytes of the payload to log
+    pub capture: usize,
+}
+
+impl Default for RequestLog {
+    fn default() -> Self {
+        RequestLog {
+            redact: &["password", "secret", "token", "authorization"],
+            capture: 256,
+        }
+    }
+}
+
+/// Type of the sockets served
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketKind {
+    /// `SOCK_STREAM`, a byte stream per connection
+    Stream,
+    /// `SOCK_SEQPACKET`, a connection keeping the boundaries of the messages: every read of the
+    /// [`crate::IpcStream`] returns bytes of a single message, the rest of a message longer than
+    /// the buffer being returned by the next reads. [`crate::IpcStream::recv_message`] receives
+    /// them whole. The framing of [`crate::codec`] applies as on a stream
+    SeqPacket,
+    /// `SOCK_DGRAM`, packets without connection, served by a [`crate::DatagramSocket`]
+    Datagram,
+}
+
+/// Change of the socket file of the UDS, found by [`TakeoverCheck`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Takeover {
+    /// The socket file no longer exists, so no client can connect
+    Removed,
+    /// Another file is at the path, such as the socket of another process
+    Replaced,
+}
+
+/// Callback invoked from the check thread when the socket file was taken over, with its path
+pub type TakeoverFn = fn(&Path, Takeover);
+
+/// Reaction to a socket file taken over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TakeoverAction {
+    /// Only report it, once per change
+    Report,
+    /// Remove the file at the path and bind it again, the previous listener finishing its
+    /// backlog
+    Rebind,
+    /// Stop the UDS, as with [`crate::ShutdownHandle::request`]
+    Shutdown,
+}
+
+/// Periodic check that the path of the UDS still points at its own socket, so a socket file
+/// replaced by another process doesn't silently hijack the clients.
+///
+/// The file is identified by its device and inode, recorded once bound. Only the path bound by
+/// [`crate::UnixDomainSocket`] is checked, and not an abstract address. A takeover is logged as
+/// an error before the callback and the action run.
+#[derive(Debug, Clone, Copy)]
+pub struct TakeoverCheck {
+    /// Period between two checks
+    pub interval: Duration,
+    /// Reaction to a takeover
+    pub action: TakeoverAction,
+    /// Callback for the takeovers, such as to emit a security event
+    pub on_takeover: Option<TakeoverFn>,
+}
+
+/// Treatment of the sockets accepted while [`Options::max_pending_tasks`] sockets are already
+/// waiting for a worker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Stop accepting until a worker takes a socket, so the clients wait in the listener backlog
+    /// and the kernel refuses them once it is full
+    Block,
+    /// Accept and close the socket at once
+    DropNewest,
+    /// Accept the socket, write an [`codec::ErrorFrame`] with [`codec::ErrorCode::OVERLOADED`],
+    /// then shut it down and close it, so the peer can tell the rejection from a failure
+    RejectWithShutdown,
+}
+
+/// Workers reserved for the sockets of some listeners, see [`Options::worker_groups`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerGroup {
+    /// Name of the group, for the logs
+    pub name: String,
+    /// Number of workers taken from [`Options::workers`] for the group
+    pub workers: usize,
+    /// Listeners whose sockets are served by the group
+    pub listeners: Vec<Address>,
+}
+
+/// Growth and retirement of the workers of an elastic pool, see [`Options::max_workers`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScalingPolicy {
+    /// Sockets waiting for a worker above which the pool grows
+    pub queue_depth: usize,
+    /// Time the queue must stay deeper than `queue_depth` before a worker is spawned, and between
+    /// two spawns
+    pub scale_up_after: Duration,
+    /// Time a worker must wait for work, with no socket in progress, before it's retired
+    pub scale_down_after: Duration,
+}
+
+impl Default for ScalingPolicy {
+    fn default() -> Self {
+        ScalingPolicy {
+            queue_depth: 0,
+            scale_up_after: Duration::from_millis(50),
+            scale_down_after: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Treatment of a connection reserving more memory than its [`MemoryBudget`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetAction {
+    /// Refuse the reservation, so the provider stops reading until it released memory
+    Backpressure,
+    /// Refuse the reservation and close the connection
+    Close,
+}
+
+/// Maximum memory a single connection may hold, so one client can't grow the memory of the
+/// daemon unbounded. See [`crate::Budget`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget {
+    /// Maximum number of bytes reserved at once by a connection
+    pub limit: usize,
+    /// Treatment of a reservation over the limit
+    pub on_exceeded: BudgetAction,
+}
+
+/// Identification frame written to every socket as soon as it is accepted, before any other
+/// byte, so clients and tools such as `socat` can tell what serves a path.
+///
+/// The frame is a [`crate::codec`] frame whose payload is the UTF-8 line
+/// `dusk-uds/<crate version> protocol/<protocol> <name>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Banner {
+    /// Name of the server
+    pub name: String,
+    /// Version of the protocol spoken on the socket
+    pub protocol: u32,
+}
+
+impl Banner {
+    /// Payload of the identification frame
+    pub fn payload(&self) -> String {
+        format!(
+            "dusk-uds/{} protocol/{} {}",
+            env!("CARGO_PKG_VERSION"),
+            self.protocol,
+            self.name
+        )
+    }
+
+    /// Encoded identification frame
+    pub(crate) fn frame(&self) -> Vec<u8> {
+        let mut frame = vec![];
+        codec::write_frame(&mut frame, self.payload().as_bytes()).unwrap_or_default();
+        frame
+    }
+}
+
 /// Set of options to define the behavior of the UDS listener
 pub struct Options {
     /// Define the number of worker threads to listen
     pub workers: usize,
+    /// Optional gate for incoming sockets, evaluated synchronously in the accept thread
+    pub accept_filter: Option<AcceptFilter>,
+    /// Half-close the connections that are still being handled after this period, so the peer
+    /// observes end of stream even if it is still active
+    pub max_connection_age: Option<Duration>,
+    /// Quit gracefully after no connections were accepted or in progress for this period. Meant
+    /// for on-demand daemons, such as the ones started by socket activation
+    pub exit_on_idle: Option<Duration>,
+    /// Shut the UDS down gracefully on `SIGTERM` and `SIGINT`: stop accepting, finish the sockets
+    /// in progress, then return from [`crate::UnixDomainSocket::bind`]. Also applies to
+    /// [`crate::UnixDomainSocket::bind_with_handle`] and [`crate::Server::run`]. The previous
+    /// handlers are restored once the UDS is stopped
+    pub handle_signals: bool,
+    /// Maximum number of in-progress provider futures owned by each worker. Futures returning
+    /// [`std::task::Poll::Pending`] are polled again, in the same worker, when they are woken.
+    /// Providers waiting on their socket through the [`futures::io::AsyncRead`] and
+    /// [`futures::io::AsyncWrite`] implementations of [`crate::IpcStream`] are woken once it's
+    /// ready, while a provider blocked in a read holds its worker
+    pub max_concurrent_per_worker: usize,
+    /// Maximum number of sockets in progress across all the workers, regardless of their number
+    /// and [`Options::max_concurrent_per_worker`]. Protects the resources the providers depend on
+    pub max_in_flight: Option<usize>,
+    /// Maximum number of accepted sockets waiting for a worker, per worker group. Past it, the
+    /// accept loops apply [`Options::backpressure`], so the queue can't grow without bound when
+    /// the workers fall behind. Sockets pushed by the application are not bounded
+    pub max_pending_tasks: Option<usize>,
+    /// Treatment of the sockets past [`Options::max_pending_tasks`]
+    pub backpressure: BackpressurePolicy,
+    /// Maximum number of sockets of a single peer user queued or in progress. Past it, the
+    /// sockets of the user are closed as soon as accepted, so one local user can't take every
+    /// worker. Sockets pushed by the application are not counted
+    pub max_connections_per_uid: Option<usize>,
+    /// Maximum number of sockets accepted per second by each listener, with bursts of up to
+    /// [`Options::accept_burst`]. The sockets past it are treated by
+    /// [`Options::accept_rate_policy`].
+    ///
+    /// This is the rate of the token bucket of the accept loop, also known as
+    /// `max_accepts_per_second`
+    #[doc(alias = "max_accepts_per_second")]
+    pub max_accept_rate: Option<u32>,
+    /// Sockets accepted at once by a listener limited by [`Options::max_accept_rate`], after a
+    /// quiet period. Defaults to a second worth of sockets.
+    ///
+    /// This is the size of the token bucket of the accept loop, also known as `burst`
+    #[doc(alias = "burst")]
+    pub accept_burst: Option<u32>,
+    /// Treatment of the sockets past [`Options::max_accept_rate`]. With
+    /// [`BackpressurePolicy::Block`], they wait in the listener backlog until the rate allows
+    /// them, otherwise they are accepted and closed at once
+    pub accept_rate_policy: BackpressurePolicy,
+    /// Partition the workers into isolated groups, such as a few workers reserved for an admin
+    /// socket. The sockets accepted on the listeners of a group are served only by its workers,
+    /// and the other sockets only by the remaining workers, so heavy traffic on a listener can't
+    /// starve the others. With groups, [`Options::max_in_flight`] applies to each group
+    pub worker_groups: Vec<WorkerGroup>,
+    /// Grow the pool up to this many workers while sockets wait for one, see
+    /// [`Options::scaling`]. [`Options::workers`] are spawned first, and without it the pool
+    /// doesn't grow past them
+    pub max_workers: Option<usize>,
+    /// Retire the idle workers down to this many, see [`Options::scaling`]. The pool grows back
+    /// up to [`Options::workers`], or [`Options::max_workers`] if set, while sockets wait for a
+    /// worker. Without it, the pool doesn't shrink below [`Options::workers`]
+    pub min_workers: Option<usize>,
+    /// When to grow and shrink the pool, once [`Options::max_workers`] or
+    /// [`Options::min_workers`] make it elastic. Only the workers of no group are scaled
+    pub scaling: ScalingPolicy,
+    /// Time a single poll of a provider future should take at most. A poll exceeding it can't be
+    /// interrupted, but is reported in the log and in [`crate::WorkerStats::slow_polls`], and the
+    /// future yields to the other futures of the worker: it is polled again only after the ones
+    /// woken meanwhile
+    pub poll_budget: Option<Duration>,
+    /// Handling of the provider futures that can't be woken anymore
+    pub lost_waker: LostWaker,
+    /// Respawn a worker that panicked, instead of serving with one worker less until the UDS is
+    /// stopped. The sockets it was handling are closed. A provider future panicking while polled
+    /// only closes its own socket, whatever this option
+    pub respawn_workers: bool,
+    /// Check every socket was closed once its provider is dropped, reporting the leaks in the log
+    /// and in [`crate::ServerStats::fd_leaks`]. Enabled by default in debug builds
+    pub check_fd_leaks: bool,
+    /// Hand a [`crate::Budget`] to every provider, accounting the memory held by its connection
+    pub memory_budget: Option<MemoryBudget>,
+    /// Hand a [`crate::Scratch`] to every provider, releasing its resources when the connection
+    /// ends
+    pub connection_scratch: bool,
+    /// Type of the sockets served. [`SocketKind::Datagram`] is only served by a
+    /// [`crate::DatagramSocket`]
+    pub socket_kind: SocketKind,
+    /// Permission bits set on the socket file once bound, such as `0o660`. Without it, the
+    /// process umask applies
+    pub mode: Option<u32>,
+    /// User owning the socket file once bound
+    pub owner: Option<libc::uid_t>,
+    /// Group owning the socket file once bound, so its members can connect with
+    /// [`Options::mode`]
+    pub group: Option<libc::gid_t>,
+    /// Set `SO_LINGER` with this timeout on every accepted socket
+    pub linger: Option<Duration>,
+    /// Read timeout set on every accepted socket before it reaches the provider, so a stalled
+    /// peer can't hold a worker blocked reading forever. A read timing out fails with an error
+    /// of kind [`std::io::ErrorKind::WouldBlock`] or [`std::io::ErrorKind::TimedOut`]
+    pub read_timeout: Option<Duration>,
+    /// Write timeout set on every accepted socket before it reaches the provider, so a peer not
+    /// reading its responses can't hold a worker blocked writing forever
+    pub write_timeout: Option<Duration>,
+    /// Once a provider is dropped, signal end of stream and read and discard the input of the peer
+    /// until it closes, for up to this period. Prevents the peer from losing a response when the
+    /// connection is closed with unread input. The worker is blocked while draining
+    pub drain_on_close: Option<Duration>,
+    /// Record the lifecycle transitions of the connections in a ring buffer holding this many
+    /// events, retrieved with [`crate::ServerHandle::connection_trace`]. Meant for diagnosing
+    /// where the latency is spent
+    pub trace_connections: Option<usize>,
+    /// Count the bytes read from and written to the [`crate::IpcStream`] of every connection,
+    /// reported per connection in progress and per peer user in
+    /// [`crate::ServerStats::connections`] and [`crate::ServerStats::peers`]. Meant for finding
+    /// the local client responsible for the traffic of the socket
+    pub account_traffic: bool,
+    /// Prepare the application, such as loading caches or opening pools, before accepting. The
+    /// clients connecting meanwhile wait in the listener backlog. If it fails, the UDS is not
+    /// started
+    pub warm_up: Option<WarmUp>,
+    /// Maximum time to wait for [`Options::warm_up`] to finish, before failing the start
+    pub warm_up_timeout: Duration,
+    /// Validate the path and the options before binding, refusing to start according to the
+    /// mode. See [`crate::UnixDomainSocket::validate`]
+    pub validation: Option<Validation>,
+    /// Treatment of the connections in progress when the workers are asked to quit. Without it,
+    /// every connection is waited for indefinitely
+    pub drain_policy: Option<DrainPolicy>,
+    /// Write an identification frame to every accepted socket
+    pub banner: Option<Banner>,
+    /// Log every connection once it finishes, with its payload redacted
+    pub request_log: Option<RequestLog>,
+    /// Call this function every time a connection finishes
+    pub on_complete: Option<CompletionFn>,
+    /// Check periodically whether the peers of the connections in progress are still the same
+    /// processes
+    pub peer_revalidation: Option<PeerRevalidation>,
+    /// Check periodically whether the socket file was replaced or removed
+    pub takeover_check: Option<TakeoverCheck>,
+    /// Maximum time to wait for the accept thread to finish once the workers are done
+    pub accept_shutdown_timeout: Duration,
+    /// Runtime that will run the worker loops
+    pub executor: Arc<dyn Executor>,
+    /// Source of time of every deadline, timeout and timestamp. [`crate::ManualClock`] makes
+    /// them deterministic in tests
+    pub clock: Arc<dyn Clock>,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Options {
             workers: num_cpus::get(),
+            accept_filter: None,
+            max_connection_age: None,
+            exit_on_idle: None,
+            handle_signals: false,
+            max_concurrent_per_worker: 1,
+            max_in_flight: None,
+            max_pending_tasks: None,
+            backpressure: BackpressurePolicy::Block,
+            max_connections_per_uid: None,
+            max_accept_rate: None,
+            accept_burst: None,
+            accept_rate_policy: BackpressurePolicy::Block,
+            worker_groups: vec![],
+            max_workers: None,
+            min_workers: None,
+            scaling: ScalingPolicy::default(),
+            poll_budget: None,
+            lost_waker: LostWaker::Park,
+            respawn_workers: true,
+            check_fd_leaks: cfg!(debug_assertions),
+            memory_budget: None,
+            connection_scratch: false,
+            socket_kind: SocketKind::Stream,
+            mode: None,
+            owner: None,
+            group: None,
+            linger: None,
+            read_timeout: None,
+            write_timeout: None,
+            drain_on_close: None,
+            trace_connections: None,
+            account_traffic: false,
+            warm_up: None,
+            warm_up_timeout: Duration::from_secs(30),
+            validation: None,
+            drain_policy: None,
+            banner: None,
+            request_log: None,
+            on_complete: None,
+            peer_revalidation: None,
+            takeover_check: None,
+            accept_shutdown_timeout: Duration::from_secs(5),
+            executor: Arc::new(ThreadExecutor),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl Options {
+    /// Default options with the protections of the provided level. See [`Hardening`]
+    pub fn hardening(hardening: Hardening) -> Self {
+        hardening.apply(Options::default())
+    }
+
+    /// Builder starting from the default options, checking them once built
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::from(Options::default())
+    }
+
+    /// Options tuned for the provided workload class. The fields can still be changed afterwards.
+    pub fn profile(profile: Profile) -> Self {
+        let options = Options::default();
+
+        match profile {
+            Profile::LowLatency => Options {
+                max_concurrent_per_worker: 1,
+                max_pending_tasks: Some(options.workers.saturating_mul(4)),
+                backpressure: BackpressurePolicy::RejectWithShutdown,
+                poll_budget: Some(Duration::from_millis(1)),
+                ..options
+            },
+
+            Profile::Throughput => Options {
+                max_concurrent_per_worker: 64,
+                max_pending_tasks: Some(4096),
+                backpressure: BackpressurePolicy::Block,
+                poll_budget: Some(Duration::from_millis(10)),
+                ..options
+            },
+
+            Profile::Minimal => Options {
+                workers: 1,
+                max_concurrent_per_worker: 1,
+                max_pending_tasks: Some(64),
+                backpressure: BackpressurePolicy::Block,
+                max_in_flight: Some(1),
+                check_fd_leaks: false,
+                ..options
+            },
         }
     }
 }
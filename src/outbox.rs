@@ -0,0 +1,278 @@
+use crate::codec;
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Error as IoError, Write},
+    sync::{Arc, Condvar, Mutex},
+    task::Waker,
+    time::{Duration, Instant},
+};
+
+/// Handling of a frame pushed to a full [`Outbox`], when the subscriber stopped reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumer {
+    /// Disconnect the subscriber, dropping its queued frames
+    Disconnect,
+    /// Drop the oldest queued frame to make room for the new one
+    DropOldest,
+    /// Block the publisher until there is room, disconnecting the subscriber once the timeout
+    /// elapsed
+    Block(Duration),
+}
+
+/// Bounded queue of the frames to write to a single connection, for broadcast and pub/sub.
+///
+/// The publisher pushes frames, usually through a [`Fanout`], and the provider of the connection
+/// writes them to its socket with [`Outbox::flush`]. Once full, a push is handled according to
+/// the [`SlowConsumer`] policy, so a subscriber that stopped reading can't wedge the publisher.
+///
+/// Clones share the queue.
+#[derive(Clone)]
+pub struct Outbox(Arc<Shared>);
+
+struct Shared {
+    capacity: usize,
+    policy: SlowConsumer,
+    state: Mutex<State>,
+    /// Notified when a frame is taken or the subscriber disconnected, for the blocked publishers
+    room: Condvar,
+}
+
+struct State {
+    frames: VecDeque<Vec<u8>>,
+    dropped: u64,
+    disconnected: bool,
+    /// Waker of the provider, woken when a frame is pushed or the subscriber disconnected
+    waker: Option<Waker>,
+}
+
+impl Outbox {
+    /// Outbox holding up to `capacity` frames, at least one
+    pub fn new(capacity: usize, policy: SlowConsumer) -> Self {
+        Outbox(Arc::new(Shared {
+            capacity: capacity.max(1),
+            policy,
+            state: Mutex::new(State {
+                frames: VecDeque::new(),
+                dropped: 0,
+                disconnected: false,
+                waker: None,
+            }),
+            room: Condvar::new(),
+        }))
+    }
+
+    /// Maximum number of queued frames
+    pub fn capacity(&self) -> usize {
+        self.0.capacity
+    }
+
+    /// Number of queued frames
+    pub fn len(&self) -> usize {
+        self.0.state.lock().unwrap().frames.len()
+    }
+
+    /// Whether no frame is queued
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of frames dropped by [`SlowConsumer::DropOldest`]
+    pub fn dropped(&self) -> u64 {
+        self.0.state.lock().unwrap().dropped
+    }
+
+    /// Whether the subscriber is disconnected, so the provider should close the connection
+    pub fn is_disconnected(&self) -> bool {
+        self.0.state.lock().unwrap().disconnected
+    }
+
+    /// Queue a frame with the provided payload.
+    ///
+    /// Fails with an error of kind [`io::ErrorKind::BrokenPipe`] if the subscriber is
+    /// disconnected, or gets disconnected by [`SlowConsumer::Disconnect`]. Under
+    /// [`SlowConsumer::Block`], fails with an error of kind [`io::ErrorKind::TimedOut`] once the
+    /// subscriber is disconnected for not making room in time.
+    pub fn push(&self, payload: Vec<u8>) -> Result<(), IoError> {
+        let mut state = self.0.state.lock().unwrap();
+
+        if state.disconnected {
+            return Err(disconnected());
+        }
+
+        if state.frames.len() >= self.0.capacity {
+            match self.0.policy {
+                SlowConsumer::Disconnect => {
+                    self.disconnect_locked(&mut state);
+                    return Err(IoError::new(
+                        io::ErrorKind::BrokenPipe,
+                        "The subscriber stopped reading and was disconnected",
+                    ));
+                }
+
+                SlowConsumer::DropOldest => {
+                    state.frames.pop_front();
+                    state.dropped += 1;
+                }
+
+                SlowConsumer::Block(timeout) => {
+                    let deadline = Instant::now() + timeout;
+
+                    while state.frames.len() >= self.0.capacity && !state.disconnected {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        if remaining.is_zero() {
+                            break;
+                        }
+
+                        state = self.0.room.wait_timeout(state, remaining).unwrap().0;
+                    }
+
+                    if state.disconnected {
+                        return Err(disconnected());
+                    }
+
+                    if state.frames.len() >= self.0.capacity {
+                        self.disconnect_locked(&mut state);
+                        return Err(IoError::new(
+                            io::ErrorKind::TimedOut,
+                            format!(
+                                "The subscriber made no room within {:?} and was disconnected",
+                                timeout
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        state.frames.push_back(payload);
+        if let Some(w) = state.waker.take() {
+            w.wake();
+        }
+
+        Ok(())
+    }
+
+    /// Take the oldest queued frame
+    pub fn pop(&self) -> Option<Vec<u8>> {
+        let frame = self.0.state.lock().unwrap().frames.pop_front();
+        if frame.is_some() {
+            self.0.room.notify_all();
+        }
+
+        frame
+    }
+
+    /// Write every queued frame with [`codec::write_frame`], returning how many were written.
+    /// Stops at the first error, dropping the frame that failed
+    pub fn flush<W: Write>(&self, writer: &mut W) -> Result<usize, IoError> {
+        let mut written = 0;
+
+        while let Some(frame) = self.pop() {
+            codec::write_frame(writer, &frame)?;
+            written += 1;
+        }
+
+        Ok(written)
+    }
+
+    /// Wake the provided waker on the next frame pushed or once the subscriber is disconnected,
+    /// so a provider waiting for frames is polled again
+    pub fn set_waker(&self, waker: &Waker) {
+        self.0.state.lock().unwrap().waker.replace(waker.clone());
+    }
+
+    /// Disconnect the subscriber, dropping the queued frames and failing the next pushes
+    pub fn disconnect(&self) {
+        let mut state = self.0.state.lock().unwrap();
+        self.disconnect_locked(&mut state);
+    }
+
+    fn disconnect_locked(&self, state: &mut State) {
+        state.disconnected = true;
+        state.frames.clear();
+        self.0.room.notify_all();
+
+        if let Some(w) = state.waker.take() {
+            w.wake();
+        }
+    }
+}
+
+impl fmt::Debug for Outbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.0.state.lock().unwrap();
+
+        f.debug_struct("Outbox")
+            .field("capacity", &self.0.capacity)
+            .field("policy", &self.0.policy)
+            .field("queued", &state.frames.len())
+            .field("dropped", &state.dropped)
+            .field("disconnected", &state.disconnected)
+            .finish()
+    }
+}
+
+fn disconnected() -> IoError {
+    IoError::new(io::ErrorKind::BrokenPipe, "The subscriber is disconnected")
+}
+
+/// Subscribers of a publisher, each with an [`Outbox`] of its own.
+///
+/// Publishing pushes the payload to every outbox, and forgets the subscribers that got
+/// disconnected. Only [`SlowConsumer::Block`] blocks the publisher, for up to its timeout per
+/// stalled subscriber.
+///
+/// Clones share the subscribers.
+#[derive(Debug, Clone, Default)]
+pub struct Fanout {
+    outboxes: Arc<Mutex<Vec<Outbox>>>,
+}
+
+impl Fanout {
+    /// Fanout with no subscribers
+    pub fn new() -> Self {
+        Fanout::default()
+    }
+
+    /// Add a subscriber, returning the outbox its provider writes from
+    pub fn subscribe(&self, capacity: usize, policy: SlowConsumer) -> Outbox {
+        let outbox = Outbox::new(capacity, policy);
+        self.outboxes.lock().unwrap().push(outbox.clone());
+
+        outbox
+    }
+
+    /// Number of subscribers still connected
+    pub fn subscribers(&self) -> usize {
+        self.outboxes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|o| !o.is_disconnected())
+            .count()
+    }
+
+    /// Push the payload to every subscriber, returning the number that queued it
+    pub fn publish(&self, payload: &[u8]) -> usize {
+        // Pushed without the lock, so a blocked publisher doesn't hold back the subscriptions
+        let outboxes = self.outboxes.lock().unwrap().clone();
+
+        let delivered = outboxes
+            .iter()
+            .filter(|o| {
+                o.push(payload.to_vec())
+                    .map_err(|e| debug!("Subscriber not delivered: {}", e))
+                    .is_ok()
+            })
+            .count();
+
+        self.outboxes
+            .lock()
+            .unwrap()
+            .retain(|o| !o.is_disconnected());
+
+        delivered
+    }
+}
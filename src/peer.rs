@@ -0,0 +1,70 @@
+use std::{
+    io::{self, Error as IoError},
+    mem,
+    os::unix::{io::AsRawFd, net::UnixStream},
+};
+
+/// Credentials of the process on the other end of an accepted socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCreds {
+    /// Process id of the peer. Not every platform reports it
+    pub pid: Option<libc::pid_t>,
+    /// Effective user id of the peer
+    pub uid: libc::uid_t,
+    /// Effective group id of the peer
+    pub gid: libc::gid_t,
+}
+
+impl PeerCreds {
+    /// Fetch the credentials of the peer connected to the provided socket
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn from_stream(stream: &UnixStream) -> Result<Self, IoError> {
+        let mut cred: libc::ucred = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut cred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+
+        if ret != 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        if len as usize != mem::size_of::<libc::ucred>() {
+            return Err(IoError::new(
+                io::ErrorKind::InvalidData,
+                "Unexpected SO_PEERCRED length",
+            ));
+        }
+
+        Ok(PeerCreds {
+            pid: Some(cred.pid),
+            uid: cred.uid,
+            gid: cred.gid,
+        })
+    }
+
+    /// Fetch the credentials of the peer connected to the provided socket
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn from_stream(stream: &UnixStream) -> Result<Self, IoError> {
+        let mut uid: libc::uid_t = 0;
+        let mut gid: libc::gid_t = 0;
+
+        let ret = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+        if ret != 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        Ok(PeerCreds {
+            pid: None,
+            uid,
+            gid,
+        })
+    }
+}
@@ -0,0 +1,370 @@
+use crate::{
+    clock, events::EventBus, Address, Clock, Message, ServerEvent, ShutdownReason, Task, UdsError,
+    WorkerGroup,
+};
+
+use std::{
+    collections::VecDeque,
+    io::Error as IoError,
+    os::unix::net::UnixStream,
+    sync::{Arc, Condvar, Mutex},
+    time::Instant,
+};
+
+/// Task queue shared amongst the worker threads.
+///
+/// Besides the tasks, it carries the wake notifications of the futures each worker has in
+/// progress, so a worker can sleep until either a new task arrives or one of its futures can make
+/// progress.
+///
+/// It also bounds the number of sockets in progress across all the workers, so a worker with
+/// room for more sockets still waits while the limit is reached.
+///
+/// The workers can be partitioned into groups, see [`crate::Options::worker_groups`]. Every group
+/// has its own sockets and limit, while the messages are taken by any worker. Group 0 holds the
+/// workers of no configured group.
+///
+/// Every worker has a slot, up to the maximum of an elastic pool, see
+/// [`crate::Options::max_workers`]. Only the slots of the running workers receive messages.
+pub struct Queue {
+    state: Mutex<State>,
+    cond: Condvar,
+    max_in_flight: usize,
+    /// Running workers below which none is retired
+    min_workers: usize,
+    clock: Arc<dyn Clock>,
+    /// Group of every worker
+    workers: Vec<usize>,
+    /// Group serving the sockets of every routed listener
+    listeners: Vec<(Address, usize)>,
+    /// Subscribers told when the workers start draining
+    events: Arc<EventBus>,
+}
+
+struct State {
+    closed: bool,
+    /// Sequence of the next task, so the messages and the sockets of a group are taken in the
+    /// order they were pushed
+    seq: u64,
+    messages: VecDeque<(u64, Message)>,
+    groups: Vec<Group>,
+    inbox: Vec<VecDeque<Message>>,
+    woken: Vec<Vec<u64>>,
+    /// Buffers of [`Event::Woken`] given back by the workers, so waking a future doesn't
+    /// allocate
+    spare: Vec<Vec<u64>>,
+    /// Reason of the first shutdown requested with [`Queue::quit`]
+    shutdown: Option<ShutdownReason>,
+    /// Workers that received [`Event::Shutdown`]
+    told: Vec<bool>,
+    /// Slots of the running workers
+    running: Vec<bool>,
+}
+
+#[derive(Default)]
+struct Group {
+    in_flight: usize,
+    sockets: VecDeque<(u64, UnixStream)>,
+}
+
+/// Next unit of work for a worker
+pub enum Event {
+    /// A task taken from the queue
+    Task(Task),
+    /// Ids of the in-progress futures of the worker that were woken
+    Woken(Vec<u64>),
+    /// The deadline elapsed with no work for the worker
+    Timeout,
+    /// The workers are asked to quit for the provided reason
+    Shutdown(ShutdownReason),
+}
+
+impl Queue {
+    /// Create a queue for the provided number of running workers, within the bounds of the pool,
+    /// and the maximum number of sockets each group can have in progress, if any. The workers of
+    /// the groups are taken from the last running ones, and the others form group 0, along with
+    /// the slots spawned later. Deadlines are read from the clock
+    pub fn new(
+        workers: usize,
+        (min_workers, max_workers): (usize, usize),
+        groups: &[WorkerGroup],
+        max_in_flight: Option<usize>,
+        clock: Arc<dyn Clock>,
+        events: Arc<EventBus>,
+    ) -> Self {
+        let slots = max_workers.max(workers);
+        let mut members = vec![0; slots];
+        let mut last = workers;
+
+        for (i, group) in groups.iter().enumerate() {
+            let first = last.saturating_sub(group.workers);
+            members[first..last].iter_mut().for_each(|g| *g = i + 1);
+            last = first;
+        }
+
+        let listeners = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(i, g)| g.listeners.iter().map(move |l| (l.clone(), i + 1)))
+            .collect();
+
+        Queue {
+            state: Mutex::new(State {
+                closed: false,
+                seq: 0,
+                messages: VecDeque::new(),
+                groups: (0..=groups.len()).map(|_| Group::default()).collect(),
+                inbox: vec![VecDeque::new(); slots],
+                woken: vec![vec![]; slots],
+                spare: vec![vec![]; slots],
+                shutdown: None,
+                told: vec![false; slots],
+                running: (0..slots).map(|w| w < workers).collect(),
+            }),
+            cond: Condvar::new(),
+            max_in_flight: max_in_flight.unwrap_or(usize::MAX).max(1),
+            min_workers,
+            clock,
+            workers: members,
+            listeners,
+            events,
+        }
+    }
+
+    /// Group of the worker
+    pub fn group(&self, worker: usize) -> usize {
+        self.workers[worker]
+    }
+
+    /// Group serving the sockets accepted on the listener
+    pub fn listener_group(&self, address: &Address) -> usize {
+        self.listeners
+            .iter()
+            .find(|(a, _)| a == address)
+            .map_or(0, |(_, g)| *g)
+    }
+
+    /// Ask the workers to quit, recording the reason unless a previous shutdown already did. Every
+    /// worker receives the reason once with [`Event::Shutdown`], even without room for more
+    /// sockets. Will fail if the queue was closed.
+    pub fn quit(&self, reason: ShutdownReason) -> Result<(), UdsError> {
+        let first = {
+            let mut state = self.state.lock().unwrap();
+            let first = state.shutdown.is_none();
+            state.shutdown.get_or_insert(reason.clone());
+            first
+        };
+        self.cond.notify_all();
+
+        if first {
+            self.events.emit(|| ServerEvent::Draining(reason));
+        }
+
+        self.push(Task::Message(Message::ShouldQuit))
+    }
+
+    /// Enqueue a task, for the workers of group 0 if it is a socket. Will fail if the queue was
+    /// closed.
+    pub fn push(&self, task: Task) -> Result<(), UdsError> {
+        self.push_to(0, task)
+    }
+
+    /// Enqueue a task, for the workers of the provided group if it is a socket. Will fail if the
+    /// queue was closed.
+    pub fn push_to(&self, group: usize, task: Task) -> Result<(), UdsError> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(UdsError::ChannelClosed);
+        }
+
+        let seq = state.seq;
+        state.seq += 1;
+
+        match task {
+            Task::Message(m) => state.messages.push_back((seq, m)),
+            Task::Socket(s) => match state.groups.get_mut(group) {
+                Some(g) => g.sockets.push_back((seq, s)),
+                None => return Err(IoError::other("The worker group doesn't exist").into()),
+            },
+        }
+
+        self.cond.notify_all();
+
+        Ok(())
+    }
+
+    /// Deliver a message to every worker. Will fail if the queue was closed.
+    pub fn broadcast(&self, message: Message) -> Result<(), UdsError> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(UdsError::ChannelClosed);
+        }
+
+        let State { inbox, running, .. } = &mut *state;
+        inbox
+            .iter_mut()
+            .zip(running.iter())
+            .filter(|(_, r)| **r)
+            .for_each(|(i, _)| i.push_back(message.clone()));
+        self.cond.notify_all();
+
+        Ok(())
+    }
+
+    /// Notify the worker that one of its futures can make progress
+    pub fn wake(&self, worker: usize, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.woken[worker].push(id);
+        self.cond.notify_all();
+    }
+
+    /// Give back the buffer of an [`Event::Woken`], to be reused by the next one of the worker
+    pub fn recycle(&self, worker: usize, mut ids: Vec<u64>) {
+        ids.clear();
+
+        let mut state = self.state.lock().unwrap();
+        if state.spare[worker].capacity() < ids.capacity() {
+            state.spare[worker] = ids;
+        }
+    }
+
+    /// Record the end of a socket taken by the worker with [`Queue::next`], making room for
+    /// another one
+    pub fn release(&self, worker: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.groups[self.workers[worker]].in_flight -= 1;
+        self.cond.notify_all();
+    }
+
+    /// Number of sockets waiting for a worker
+    pub fn queued(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.groups.iter().map(|g| g.sockets.len()).sum()
+    }
+
+    /// Number of sockets waiting for a worker of the group
+    pub fn queued_in(&self, group: usize) -> usize {
+        let state = self.state.lock().unwrap();
+        state.groups.get(group).map_or(0, |g| g.sockets.len())
+    }
+
+    /// Whether the queue was closed
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    /// Whether the workers were asked to quit, or the queue was closed
+    pub fn is_quitting(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.closed || state.shutdown.is_some()
+    }
+
+    /// Take a free slot for a new worker of group 0, unless the pool is full or the workers are
+    /// quitting
+    pub fn enlist(&self) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed || state.shutdown.is_some() {
+            return None;
+        }
+
+        let worker =
+            (0..state.running.len()).find(|w| !state.running[*w] && self.workers[*w] == 0)?;
+        state.running[worker] = true;
+        state.told[worker] = false;
+
+        Some(worker)
+    }
+
+    /// Free the slot of an idle worker of group 0, returning whether it is to quit. Refused while
+    /// sockets wait for the group, or if it would leave fewer running workers than the minimum
+    pub fn retire(&self, worker: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        let running = state.running.iter().filter(|r| **r).count();
+        if self.workers[worker] != 0
+            || running <= self.min_workers
+            || !state.groups[0].sockets.is_empty()
+            || !state.inbox[worker].is_empty()
+        {
+            return false;
+        }
+
+        state.running[worker] = false;
+        state.woken[worker].clear();
+
+        true
+    }
+
+    /// Reject any further task and drop the pending ones
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.messages.clear();
+        state.groups.iter_mut().for_each(|g| g.sockets.clear());
+    }
+
+    /// Block until there is work for the worker. Broadcast messages and woken futures take
+    /// precedence over new tasks, and new tasks are only taken if `accept` is set and the limit
+    /// of sockets in progress of its group is not reached. Every socket returned must be given back with
+    /// [`Queue::release`]. If there is no work until the deadline, [`Event::Timeout`] is returned.
+    pub fn next(&self, worker: usize, accept: bool, deadline: Option<Instant>) -> Event {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if let Some(message) = state.inbox[worker].pop_front() {
+                return Event::Task(Task::Message(message));
+            }
+
+            if !state.told[worker] {
+                if let Some(reason) = state.shutdown.clone() {
+                    state.told[worker] = true;
+                    return Event::Shutdown(reason);
+                }
+            }
+
+            if !state.woken[worker].is_empty() {
+                let spare = std::mem::take(&mut state.spare[worker]);
+                let mut ids = std::mem::replace(&mut state.woken[worker], spare);
+                ids.sort_unstable();
+                ids.dedup();
+                return Event::Woken(ids);
+            }
+
+            let group = self.workers[worker];
+            if accept && state.groups[group].in_flight < self.max_in_flight {
+                let message = state.messages.front().map(|(seq, _)| *seq);
+                let socket = state.groups[group].sockets.front().map(|(seq, _)| *seq);
+
+                match (message, socket) {
+                    (Some(m), s) if s.is_none_or(|s| m < s) => {
+                        if let Some((_, message)) = state.messages.pop_front() {
+                            return Event::Task(Task::Message(message));
+                        }
+                    }
+
+                    (_, Some(_)) => {
+                        let group = &mut state.groups[group];
+                        if let Some((_, socket)) = group.sockets.pop_front() {
+                            group.in_flight += 1;
+                            return Event::Task(Task::Socket(socket));
+                        }
+                    }
+
+                    _ => (),
+                }
+            }
+
+            state = match deadline {
+                Some(d) => {
+                    let timeout = clock::wait_until(&*self.clock, d);
+                    if timeout.is_zero() {
+                        return Event::Timeout;
+                    }
+
+                    self.cond.wait_timeout(state, timeout).unwrap().0
+                }
+                None => self.cond.wait(state).unwrap(),
+            };
+        }
+    }
+}
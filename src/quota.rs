@@ -0,0 +1,66 @@
+use crate::fd;
+
+use std::{
+    collections::HashMap,
+    os::unix::{io::AsRawFd, net::UnixStream},
+    sync::Mutex,
+};
+
+/// Sockets of every peer user queued or in progress, capped by
+/// [`crate::Options::max_connections_per_uid`].
+///
+/// The sockets are identified by their inode, so the workers release them without keeping the
+/// credentials of the peer.
+pub struct UidQuota {
+    max: usize,
+    counted: Mutex<Counted>,
+}
+
+#[derive(Default)]
+struct Counted {
+    users: HashMap<libc::uid_t, usize>,
+    sockets: HashMap<libc::ino_t, libc::uid_t>,
+}
+
+impl UidQuota {
+    pub fn new(max: usize) -> Self {
+        UidQuota {
+            max,
+            counted: Mutex::new(Counted::default()),
+        }
+    }
+
+    /// Count the socket against the quota of the user. Returns `false`, without counting it, if
+    /// the user already reached its quota
+    pub fn acquire(&self, socket: &UnixStream, uid: libc::uid_t) -> bool {
+        let ino = match fd::inode(socket.as_raw_fd()) {
+            Some(i) => i,
+            None => return true,
+        };
+
+        let mut counted = self.counted.lock().unwrap();
+        let counted = &mut *counted;
+        let sockets = counted.users.entry(uid).or_default();
+        if *sockets >= self.max {
+            return false;
+        }
+
+        *sockets += 1;
+        counted.sockets.insert(ino, uid);
+        true
+    }
+
+    /// Release the socket with the provided inode, if it was counted
+    pub fn release(&self, ino: libc::ino_t) {
+        let mut counted = self.counted.lock().unwrap();
+
+        if let Some(uid) = counted.sockets.remove(&ino) {
+            if let Some(sockets) = counted.users.get_mut(&uid) {
+                *sockets -= 1;
+                if *sockets == 0 {
+                    counted.users.remove(&uid);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::{self, Error as IoError, Read, Write},
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::UnixStream,
+    },
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Readiness a task waits for on a socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Interest {
+    Readable,
+    Writable,
+}
+
+impl Interest {
+    fn events(self) -> libc::c_short {
+        match self {
+            Interest::Readable => libc::POLLIN,
+            Interest::Writable => libc::POLLOUT,
+        }
+    }
+}
+
+/// Task waiting for a socket to be ready
+struct Registration {
+    token: u64,
+    fd: RawFd,
+    interest: Interest,
+    waker: Waker,
+}
+
+/// Task waiting for a deadline
+struct Timer {
+    token: u64,
+    deadline: Instant,
+    waker: Waker,
+}
+
+#[derive(Default)]
+struct State {
+    registrations: Vec<Registration>,
+    timers: Vec<Timer>,
+}
+
+/// Thread polling the sockets that tasks wait for, waking them once ready, or once their deadline
+/// passed.
+///
+/// Registrations are one-shot: a woken task tries its operation again, and registers once more if
+/// it would still block. Since the sockets are polled level-triggered, a socket that became ready
+/// before its registration wakes the task right away.
+struct Reactor {
+    state: Mutex<State>,
+    /// Write end of the socket waking the thread up, so it polls the new registrations
+    wake: UnixStream,
+}
+
+static REACTOR: OnceLock<Result<Reactor, (io::ErrorKind, String)>> = OnceLock::new();
+static TOKENS: AtomicU64 = AtomicU64::new(0);
+
+/// Identity of an owner of registrations, such as a stream. Its registrations are removed when
+/// dropped, so a descriptor reused by another socket doesn't keep the wakers of a closed one.
+#[derive(Debug)]
+pub(crate) struct Source(u64);
+
+impl Default for Source {
+    fn default() -> Self {
+        Source(TOKENS.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Source {
+    /// Wake the task once the descriptor is ready for the interest, replacing the previous
+    /// registration of the source for it
+    pub fn register(&self, fd: RawFd, interest: Interest, waker: &Waker) -> Result<(), IoError> {
+        let reactor = reactor()?;
+
+        {
+            let registrations = &mut reactor.state.lock().unwrap().registrations;
+            match registrations
+                .iter_mut()
+                .find(|r| r.token == self.0 && r.interest == interest)
+            {
+                Some(r) => {
+                    r.fd = fd;
+                    if !r.waker.will_wake(waker) {
+                        r.waker = waker.clone();
+                    }
+                }
+                None => registrations.push(Registration {
+                    token: self.0,
+                    fd,
+                    interest,
+                    waker: waker.clone(),
+                }),
+            }
+        }
+
+        reactor.wake();
+        Ok(())
+    }
+
+    /// Check whether the descriptor is ready for the interest, or register the task to be woken
+    /// once it is
+    pub fn poll_ready(
+        &self,
+        fd: RawFd,
+        interest: Interest,
+        waker: &Waker,
+    ) -> Poll<Result<(), IoError>> {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: interest.events(),
+            revents: 0,
+        };
+
+        loop {
+            match unsafe { libc::poll(&mut pollfd, 1, 0) } {
+                0 => break,
+                n if n > 0 => return Poll::Ready(Ok(())),
+                _ => {
+                    let e = IoError::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Poll::Ready(Err(e));
+                    }
+                }
+            }
+        }
+
+        match self.register(fd, interest, waker) {
+            Ok(_) => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+
+    /// Wake the task once the deadline passed, replacing the previous deadline of the source
+    fn register_timer(&self, deadline: Instant, waker: &Waker) -> Result<(), IoError> {
+        let reactor = reactor()?;
+
+        {
+            let timers = &mut reactor.state.lock().unwrap().timers;
+            match timers.iter_mut().find(|t| t.token == self.0) {
+                Some(t) => {
+                    t.deadline = deadline;
+                    if !t.waker.will_wake(waker) {
+                        t.waker = waker.clone();
+                    }
+                }
+                None => timers.push(Timer {
+                    token: self.0,
+                    deadline,
+                    waker: waker.clone(),
+                }),
+            }
+        }
+
+        reactor.wake();
+        Ok(())
+    }
+}
+
+impl Drop for Source {
+    fn drop(&mut self) {
+        if let Some(Ok(reactor)) = REACTOR.get() {
+            let mut state = reactor.state.lock().unwrap();
+            state.registrations.retain(|r| r.token != self.0);
+            state.timers.retain(|t| t.token != self.0);
+        }
+    }
+}
+
+/// Future resolved once the deadline passed, without blocking the task polling it
+#[derive(Debug)]
+pub(crate) struct Sleep {
+    deadline: Instant,
+    source: Source,
+}
+
+impl Sleep {
+    pub fn until(deadline: Instant) -> Self {
+        Sleep {
+            deadline,
+            source: Source::default(),
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = Result<(), IoError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(Ok(()));
+        }
+
+        match self.source.register_timer(self.deadline, cx.waker()) {
+            Ok(_) => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+}
+
+/// Reactor of the process, started by the first registration
+fn reactor() -> Result<&'static Reactor, IoError> {
+    REACTOR
+        .get_or_init(|| Reactor::spawn().map_err(|e| (e.kind(), e.to_string())))
+        .as_ref()
+        .map_err(|(kind, message)| IoError::new(*kind, message.clone()))
+}
+
+impl Reactor {
+    fn spawn() -> Result<Self, IoError> {
+        let (wake, receiver) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        receiver.set_nonblocking(true)?;
+
+        thread::Builder::new()
+            .name("uds-reactor".to_string())
+            .spawn(move || run(&receiver))?;
+
+        Ok(Reactor {
+            state: Mutex::new(State::default()),
+            wake,
+        })
+    }
+
+    fn wake(&self) {
+        // A full buffer already has the thread woken up
+        match (&self.wake).write(&[0x00]) {
+            Err(e) if e.kind() != io::ErrorKind::WouldBlock => {
+                error!("Error waking the reactor up: {}", e)
+            }
+            _ => (),
+        }
+    }
+}
+
+fn run(receiver: &UnixStream) {
+    let mut fds = vec![];
+    let mut woken = vec![];
+
+    loop {
+        // The reactor is stored as soon as its constructor returns, right after spawning the
+        // thread, and nothing is registered before
+        let reactor = match REACTOR.get() {
+            Some(Ok(r)) => r,
+            _ => {
+                thread::yield_now();
+                continue;
+            }
+        };
+
+        fds.clear();
+        fds.push(libc::pollfd {
+            fd: receiver.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        });
+
+        let timeout = {
+            let state = reactor.state.lock().unwrap();
+
+            let mut events: HashMap<RawFd, libc::c_short> = HashMap::new();
+            for r in state.registrations.iter() {
+                *events.entry(r.fd).or_default() |= r.interest.events();
+            }
+
+            fds.extend(events.into_iter().map(|(fd, events)| libc::pollfd {
+                fd,
+                events,
+                revents: 0,
+            }));
+
+            // Rounded up, so the thread doesn't wake up right before a deadline
+            state
+                .timers
+                .iter()
+                .map(|t| t.deadline)
+                .min()
+                .map_or(-1, |d| {
+                    let remaining = d.saturating_duration_since(Instant::now());
+                    (remaining.as_nanos().div_ceil(1_000_000)).min(libc::c_int::MAX as u128)
+                        as libc::c_int
+                })
+        };
+
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } < 0 {
+            let e = IoError::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                error!("Error polling the sockets of the reactor: {}", e);
+                thread::sleep(Duration::from_millis(10));
+            }
+            continue;
+        }
+
+        if fds[0].revents != 0 {
+            let mut buffer = [0x00u8; 64];
+            while let Ok(n) = (&*receiver).read(&mut buffer) {
+                if n == 0 {
+                    break;
+                }
+            }
+        }
+
+        let ready: HashMap<RawFd, libc::c_short> = fds[1..]
+            .iter()
+            .filter(|p| p.revents != 0)
+            .map(|p| (p.fd, p.revents))
+            .collect();
+
+        let mut state = reactor.state.lock().unwrap();
+
+        let now = Instant::now();
+        state.timers.retain(|t| {
+            if t.deadline <= now {
+                woken.push(t.waker.clone());
+            }
+
+            t.deadline > now
+        });
+
+        // Errors, hang-ups and closed descriptors wake every interest, so the task observes them
+        state.registrations.retain(|r| {
+            let woke = ready.get(&r.fd).is_some_and(|revents| {
+                revents & (r.interest.events() | libc::POLLERR | libc::POLLHUP | libc::POLLNVAL)
+                    != 0
+            });
+
+            if woke {
+                woken.push(r.waker.clone());
+            }
+
+            !woke
+        });
+
+        drop(state);
+        woken.drain(..).for_each(Waker::wake);
+    }
+}
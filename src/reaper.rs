@@ -0,0 +1,109 @@
+use crate::{clock, fd::FdGuard, stats::Stats};
+
+use std::{
+    collections::HashMap,
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Half-close connections that outlive [`crate::Options::max_connection_age`].
+///
+/// Keeps a duplicate of every registered socket, so it can shut down the write side even while
+/// the provider is blocked in the poll. The duplicate must be released as soon as the connection
+/// ends, otherwise the peer would not observe the close.
+pub struct Reaper {
+    max_age: Duration,
+    stats: Arc<Stats>,
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    next: u64,
+    closed: bool,
+    connections: HashMap<u64, (Instant, UnixStream, FdGuard)>,
+}
+
+impl Reaper {
+    /// Create the reaper and spawn its timer thread
+    pub fn spawn(max_age: Duration, stats: Arc<Stats>) -> Arc<Self> {
+        let reaper = Arc::new(Reaper {
+            max_age,
+            stats,
+            state: Mutex::new(State::default()),
+            cond: Condvar::new(),
+        });
+
+        let r = Arc::clone(&reaper);
+        thread::spawn(move || r.run());
+
+        reaper
+    }
+
+    /// Start tracking the age of a socket. The returned id must be released when the connection
+    /// ends.
+    pub fn register(&self, stream: &UnixStream) -> Option<u64> {
+        let stream = stream
+            .try_clone()
+            .map_err(|e| error!("Error duplicating the socket to track its age: {}", e))
+            .ok()?;
+
+        let mut state = self.state.lock().unwrap();
+        let id = state.next;
+        state.next += 1;
+        let deadline = self.stats.clock.now() + self.max_age;
+        state
+            .connections
+            .insert(id, (deadline, stream, FdGuard::new(&self.stats)));
+        self.cond.notify_one();
+
+        Some(id)
+    }
+
+    /// Stop tracking a socket, closing the duplicate
+    pub fn release(&self, id: u64) {
+        self.state.lock().unwrap().connections.remove(&id);
+    }
+
+    /// Finish the timer thread and release all the tracked sockets
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.connections.clear();
+        self.cond.notify_one();
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        while !state.closed {
+            let now = self.stats.clock.now();
+
+            state.connections.retain(|id, (deadline, stream, _)| {
+                if *deadline > now {
+                    return true;
+                }
+
+                debug!("Connection {} reached the maximum age, closing", id);
+                stream.shutdown(Shutdown::Write).unwrap_or_else(|e| {
+                    error!("Error closing an expired connection: {}", e);
+                });
+
+                false
+            });
+
+            let next = state.connections.values().map(|(d, _, _)| *d).min();
+            state = match next {
+                Some(d) => {
+                    let timeout = clock::wait_until(&*self.stats.clock, d);
+                    self.cond.wait_timeout(state, timeout).unwrap().0
+                }
+                None => self.cond.wait(state).unwrap(),
+            };
+        }
+    }
+}
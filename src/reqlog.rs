@@ -0,0 +1,158 @@
+use crate::{codec, ListenerInfo, Message, PeerCreds, RequestLog};
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// Replacement of the redacted values
+const REDACTED: &str = "***";
+
+/// Traffic of a connection, recorded by its [`crate::IpcStream`] for [`RequestLog`]
+pub struct Recorder {
+    received: AtomicU64,
+    sent: AtomicU64,
+    /// First bytes received, up to [`RequestLog::capture`]
+    head: Mutex<Vec<u8>>,
+    capture: usize,
+}
+
+impl Recorder {
+    pub fn new(log: &RequestLog) -> Self {
+        Recorder {
+            received: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            head: Mutex::new(vec![]),
+            capture: log.capture,
+        }
+    }
+
+    pub fn received(&self, buf: &[u8]) {
+        self.received.fetch_add(buf.len() as u64, Ordering::Relaxed);
+
+        let mut head = self.head.lock().unwrap();
+        let n = self.capture.saturating_sub(head.len()).min(buf.len());
+        head.extend_from_slice(&buf[..n]);
+    }
+
+    pub fn sent(&self, n: usize) {
+        self.sent.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Log the connection once it finished
+    pub fn log(
+        &self,
+        log: &RequestLog,
+        listener: &ListenerInfo,
+        peer: Option<&PeerCreds>,
+        message: &Message,
+        elapsed: Duration,
+    ) {
+        let head = self.head.lock().unwrap();
+        let (kind, payload) = kind(&head);
+
+        info!(
+            "{} request on {:?} from {}: {} bytes in, {} bytes out, {:?}, {}, payload {:?}",
+            kind,
+            listener.path,
+            peer.map(|p| format!("uid {} pid {:?}", p.uid, p.pid))
+                .unwrap_or_else(|| "unknown peer".to_owned()),
+            self.received.load(Ordering::Relaxed),
+            self.sent.load(Ordering::Relaxed),
+            elapsed,
+            outcome(message),
+            redact(&String::from_utf8_lossy(payload), log.redact),
+        );
+    }
+}
+
+/// Kind of the request, told from its first bytes, and its payload
+fn kind(head: &[u8]) -> (&str, &[u8]) {
+    // An HTTP-like request starts with an uppercase method token
+    let method = head
+        .iter()
+        .position(|b| *b == b' ')
+        .map(|end| &head[..end])
+        .filter(|m| !m.is_empty() && m.len() <= 16 && m.iter().all(u8::is_ascii_uppercase));
+
+    if let Some(m) = method {
+        // Checked to be ASCII
+        return (std::str::from_utf8(m).unwrap_or("RAW"), head);
+    }
+
+    if head.len() >= 4 {
+        let len = u32::from_be_bytes([head[0], head[1], head[2], head[3]]);
+        let error = len & codec::ERROR_FLAG != 0;
+        let len = (len & !codec::ERROR_FLAG) as usize;
+
+        if len <= codec::MAX_FRAME_SIZE {
+            let kind = if error { "ERROR-FRAME" } else { "FRAME" };
+            return (kind, &head[4..]);
+        }
+    }
+
+    ("RAW", head)
+}
+
+fn outcome(message: &Message) -> &'static str {
+    match message {
+        Message::Success => "success",
+        Message::Error => "error",
+        Message::Detached => "detached",
+        Message::ShouldQuit => "quit",
+        _ => "finished",
+    }
+}
+
+/// Replace the values of the provided fields, written either as `"field": value` or as
+/// `field=value`
+fn redact(payload: &str, fields: &[&str]) -> String {
+    let mut payload = payload.to_owned();
+
+    for field in fields {
+        let quoted = format!("\"{}\"", field);
+        payload = redact_after(&payload, &quoted, ':', |c| c == ',' || c == '}');
+
+        let assigned = field.to_string();
+        payload = redact_after(&payload, &assigned, '=', |c| {
+            c == '&' || c == ';' || c == ',' || c.is_whitespace()
+        });
+    }
+
+    payload
+}
+
+/// Replace the value following every occurrence of `key` and the separator, up to the end
+/// predicate. Quoted values are replaced up to their closing quote
+fn redact_after<F: Fn(char) -> bool>(payload: &str, key: &str, separator: char, end: F) -> String {
+    let mut redacted = String::with_capacity(payload.len());
+    let mut rest = payload;
+
+    while let Some(i) = rest.find(key) {
+        let (before, after) = rest.split_at(i + key.len());
+        redacted.push_str(before);
+
+        let value = after.trim_start();
+        let value = match value.strip_prefix(separator) {
+            Some(v) => v.trim_start(),
+            None => {
+                rest = after;
+                continue;
+            }
+        };
+
+        redacted.push_str(&after[..after.len() - value.len()]);
+        redacted.push_str(REDACTED);
+
+        rest = match value.strip_prefix('"') {
+            Some(quoted) => quoted.find('"').map(|e| &quoted[e + 1..]).unwrap_or(""),
+            None => value.find(&end).map(|e| &value[e..]).unwrap_or(""),
+        };
+    }
+
+    redacted.push_str(rest);
+    redacted
+}
@@ -0,0 +1,148 @@
+//! Zero-downtime restart, handing the bound listener over to a new process.
+//!
+//! The old process calls [`handover`], which spawns the new binary with the listener descriptor
+//! inherited, and then drains its own workers. The new process calls
+//! [`crate::UnixDomainSocket::from_handover`] to adopt the listener instead of binding the path
+//! again, so no client is refused in between.
+
+use crate::{Address, Message, ServerHandle, Task};
+
+use std::{
+    env,
+    ffi::OsStr,
+    fs::File,
+    io::{self, Error as IoError, Read, Write},
+    os::unix::{
+        io::{AsRawFd, FromRawFd, RawFd},
+        net::UnixListener,
+    },
+    process::{Child, Command},
+};
+
+/// Environment variable with the descriptor of the pipe carrying the handover state
+pub const HANDOVER_ENV: &str = "DUSK_UDS_HANDOVER_FD";
+
+/// Hand the listener of the provided server over to a new process, and drain the old one.
+///
+/// The new process is spawned from `exec_path` and `args`, inheriting the listener and a pipe
+/// with the handover state: the listener descriptor and the bound path. Once the child is
+/// spawned, the old server stops accepting, finishes the sockets already queued or in progress,
+/// and its workers are joined. The socket path is not removed.
+///
+/// While the listener is made inheritable, any other process spawned concurrently by the
+/// application will inherit it as well.
+pub fn handover<P, I, S>(handle: ServerHandle, exec_path: P, args: I) -> Result<Child, IoError>
+where
+    P: AsRef<OsStr>,
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let (address, listener) = handle
+        .detach_listener()
+        .ok_or_else(|| IoError::other("The UDS has no listener to hand over"))?;
+    let path = address
+        .to_str()
+        .ok_or_else(|| IoError::other("Invalid path returned by the buffer"))?;
+
+    let (read, mut write) = pipe()?;
+
+    set_inheritable(listener.as_raw_fd(), true)?;
+    set_inheritable(read.as_raw_fd(), true)?;
+
+    let child = Command::new(exec_path)
+        .args(args)
+        .env(HANDOVER_ENV, read.as_raw_fd().to_string())
+        .spawn();
+
+    set_inheritable(listener.as_raw_fd(), false)?;
+    drop(read);
+    let child = child?;
+
+    write.write_all(format!("{}\n{}\n", listener.as_raw_fd(), path).as_bytes())?;
+    drop(write);
+    drop(listener);
+    info!(
+        "UDS listener of {} handed over to process {}",
+        path,
+        child.id()
+    );
+
+    // Queued after the pending sockets, so they are still handled by this process
+    handle
+        .task_sender()
+        .send(Task::Message(Message::ShouldQuit))?;
+    handle.join()?;
+
+    Ok(child)
+}
+
+/// Adopt the listener handed over by the parent process, if any
+pub(crate) fn inherited() -> Result<Option<(Address, UnixListener)>, IoError> {
+    let fd = match env::var(HANDOVER_ENV) {
+        Ok(fd) => fd,
+        Err(_) => return Ok(None),
+    };
+
+    // Grandchildren should not try to adopt it again
+    env::remove_var(HANDOVER_ENV);
+
+    let fd: RawFd = fd
+        .parse()
+        .map_err(|_| IoError::new(io::ErrorKind::InvalidData, "Invalid handover descriptor"))?;
+
+    let mut state = String::new();
+    unsafe { File::from_raw_fd(fd) }.read_to_string(&mut state)?;
+
+    let mut lines = state.lines();
+    let listener: RawFd = lines
+        .next()
+        .and_then(|l| l.parse().ok())
+        .ok_or_else(|| IoError::new(io::ErrorKind::InvalidData, "Invalid handover state"))?;
+    let address = lines
+        .next()
+        .map(Address::from)
+        .ok_or_else(|| IoError::new(io::ErrorKind::InvalidData, "Invalid handover state"))?;
+
+    set_inheritable(listener, false)?;
+    info!(
+        "UDS listener of {} adopted from the parent process",
+        address
+    );
+
+    Ok(Some((address, unsafe {
+        UnixListener::from_raw_fd(listener)
+    })))
+}
+
+fn pipe() -> Result<(File, File), IoError> {
+    let mut fds: [RawFd; 2] = [-1; 2];
+
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    let (read, write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    set_inheritable(fds[0], false)?;
+    set_inheritable(fds[1], false)?;
+
+    Ok((read, write))
+}
+
+fn set_inheritable(fd: RawFd, inheritable: bool) -> Result<(), IoError> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    let flags = if inheritable {
+        flags & !libc::FD_CLOEXEC
+    } else {
+        flags | libc::FD_CLOEXEC
+    };
+
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use crate::{clock, fd::FdGuard, stats::Stats, PeerChange, PeerCreds, PeerRevalidation};
+
+use std::{
+    collections::HashMap,
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::{Arc, Mutex, Weak},
+    thread,
+};
+
+/// Check periodically the identity of the peers of the connections in progress, reporting the
+/// ones that exited or replaced their executable. See [`crate::Options::peer_revalidation`].
+///
+/// Keeps a duplicate of every registered socket, handed to the callback. The timer thread ends
+/// once the revalidator is dropped by every worker.
+pub struct Revalidator {
+    policy: PeerRevalidation,
+    stats: Arc<Stats>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    next: u64,
+    connections: HashMap<u64, Tracked>,
+}
+
+struct Tracked {
+    stream: UnixStream,
+    creds: PeerCreds,
+    pid: libc::pid_t,
+    identity: Identity,
+    _fd: FdGuard,
+}
+
+/// Identity of a running process, to tell it apart from a later process reusing its pid
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Identity {
+    /// Start time of the process, in clock ticks since boot
+    started: Option<u64>,
+    /// Executable of the process
+    exe: Option<PathBuf>,
+}
+
+impl Revalidator {
+    /// Create the revalidator and spawn its timer thread
+    pub fn spawn(policy: PeerRevalidation, stats: Arc<Stats>) -> Arc<Self> {
+        let revalidator = Arc::new(Revalidator {
+            policy,
+            stats,
+            state: Mutex::new(State::default()),
+        });
+
+        let r = Arc::downgrade(&revalidator);
+        thread::spawn(move || Revalidator::run(r, policy));
+
+        revalidator
+    }
+
+    /// Start tracking the peer of a socket. The returned id must be released when the connection
+    /// ends. Peers whose pid is not reported by the platform can't be tracked.
+    pub fn register(&self, stream: &UnixStream, creds: &PeerCreds) -> Option<u64> {
+        let pid = creds.pid?;
+        let identity = identity(pid)?;
+        let stream = stream
+            .try_clone()
+            .map_err(|e| error!("Error duplicating the socket to revalidate its peer: {}", e))
+            .ok()?;
+
+        let mut state = self.state.lock().unwrap();
+        let id = state.next;
+        state.next += 1;
+        state.connections.insert(
+            id,
+            Tracked {
+                stream,
+                creds: *creds,
+                pid,
+                identity,
+                _fd: FdGuard::new(&self.stats),
+            },
+        );
+
+        Some(id)
+    }
+
+    /// Stop tracking a connection
+    pub fn release(&self, id: u64) {
+        self.state.lock().unwrap().connections.remove(&id);
+    }
+
+    fn run(revalidator: Weak<Self>, policy: PeerRevalidation) {
+        let mut next = None;
+
+        loop {
+            let r = match revalidator.upgrade() {
+                Some(r) => r,
+                None => return,
+            };
+
+            let now = r.stats.clock.now();
+            let due = *next.get_or_insert(now + policy.interval);
+            let wait = clock::wait_until(&*r.stats.clock, due);
+
+            if wait.is_zero() {
+                r.check();
+                next = Some(now + policy.interval);
+                continue;
+            }
+
+            // The revalidator is not kept alive while sleeping
+            drop(r);
+            thread::sleep(wait);
+        }
+    }
+
+    fn check(&self) {
+        let mut changed = vec![];
+
+        self.state
+            .lock()
+            .unwrap()
+            .connections
+            .retain(|_, tracked| match change(tracked) {
+                Some(c) => {
+                    changed.push((c, tracked.creds, tracked.stream.try_clone()));
+                    false
+                }
+                None => true,
+            });
+
+        // The callback is invoked without the lock, so it may take its time
+        for (change, creds, stream) in changed {
+            debug!("Peer {:?} of a connection changed: {:?}", creds, change);
+
+            match stream {
+                Ok(s) => (self.policy.on_change)(&s, &creds, change),
+                Err(e) => error!("Error duplicating the socket of a changed peer: {}", e),
+            }
+        }
+    }
+}
+
+fn change(tracked: &Tracked) -> Option<PeerChange> {
+    match identity(tracked.pid) {
+        None => Some(PeerChange::Exited),
+        // The pid was reused by another process
+        Some(i) if i.started != tracked.identity.started => Some(PeerChange::Exited),
+        Some(i) if i.exe != tracked.identity.exe => Some(PeerChange::ExeChanged),
+        Some(_) => None,
+    }
+}
+
+/// Identity of the process, or `None` if it is not running
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn identity(pid: libc::pid_t) -> Option<Identity> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+    // The name of the process may contain spaces, so the fields are counted after it. The start
+    // time is the 22nd field, and the 20th after the name
+    let started = stat
+        .rsplit_once(')')
+        .and_then(|(_, fields)| fields.split_whitespace().nth(19))
+        .and_then(|s| s.parse().ok());
+
+    // Not readable for the processes of other users, unless privileged
+    let exe = std::fs::read_link(format!("/proc/{}/exe", pid)).ok();
+
+    Some(Identity { started, exe })
+}
+
+/// Identity of the process, or `None` if it is not running
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn identity(pid: libc::pid_t) -> Option<Identity> {
+    let alive = unsafe { libc::kill(pid, 0) } == 0
+        || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+
+    alive.then_some(Identity {
+        started: None,
+        exe: None,
+    })
+}
@@ -0,0 +1,91 @@
+use crate::{tenants::TenantRoutes, Message, PeerCreds, TaskProvider};
+
+use std::os::unix::net::UnixStream;
+
+/// Providers of a worker, selected by the listener that accepted the socket or by the credentials
+/// of the peer.
+///
+/// When the worker serves tenants, the provider of the tenant is selected and the remaining rules
+/// are ignored. Otherwise, rules registered by uid take precedence over the ones registered by
+/// gid. Sockets matching no rule, or whose credentials can't be fetched, are handled by the
+/// default provider.
+#[derive(Clone)]
+pub struct Routes<T> {
+    pub default: Option<T>,
+    pub uid: Vec<(libc::uid_t, T)>,
+    pub gid: Vec<(libc::gid_t, T)>,
+    pub named: Vec<(String, T)>,
+    pub tenants: Option<TenantRoutes<T>>,
+}
+
+impl<T: TaskProvider> Routes<T> {
+    pub fn new(default: T) -> Self {
+        Routes {
+            default: Some(default),
+            uid: vec![],
+            gid: vec![],
+            named: vec![],
+            tenants: None,
+        }
+    }
+
+    /// Routes of a worker serving only tenants
+    pub fn tenants(tenants: TenantRoutes<T>) -> Self {
+        Routes {
+            default: None,
+            uid: vec![],
+            gid: vec![],
+            named: vec![],
+            tenants: Some(tenants),
+        }
+    }
+
+    /// Provider that should handle the socket, if any
+    pub fn select(&mut self, stream: &UnixStream, peer: Option<&PeerCreds>) -> Option<&T> {
+        match self.tenants {
+            Some(ref mut tenants) => tenants.select(stream),
+            None => self.select_by_creds(peer),
+        }
+    }
+
+    fn select_by_creds(&self, peer: Option<&PeerCreds>) -> Option<&T> {
+        if self.uid.is_empty() && self.gid.is_empty() {
+            return self.default.as_ref();
+        }
+
+        let creds = match peer {
+            Some(c) => c,
+            None => return self.default.as_ref(),
+        };
+
+        self.uid
+            .iter()
+            .find(|(uid, _)| *uid == creds.uid)
+            .or_else(|| self.gid.iter().find(|(gid, _)| *gid == creds.gid))
+            .map(|(_, p)| p)
+            .or(self.default.as_ref())
+    }
+
+    /// Provider registered with the provided name, target of [`Message::Handoff`]
+    pub fn named(&self, name: &str) -> Option<&T> {
+        self.named.iter().find(|(n, _)| n == name).map(|(_, p)| p)
+    }
+
+    /// Deliver the message to every provider
+    pub fn handle_message(&mut self, message: &Message) {
+        if let Some(p) = self.default.as_mut() {
+            p.handle_message(message);
+        }
+
+        if let Some(tenants) = self.tenants.as_mut() {
+            tenants.handle_message(message);
+        }
+
+        self.uid
+            .iter_mut()
+            .chain(self.gid.iter_mut())
+            .map(|(_, p)| p)
+            .chain(self.named.iter_mut().map(|(_, p)| p))
+            .for_each(|p| p.handle_message(message));
+    }
+}
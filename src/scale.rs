@@ -0,0 +1,55 @@
+use crate::{clock, queue::Queue, stats::Stats, ScalingPolicy, ServerEvent};
+
+use std::{sync::Arc, thread, time::Duration};
+
+/// Spawn a thread that grows the pool while the sockets of group 0 wait for a worker, calling
+/// `spawn` with the slot of every new worker. See [`crate::Options::max_workers`].
+///
+/// The idle workers retire themselves. The thread ends once the workers are asked to quit,
+/// dropping `spawn` and what it holds.
+pub fn spawn<F>(policy: ScalingPolicy, queue: Arc<Queue>, stats: Arc<Stats>, spawn: F)
+where
+    F: Fn(usize) + Send + 'static,
+{
+    // Sampled a few times per period, so a short drop of the queue resets the period
+    let period = (policy.scale_up_after / 4).max(Duration::from_millis(1));
+
+    thread::spawn(move || {
+        let mut deep_since = None;
+
+        loop {
+            thread::sleep(clock::wait_for(&*stats.clock, period));
+
+            if queue.is_quitting() {
+                return;
+            }
+
+            let queued = queue.queued_in(0);
+            if queued <= policy.queue_depth {
+                deep_since = None;
+                continue;
+            }
+
+            let now = stats.clock.now();
+            let since = *deep_since.get_or_insert(now);
+            if now.saturating_duration_since(since) < policy.scale_up_after {
+                continue;
+            }
+
+            // Measured again from now, so the new worker has time to drain the queue
+            deep_since = None;
+
+            if let Some(worker) = queue.enlist() {
+                info!(
+                    "{} sockets waiting for a worker, spawning worker {}",
+                    queued, worker
+                );
+
+                // Before the event, so its subscribers see the worker running in the stats
+                stats.workers[worker].set_running(true);
+                stats.events.emit(|| ServerEvent::WorkerSpawned { worker });
+                spawn(worker);
+            }
+        }
+    });
+}
@@ -0,0 +1,130 @@
+use std::{
+    any::Any,
+    env, fs,
+    io::{self, Error as IoError},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+};
+
+/// Sequence used to name the scratch directories of the process
+static NEXT_DIR: AtomicU64 = AtomicU64::new(0);
+
+/// Resources owned by a single connection, released once it ends.
+///
+/// Handed to the provider with [`crate::TaskProvider::set_scratch`] when
+/// [`crate::Options::connection_scratch`] is set. The worker keeps its own reference, and releases
+/// everything as soon as the provider future is dropped, however it ends: resolved, cancelled by a
+/// shutdown or unwound by a panic. Clones of the handle kept by the provider don't extend the
+/// lifetime of the resources.
+#[derive(Clone)]
+pub struct Scratch(Arc<Mutex<Resources>>);
+
+#[derive(Default)]
+struct Resources {
+    closed: bool,
+    dir: Option<PathBuf>,
+    paths: Vec<PathBuf>,
+    held: Vec<Box<dyn Any + Send>>,
+}
+
+impl Scratch {
+    /// Temporary directory of the connection, created on the first call and removed recursively
+    /// when the connection ends
+    pub fn dir(&self) -> Result<PathBuf, IoError> {
+        let mut resources = self.lock();
+        if resources.closed {
+            return Err(IoError::other(
+                "The connection of the scratch already ended",
+            ));
+        }
+
+        if let Some(dir) = resources.dir.as_ref() {
+            return Ok(dir.clone());
+        }
+
+        let dir = env::temp_dir().join(format!(
+            "dusk-uds-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir(&dir)?;
+
+        Ok(resources.dir.insert(dir).clone())
+    }
+
+    /// Remove the file or directory at the provided path when the connection ends
+    pub fn remove_on_close<P: Into<PathBuf>>(&self, path: P) {
+        let path = path.into();
+
+        let mut resources = self.lock();
+        if resources.closed {
+            drop(resources);
+            remove(&path);
+        } else {
+            resources.paths.push(path);
+        }
+    }
+
+    /// Keep the provided resource, such as a file handle, until the connection ends
+    pub fn hold<R: Send + 'static>(&self, resource: R) {
+        let mut resources = self.lock();
+        if !resources.closed {
+            resources.held.push(Box::new(resource));
+        }
+    }
+
+    /// Release every registered resource. The handles are dropped before the paths are removed
+    pub(crate) fn close(&self) {
+        let (held, paths, dir) = {
+            let mut resources = self.lock();
+            resources.closed = true;
+
+            (
+                std::mem::take(&mut resources.held),
+                std::mem::take(&mut resources.paths),
+                resources.dir.take(),
+            )
+        };
+
+        drop(held);
+        paths.iter().chain(dir.iter()).for_each(|p| remove(p));
+    }
+
+    // Resources must be released even after a provider panicked while holding the lock
+    fn lock(&self) -> MutexGuard<'_, Resources> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for Scratch {
+    fn default() -> Self {
+        Scratch(Arc::new(Mutex::new(Resources::default())))
+    }
+}
+
+fn remove(path: &Path) {
+    let result = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+
+    match result {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            error!("Error removing the scratch path {}: {}", path.display(), e);
+        }
+        _ => (),
+    }
+}
+
+/// Owned by the worker, closes the scratch of a connection when dropped
+pub struct ScratchGuard(pub Scratch);
+
+impl Drop for ScratchGuard {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{
+    listener::{self, Acceptor, BindSettings},
+    routes::Routes,
+    signals::SignalGuard,
+    tenants::{Registry, TenantRoutes},
+    uds, validate, warmup, Address, CancelToken, Options, ServerHandle, ShutdownHandle,
+    ShutdownReason, TaskProvider, UdsError,
+};
+
+use std::{
+    fs,
+    io::{self, Error as IoError},
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// Background task registered with [`ServerBuilder::task`]
+type BackgroundTask = Box<dyn FnOnce(CancelToken) + Send + 'static>;
+
+/// Builder of a daemon serving several listeners, such as a data socket and an admin socket,
+/// from a single worker pool.
+///
+/// Every listener has its own provider, selected by the path the socket was accepted on. State
+/// shared by the listeners is shared by their providers, for instance behind an [`Arc`] cloned
+/// into each of them. Only unix domain sockets are supported.
+pub struct ServerBuilder<T: TaskProvider + 'static> {
+    options: Options,
+    listeners: Vec<(PathBuf, T)>,
+    tasks: Vec<(String, BackgroundTask)>,
+    signals: bool,
+}
+
+impl<T: TaskProvider> ServerBuilder<T> {
+    /// Builder with no listener
+    pub fn new(options: Option<Options>) -> Self {
+        ServerBuilder {
+            options: options.unwrap_or_default(),
+            listeners: vec![],
+            tasks: vec![],
+            signals: false,
+        }
+    }
+
+    /// Bind the path once built, handling its sockets with the provided provider
+    pub fn listener<P: Into<PathBuf>>(mut self, path: P, provider: T) -> Self {
+        self.listeners.push((path.into(), provider));
+        self
+    }
+
+    /// Run a background task in the [`crate::TaskGroup`] of the server, cancelled and joined on
+    /// shutdown
+    pub fn task<N, F>(mut self, name: N, task: F) -> Self
+    where
+        N: Into<String>,
+        F: FnOnce(CancelToken) + Send + 'static,
+    {
+        self.tasks.push((name.into(), Box::new(task)));
+        self
+    }
+
+    /// Shut the server down gracefully on `SIGTERM` and `SIGINT` while [`Server::run`] is
+    /// running. The previous handlers are restored once it returns. Same as
+    /// [`Options::handle_signals`]
+    pub fn handle_signals(mut self) -> Self {
+        self.signals = true;
+        self
+    }
+
+    /// Validate and bind every listener, run the warm-up, then spawn the workers and start
+    /// accepting
+    pub fn build(self) -> Result<Server, UdsError> {
+        if self.listeners.is_empty() {
+            return Err(
+                IoError::new(io::ErrorKind::InvalidInput, "The server has no listener").into(),
+            );
+        }
+
+        for (i, (path, _)) in self.listeners.iter().enumerate() {
+            if self.listeners[..i].iter().any(|(p, _)| p == path) {
+                return Err(IoError::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The path {} is bound twice", path.display()),
+                )
+                .into());
+            }
+
+            if let Some(validation) = self.options.validation {
+                validate::enforce(
+                    validate::validate(Some(path.as_path()), &self.options),
+                    validation,
+                )?;
+            }
+        }
+
+        let settings = BindSettings::of(&self.options);
+        let mut bound = vec![];
+        for (path, _) in self.listeners.iter() {
+            match listener::bind(&Address::Path(path.clone()), &settings) {
+                Ok(l) => bound.push(l),
+                Err(e) => {
+                    self.listeners[..bound.len()]
+                        .iter()
+                        .for_each(|(p, _)| fs::remove_file(p).unwrap_or_default());
+                    return Err(e);
+                }
+            }
+        }
+
+        if let Some(warm_up) = self.options.warm_up.as_ref() {
+            warmup::run(warm_up, self.options.warm_up_timeout)?;
+            info!("UDS warm-up finished");
+        }
+
+        let registry = Arc::new(Registry::default());
+        let routes = Routes::tenants(TenantRoutes::new(Arc::clone(&registry)));
+        let handle = uds::spawn_workers(&self.options, &routes);
+
+        let mut server = Server {
+            shutdown: handle.shutdown_handle(),
+            handle,
+            acceptors: vec![],
+            signals: self.signals || self.options.handle_signals,
+        };
+
+        for ((path, provider), listener) in self.listeners.into_iter().zip(bound) {
+            registry.insert(path.clone(), provider);
+
+            let address = Address::Path(path.clone());
+            match Acceptor::spawn(listener, address, server.handle.accept_context()) {
+                Ok(acceptor) => server.acceptors.push(acceptor),
+                Err(e) => {
+                    fs::remove_file(path.as_path()).unwrap_or_default();
+                    return Err(server.abort(e.into()));
+                }
+            }
+        }
+
+        let tasks = server.handle.task_group();
+        for (name, task) in self.tasks {
+            if let Err(e) = tasks.spawn(name, task) {
+                return Err(server.abort(e.into()));
+            }
+        }
+
+        Ok(server)
+    }
+}
+
+/// Daemon built with a [`ServerBuilder`]
+pub struct Server {
+    handle: ServerHandle,
+    shutdown: ShutdownHandle,
+    acceptors: Vec<Acceptor>,
+    signals: bool,
+}
+
+impl Server {
+    /// Handle to the workers, to push tasks or take a snapshot of their counters
+    pub fn handle(&self) -> &ServerHandle {
+        &self.handle
+    }
+
+    /// Handle to stop the server from another thread
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Stop the server that failed to start, returning the error
+    fn abort(mut self, e: UdsError) -> UdsError {
+        self.signals = false;
+        self.shutdown
+            .request_for(ShutdownReason::Fatal(e.to_string()));
+        self.run().unwrap_or_default();
+        e
+    }
+
+    /// Block until the server is stopped, by a [`ShutdownHandle`], a provider resolving to
+    /// [`crate::Message::ShouldQuit`] or a signal if handled.
+    ///
+    /// Then the background tasks are cancelled and joined, and the listeners are closed and their
+    /// paths removed.
+    pub fn run(self) -> Result<(), UdsError> {
+        let _signals = if self.signals {
+            let shutdown = self.shutdown.clone();
+            Some(SignalGuard::install(move |signal| {
+                shutdown.request_for(ShutdownReason::Signal(signal))
+            })?)
+        } else {
+            None
+        };
+
+        self.handle.wait_workers();
+
+        let timeout = self.handle.accept_shutdown_timeout();
+        let mut result = Ok(());
+
+        for acceptor in self.acceptors {
+            let address = acceptor.address().clone();
+
+            if let Err(e) = acceptor
+                .shutdown(timeout)
+                .and_then(|_| address.remove_file().map_err(UdsError::from))
+            {
+                error!("Error closing the listener {}: {}", address, e);
+                result = Err(e);
+            }
+        }
+
+        let result = self.handle.join().and(result);
+        self.shutdown
+            .finish(result.as_ref().map(|_| ()).map_err(UdsError::duplicate));
+
+        result
+    }
+}
@@ -0,0 +1,601 @@
+use crate::{
+    codec::{self, ErrorCode, ErrorFrame},
+    IpcStream, PeerCreds, UnixDomainClient,
+};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryFrom,
+    fmt,
+    fs::File,
+    io::{self, Error as IoError, Read, Write},
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Default number of unacknowledged frames kept for replay, per session
+pub const REPLAY_CAPACITY: usize = 64;
+
+/// Time a resumption waits for the previous connection of the session to let it go
+const RESUME_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Length of the sequence and acknowledgement numbers heading every frame of a session
+const HEADER_LEN: usize = 16;
+
+/// Frames of a logical stream, numbered so they survive a reconnection.
+///
+/// Every frame is headed by its sequence number, starting at 1, and by the last sequence number
+/// received in order from the peer. A frame with sequence number 0 only carries the
+/// acknowledgement. The sent frames are kept until acknowledged, and written again on
+/// resumption; the frames received twice are dropped.
+struct Sequence {
+    /// Sequence number of the next frame sent
+    next: u64,
+    /// Sequence number of the last frame received in order
+    received: u64,
+    unacked: VecDeque<(u64, Vec<u8>)>,
+    /// Frames received and acknowledged, but not yet taken
+    inbox: VecDeque<Vec<u8>>,
+    capacity: usize,
+    max_frame: usize,
+}
+
+impl Sequence {
+    fn new(capacity: usize, max_frame: usize) -> Self {
+        Sequence {
+            next: 1,
+            received: 0,
+            unacked: VecDeque::new(),
+            inbox: VecDeque::new(),
+            capacity: capacity.max(1),
+            max_frame,
+        }
+    }
+
+    /// Forget the frames the peer didn't receive, and write them again
+    fn resume<S: Write>(&mut self, stream: &mut S, peer_received: u64) -> Result<(), IoError> {
+        if peer_received >= self.next {
+            return Err(IoError::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The peer acknowledged frame {} of the session, never sent",
+                    peer_received
+                ),
+            ));
+        }
+
+        self.acknowledged(peer_received);
+        for (seq, payload) in self.unacked.iter() {
+            write_frame(stream, *seq, self.received, payload)?;
+        }
+
+        Ok(())
+    }
+
+    /// Queue the payload for replay, then write it. Blocks reading the peer while the replay
+    /// buffer is full, until it acknowledges some frames
+    fn send<S: Read + Write>(&mut self, stream: &mut S, payload: &[u8]) -> Result<(), IoError> {
+        while self.unacked.len() >= self.capacity {
+            self.read(stream)?;
+        }
+
+        let seq = self.next;
+        self.next += 1;
+        self.unacked.push_back((seq, payload.to_vec()));
+
+        write_frame(stream, seq, self.received, payload)
+    }
+
+    /// Take the next frame received in order
+    fn recv<S: Read + Write>(&mut self, stream: &mut S) -> Result<Vec<u8>, IoError> {
+        loop {
+            if let Some(payload) = self.inbox.pop_front() {
+                return Ok(payload);
+            }
+
+            self.read(stream)?;
+        }
+    }
+
+    /// Read a frame, queuing and acknowledging it if it's the next one in order
+    fn read<S: Read + Write>(&mut self, stream: &mut S) -> Result<(), IoError> {
+        let mut frame = codec::read_frame(stream, self.max_frame.saturating_add(HEADER_LEN))?;
+        let (seq, ack) = decode_header(&frame)?;
+        frame.drain(..HEADER_LEN);
+
+        if ack >= self.next {
+            return Err(IoError::new(
+                io::ErrorKind::InvalidData,
+                format!("The peer acknowledged frame {}, never sent", ack),
+            ));
+        }
+        self.acknowledged(ack);
+
+        if seq == 0 || seq <= self.received {
+            // Acknowledgement only, or frame replayed after it was received
+            return Ok(());
+        } else if seq != self.received + 1 {
+            return Err(IoError::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Frame {} of the session received after frame {}",
+                    seq, self.received
+                ),
+            ));
+        }
+
+        self.received = seq;
+        self.inbox.push_back(frame);
+
+        write_frame(stream, 0, self.received, &[])
+    }
+
+    fn acknowledged(&mut self, ack: u64) {
+        while self.unacked.front().map(|(seq, _)| *seq <= ack) == Some(true) {
+            self.unacked.pop_front();
+        }
+    }
+}
+
+fn write_frame<W: Write>(
+    writer: &mut W,
+    seq: u64,
+    ack: u64,
+    payload: &[u8],
+) -> Result<(), IoError> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&seq.to_be_bytes());
+    frame.extend_from_slice(&ack.to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    codec::write_frame(writer, &frame)
+}
+
+fn decode_header(frame: &[u8]) -> Result<(u64, u64), IoError> {
+    match (
+        frame.get(..8).and_then(|s| <[u8; 8]>::try_from(s).ok()),
+        frame.get(8..16).and_then(|s| <[u8; 8]>::try_from(s).ok()),
+    ) {
+        (Some(seq), Some(ack)) => Ok((u64::from_be_bytes(seq), u64::from_be_bytes(ack))),
+        _ => Err(IoError::new(
+            io::ErrorKind::InvalidData,
+            "Session frame without sequence numbers",
+        )),
+    }
+}
+
+/// Sessions of the connections of a UDS, resumed by the clients reconnecting with their token.
+///
+/// A provider hands its stream to [`SessionStore::accept`], and exchanges the frames of the
+/// logical stream through the returned [`Session`]. The frames not acknowledged by the client
+/// are kept, up to the replay capacity, and written again once it resumes the session from
+/// another connection. Together with [`SessionClient`], every frame is then delivered once and
+/// in order, for as long as the session is kept.
+///
+/// A session is only resumed by a peer of the same user. Past `max_sessions`, the sessions
+/// detached the longest ago are forgotten, and their clients get an error when resuming.
+///
+/// Clones share the sessions.
+#[derive(Clone)]
+pub struct SessionStore {
+    max_sessions: usize,
+    capacity: usize,
+    max_frame: usize,
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    sessions: Mutex<Sessions>,
+    /// Notified when a session is detached, for the resumptions waiting for it
+    detached: Condvar,
+}
+
+#[derive(Default)]
+struct Sessions {
+    map: HashMap<u64, Slot>,
+    /// Tokens of the detached sessions, the least recently detached first
+    detached: VecDeque<u64>,
+}
+
+struct Slot {
+    owner: libc::uid_t,
+    /// Taken by the session while attached to a connection
+    sequence: Option<Sequence>,
+    /// Clone of the attached connection, shut down to let a resumption take over
+    attached: Option<UnixStream>,
+}
+
+impl SessionStore {
+    /// Store of up to `max_sessions` sessions, each replaying up to [`REPLAY_CAPACITY`] frames
+    pub fn new(max_sessions: usize) -> Self {
+        SessionStore {
+            max_sessions: max_sessions.max(1),
+            capacity: REPLAY_CAPACITY,
+            max_frame: codec::MAX_FRAME_SIZE,
+            shared: Arc::default(),
+        }
+    }
+
+    /// Keep up to `capacity` unacknowledged frames per session, at least one. Sending past it
+    /// blocks until the client acknowledges some. Defaults to [`REPLAY_CAPACITY`]
+    pub fn replay_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Reject the frames longer than `max` bytes. Defaults to [`codec::MAX_FRAME_SIZE`]
+    pub fn max_frame(mut self, max: usize) -> Self {
+        self.max_frame = max;
+        self
+    }
+
+    /// Number of sessions kept, attached or not
+    pub fn sessions(&self) -> usize {
+        self.shared.sessions.lock().unwrap().map.len()
+    }
+
+    /// Read the handshake of the client, then start or resume its session.
+    ///
+    /// On resumption, the frames the client didn't receive are written again. If the session is
+    /// still attached to another connection, that one is shut down, and its [`Session`] fails
+    /// once used. An unknown token, or one of another user, starts a new session.
+    pub fn accept(&self, mut stream: IpcStream) -> Result<Session, IoError> {
+        let owner = PeerCreds::from_stream(&stream)?.uid;
+        let (token, received) = decode_header(&codec::read_frame(&mut stream, HEADER_LEN)?)?;
+
+        let (token, sequence, resumed) = match self.resume(token, owner)? {
+            Some(sequence) => (token, sequence, true),
+            None => match self.open(owner) {
+                Ok(token) => (token, Sequence::new(self.capacity, self.max_frame), false),
+                Err(e) => {
+                    let error = ErrorFrame::new(ErrorCode::OVERLOADED, e.to_string());
+                    codec::write_error(&mut stream, &error).unwrap_or_default();
+                    return Err(e);
+                }
+            },
+        };
+
+        if let Some(slot) = self.shared.sessions.lock().unwrap().map.get_mut(&token) {
+            slot.attached = stream.try_clone().ok();
+        }
+
+        // Detached again on drop, whatever fails next
+        let mut session = Session {
+            shared: Arc::clone(&self.shared),
+            token,
+            resumed,
+            sequence: Some(sequence),
+            stream,
+        };
+
+        let sequence = session.sequence.as_mut().unwrap();
+        write_frame(&mut session.stream, token, sequence.received, &[])?;
+        // The client of a forgotten session starts over
+        sequence.resume(&mut session.stream, if resumed { received } else { 0 })?;
+
+        debug!(
+            "UDS session {:016x} {}",
+            token,
+            if resumed { "resumed" } else { "started" }
+        );
+
+        Ok(session)
+    }
+
+    /// Take the sequence of the session, waiting for its current connection to let it go
+    fn resume(&self, token: u64, owner: libc::uid_t) -> Result<Option<Sequence>, IoError> {
+        let mut sessions = self.shared.sessions.lock().unwrap();
+
+        match sessions.map.get(&token) {
+            Some(slot) if token != 0 && slot.owner == owner => (),
+            _ => return Ok(None),
+        }
+
+        let deadline = Instant::now() + RESUME_TIMEOUT;
+
+        loop {
+            let slot = match sessions.map.get_mut(&token) {
+                Some(s) => s,
+                // Forgotten while waiting
+                None => return Ok(None),
+            };
+
+            if let Some(sequence) = slot.sequence.take() {
+                sessions.detached.retain(|t| *t != token);
+                return Ok(Some(sequence));
+            }
+
+            if let Some(s) = slot.attached.take() {
+                s.shutdown(Shutdown::Both).unwrap_or_default();
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(IoError::new(
+                    io::ErrorKind::TimedOut,
+                    "The previous connection of the session didn't let it go",
+                ));
+            }
+
+            sessions = self
+                .shared
+                .detached
+                .wait_timeout(sessions, remaining)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Record a new attached session, forgetting the least recently detached ones past the limit
+    fn open(&self, owner: libc::uid_t) -> Result<u64, IoError> {
+        let token = random_token()?;
+        let mut sessions = self.shared.sessions.lock().unwrap();
+
+        while sessions.map.len() >= self.max_sessions {
+            match sessions.detached.pop_front() {
+                Some(t) => {
+                    sessions.map.remove(&t);
+                }
+                None => {
+                    return Err(IoError::other(format!(
+                        "Too many sessions attached, {} max",
+                        self.max_sessions
+                    )))
+                }
+            }
+        }
+
+        sessions.map.insert(
+            token,
+            Slot {
+                owner,
+                sequence: None,
+                attached: None,
+            },
+        );
+
+        Ok(token)
+    }
+}
+
+impl fmt::Debug for SessionStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionStore")
+            .field("max_sessions", &self.max_sessions)
+            .field("replay_capacity", &self.capacity)
+            .field("sessions", &self.sessions())
+            .finish()
+    }
+}
+
+/// Non-zero token, unpredictable by the other processes
+fn random_token() -> Result<u64, IoError> {
+    let mut urandom = File::open("/dev/urandom")?;
+
+    loop {
+        let mut token = [0x00u8; 8];
+        urandom.read_exact(&mut token)?;
+
+        match u64::from_be_bytes(token) {
+            0 => continue,
+            token => return Ok(token),
+        }
+    }
+}
+
+/// Logical stream of a client, attached to its current connection, returned by
+/// [`SessionStore::accept`].
+///
+/// The frames are exchanged in order through [`Session::send`] and [`Session::recv`]. Any error
+/// ends the connection, not the session: it's detached once dropped, for the client to resume.
+pub struct Session {
+    shared: Arc<Shared>,
+    token: u64,
+    resumed: bool,
+    /// Given back to the store on drop
+    sequence: Option<Sequence>,
+    stream: IpcStream,
+}
+
+impl Session {
+    /// Token of the session, presented by the client to resume it
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+
+    /// Whether the session was resumed, rather than started by this connection
+    pub fn is_resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// Number of frames sent and not yet acknowledged by the client
+    pub fn unacknowledged(&self) -> usize {
+        self.sequence.as_ref().map(|s| s.unacked.len()).unwrap_or(0)
+    }
+
+    /// Underlying stream of the current connection. Frames written to it directly are not part
+    /// of the session, and corrupt it
+    pub fn get_ref(&self) -> &IpcStream {
+        &self.stream
+    }
+
+    /// Send a frame of the session, kept until the client acknowledges it. Blocks reading the
+    /// client while the replay buffer is full, queuing the frames it sends meanwhile
+    pub fn send(&mut self, payload: &[u8]) -> Result<(), IoError> {
+        let sequence = self.sequence.as_mut().unwrap();
+        sequence.send(&mut self.stream, payload)
+    }
+
+    /// Receive the next frame of the session, in order and once, whatever the connection it was
+    /// sent on
+    pub fn recv(&mut self) -> Result<Vec<u8>, IoError> {
+        let sequence = self.sequence.as_mut().unwrap();
+        sequence.recv(&mut self.stream)
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("token", &format_args!("{:016x}", self.token))
+            .field("resumed", &self.resumed)
+            .field("unacknowledged", &self.unacknowledged())
+            .finish()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let mut sessions = self.shared.sessions.lock().unwrap();
+
+        if let Some(slot) = sessions.map.get_mut(&self.token) {
+            slot.sequence = self.sequence.take();
+            slot.attached.take();
+            sessions.detached.push_back(self.token);
+        }
+
+        self.shared.detached.notify_all();
+    }
+}
+
+/// Client of a session of a [`SessionStore`], created with [`UnixDomainClient::session`].
+///
+/// The session is started on the first call. Once its connection fails, the next call connects
+/// again with the settings of the client and resumes the session: the frames not acknowledged
+/// on either side are written again, so every frame is delivered once and in order.
+///
+/// If the server forgot the session, the call fails with an error of kind
+/// [`io::ErrorKind::ConnectionReset`], since frames may have been lost, and a new session is
+/// started.
+#[derive(Debug)]
+pub struct SessionClient {
+    client: UnixDomainClient,
+    stream: Option<UnixStream>,
+    token: u64,
+    sequence: Sequence,
+}
+
+impl SessionClient {
+    pub(crate) fn new(client: UnixDomainClient, capacity: usize, max_frame: usize) -> Self {
+        SessionClient {
+            client,
+            stream: None,
+            token: 0,
+            sequence: Sequence::new(capacity, max_frame),
+        }
+    }
+
+    /// Token of the session, 0 until it's started
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+
+    /// Whether the session is attached to an open connection
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Number of frames sent and not yet acknowledged by the server
+    pub fn unacknowledged(&self) -> usize {
+        self.sequence.unacked.len()
+    }
+
+    /// Send a frame of the session.
+    ///
+    /// If the connection fails, the session is resumed once from a new one, which writes the
+    /// frame again if the server didn't receive it. If that fails too, the frame stays queued
+    /// and is written on the next resumption.
+    pub fn send(&mut self, payload: &[u8]) -> Result<(), IoError> {
+        let (token, queued) = (self.token, self.sequence.next);
+
+        match self.attempt(|sequence, stream| sequence.send(stream, payload)) {
+            Err(e) if self.forgotten(token) => Err(e),
+            // Queued before failing, written again by the resumption
+            Err(_) if self.sequence.next > queued => self.connected().map(|_| ()),
+            Err(_) => self.attempt(|sequence, stream| sequence.send(stream, payload)),
+            result => result,
+        }
+    }
+
+    /// Receive the next frame of the session, resuming it once from a new connection if it fails
+    pub fn recv(&mut self) -> Result<Vec<u8>, IoError> {
+        let token = self.token;
+
+        match self.attempt(|sequence, stream| sequence.recv(stream)) {
+            Err(e) if self.forgotten(token) => Err(e),
+            Err(_) => self.attempt(|sequence, stream| sequence.recv(stream)),
+            result => result,
+        }
+    }
+
+    /// Close the connection, keeping the session. The next call resumes it
+    pub fn close(&mut self) {
+        self.stream.take();
+    }
+
+    fn attempt<T, F>(&mut self, f: F) -> Result<T, IoError>
+    where
+        F: FnOnce(&mut Sequence, &mut UnixStream) -> Result<T, IoError>,
+    {
+        self.connected()?;
+
+        let result = f(&mut self.sequence, self.stream.as_mut().unwrap());
+        if let Err(e) = result.as_ref() {
+            debug!("UDS session {:016x} interrupted: {}", self.token, e);
+            self.stream.take();
+        }
+
+        result
+    }
+
+    /// Whether the session was started again since the token was read
+    fn forgotten(&self, token: u64) -> bool {
+        token != 0 && self.token != token
+    }
+
+    /// Connect and resume the session, unless already connected
+    fn connected(&mut self) -> Result<&mut UnixStream, IoError> {
+        if self.stream.is_none() {
+            let mut stream = self.client.open()?;
+
+            write_frame(&mut stream, self.token, self.sequence.received, &[])?;
+            let (token, received) = decode_header(&codec::read_frame(&mut stream, HEADER_LEN)?)?;
+
+            if self.token != 0 && token != self.token {
+                let lost = self.token;
+
+                self.token = token;
+                self.sequence = Sequence::new(self.sequence.capacity, self.sequence.max_frame);
+                self.stream.replace(stream);
+
+                return Err(IoError::new(
+                    io::ErrorKind::ConnectionReset,
+                    format!(
+                        "Session {:016x} forgotten by the server, frames may be lost",
+                        lost
+                    ),
+                ));
+            }
+
+            self.token = token;
+            self.sequence.resume(&mut stream, received)?;
+            self.stream.replace(stream);
+        }
+
+        Ok(self.stream.as_mut().unwrap())
+    }
+}
+
+impl fmt::Debug for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sequence")
+            .field("next", &self.next)
+            .field("received", &self.received)
+            .field("unacked", &self.unacked.len())
+            .field("inbox", &self.inbox.len())
+            .finish()
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    convert::TryFrom,
+    fmt,
+    io::{self, Error as IoError},
+    ops::{Deref, DerefMut},
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd},
+    ptr, slice,
+};
+
+/// Default payload length from which [`crate::codec::write_frame_shared`] passes a memfd
+pub const SHARED_THRESHOLD: usize = 1024 * 1024;
+
+/// Seals required on a received memfd, so the sender can neither change nor truncate it while
+/// mapped
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const REQUIRED_SEALS: libc::c_int = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+
+/// Region of memory mapped from a descriptor, unmapped on drop
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapping is only written through `&mut`
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn new(fd: BorrowedFd, len: usize, writable: bool) -> Result<Self, IoError> {
+        // Empty mappings are refused by the kernel
+        if len == 0 {
+            return Ok(Mapping {
+                ptr: ptr::NonNull::dangling().as_ptr(),
+                len,
+            });
+        }
+
+        let prot = match writable {
+            true => libc::PROT_READ | libc::PROT_WRITE,
+            false => libc::PROT_READ,
+        };
+
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                prot,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+
+        if ptr == libc::MAP_FAILED {
+            return Err(IoError::last_os_error());
+        }
+
+        Ok(Mapping {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+        }
+    }
+}
+
+/// Payload written in place in a memfd, then sealed and passed to the peer with
+/// [`crate::codec::write_shared`], so a jumbo payload is never copied through the socket.
+///
+/// Only supported on Linux.
+pub struct SharedBuffer {
+    fd: OwnedFd,
+    map: Mapping,
+}
+
+impl SharedBuffer {
+    /// Zeroed buffer of `len` bytes
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn new(len: usize) -> Result<Self, IoError> {
+        use std::os::unix::io::FromRawFd;
+
+        let fd = unsafe {
+            libc::memfd_create(
+                b"dusk-uds-frame\0".as_ptr() as *const libc::c_char,
+                libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+            )
+        };
+        if fd < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        // Owned right away, so the descriptor is closed on every error
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let size = libc::off_t::try_from(len)
+            .map_err(|_| IoError::new(io::ErrorKind::InvalidInput, "Shared buffer too long"))?;
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), size) } < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        let map = Mapping::new(fd.as_fd(), len, true)?;
+
+        Ok(SharedBuffer { fd, map })
+    }
+
+    /// Zeroed buffer of `len` bytes
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn new(_len: usize) -> Result<Self, IoError> {
+        Err(IoError::new(
+            io::ErrorKind::Unsupported,
+            "Shared buffers are only supported on Linux",
+        ))
+    }
+
+    /// Unmap the buffer and seal the memfd against any change, returning it with the payload
+    /// length
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn seal(self) -> Result<(OwnedFd, usize), IoError> {
+        let SharedBuffer { fd, map } = self;
+        let len = map.len;
+
+        // Writing can't be sealed while a writable mapping exists
+        drop(map);
+
+        let seals = REQUIRED_SEALS | libc::F_SEAL_SEAL;
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        Ok((fd, len))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub(crate) fn seal(self) -> Result<(OwnedFd, usize), IoError> {
+        Ok((self.fd, self.map.len))
+    }
+}
+
+impl Deref for SharedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.map.as_slice()
+    }
+}
+
+impl DerefMut for SharedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.map.as_mut_slice()
+    }
+}
+
+impl fmt::Debug for SharedBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedBuffer")
+            .field("fd", &self.fd)
+            .field("len", &self.map.len)
+            .finish()
+    }
+}
+
+/// Payload of a frame read with [`crate::codec::read_frame_shared`]: read inline from the
+/// socket, or mapped read-only from the sealed memfd passed by the peer, without copy.
+pub struct SharedPayload(Contents);
+
+enum Contents {
+    Inline(Vec<u8>),
+    Mapped(Mapping),
+}
+
+impl SharedPayload {
+    pub(crate) fn inline(payload: Vec<u8>) -> Self {
+        SharedPayload(Contents::Inline(payload))
+    }
+
+    /// Map the first `len` bytes of a received memfd, refusing it unless sealed against any
+    /// change
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn map(fd: OwnedFd, len: usize) -> Result<Self, IoError> {
+        let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+        if seals < 0 {
+            return Err(IoError::last_os_error());
+        } else if seals & REQUIRED_SEALS != REQUIRED_SEALS {
+            return Err(IoError::new(
+                io::ErrorKind::InvalidData,
+                "The memfd of a shared frame is not sealed",
+            ));
+        }
+
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+            return Err(IoError::last_os_error());
+        } else if (stat.st_size as u64) < len as u64 {
+            return Err(IoError::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The memfd of a shared frame holds {} bytes, {} announced",
+                    stat.st_size, len
+                ),
+            ));
+        }
+
+        // The mapping stays valid once the descriptor is closed
+        Ok(SharedPayload(Contents::Mapped(Mapping::new(
+            fd.as_fd(),
+            len,
+            false,
+        )?)))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub(crate) fn map(_fd: OwnedFd, _len: usize) -> Result<Self, IoError> {
+        Err(IoError::new(
+            io::ErrorKind::Unsupported,
+            "Shared frames are only supported on Linux",
+        ))
+    }
+
+    /// Whether the payload is mapped from a memfd, rather than read from the socket
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.0, Contents::Mapped(_))
+    }
+
+    /// Take the payload, copying it out of the mapping if mapped
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.0 {
+            Contents::Inline(payload) => payload,
+            Contents::Mapped(map) => map.as_slice().to_vec(),
+        }
+    }
+}
+
+impl Deref for SharedPayload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            Contents::Inline(payload) => payload,
+            Contents::Mapped(map) => map.as_slice(),
+        }
+    }
+}
+
+impl fmt::Debug for SharedPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedPayload")
+            .field("len", &self.len())
+            .field("mapped", &self.is_mapped())
+            .finish()
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    io::{self, Error as IoError, Read, Write},
+    mem,
+    os::unix::{io::AsRawFd, net::UnixStream},
+    ptr,
+    sync::atomic::{AtomicI32, Ordering},
+    thread,
+};
+
+/// Signals requesting the process to stop
+const SIGNALS: [libc::c_int; 2] = [libc::SIGTERM, libc::SIGINT];
+
+/// Byte written by the guard to end the thread. The handler writes the number of the signal
+const WAKE_STOP: u8 = 0x00;
+
+/// Write end of the pipe of the installed handler, or -1 if there is none
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    let fd = PIPE.load(Ordering::Relaxed);
+    let wake = signal as u8;
+
+    // Only async-signal-safe calls are allowed here. The write doesn't block, so a burst of
+    // signals filling the socket buffer is dropped, and the errno of the interrupted thread is
+    // restored
+    if fd >= 0 {
+        unsafe {
+            let errno = *errno_location();
+            libc::write(fd, &wake as *const u8 as *const libc::c_void, 1);
+            *errno_location() = errno;
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__error()
+}
+
+/// Handlers of `SIGTERM` and `SIGINT`, installed while it is alive.
+///
+/// The handler only writes to a socket pair, and the callback runs on a thread of its own. The
+/// previous handlers are restored when it is dropped. Only one can be installed at a time.
+pub struct SignalGuard {
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+    wake: UnixStream,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl SignalGuard {
+    /// Install the handlers, calling `on_signal` with the signal every time one of them is
+    /// received
+    pub fn install<F: Fn(libc::c_int) + Send + 'static>(on_signal: F) -> Result<Self, IoError> {
+        let (wake, mut rx) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+
+        if PIPE
+            .compare_exchange(-1, wake.as_raw_fd(), Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(IoError::new(
+                io::ErrorKind::AlreadyExists,
+                "Signal handlers are already installed",
+            ));
+        }
+
+        let mut guard = SignalGuard {
+            previous: vec![],
+            wake,
+            thread: None,
+        };
+
+        for signal in SIGNALS {
+            let previous = set_handler(signal, handle_signal as *const () as libc::sighandler_t)?;
+            guard.previous.push((signal, previous));
+        }
+
+        guard.thread.replace(thread::spawn(move || {
+            let mut byte = [WAKE_STOP];
+
+            loop {
+                match rx.read(&mut byte) {
+                    Ok(1) if byte[0] != WAKE_STOP => {
+                        info!("Stop signal {} received, shutting down the UDS", byte[0]);
+                        on_signal(libc::c_int::from(byte[0]));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    _ => return,
+                }
+            }
+        }));
+
+        Ok(guard)
+    }
+}
+
+impl Drop for SignalGuard {
+    fn drop(&mut self) {
+        for (signal, previous) in self.previous.drain(..) {
+            let ret = unsafe { libc::sigaction(signal, &previous, ptr::null_mut()) };
+            if ret < 0 {
+                error!(
+                    "Error restoring the handler of the signal {}: {}",
+                    signal,
+                    IoError::last_os_error()
+                );
+            }
+        }
+
+        PIPE.store(-1, Ordering::Release);
+
+        // Blocking again, so the stop byte waits for the thread to drain a burst of signals
+        self.wake.set_nonblocking(false).unwrap_or_default();
+        (&self.wake).write_all(&[WAKE_STOP]).unwrap_or_default();
+        if let Some(t) = self.thread.take() {
+            t.join().unwrap_or_default();
+        }
+    }
+}
+
+fn set_handler(
+    signal: libc::c_int,
+    handler: libc::sighandler_t,
+) -> Result<libc::sigaction, IoError> {
+    let mut action: libc::sigaction = unsafe { mem::zeroed() };
+    action.sa_sigaction = handler;
+    action.sa_flags = libc::SA_RESTART;
+    unsafe { libc::sigemptyset(&mut action.sa_mask) };
+
+    let mut previous: libc::sigaction = unsafe { mem::zeroed() };
+    let ret = unsafe { libc::sigaction(signal, &action, &mut previous) };
+    if ret < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    Ok(previous)
+}
@@ -0,0 +1,722 @@
+use crate::{
+    events::EventBus,
+    quota::UidQuota,
+    trace::{self, ConnectionEvent, ConnectionState, Trace},
+    Clock,
+};
+
+use std::{
+    collections::HashMap,
+    os::unix::io::AsRawFd,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Snapshot of the state of a running UDS, returned by [`crate::ServerHandle::stats`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerStats {
+    /// Time since the UDS was bound
+    pub uptime: Duration,
+    /// Sockets accepted and queued to the workers
+    pub accepted: u64,
+    /// Sockets dropped by the accept filter or the backpressure policy
+    pub rejected: u64,
+    /// Sockets currently being handled by the workers
+    pub active: usize,
+    /// Tasks waiting in the queue for an available worker
+    pub queued: usize,
+    /// Failures while accepting or queueing a socket
+    pub accept_errors: u64,
+    /// Provider futures that resolved to [`crate::Message::Error`]
+    pub handler_errors: u64,
+    /// Descriptors owned by the crate: listeners, queued and in-progress sockets, and the
+    /// duplicates kept to enforce [`crate::Options::max_connection_age`]
+    pub open_fds: usize,
+    /// Sockets found still open after their provider was dropped, when
+    /// [`crate::Options::check_fd_leaks`] is set
+    pub fd_leaks: u64,
+    /// Distribution of the time the handled connections took
+    pub handler_times: HandlerTimes,
+    /// State of every worker
+    pub workers: Vec<WorkerStats>,
+    /// Bytes exchanged by every connection in progress, by increasing identifier. Empty unless
+    /// [`crate::Options::account_traffic`] is set
+    pub connections: Vec<ConnectionTraffic>,
+    /// Bytes exchanged by every peer user since the UDS was started, by increasing uid. Empty
+    /// unless [`crate::Options::account_traffic`] is set
+    pub peers: Vec<PeerTraffic>,
+}
+
+/// Distribution of the time the connections took, from the socket taken by a worker to its
+/// provider resolved
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HandlerTimes {
+    /// Number of connections that took less than the bound, by increasing bound. The bounds are
+    /// powers of two of microseconds
+    pub buckets: Vec<(Duration, u64)>,
+    /// Time taken by every connection, summed
+    pub sum: Duration,
+    /// Number of connections, including the ones longer than the last bound
+    pub count: u64,
+}
+
+/// Bytes exchanged by a connection in progress, through its [`crate::IpcStream`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionTraffic {
+    /// Identifier of the connection, as in [`crate::ConnectionEvent::connection`]
+    pub id: u64,
+    /// User of the peer, if its credentials could be fetched
+    pub uid: Option<libc::uid_t>,
+    /// Bytes read from the connection
+    pub received: u64,
+    /// Bytes written to the connection
+    pub sent: u64,
+}
+
+/// Bytes exchanged by the connections of a peer user, finished or in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerTraffic {
+    /// User of the peers
+    pub uid: libc::uid_t,
+    /// Connections of the user handled by the workers
+    pub connections: u64,
+    /// Bytes read from the connections of the user
+    pub received: u64,
+    /// Bytes written to the connections of the user
+    pub sent: u64,
+}
+
+/// Load of the workers at the time a socket is accepted, passed to [`crate::AcceptFilter`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Load {
+    /// Sockets waiting in the queue for an available worker
+    pub queued: usize,
+    /// Sockets currently being handled by the workers
+    pub active: usize,
+    /// Upper bound of the time 95% of the handled connections took, since the UDS was started.
+    /// `None` before the first connection is handled
+    pub p95_handler_time: Option<Duration>,
+}
+
+/// Snapshot of the state of a worker
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerStats {
+    /// Whether the worker is running. The slots of an elastic pool keep the counters of their
+    /// retired workers, see [`crate::Options::max_workers`]
+    pub running: bool,
+    /// Sockets currently being handled by the worker
+    pub active: usize,
+    /// Sockets handled to completion by the worker
+    pub handled: u64,
+    /// Polls that exceeded [`crate::Options::poll_budget`]
+    pub slow_polls: u64,
+    /// Connections closed for exceeding [`crate::Options::memory_budget`]
+    pub over_budget: u64,
+    /// Provider futures of the worker that resolved to [`crate::Message::Error`]
+    pub handler_errors: u64,
+    /// Provider futures of the worker that panicked while polled
+    pub panics: u64,
+    /// Times the worker was respawned after panicking, see [`crate::Options::respawn_workers`]
+    pub respawns: u64,
+    /// CPU time consumed by the worker thread
+    pub cpu_time: Duration,
+    /// Wall time the worker spent handling tasks and polling futures
+    pub busy_time: Duration,
+    /// Wall time the worker spent waiting for work, updated once it wakes up
+    pub idle_time: Duration,
+}
+
+impl ServerStats {
+    /// Counters in the Prometheus text exposition format, for a scrape endpoint of the
+    /// application. The metrics are prefixed with `dusk_uds_`, and the ones of the workers
+    /// labeled with their index
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let total = |value: u64| vec![(String::new(), value.to_string())];
+        let gauge = |value: usize| vec![(String::new(), value.to_string())];
+        let per_worker = |f: fn(&WorkerStats) -> String| -> Vec<(String, String)> {
+            self.workers
+                .iter()
+                .enumerate()
+                .map(|(i, w)| (format!("{{worker=\"{}\"}}", i), f(w)))
+                .collect()
+        };
+        let per_peer = |f: fn(&PeerTraffic) -> u64| -> Vec<(String, String)> {
+            self.peers
+                .iter()
+                .map(|p| (format!("{{uid=\"{}\"}}", p.uid), f(p).to_string()))
+                .collect()
+        };
+
+        #[rustfmt::skip]
+        let mut metrics = vec![
+            ("uptime_seconds", "gauge", "Time since the UDS was bound",
+                vec![(String::new(), self.uptime.as_secs_f64().to_string())]),
+            ("accepted_total", "counter", "Sockets accepted and queued to the workers",
+                total(self.accepted)),
+            ("rejected_total", "counter", "Sockets dropped by the accept filter or the backpressure policy",
+                total(self.rejected)),
+            ("active", "gauge", "Sockets being handled by the workers", gauge(self.active)),
+            ("queued", "gauge", "Tasks waiting in the queue for an available worker",
+                gauge(self.queued)),
+            ("accept_errors_total", "counter", "Failures while accepting or queueing a socket",
+                total(self.accept_errors)),
+            ("handler_errors_total", "counter", "Provider futures that resolved to an error",
+                total(self.handler_errors)),
+            ("open_fds", "gauge", "Descriptors owned by the UDS", gauge(self.open_fds)),
+            ("fd_leaks_total", "counter", "Sockets found open after their provider was dropped",
+                total(self.fd_leaks)),
+            ("workers_running", "gauge", "Workers running", gauge(self.workers.iter().filter(|w| w.running).count())),
+            ("worker_active", "gauge", "Sockets being handled by the worker",
+                per_worker(|w| w.active.to_string())),
+            ("worker_handled_total", "counter", "Sockets handled to completion by the worker",
+                per_worker(|w| w.handled.to_string())),
+            ("worker_handler_errors_total", "counter", "Provider futures of the worker that resolved to an error",
+                per_worker(|w| w.handler_errors.to_string())),
+            ("worker_panics_total", "counter", "Provider futures of the worker that panicked while polled",
+                per_worker(|w| w.panics.to_string())),
+            ("worker_respawns_total", "counter", "Times the worker was respawned after panicking",
+                per_worker(|w| w.respawns.to_string())),
+            ("worker_slow_polls_total", "counter", "Polls of the worker that exceeded the poll budget",
+                per_worker(|w| w.slow_polls.to_string())),
+            ("worker_over_budget_total", "counter", "Connections of the worker closed for exceeding their memory budget",
+                per_worker(|w| w.over_budget.to_string())),
+            ("worker_cpu_seconds_total", "counter", "CPU time consumed by the worker thread",
+                per_worker(|w| w.cpu_time.as_secs_f64().to_string())),
+            ("worker_busy_seconds_total", "counter", "Wall time the worker spent handling tasks and polling futures",
+                per_worker(|w| w.busy_time.as_secs_f64().to_string())),
+            ("worker_idle_seconds_total", "counter", "Wall time the worker spent waiting for work",
+                per_worker(|w| w.idle_time.as_secs_f64().to_string())),
+        ];
+
+        if !self.peers.is_empty() {
+            #[rustfmt::skip]
+            metrics.extend([
+                ("peer_connections_total", "counter", "Connections of the peer user handled by the workers",
+                    per_peer(|p| p.connections)),
+                ("peer_received_bytes_total", "counter", "Bytes read from the connections of the peer user",
+                    per_peer(|p| p.received)),
+                ("peer_sent_bytes_total", "counter", "Bytes written to the connections of the peer user",
+                    per_peer(|p| p.sent)),
+            ]);
+        }
+
+        for (name, kind, help, samples) in metrics.iter() {
+            metric_header(&mut out, name, kind, help);
+            for (labels, value) in samples {
+                out.push_str(&format!("dusk_uds_{}{} {}\n", name, labels, value));
+            }
+        }
+
+        let times = &self.handler_times;
+        metric_header(
+            &mut out,
+            "handler_seconds",
+            "histogram",
+            "Time the connections took, from the socket taken by a worker to its provider resolved",
+        );
+        for (bound, count) in times.buckets.iter() {
+            out.push_str(&format!(
+                "dusk_uds_handler_seconds_bucket{{le=\"{}\"}} {}\n",
+                bound.as_secs_f64(),
+                count
+            ));
+        }
+        out.push_str(&format!(
+            "dusk_uds_handler_seconds_bucket{{le=\"+Inf\"}} {}\n",
+            times.count
+        ));
+        out.push_str(&format!(
+            "dusk_uds_handler_seconds_sum {}\n",
+            times.sum.as_secs_f64()
+        ));
+        out.push_str(&format!("dusk_uds_handler_seconds_count {}\n", times.count));
+
+        out
+    }
+}
+
+fn metric_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    out.push_str(&format!("# HELP dusk_uds_{} {}\n", name, help));
+    out.push_str(&format!("# TYPE dusk_uds_{} {}\n", name, kind));
+}
+
+impl WorkerStats {
+    /// Whether the worker has at least one socket in progress
+    pub fn is_busy(&self) -> bool {
+        self.active > 0
+    }
+
+    /// Fraction of the wall time the worker was busy. `None` before it ran
+    pub fn utilization(&self) -> Option<f64> {
+        let total = self.busy_time + self.idle_time;
+        (!total.is_zero()).then(|| self.busy_time.as_secs_f64() / total.as_secs_f64())
+    }
+
+    /// Fraction of the busy time spent on the CPU. Close to 1 when the handlers are limited by
+    /// CPU, and lower when they block on I/O. `None` before the worker was busy
+    pub fn cpu_share(&self) -> Option<f64> {
+        (!self.busy_time.is_zero())
+            .then(|| (self.cpu_time.as_secs_f64() / self.busy_time.as_secs_f64()).min(1.0))
+    }
+}
+
+/// Live counters of the UDS.
+///
+/// The counters updated by the workers live in a slot per worker, aligned to its own cache line
+/// and written only by the owning worker, so the hot path needs no shared atomic operation. The
+/// slots are aggregated when a snapshot is taken. The remaining counters are updated by the
+/// accept threads and the handle, away from the workers.
+pub struct Stats {
+    pub clock: Arc<dyn Clock>,
+    started: Instant,
+    /// Milliseconds from `started` to the last accepted connection
+    last_activity: AtomicU64,
+    pub accepted: AtomicU64,
+    pub rejected: AtomicU64,
+    pub accept_errors: AtomicU64,
+    pub open_fds: AtomicUsize,
+    pub workers: Vec<WorkerCounters>,
+    pub trace: Option<Trace>,
+    /// Sockets per peer user, shared by the accept loops and the workers
+    pub quota: Option<UidQuota>,
+    /// Subscribers of [`crate::ServerHandle::events`], shared with the queue
+    pub events: Arc<EventBus>,
+    /// Bytes exchanged per connection and per peer user, if accounted
+    pub traffic: Option<TrafficLedger>,
+}
+
+/// Number of buckets of the handler time histograms. Bucket `i` counts the connections that took
+/// less than `2^(i + 1)` microseconds, and the last one every longer connection
+const DURATION_BUCKETS: usize = 32;
+
+/// Live counters of a single worker. Must be written only by the owning worker.
+#[derive(Default)]
+#[repr(align(64))]
+pub struct WorkerCounters {
+    running: AtomicBool,
+    active: AtomicUsize,
+    handled: AtomicU64,
+    durations: [AtomicU64; DURATION_BUCKETS],
+    /// Sum of the handler times, in microseconds
+    handler_micros: AtomicU64,
+    cpu_micros: AtomicU64,
+    busy_micros: AtomicU64,
+    idle_micros: AtomicU64,
+    handler_errors: AtomicU64,
+    panics: AtomicU64,
+    respawns: AtomicU64,
+    fd_leaks: AtomicU64,
+    slow_polls: AtomicU64,
+    over_budget: AtomicU64,
+    /// Milliseconds from [`Stats`] start to the last finished connection
+    last_activity: AtomicU64,
+}
+
+impl WorkerCounters {
+    /// Record the worker started or finished
+    pub fn set_running(&self, running: bool) {
+        self.running.store(running, Ordering::Relaxed);
+    }
+
+    /// Record a socket taken by the worker
+    pub fn started(&self) {
+        let active = self.active.load(Ordering::Relaxed);
+        self.active.store(active + 1, Ordering::Relaxed);
+    }
+
+    /// Record the end of a socket handled by the worker, after the provided time
+    pub fn finished(&self, stats: &Stats, error: bool, elapsed: Duration) {
+        let active = self.active.load(Ordering::Relaxed);
+        self.active.store(active - 1, Ordering::Relaxed);
+
+        let micros = elapsed.as_micros().max(1) as u64;
+        let bucket = (63 - micros.leading_zeros() as usize).min(DURATION_BUCKETS - 1);
+        bump(&self.durations[bucket]);
+
+        let sum = self.handler_micros.load(Ordering::Relaxed);
+        self.handler_micros.store(sum + micros, Ordering::Relaxed);
+
+        bump(&self.handled);
+        if error {
+            bump(&self.handler_errors);
+        }
+
+        self.last_activity.store(stats.now(), Ordering::Relaxed);
+    }
+
+    /// Record a socket left open after its provider was dropped
+    pub fn leaked(&self) {
+        bump(&self.fd_leaks);
+    }
+
+    /// Record a provider future that panicked while polled
+    pub fn panicked(&self) {
+        bump(&self.panics);
+    }
+
+    /// Record the worker respawned after panicking
+    pub fn respawned(&self) {
+        bump(&self.respawns);
+    }
+
+    /// Record a poll that exceeded the poll budget
+    pub fn slow_poll(&self) {
+        bump(&self.slow_polls);
+    }
+
+    /// Record a connection closed for exceeding its memory budget
+    pub fn over_budget(&self) {
+        bump(&self.over_budget);
+    }
+
+    /// Record the time spent busy, then waiting for work, and the CPU time consumed so far by
+    /// the calling thread
+    pub fn account(&self, busy: Duration, idle: Duration) {
+        let add = |counter: &AtomicU64, d: Duration| {
+            let total = counter.load(Ordering::Relaxed);
+            counter.store(total + d.as_micros() as u64, Ordering::Relaxed);
+        };
+
+        add(&self.busy_micros, busy);
+        add(&self.idle_micros, idle);
+
+        if let Some(cpu) = thread_cpu_time() {
+            self.cpu_micros
+                .store(cpu.as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Sockets currently being handled by the worker
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+}
+
+/// Bytes exchanged by a connection, counted by its [`crate::IpcStream`]
+#[derive(Debug, Default)]
+pub struct Traffic {
+    received: AtomicU64,
+    sent: AtomicU64,
+}
+
+impl Traffic {
+    /// Count bytes read from the connection
+    pub fn received(&self, n: usize) {
+        self.received.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Count bytes written to the connection
+    pub fn sent(&self, n: usize) {
+        self.sent.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+/// Traffic of the connections in progress, keyed by their identifier, and of the finished
+/// connections folded into the totals of their peer user.
+///
+/// The counters of a connection are written by its stream only, so the ledger is locked once
+/// when it's taken by a worker, once when it finishes, and by the snapshots.
+#[derive(Default)]
+pub struct TrafficLedger {
+    accounts: Mutex<Accounts>,
+}
+
+#[derive(Default)]
+struct Accounts {
+    live: HashMap<u64, (Option<libc::uid_t>, Arc<Traffic>)>,
+    peers: HashMap<libc::uid_t, PeerTraffic>,
+}
+
+impl TrafficLedger {
+    /// Start counting the traffic of the connection, returning the counters for its stream
+    pub fn open(&self, id: u64, uid: Option<libc::uid_t>) -> Arc<Traffic> {
+        let traffic = Arc::new(Traffic::default());
+
+        let mut accounts = self.accounts.lock().unwrap();
+        if let Some(uid) = uid {
+            peer_entry(&mut accounts.peers, uid).connections += 1;
+        }
+        accounts.live.insert(id, (uid, Arc::clone(&traffic)));
+
+        traffic
+    }
+
+    /// Fold the traffic of the finished connection into the totals of its peer user
+    pub fn close(&self, id: u64) {
+        let mut accounts = self.accounts.lock().unwrap();
+
+        if let Some((Some(uid), traffic)) = accounts.live.remove(&id) {
+            let peer = peer_entry(&mut accounts.peers, uid);
+            peer.received += traffic.received.load(Ordering::Relaxed);
+            peer.sent += traffic.sent.load(Ordering::Relaxed);
+        }
+    }
+
+    fn snapshot(&self) -> (Vec<ConnectionTraffic>, Vec<PeerTraffic>) {
+        let accounts = self.accounts.lock().unwrap();
+        let mut peers = accounts.peers.clone();
+
+        let mut connections: Vec<ConnectionTraffic> = accounts
+            .live
+            .iter()
+            .map(|(id, (uid, traffic))| {
+                let connection = ConnectionTraffic {
+                    id: *id,
+                    uid: *uid,
+                    received: traffic.received.load(Ordering::Relaxed),
+                    sent: traffic.sent.load(Ordering::Relaxed),
+                };
+
+                if let Some(uid) = uid {
+                    let peer = peer_entry(&mut peers, *uid);
+                    peer.received += connection.received;
+                    peer.sent += connection.sent;
+                }
+
+                connection
+            })
+            .collect();
+        connections.sort_by_key(|c| c.id);
+
+        let mut peers: Vec<PeerTraffic> = peers.into_values().collect();
+        peers.sort_by_key(|p| p.uid);
+
+        (connections, peers)
+    }
+}
+
+fn peer_entry(peers: &mut HashMap<libc::uid_t, PeerTraffic>, uid: libc::uid_t) -> &mut PeerTraffic {
+    peers.entry(uid).or_insert(PeerTraffic {
+        uid,
+        connections: 0,
+        received: 0,
+        sent: 0,
+    })
+}
+
+/// CPU time consumed by the calling thread
+fn thread_cpu_time() -> Option<Duration> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) } < 0 {
+        return None;
+    }
+
+    Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+/// Increment a counter that has a single writer, without a locked read-modify-write
+fn bump(counter: &AtomicU64) {
+    counter.store(counter.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+}
+
+impl Stats {
+    pub fn new(
+        workers: usize,
+        trace: Option<usize>,
+        quota: Option<usize>,
+        traffic: bool,
+        clock: Arc<dyn Clock>,
+        events: Arc<EventBus>,
+    ) -> Self {
+        Stats {
+            started: clock.now(),
+            clock,
+            last_activity: AtomicU64::new(0),
+            accepted: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            accept_errors: AtomicU64::new(0),
+            open_fds: AtomicUsize::new(0),
+            workers: (0..workers).map(|_| WorkerCounters::default()).collect(),
+            trace: trace.map(Trace::new),
+            quota: quota.map(UidQuota::new),
+            events,
+            traffic: traffic.then(TrafficLedger::default),
+        }
+    }
+
+    /// Milliseconds since the UDS was started
+    fn now(&self) -> u64 {
+        self.uptime().as_millis() as u64
+    }
+
+    /// Time since the UDS was started, according to its clock
+    fn uptime(&self) -> Duration {
+        self.clock.now().saturating_duration_since(self.started)
+    }
+
+    /// Release the socket with the provided inode from the quota of its peer user
+    pub fn release_quota(&self, ino: Option<libc::ino_t>) {
+        if let Some((quota, ino)) = self.quota.as_ref().zip(ino) {
+            quota.release(ino);
+        }
+    }
+
+    /// Fold the traffic of the finished connection with the provided identifier into the totals
+    /// of its peer user
+    pub fn close_traffic(&self, id: Option<u64>) {
+        if let Some((ledger, id)) = self.traffic.as_ref().zip(id) {
+            ledger.close(id);
+        }
+    }
+
+    /// Record a connection was accepted, or is still in progress
+    pub fn touch(&self) {
+        self.last_activity.fetch_max(self.now(), Ordering::Relaxed);
+    }
+
+    /// Time since the last accepted or finished connection
+    pub fn idle_for(&self) -> Duration {
+        let last = self
+            .workers
+            .iter()
+            .map(|w| w.last_activity.load(Ordering::Relaxed))
+            .fold(self.last_activity.load(Ordering::Relaxed), u64::max);
+
+        self.uptime().saturating_sub(Duration::from_millis(last))
+    }
+
+    /// Identifier of the connection of the socket, if connections are traced
+    pub fn traced<S: AsRawFd>(&self, socket: &S) -> Option<u64> {
+        self.trace.as_ref().map(|_| trace::connection_id(socket))
+    }
+
+    /// Record a transition of a traced connection
+    pub fn record(&self, connection: Option<u64>, state: ConnectionState) {
+        if let (Some(trace), Some(connection)) = (self.trace.as_ref(), connection) {
+            trace.record(ConnectionEvent {
+                connection,
+                state,
+                at: self.uptime(),
+            });
+        }
+    }
+
+    /// Cheap view of the load, for the accept filter
+    pub fn load(&self, queued: usize) -> Load {
+        Load {
+            queued,
+            active: self.workers.iter().map(WorkerCounters::active).sum(),
+            p95_handler_time: self.handler_time_percentile(0.95),
+        }
+    }
+
+    /// Handled connections of every worker per bucket of handler time
+    fn handler_buckets(&self) -> [u64; DURATION_BUCKETS] {
+        let mut buckets = [0u64; DURATION_BUCKETS];
+        for w in self.workers.iter() {
+            for (b, d) in buckets.iter_mut().zip(w.durations.iter()) {
+                *b += d.load(Ordering::Relaxed);
+            }
+        }
+
+        buckets
+    }
+
+    /// Upper bound of the handler time of the provided fraction of the handled connections
+    fn handler_time_percentile(&self, fraction: f64) -> Option<Duration> {
+        let buckets = self.handler_buckets();
+
+        let total: u64 = buckets.iter().sum();
+        if total == 0 {
+            return None;
+        }
+
+        let target = (total as f64 * fraction).ceil() as u64;
+        let mut seen = 0;
+        let bucket = buckets
+            .iter()
+            .position(|b| {
+                seen += b;
+                seen >= target
+            })
+            .unwrap_or(DURATION_BUCKETS - 1);
+
+        Some(Duration::from_micros(1 << (bucket + 1)))
+    }
+
+    fn handler_times(&self) -> HandlerTimes {
+        let mut seen = 0;
+        let mut buckets: Vec<(Duration, u64)> = self
+            .handler_buckets()
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                seen += b;
+                (Duration::from_micros(1 << (i + 1)), seen)
+            })
+            .collect();
+
+        // The last bucket counts every longer connection, it has no bound
+        buckets.pop();
+
+        let micros = self
+            .workers
+            .iter()
+            .map(|w| w.handler_micros.load(Ordering::Relaxed))
+            .sum();
+
+        HandlerTimes {
+            buckets,
+            sum: Duration::from_micros(micros),
+            count: seen,
+        }
+    }
+
+    pub fn snapshot(&self, queued: usize) -> ServerStats {
+        let workers: Vec<WorkerStats> = self
+            .workers
+            .iter()
+            .map(|w| WorkerStats {
+                running: w.running.load(Ordering::Relaxed),
+                active: w.active.load(Ordering::Relaxed),
+                handled: w.handled.load(Ordering::Relaxed),
+                slow_polls: w.slow_polls.load(Ordering::Relaxed),
+                over_budget: w.over_budget.load(Ordering::Relaxed),
+                handler_errors: w.handler_errors.load(Ordering::Relaxed),
+                panics: w.panics.load(Ordering::Relaxed),
+                respawns: w.respawns.load(Ordering::Relaxed),
+                cpu_time: Duration::from_micros(w.cpu_micros.load(Ordering::Relaxed)),
+                busy_time: Duration::from_micros(w.busy_micros.load(Ordering::Relaxed)),
+                idle_time: Duration::from_micros(w.idle_micros.load(Ordering::Relaxed)),
+            })
+            .collect();
+
+        let active = workers.iter().map(|w| w.active).sum();
+        let sum = |f: fn(&WorkerCounters) -> &AtomicU64| -> u64 {
+            self.workers
+                .iter()
+                .map(|w| f(w).load(Ordering::Relaxed))
+                .sum()
+        };
+
+        let (connections, peers) = self
+            .traffic
+            .as_ref()
+            .map(TrafficLedger::snapshot)
+            .unwrap_or_default();
+
+        ServerStats {
+            uptime: self.uptime(),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            active,
+            queued,
+            accept_errors: self.accept_errors.load(Ordering::Relaxed),
+            handler_errors: sum(|w| &w.handler_errors),
+            open_fds: self.open_fds.load(Ordering::Relaxed) + queued + active,
+            fd_leaks: sum(|w| &w.fd_leaks),
+            handler_times: self.handler_times(),
+            workers,
+            connections,
+            peers,
+        }
+    }
+}
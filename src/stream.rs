@@ -0,0 +1,608 @@
+use crate::{
+    reactor::{Interest, Source},
+    reqlog::Recorder,
+    stats::Traffic,
+};
+
+use futures::io::{AsyncRead, AsyncWrite};
+
+use std::{
+    fmt,
+    io::{self, IoSlice, IoSliceMut, Read, Write},
+    mem,
+    net::Shutdown,
+    ops::{Deref, DerefMut},
+    os::unix::{
+        io::{AsRawFd, IntoRawFd, RawFd},
+        net::UnixStream,
+    },
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+/// Flags of every write, so writing to a socket closed by the peer fails with `EPIPE` instead of
+/// raising `SIGPIPE`, whose default action terminates the process
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) const SEND_FLAGS: libc::c_int = libc::MSG_NOSIGNAL;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) const SEND_FLAGS: libc::c_int = 0;
+
+/// Maximum number of buffers passed to a single vectored write
+const MAX_IOV: usize = 1024;
+
+/// Initial size of the buffer receiving the messages of a `SOCK_SEQPACKET` socket, doubled until
+/// a message fits
+const MESSAGE_RESERVE: usize = 4096;
+
+/// Stream of a connection, handed to the providers.
+///
+/// Owned by the crate, so other transports can be supported without changing the signature of
+/// [`crate::TaskProvider`]. On Unix it wraps a [`UnixStream`], and dereferences to it.
+///
+/// Writes through its [`Write`] implementations never raise `SIGPIPE`, so a peer closing early
+/// can't terminate a process that doesn't ignore the signal, such as a C host embedding the
+/// crate. Writes performed on the dereferenced [`UnixStream`] are not covered.
+///
+/// Its [`AsyncRead`] and [`AsyncWrite`] implementations never block: an operation that would
+/// block registers the waker of the task to be woken once the socket is ready, and returns
+/// [`Poll::Pending`]. A worker owning several connections, see
+/// [`crate::Options::max_concurrent_per_worker`], keeps polling the others meanwhile, while a
+/// provider blocked in [`Read`] or [`Write`] holds its worker until the operation completes.
+///
+/// On a `SOCK_SEQPACKET` socket, see [`crate::SocketKind::SeqPacket`], every read returns bytes
+/// of a single message. A message longer than the buffer is returned over the next reads rather
+/// than truncated, so no data is lost, and [`IpcStream::recv_message`] receives the messages
+/// whole.
+///
+/// With [`crate::Options::request_log`], the traffic through its [`Read`], [`Write`],
+/// [`AsyncRead`] and [`AsyncWrite`] implementations is recorded for the log. Traffic on the
+/// dereferenced [`UnixStream`] is not. The same goes for the bytes counted by
+/// [`crate::Options::account_traffic`].
+pub struct IpcStream {
+    socket: UnixStream,
+    recorder: Option<Arc<Recorder>>,
+    traffic: Option<Arc<Traffic>>,
+    source: Source,
+    /// Message being read, for `SOCK_SEQPACKET` sockets
+    message: Option<Mutex<Received>>,
+}
+
+/// Message received whole from a `SOCK_SEQPACKET` socket, and the position of the next read in it
+#[derive(Default)]
+struct Received {
+    data: Vec<u8>,
+    read: usize,
+}
+
+impl Received {
+    fn remaining(&self) -> &[u8] {
+        &self.data[self.read..]
+    }
+}
+
+impl IpcStream {
+    /// Take the underlying OS stream
+    pub fn into_inner(self) -> UnixStream {
+        self.socket
+    }
+
+    /// Stream whose traffic is recorded for the request log
+    pub(crate) fn recorded(stream: UnixStream, recorder: Arc<Recorder>) -> Self {
+        let mut stream = IpcStream::from(stream);
+        stream.recorder.replace(recorder);
+        stream
+    }
+
+    /// Count the bytes exchanged by the stream in the provided counters
+    pub(crate) fn set_traffic(&mut self, traffic: Arc<Traffic>) {
+        self.traffic.replace(traffic);
+    }
+
+    /// Receive the rest of the message being read from a `SOCK_SEQPACKET` socket, or the next
+    /// message whole. An empty payload is returned at the end of the connection, as for an empty
+    /// message. Fails with `InvalidInput` on other sockets
+    pub fn recv_message(&self) -> io::Result<Vec<u8>> {
+        let mut message = self
+            .message
+            .as_ref()
+            .ok_or_else(not_seqpacket)?
+            .lock()
+            .unwrap();
+
+        if message.remaining().is_empty() {
+            recv_message(&self.socket, &mut message, 0)?;
+        }
+
+        Ok(self.take_message(&mut message))
+    }
+
+    /// [`IpcStream::recv_message`] without blocking, registering the waker of the task if no
+    /// message is available
+    pub fn poll_recv_message(&self, cx: &mut Context<'_>) -> Poll<io::Result<Vec<u8>>> {
+        let message = match self.message.as_ref() {
+            Some(m) => m,
+            None => return Poll::Ready(Err(not_seqpacket())),
+        };
+
+        let mut message = message.lock().unwrap();
+        if message.remaining().is_empty() {
+            let received = self.poll_io(cx, Interest::Readable, || {
+                recv_message(&self.socket, &mut message, libc::MSG_DONTWAIT)
+            });
+
+            if received?.is_pending() {
+                return Poll::Pending;
+            }
+        }
+
+        Poll::Ready(Ok(self.take_message(&mut message)))
+    }
+
+    fn take_message(&self, message: &mut Received) -> Vec<u8> {
+        let payload = message.remaining().to_vec();
+        message.read = message.data.len();
+        self.count_received(&payload);
+
+        payload
+    }
+
+    /// Read from the message being read, receiving the next one whole once it's consumed
+    fn read_message(&self, buf: &mut [u8], flags: libc::c_int) -> io::Result<usize> {
+        let mut message = match self.message.as_ref() {
+            Some(m) => m.lock().unwrap(),
+            None => return Err(not_seqpacket()),
+        };
+
+        if message.remaining().is_empty() && !buf.is_empty() {
+            recv_message(&self.socket, &mut message, flags)?;
+        }
+
+        let n = message.remaining().len().min(buf.len());
+        buf[..n].copy_from_slice(&message.remaining()[..n]);
+        message.read += n;
+
+        Ok(n)
+    }
+
+    /// Resolve once a read wouldn't block, registering the waker of the task otherwise. For
+    /// providers reading the socket by other means than [`AsyncRead`], such as
+    /// [`crate::recv_fds`]
+    pub fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.source
+            .poll_ready(self.as_raw_fd(), Interest::Readable, cx.waker())
+    }
+
+    /// Resolve once a write wouldn't block, registering the waker of the task otherwise
+    pub fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.source
+            .poll_ready(self.as_raw_fd(), Interest::Writable, cx.waker())
+    }
+
+    /// Retry the operation until it doesn't fail with `EINTR`, registering the waker of the task
+    /// for the interest if it would block
+    fn poll_io<F>(
+        &self,
+        cx: &mut Context<'_>,
+        interest: Interest,
+        mut f: F,
+    ) -> Poll<io::Result<usize>>
+    where
+        F: FnMut() -> io::Result<usize>,
+    {
+        loop {
+            match f() {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return match self.source.register(self.as_raw_fd(), interest, cx.waker()) {
+                        Ok(_) => Poll::Pending,
+                        Err(e) => Poll::Ready(Err(e)),
+                    }
+                }
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    fn count_received(&self, buf: &[u8]) {
+        if let Some(r) = self.recorder.as_ref() {
+            r.received(buf);
+        }
+
+        if let Some(t) = self.traffic.as_ref() {
+            t.received(buf.len());
+        }
+    }
+
+    fn received(&self, buf: &[u8], n: io::Result<usize>) -> io::Result<usize> {
+        if let Ok(n) = n.as_ref() {
+            self.count_received(&buf[..*n]);
+        }
+
+        n
+    }
+
+    fn received_vectored(
+        &self,
+        bufs: &[IoSliceMut<'_>],
+        n: io::Result<usize>,
+    ) -> io::Result<usize> {
+        if let Ok(n) = n.as_ref() {
+            let mut remaining = *n;
+            for buf in bufs {
+                let len = remaining.min(buf.len());
+                self.count_received(&buf[..len]);
+                remaining -= len;
+            }
+        }
+
+        n
+    }
+
+    fn sent(&self, n: io::Result<usize>) -> io::Result<usize> {
+        if let Ok(n) = n.as_ref() {
+            if let Some(r) = self.recorder.as_ref() {
+                r.sent(*n);
+            }
+
+            if let Some(t) = self.traffic.as_ref() {
+                t.sent(*n);
+            }
+        }
+
+        n
+    }
+}
+
+impl From<UnixStream> for IpcStream {
+    fn from(stream: UnixStream) -> Self {
+        #[cfg(any(target_vendor = "apple", target_os = "freebsd"))]
+        set_nosigpipe(&stream).unwrap_or_else(|e| {
+            error!("Error disabling SIGPIPE on the socket: {}", e);
+        });
+
+        let message = is_seqpacket(&stream).then(Default::default);
+
+        IpcStream {
+            socket: stream,
+            recorder: None,
+            traffic: None,
+            source: Source::default(),
+            message,
+        }
+    }
+}
+
+fn not_seqpacket() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "The socket is not a SOCK_SEQPACKET socket",
+    )
+}
+
+fn is_seqpacket(stream: &UnixStream) -> bool {
+    let mut kind: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut kind as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    ret == 0 && kind == libc::SOCK_SEQPACKET
+}
+
+/// Receive the next message of a `SOCK_SEQPACKET` socket whole, peeking at it with a buffer grown
+/// until it isn't truncated, returning its length
+fn recv_message(
+    stream: &UnixStream,
+    message: &mut Received,
+    flags: libc::c_int,
+) -> io::Result<usize> {
+    message.read = 0;
+    message.data.clear();
+
+    let mut capacity = message.data.capacity().max(MESSAGE_RESERVE);
+    loop {
+        message.data.resize(capacity, 0x00);
+
+        let mut iov = libc::iovec {
+            iov_base: message.data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: message.data.len(),
+        };
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+
+        let ret = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, flags | libc::MSG_PEEK) };
+        if ret < 0 {
+            message.data.clear();
+            return Err(io::Error::last_os_error());
+        }
+
+        if msg.msg_flags & libc::MSG_TRUNC == 0 {
+            break;
+        }
+
+        capacity *= 2;
+    }
+
+    // Peeked whole, so it's received right away
+    let ret = unsafe {
+        libc::recv(
+            stream.as_raw_fd(),
+            message.data.as_mut_ptr() as *mut libc::c_void,
+            message.data.len(),
+            flags,
+        )
+    };
+
+    if ret < 0 {
+        message.data.clear();
+        return Err(io::Error::last_os_error());
+    }
+
+    message.data.truncate(ret as usize);
+    Ok(ret as usize)
+}
+
+/// Platforms without `MSG_NOSIGNAL` disable the signal per socket instead
+#[cfg(any(target_vendor = "apple", target_os = "freebsd"))]
+fn set_nosigpipe(stream: &UnixStream) -> io::Result<()> {
+    let on: libc::c_int = 1;
+
+    let ret = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_NOSIGPIPE,
+            &on as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Write the whole buffer to a socket not wrapped in an [`IpcStream`] yet, without raising
+/// `SIGPIPE`
+pub(crate) fn send_all(stream: &UnixStream, mut buf: &[u8]) -> io::Result<()> {
+    #[cfg(any(target_vendor = "apple", target_os = "freebsd"))]
+    set_nosigpipe(stream)?;
+
+    while !buf.is_empty() {
+        match send(stream, buf) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => buf = &buf[n..],
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+fn send(stream: &UnixStream, buf: &[u8]) -> io::Result<usize> {
+    send_with(stream, buf, SEND_FLAGS)
+}
+
+fn send_with(stream: &UnixStream, buf: &[u8], flags: libc::c_int) -> io::Result<usize> {
+    let ret = unsafe {
+        libc::send(
+            stream.as_raw_fd(),
+            buf.as_ptr() as *const libc::c_void,
+            buf.len(),
+            flags,
+        )
+    };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ret as usize)
+}
+
+/// Read without blocking, whether the socket is in non-blocking mode or not
+fn recv_nonblocking(stream: &UnixStream, buf: &mut [u8]) -> io::Result<usize> {
+    let ret = unsafe {
+        libc::recv(
+            stream.as_raw_fd(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+            libc::MSG_DONTWAIT,
+        )
+    };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ret as usize)
+}
+
+fn send_vectored(stream: &UnixStream, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+    let bufs = &bufs[..bufs.len().min(MAX_IOV)];
+
+    // IoSlice is guaranteed to be ABI compatible with iovec
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = bufs.as_ptr() as *mut libc::iovec;
+    msg.msg_iovlen = bufs.len() as _;
+
+    let ret = unsafe { libc::sendmsg(stream.as_raw_fd(), &msg, SEND_FLAGS) };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ret as usize)
+}
+
+impl Deref for IpcStream {
+    type Target = UnixStream;
+
+    fn deref(&self) -> &UnixStream {
+        &self.socket
+    }
+}
+
+impl DerefMut for IpcStream {
+    fn deref_mut(&mut self) -> &mut UnixStream {
+        &mut self.socket
+    }
+}
+
+impl fmt::Debug for IpcStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.socket.fmt(f)
+    }
+}
+
+impl AsRawFd for IpcStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for IpcStream {
+    fn into_raw_fd(self) -> RawFd {
+        self.socket.into_raw_fd()
+    }
+}
+
+impl Read for IpcStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        (&*self).read_vectored(bufs)
+    }
+}
+
+impl Read for &IpcStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match self.message {
+            Some(_) => self.read_message(buf, 0),
+            None => (&self.socket).read(buf),
+        };
+        self.received(buf, n)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        if self.message.is_some() {
+            return match bufs.iter_mut().find(|b| !b.is_empty()) {
+                Some(buf) => self.read(buf),
+                None => Ok(0),
+            };
+        }
+
+        let n = (&self.socket).read_vectored(bufs);
+        self.received_vectored(bufs, n)
+    }
+}
+
+impl Write for IpcStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        (&*self).write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush()
+    }
+}
+
+impl Write for &IpcStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sent(send(&self.socket, buf))
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.sent(send_vectored(&self.socket, bufs))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&self.socket).flush()
+    }
+}
+
+impl AsyncRead for IpcStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_read(cx, buf)
+    }
+}
+
+impl AsyncRead for &IpcStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let stream = *self;
+        stream
+            .poll_io(cx, Interest::Readable, || match stream.message {
+                Some(_) => stream.read_message(buf, libc::MSG_DONTWAIT),
+                None => recv_nonblocking(&stream.socket, buf),
+            })
+            .map(|n| stream.received(buf, n))
+    }
+}
+
+impl AsyncWrite for IpcStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_close(cx)
+    }
+}
+
+impl AsyncWrite for &IpcStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let stream = *self;
+        stream
+            .poll_io(cx, Interest::Writable, || {
+                send_with(&stream.socket, buf, SEND_FLAGS | libc::MSG_DONTWAIT)
+            })
+            .map(|n| stream.sent(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Shut the writing half down, so the peer observes end of stream
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.socket.shutdown(Shutdown::Write))
+    }
+}
@@ -0,0 +1,96 @@
+use crate::Address;
+
+use std::{
+    env,
+    io::{self, Error as IoError},
+    mem,
+    os::unix::{
+        io::{FromRawFd, RawFd},
+        net::UnixListener,
+    },
+    process,
+};
+
+/// First descriptor passed by systemd, see `sd_listen_fds(3)`
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Variables set by systemd for the activated process
+const LISTEN_PID: &str = "LISTEN_PID";
+const LISTEN_FDS: &str = "LISTEN_FDS";
+const LISTEN_FDNAMES: &str = "LISTEN_FDNAMES";
+
+/// Adopt the listener passed by systemd socket activation, if any.
+///
+/// The variables are removed, so the processes spawned later don't try to adopt it again. Only
+/// the first descriptor is adopted, and it must be a `SOCK_STREAM` unix socket.
+pub(crate) fn inherited() -> Result<Option<(Address, UnixListener)>, IoError> {
+    let pid = env::var(LISTEN_PID).ok();
+    let fds = env::var(LISTEN_FDS).ok();
+
+    env::remove_var(LISTEN_PID);
+    env::remove_var(LISTEN_FDS);
+    env::remove_var(LISTEN_FDNAMES);
+
+    // Set for another process, inherited through a fork
+    if pid.and_then(|p| p.parse::<u32>().ok()) != Some(process::id()) {
+        return Ok(None);
+    }
+
+    let fds: RawFd = fds
+        .and_then(|f| f.parse().ok())
+        .ok_or_else(|| IoError::new(io::ErrorKind::InvalidData, "Invalid LISTEN_FDS"))?;
+
+    if fds < 1 {
+        return Ok(None);
+    } else if fds > 1 {
+        warn!(
+            "{} descriptors passed by systemd, only the first one is adopted",
+            fds
+        );
+    }
+
+    let fd = LISTEN_FDS_START;
+    if socket_option(fd, libc::SO_TYPE)? != libc::SOCK_STREAM {
+        return Err(IoError::new(
+            io::ErrorKind::InvalidInput,
+            "The descriptor passed by systemd is not a unix stream socket",
+        ));
+    }
+
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    // Fails if the socket is not a unix one
+    let listener = unsafe { UnixListener::from_raw_fd(fd) };
+    let address = Address::of(&listener.local_addr()?).ok_or_else(|| {
+        IoError::new(
+            io::ErrorKind::InvalidInput,
+            "The socket passed by systemd is not bound",
+        )
+    })?;
+
+    info!("UDS listener of {} adopted from systemd", address);
+    Ok(Some((address, listener)))
+}
+
+fn socket_option(fd: RawFd, option: libc::c_int) -> Result<libc::c_int, IoError> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if ret < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    Ok(value)
+}
@@ -0,0 +1,256 @@
+use crate::{
+    listener::{self, Acceptor},
+    routes::Routes,
+    uds, Address, Message, Options, ServerHandle, TaskProvider, UdsError,
+};
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Error as IoError},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Set of UDS sharing a single worker pool, one per tenant.
+///
+/// Every tenant is bound to `<dir>/<id>.sock` and has its own provider, so its state is not shared
+/// with the other tenants. Tenants can be added and removed while the workers are running.
+pub struct TenantManager<T: TaskProvider + 'static> {
+    dir: PathBuf,
+    handle: ServerHandle,
+    registry: Arc<Registry<T>>,
+    acceptors: Mutex<HashMap<String, Acceptor>>,
+}
+
+impl<T: TaskProvider> TenantManager<T> {
+    /// Create the directory, if it doesn't exist, and spawn the workers with no tenant.
+    pub fn start<P: Into<PathBuf>>(dir: P, options: Option<Options>) -> Result<Self, IoError> {
+        let dir = dir.into();
+        let options = options.unwrap_or_default();
+        fs::create_dir_all(dir.as_path())?;
+
+        let registry = Arc::new(Registry::default());
+        let routes = Routes::tenants(TenantRoutes::new(Arc::clone(&registry)));
+        let handle = uds::spawn_workers(&options, &routes);
+
+        Ok(TenantManager {
+            dir,
+            handle,
+            registry,
+            acceptors: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Path of the socket of the provided tenant
+    pub fn tenant_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.sock", id))
+    }
+
+    /// Bind the socket of a new tenant and start accepting there, handling its sockets with the
+    /// provided provider. Will fail if the tenant already exists.
+    pub fn add_tenant(&self, id: &str, provider: T) -> Result<(), IoError> {
+        validate_id(id)?;
+
+        let mut acceptors = self.acceptors.lock().unwrap();
+        if acceptors.contains_key(id) {
+            return Err(IoError::new(
+                io::ErrorKind::AlreadyExists,
+                format!("The tenant {} already exists", id),
+            ));
+        }
+
+        let path = self.tenant_path(id);
+        let address = Address::Path(path.clone());
+        let listener = listener::bind(&address, &self.handle.bind_settings())?;
+
+        // Registered before accepting, so the workers never receive a socket of an unknown tenant
+        self.registry.insert(path.clone(), provider);
+
+        match Acceptor::spawn(listener, address, self.handle.accept_context()) {
+            Ok(acceptor) => {
+                acceptors.insert(id.to_owned(), acceptor);
+                info!("Tenant {} added", id);
+                Ok(())
+            }
+
+            Err(e) => {
+                self.registry.remove(path.as_path());
+                fs::remove_file(path.as_path()).unwrap_or_default();
+                Err(e)
+            }
+        }
+    }
+
+    /// Stop accepting on the socket of the tenant, close its listener and remove its path.
+    ///
+    /// The sockets of the tenant in progress are handled to completion, and the ones still waiting
+    /// in the queue or in the listener backlog are closed.
+    pub fn remove_tenant(&self, id: &str) -> Result<(), IoError> {
+        let acceptor = self.acceptors.lock().unwrap().remove(id).ok_or_else(|| {
+            IoError::new(
+                io::ErrorKind::NotFound,
+                format!("The tenant {} doesn't exist", id),
+            )
+        })?;
+
+        let path = self.tenant_path(id);
+        let stopped = acceptor.shutdown(self.handle.accept_shutdown_timeout());
+
+        self.registry.remove(path.as_path());
+        fs::remove_file(path.as_path())?;
+        info!("Tenant {} removed", id);
+
+        stopped.map_err(IoError::from)
+    }
+
+    /// Ids of the current tenants
+    pub fn tenants(&self) -> Vec<String> {
+        self.acceptors.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Handle to the shared workers, to push tasks or take a snapshot of their counters
+    pub fn handle(&self) -> &ServerHandle {
+        &self.handle
+    }
+
+    /// Block until all the workers are finished.
+    ///
+    /// Then the listener of every tenant is closed and its path removed.
+    pub fn join(self) -> Result<(), UdsError> {
+        self.handle.wait_workers();
+
+        let timeout = self.handle.accept_shutdown_timeout();
+        let mut result = Ok(());
+
+        for (id, acceptor) in self.acceptors.into_inner().unwrap() {
+            let path = self.dir.join(format!("{}.sock", id));
+
+            if let Err(e) = acceptor
+                .shutdown(timeout)
+                .and_then(|_| fs::remove_file(path.as_path()).map_err(UdsError::from))
+            {
+                error!("Error closing the listener of the tenant {}: {}", id, e);
+                result = Err(e);
+            }
+        }
+
+        self.handle.join().and(result)
+    }
+}
+
+/// Ids are used as file names inside the tenants directory
+fn validate_id(id: &str) -> Result<(), IoError> {
+    if id.is_empty() || id == "." || id == ".." || id.contains('/') || id.contains('\0') {
+        return Err(IoError::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid tenant id: {:?}", id),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Providers of the tenants, shared by the manager and the workers.
+///
+/// Every change bumps the version, so the workers know when to refresh their own copies. Every
+/// provider is tagged with a generation, so a tenant removed and added again is not confused with
+/// the previous one.
+pub(crate) struct Registry<T> {
+    providers: Mutex<HashMap<PathBuf, (u64, T)>>,
+    version: AtomicU64,
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Registry {
+            providers: Mutex::new(HashMap::new()),
+            version: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<T> Registry<T> {
+    pub(crate) fn insert(&self, path: PathBuf, provider: T) {
+        let mut providers = self.providers.lock().unwrap();
+        let generation = self.version.load(Ordering::Relaxed) + 1;
+
+        providers.insert(path, (generation, provider));
+        self.version.store(generation, Ordering::Release);
+    }
+
+    fn remove(&self, path: &Path) {
+        let mut providers = self.providers.lock().unwrap();
+
+        providers.remove(path);
+        self.version.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Copies of the tenant providers owned by a worker, refreshed from the registry when it changes
+#[derive(Clone)]
+pub struct TenantRoutes<T> {
+    registry: Arc<Registry<T>>,
+    version: u64,
+    providers: HashMap<PathBuf, (u64, T)>,
+}
+
+impl<T: TaskProvider> TenantRoutes<T> {
+    pub(crate) fn new(registry: Arc<Registry<T>>) -> Self {
+        TenantRoutes {
+            registry,
+            version: 0,
+            providers: HashMap::new(),
+        }
+    }
+
+    /// Provider of the tenant whose listener accepted the socket, if it still exists
+    pub fn select(&mut self, stream: &UnixStream) -> Option<&T> {
+        self.sync();
+
+        // The local address of an accepted socket is the path of its listener
+        let path = match stream.local_addr() {
+            Ok(addr) => addr.as_pathname()?.to_path_buf(),
+            Err(e) => {
+                warn!(
+                    "Error fetching the listener path to route the socket: {}",
+                    e
+                );
+                return None;
+            }
+        };
+
+        self.providers.get(&path).map(|(_, p)| p)
+    }
+
+    /// Deliver the message to the provider of every tenant
+    pub fn handle_message(&mut self, message: &Message) {
+        self.sync();
+        self.providers
+            .values_mut()
+            .for_each(|(_, p)| p.handle_message(message));
+    }
+
+    fn sync(&mut self) {
+        let version = self.registry.version.load(Ordering::Acquire);
+        if version == self.version {
+            return;
+        }
+
+        let shared = self.registry.providers.lock().unwrap();
+
+        self.providers
+            .retain(|path, (g, _)| shared.get(path).is_some_and(|(s, _)| s == g));
+        for (path, (g, p)) in shared.iter() {
+            self.providers
+                .entry(path.clone())
+                .or_insert_with(|| (*g, p.clone()));
+        }
+
+        self.version = version;
+    }
+}
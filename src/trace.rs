@@ -0,0 +1,67 @@
+use crate::fd;
+
+use std::{collections::VecDeque, os::unix::io::AsRawFd, sync::Mutex, time::Duration};
+
+/// Stage of the lifecycle of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Accepted from the listener, created with [`crate::ServerHandle::connect_pair`] or handed
+    /// over with [`crate::Message::Handover`]
+    Accepted,
+    /// Pushed to the queue, waiting for a worker
+    Queued,
+    /// The provider future is being polled
+    Polling,
+    /// The provider future returned [`std::task::Poll::Pending`] and waits to be woken
+    Pending,
+    /// The provider future resolved
+    Finished,
+    /// Dropped by the worker without reaching a provider
+    Cancelled,
+}
+
+/// Transition of a connection, recorded when [`crate::Options::trace_connections`] is set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionEvent {
+    /// Identifier of the connection, the inode of its socket. Unique while the socket is open
+    pub connection: u64,
+    /// State the connection entered
+    pub state: ConnectionState,
+    /// Time since the UDS was started
+    pub at: Duration,
+}
+
+/// Ring buffer with the most recent connection events
+pub struct Trace {
+    capacity: usize,
+    events: Mutex<VecDeque<ConnectionEvent>>,
+}
+
+impl Trace {
+    pub fn new(capacity: usize) -> Self {
+        Trace {
+            capacity: capacity.max(1),
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Record an event, dropping the oldest one if the buffer is full
+    pub fn record(&self, event: ConnectionEvent) {
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+
+        events.push_back(event);
+    }
+
+    /// Recorded events, from the oldest to the most recent
+    pub fn events(&self) -> Vec<ConnectionEvent> {
+        self.events.lock().unwrap().iter().copied().collect()
+    }
+}
+
+/// Identifier of the connection of the socket, see [`ConnectionEvent::connection`]
+pub fn connection_id<S: AsRawFd>(socket: &S) -> u64 {
+    fd::inode(socket.as_raw_fd()).unwrap_or_default()
+}
@@ -1,103 +1,418 @@
-use crate::{worker::worker, Options, Task, TaskProvider};
+use crate::{
+    events::EventBus,
+    handle::WorkerDone,
+    idle,
+    listener::{self, AcceptSettings, BindSettings, ServerInfo},
+    queue::Queue,
+    reaper::Reaper,
+    restart,
+    revalidate::Revalidator,
+    routes::Routes,
+    scale,
+    signals::SignalGuard,
+    stats::Stats,
+    systemd,
+    validate::{self, ValidationReport},
+    warmup,
+    worker::{panic_message, worker, WorkerSettings},
+    Address, BoundListener, Options, ServerEvent, ServerHandle, ShutdownHandle, ShutdownReason,
+    TaskProvider, UdsError,
+};
 
 use std::{
-    fs,
     io::{self, Error as IoError},
-    os::unix::net::UnixListener,
-    path::PathBuf,
-    sync::{mpsc, Arc, Mutex},
+    os::unix::net::{UnixListener, UnixStream},
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc},
     thread,
 };
 
-/// Boilerplate for [`UnixListener`].
+/// Boilerplate for [`std::os::unix::net::UnixListener`].
 ///
 /// Will receive a path to bind to, a set of options and an implementation of future that will
 /// handle the incoming sockets.
 pub struct UnixDomainSocket<T: TaskProvider + 'static> {
-    path: PathBuf,
+    address: Address,
+    listener: Option<UnixListener>,
+    /// Set when the path belongs to a systemd socket unit, so it is not removed
+    keep_path: bool,
     options: Options,
-    provider: T,
+    routes: Routes<T>,
 }
 
 impl<T: TaskProvider> UnixDomainSocket<T> {
     /// Default constructor.
-    pub fn new<P: Into<PathBuf>>(path: P, options: Option<Options>, provider: T) -> Self {
-        let path = path.into();
-        let options = options.unwrap_or(Options::default());
+    ///
+    /// The address is a filesystem path, or a name in the abstract namespace of Linux, written
+    /// as `@name`. See [`Address`].
+    pub fn new<A: Into<Address>>(address: A, options: Option<Options>, provider: T) -> Self {
+        let address = address.into();
+        let options = options.unwrap_or_default();
 
         UnixDomainSocket {
-            path,
+            address,
+            listener: None,
+            keep_path: false,
             options,
-            provider,
+            routes: Routes::new(provider),
         }
     }
 
-    /// Will remove the [`UnixDomainSocket::path`], if it exists, so it cant bind properly to that
+    /// Handle the sockets of peers with the provided effective user id with a different provider.
+    ///
+    /// The peer credentials are fetched with `SO_PEERCRED` before the socket is handed to a
+    /// provider. Rules by uid take precedence over the ones by gid, and peers matching no rule are
+    /// handled by the provider passed to [`UnixDomainSocket::new`].
+    pub fn route_uid(mut self, uid: libc::uid_t, provider: T) -> Self {
+        self.routes.uid.push((uid, provider));
+        self
+    }
+
+    /// Handle the sockets of peers with the provided effective group id with a different provider.
+    ///
+    /// See [`UnixDomainSocket::route_uid`].
+    pub fn route_gid(mut self, gid: libc::gid_t, provider: T) -> Self {
+        self.routes.gid.push((gid, provider));
+        self
+    }
+
+    /// Register a provider under the provided name, so the other providers can hand their sockets
+    /// over to it by resolving to [`crate::Message::Handoff`].
+    ///
+    /// The registered provider is not selected for new sockets.
+    pub fn register_provider<N: Into<String>>(mut self, name: N, provider: T) -> Self {
+        self.routes.named.push((name.into(), provider));
+        self
+    }
+
+    /// Adopt the listener handed over by [`crate::restart::handover`] in the parent process.
+    ///
+    /// Returns `None` if the process was not spawned by a handover, in which case the UDS should
+    /// be created with [`UnixDomainSocket::new`].
+    pub fn from_handover(options: Option<Options>, provider: T) -> Result<Option<Self>, IoError> {
+        Ok(restart::inherited()?.map(|(address, listener)| {
+            let mut uds = UnixDomainSocket::new(address, options, provider);
+            uds.listener.replace(listener);
+            uds
+        }))
+    }
+
+    /// Serve a listener already bound, such as one re-created from an inherited descriptor with
+    /// [`BoundListener::from_raw_fd`]. The path is not removed before serving it
+    pub fn from_listener(listener: BoundListener, options: Option<Options>, provider: T) -> Self {
+        let (address, listener) = listener.into_parts();
+
+        let mut uds = UnixDomainSocket::new(address, options, provider);
+        uds.listener.replace(listener);
+        uds
+    }
+
+    /// Adopt the listener passed by systemd socket activation, with `LISTEN_FDS` and
+    /// `LISTEN_PID`.
+    ///
+    /// Returns `None` if the process was not activated by systemd, in which case the UDS should
+    /// be created with [`UnixDomainSocket::new`]. Only the first descriptor is adopted, and the
+    /// variables are removed so spawned processes don't adopt it again. The path belongs to the
+    /// socket unit, and is never removed.
+    pub fn from_systemd(options: Option<Options>, provider: T) -> Result<Option<Self>, IoError> {
+        Ok(systemd::inherited()?.map(|(address, listener)| {
+            let mut uds = UnixDomainSocket::new(address, options, provider);
+            uds.listener.replace(listener);
+            uds.keep_path = true;
+            uds
+        }))
+    }
+
+    /// Unless the listener was adopted from another process, will remove the file at the
+    /// [`Address::as_path`] it is bound to, if it exists, so it cant bind properly to that
     /// location.
     ///
     /// If the future returns a [`crate::Message::ShouldQuit`], the worker threads will be finished after
-    /// the current queue of sockets and the main loop will end.
-    pub fn bind(self) -> Result<(), IoError> {
-        // Grant the provided path is available to the process
-        if self.path.as_path().exists() {
-            fs::remove_file(self.path.as_path())?;
-        }
+    /// the current queue of sockets and the main loop will end. So will they on a stop signal, with
+    /// [`Options::handle_signals`].
+    pub fn bind(self) -> Result<(), UdsError> {
+        let signals = self.options.handle_signals;
+        let (handle, _signals) = install_signals(self.start()?, signals)?;
 
-        // Prepare the path to bind
-        let path = self
-            .path
-            .to_str()
-            .map(|p| Ok(p))
-            .unwrap_or(Err(IoError::new(
-                io::ErrorKind::Other,
-                "Invalid path returned by the buffer",
-            )))?;
-
-        // Create the task queue channel that will be share amongst the worker threads
-        let (tx, rx) = mpsc::channel();
-        let rx = Mutex::new(rx);
-        let rx = Arc::new(rx);
-
-        // Perform the bind
-        let listener = UnixListener::bind(path)?;
-        info!("UnixDomainSocket bound on {}", path);
-
-        // Spawn the workers, each opne with an ownership to the queue channel, and the future
-        // provider
-        let workers: Vec<thread::JoinHandle<_>> = (0..self.options.workers)
-            .map(|_| {
-                let t = tx.clone();
-                let r = Arc::clone(&rx);
-                let p = self.provider.clone();
-
-                thread::spawn(move || worker(t, r, p))
-            })
-            .collect();
-
-        // Spawn a thread to perform the actual listening.
-        //
-        // When there is an incoming socket, transform it to a Task and send to the queue channel
-        let t = tx.clone();
+        handle.join()
+    }
+
+    /// Same as [`UnixDomainSocket::bind`], but runs in the background and returns a
+    /// [`ShutdownHandle`] to stop it from another thread.
+    ///
+    /// Once the workers are finished, however they were asked to quit, the listener is closed
+    /// and the path is removed, unless it was adopted from systemd.
+    pub fn bind_with_handle(self) -> Result<ShutdownHandle, UdsError> {
+        let address = Some(self.address.clone()).filter(|_| !self.keep_path);
+        let signals = self.options.handle_signals;
+        let (handle, guard) = install_signals(self.start()?, signals)?;
+        let shutdown = handle.shutdown_handle();
+
+        let s = shutdown.clone();
         thread::spawn(move || {
-            for socket in listener.incoming() {
-                socket
-                    .and_then(|s| {
-                        t.send(Task::Socket(s))
-                            .map_err(|e| IoError::new(io::ErrorKind::Other, e))
-                    })
-                    .unwrap_or_else(|e| {
-                        error!("Error receiving the UDS socket: {}", e);
+            let _signals = guard;
+            let outcome =
+                handle
+                    .join()
+                    .and_then(|_| match address.map_or(Ok(()), |a| a.remove_file()) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+                        _ => Ok(()),
                     });
-            }
+
+            s.finish(outcome);
         });
 
-        // Wait until all the workers are finished
-        for w in workers {
-            w.join().unwrap_or_else(|e| {
-                error!("Error ending the worker thread gracefully: {:?}", e);
-            });
+        Ok(shutdown)
+    }
+
+    /// Same as [`UnixDomainSocket::bind`], but will return as soon as the workers are spawned.
+    ///
+    /// The returned [`ServerHandle`] can be used to interact with the running workers, and to wait
+    /// for them to finish.
+    pub fn start(mut self) -> Result<ServerHandle, UdsError> {
+        if let Some(validation) = self.options.validation {
+            validate::enforce(self.validate(), validation)?;
+        }
+
+        let listener = match self.listener.take() {
+            Some(l) => l,
+            None => listener::bind(&self.address, &BindSettings::of(&self.options))?,
+        };
+
+        if let Some(warm_up) = self.options.warm_up.as_ref() {
+            warmup::run(warm_up, self.options.warm_up_timeout)?;
+            info!("UDS warm-up finished");
+        }
+
+        let handle = spawn_workers(&self.options, &self.routes);
+        let watched = self.address.as_path().is_some();
+        handle.listen(listener, self.address)?;
+
+        if let Some(check) = self.options.takeover_check.filter(|_| watched) {
+            handle.watch_takeover(check)?;
+        }
+
+        Ok(handle)
+    }
+
+    /// Check the path and the options for misconfiguration, without binding.
+    ///
+    /// Covers the length of the path, the permissions of its directory, another instance already
+    /// accepting on it, the descriptor limit of the process and the consistency of the options.
+    /// The path is not checked if the listener was adopted from another process. Another instance
+    /// is detected by connecting to the path, so it receives an empty connection.
+    pub fn validate(&self) -> ValidationReport {
+        let path = self
+            .listener
+            .is_none()
+            .then(|| self.address.as_path())
+            .flatten();
+        validate::validate(path, &self.options)
+    }
+
+    /// Spawn the workers without binding any path.
+    ///
+    /// The returned stream is connected to the workers over an in-memory socket pair, and more
+    /// connections can be created with [`ServerHandle::connect_pair`]. Meant for end-to-end tests
+    /// of providers.
+    pub fn loopback(
+        options: Option<Options>,
+        provider: T,
+    ) -> Result<(ServerHandle, UnixStream), IoError> {
+        let options = options.unwrap_or_default();
+        let handle = spawn_workers(&options, &Routes::new(provider));
+        let client = handle.connect_pair()?;
+
+        Ok((handle, client))
+    }
+}
+
+/// Install the handlers of [`Options::handle_signals`], if enabled, shutting the started UDS down
+/// on a stop signal. If they can't be installed, the UDS is stopped before returning the error
+fn install_signals(
+    handle: ServerHandle,
+    enabled: bool,
+) -> Result<(ServerHandle, Option<SignalGuard>), UdsError> {
+    if !enabled {
+        return Ok((handle, None));
+    }
+
+    let shutdown = handle.shutdown_handle();
+    let installed =
+        SignalGuard::install(move |signal| shutdown.request_for(ShutdownReason::Signal(signal)));
+
+    match installed {
+        Ok(guard) => Ok((handle, Some(guard))),
+        Err(e) => {
+            error!("Error installing the signal handlers: {}", e);
+            handle
+                .shutdown_handle()
+                .request_for(ShutdownReason::Fatal(e.to_string()));
+            handle.join().unwrap_or_default();
+            Err(e.into())
         }
+    }
+}
+
+/// Spawn the workers, each opne with an ownership to the queue, and the future provider
+pub(crate) fn spawn_workers<T: TaskProvider + 'static>(
+    options: &Options,
+    routes: &Routes<T>,
+) -> ServerHandle {
+    let events = Arc::new(EventBus::default());
+    let min_workers = options.min_workers.unwrap_or(options.workers).max(1);
+    let max_workers = options.max_workers.unwrap_or(0).max(options.workers);
+    let elastic = min_workers < options.workers || max_workers > options.workers;
+
+    // Create the task queue that will be share amongst the worker threads
+    let queue = Arc::new(Queue::new(
+        options.workers,
+        (min_workers, max_workers),
+        &options.worker_groups,
+        options.max_in_flight,
+        Arc::clone(&options.clock),
+        Arc::clone(&events),
+    ));
+    let stats = Arc::new(Stats::new(
+        max_workers,
+        options.trace_connections,
+        options.max_connections_per_uid,
+        options.account_traffic,
+        Arc::clone(&options.clock),
+        events,
+    ));
+    let reaper = options
+        .max_connection_age
+        .map(|age| Reaper::spawn(age, Arc::clone(&stats)));
+    let revalidator = options
+        .peer_revalidation
+        .map(|policy| Revalidator::spawn(policy, Arc::clone(&stats)));
+
+    // Each worker reports back through the done channel when it finishes, since the executor
+    // provides no join handles
+    let (done_tx, done_rx) = mpsc::channel();
+    let server = ServerInfo {
+        started: options.clock.system_time(),
+        workers: options.workers,
+        max_concurrent_per_worker: options.max_concurrent_per_worker,
+    };
+    let settings = WorkerSettings {
+        max_concurrent: options.max_concurrent_per_worker.max(1),
+        check_fd_leaks: options.check_fd_leaks,
+        connection_scratch: options.connection_scratch,
+        memory_budget: options.memory_budget,
+        linger: options.linger,
+        read_timeout: options.read_timeout,
+        write_timeout: options.write_timeout,
+        drain_on_close: options.drain_on_close,
+        server,
+        lost_waker: options.lost_waker,
+        poll_budget: options.poll_budget,
+        drain_policy: options.drain_policy,
+        request_log: options.request_log,
+        on_complete: options.on_complete,
+        retire_after: elastic.then_some(options.scaling.scale_down_after),
+    };
+    for group in options.worker_groups.iter() {
+        info!(
+            "{} workers reserved for the group {}, serving {} listeners",
+            group.workers,
+            group.name,
+            group.listeners.len()
+        );
+    }
+
+    let respawn = options.respawn_workers;
+    let executor = Arc::clone(&options.executor);
+    let (q, p, a, r, s) = (
+        Arc::clone(&queue),
+        routes.clone(),
+        reaper.clone(),
+        revalidator.clone(),
+        Arc::clone(&stats),
+    );
+
+    // Holds a sender of the done channel, so the workers are only joined once it is dropped
+    let spawn = move |id: usize| {
+        let q = Arc::clone(&q);
+        let p = p.clone();
+        let a = a.clone();
+        let r = r.clone();
+        let s = Arc::clone(&s);
+        let d = WorkerDone(done_tx.clone());
+
+        executor.spawn(Box::new(move || {
+            let _done = d;
+
+            // Respawned in place, so the executor keeps a single job per worker
+            loop {
+                let run = || {
+                    let (q, p, a, r, s) = (
+                        Arc::clone(&q),
+                        p.clone(),
+                        a.clone(),
+                        r.clone(),
+                        Arc::clone(&s),
+                    );
+                    worker(id, q, p, a, r, s, settings)
+                };
 
-        Ok(info!("Unbinding UDS"))
+                match panic::catch_unwind(AssertUnwindSafe(run)) {
+                    Ok(()) => break,
+
+                    Err(payload) if respawn && !q.is_closed() => {
+                        error!(
+                            "Worker {} panicked, respawning it: {}",
+                            id,
+                            panic_message(payload.as_ref())
+                                .as_deref()
+                                .unwrap_or("no message")
+                        );
+
+                        s.workers[id].respawned();
+                        s.events
+                            .emit(|| ServerEvent::WorkerRespawned { worker: id });
+                    }
+
+                    Err(payload) => panic::resume_unwind(payload),
+                }
+            }
+        }));
+    };
+
+    (0..options.workers).for_each(&spawn);
+
+    // Also spawned for a pool that only shrinks, so it grows back to its initial size
+    if elastic {
+        info!("Elastic pool of {} to {} workers", min_workers, max_workers);
+        scale::spawn(
+            options.scaling,
+            Arc::clone(&queue),
+            Arc::clone(&stats),
+            spawn,
+        );
+    }
+
+    if let Some(idle) = options.exit_on_idle {
+        idle::spawn(idle, Arc::clone(&queue), Arc::clone(&stats));
     }
+
+    ServerHandle::new(
+        queue,
+        done_rx,
+        reaper,
+        stats,
+        AcceptSettings {
+            filter: options.accept_filter,
+            banner: options.banner.as_ref().map(|b| b.frame().into()),
+            shutdown_timeout: options.accept_shutdown_timeout,
+            max_pending: options.max_pending_tasks,
+            backpressure: options.backpressure,
+            accept_rate: options.max_accept_rate,
+            accept_burst: options.accept_burst,
+            rate_policy: options.accept_rate_policy,
+            bind: BindSettings::of(options),
+        },
+        server,
+    )
 }
@@ -0,0 +1,237 @@
+use crate::{builder, Options, UdsError};
+
+use std::{
+    ffi::CString,
+    fmt, fs,
+    io::Error as IoError,
+    mem,
+    os::unix::{ffi::OsStrExt, fs::FileTypeExt, net::UnixStream},
+    path::Path,
+};
+
+/// Descriptors of the process not owned by the connections, such as the standard streams, the
+/// listener and the wake sockets of the accept loop
+const BASE_FDS: u64 = 16;
+
+/// How the findings of the startup validation are enforced. See [`crate::Options::validation`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    /// Refuse to start on any finding
+    Strict,
+    /// Refuse to start only on errors, logging the warnings
+    Permissive,
+}
+
+/// Severity of a [`Finding`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Likely to degrade the UDS under load
+    Warning,
+    /// The UDS can't work as configured
+    Error,
+}
+
+/// Problem detected by the startup validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// Severity of the problem
+    pub severity: Severity,
+    /// Description of the problem
+    pub message: String,
+}
+
+/// Findings of the startup validation, returned by [`crate::UnixDomainSocket::validate`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Every problem found, in the order they were checked
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    /// Whether the UDS can start under the provided validation mode
+    pub fn passes(&self, validation: Validation) -> bool {
+        self.findings.iter().all(|f| match validation {
+            Validation::Strict => false,
+            Validation::Permissive => f.severity < Severity::Error,
+        })
+    }
+
+    /// Findings with the provided severity
+    pub fn with_severity(&self, severity: Severity) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(move |f| f.severity == severity)
+    }
+
+    fn warn<M: Into<String>>(&mut self, message: M) {
+        self.push(Severity::Warning, message);
+    }
+
+    fn error<M: Into<String>>(&mut self, message: M) {
+        self.push(Severity::Error, message);
+    }
+
+    fn push<M: Into<String>>(&mut self, severity: Severity, message: M) {
+        self.findings.push(Finding {
+            severity,
+            message: message.into(),
+        });
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, finding) in self.findings.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+
+            write!(f, "{:?}: {}", finding.severity, finding.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Validate the options, and the path to bind, if any
+pub fn validate(path: Option<&Path>, options: &Options) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    if let Some(path) = path {
+        validate_path(path, &mut report);
+    }
+
+    validate_options(options, &mut report);
+    validate_fd_limit(options, &mut report);
+
+    report
+}
+
+/// Enforce the report under the provided mode, logging the findings that are let through
+pub fn enforce(report: ValidationReport, validation: Validation) -> Result<(), UdsError> {
+    if !report.passes(validation) {
+        return Err(UdsError::ValidationFailed(report));
+    }
+
+    report
+        .findings
+        .iter()
+        .for_each(|f| warn!("Startup validation: {}", f.message));
+
+    Ok(())
+}
+
+fn validate_path(path: &Path, report: &mut ValidationReport) {
+    let addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    let max = addr.sun_path.len();
+    let len = path.as_os_str().as_bytes().len();
+
+    // The address must fit with its nul terminator
+    if len >= max {
+        report.error(format!(
+            "The path {} has {} bytes, more than the {} supported by the platform",
+            path.display(),
+            len,
+            max - 1
+        ));
+    }
+
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+
+    if !dir.is_dir() {
+        report.error(format!("The directory {} doesn't exist", dir.display()));
+    } else if !accessible(dir, libc::W_OK | libc::X_OK) {
+        report.error(format!(
+            "The directory {} is not writable by the process",
+            dir.display()
+        ));
+    }
+
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(_) => return,
+    };
+
+    if !metadata.file_type().is_socket() {
+        report.error(format!(
+            "The path {} exists and is not a socket, but would be removed to bind",
+            path.display()
+        ));
+    } else if UnixStream::connect(path).is_ok() {
+        report.error(format!(
+            "Another instance is accepting connections on {}",
+            path.display()
+        ));
+    }
+}
+
+fn validate_options(options: &Options, report: &mut ValidationReport) {
+    builder::errors(options)
+        .into_iter()
+        .for_each(|e| report.error(e.to_string()));
+
+    if options.max_concurrent_per_worker == 0 {
+        report.warn("max_concurrent_per_worker is 0, and will be handled as 1");
+    }
+
+    if options.max_in_flight == Some(0) {
+        report.warn("max_in_flight is 0, and will be handled as 1");
+    }
+
+    if options.exit_on_idle.is_some_and(|d| d.is_zero()) {
+        report.warn("exit_on_idle is 0, so the UDS will quit as soon as it is idle");
+    }
+
+    if options.max_connection_age.is_some_and(|d| d.is_zero()) {
+        report.warn("max_connection_age is 0, so every connection will be half-closed at once");
+    }
+
+    if options.trace_connections == Some(0) {
+        report.warn("trace_connections is 0, and will be handled as 1");
+    }
+
+    let euid = unsafe { libc::geteuid() };
+    if euid != 0 && options.owner.is_some_and(|o| o != euid) {
+        report.error("owner is another user, but only root can give the socket file away");
+    }
+}
+
+fn validate_fd_limit(options: &Options, report: &mut ValidationReport) {
+    let mut limit: libc::rlimit = unsafe { mem::zeroed() };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        report.warn(format!(
+            "Error fetching the descriptor limit: {}",
+            IoError::last_os_error()
+        ));
+        return;
+    }
+
+    let connections = options
+        .max_workers
+        .unwrap_or(0)
+        .max(options.workers)
+        .saturating_mul(options.max_concurrent_per_worker.max(1));
+    let groups = options.worker_groups.len() + 1;
+    let connections = options.max_in_flight.map_or(connections, |m| {
+        connections.min(m.max(1).saturating_mul(groups))
+    });
+
+    // Every connection holds its socket, and the duplicates kept by the reaper and the drain
+    let per_connection =
+        1 + options.max_connection_age.is_some() as u64 + options.drain_on_close.is_some() as u64;
+    let needed = BASE_FDS.saturating_add((connections as u64).saturating_mul(per_connection));
+
+    if limit.rlim_cur != libc::RLIM_INFINITY && (limit.rlim_cur as u64) < needed {
+        report.warn(format!(
+            "The descriptor limit of {} is below the {} needed to keep every worker busy",
+            limit.rlim_cur, needed
+        ));
+    }
+}
+
+fn accessible(path: &Path, mode: libc::c_int) -> bool {
+    CString::new(path.as_os_str().as_bytes())
+        .map(|p| unsafe { libc::access(p.as_ptr(), mode) } == 0)
+        .unwrap_or(false)
+}
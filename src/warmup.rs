@@ -0,0 +1,34 @@
+use crate::{UdsError, WarmUp};
+
+use std::{
+    io::{self, Error as IoError},
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+/// Run the warm-up callback on its own thread, waiting up to the provided timeout. See
+/// [`crate::Options::warm_up`].
+///
+/// On timeout, the thread is left detached and keeps running.
+pub fn run(warm_up: &WarmUp, timeout: Duration) -> Result<(), UdsError> {
+    let warm_up = Arc::clone(warm_up);
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        tx.send(warm_up()).unwrap_or_default();
+    });
+
+    let result = match rx.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(IoError::new(
+            io::ErrorKind::TimedOut,
+            "Timeout waiting for the warm-up to finish",
+        )),
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            Err(IoError::other("The warm-up callback panicked"))
+        }
+    };
+
+    result.map_err(UdsError::WarmUpFailed)
+}
@@ -0,0 +1,118 @@
+use std::{
+    io::{self, Error as IoError},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// Period between two checks of a path whose directory can't be watched
+const POLL_PERIOD: Duration = Duration::from_millis(10);
+
+/// Block until the path exists, failing with an error of kind [`io::ErrorKind::TimedOut`] once
+/// the timeout elapsed.
+///
+/// The parent directory is watched with inotify. While the directory itself doesn't exist, it is
+/// checked periodically instead.
+pub(crate) fn wait_created(path: &Path, timeout: Duration) -> Result<(), IoError> {
+    let deadline = Instant::now() + timeout;
+    let dir = path
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+
+    loop {
+        let watch = Watch::new(dir);
+
+        // Checked once watched, so a file created in between is not missed
+        if path.symlink_metadata().is_ok() {
+            return Ok(());
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(IoError::new(
+                io::ErrorKind::TimedOut,
+                format!("Timed out waiting for {} to be created", path.display()),
+            ));
+        }
+
+        match watch {
+            Ok(w) => w.wait(remaining)?,
+            Err(_) => std::thread::sleep(POLL_PERIOD.min(remaining)),
+        }
+    }
+}
+
+/// Inotify instance watching the entries created in a directory
+#[cfg(any(target_os = "linux", target_os = "android"))]
+struct Watch(std::fs::File);
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Watch {
+    fn new(dir: &Path) -> Result<Self, IoError> {
+        use std::{
+            ffi::CString,
+            os::unix::{ffi::OsStrExt, io::FromRawFd},
+        };
+
+        let dir = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| IoError::new(io::ErrorKind::InvalidInput, e))?;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        // Owned right away, so the descriptor is closed on every error
+        let watch = Watch(unsafe { std::fs::File::from_raw_fd(fd) });
+        let mask = libc::IN_CREATE | libc::IN_MOVED_TO;
+        if unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), mask) } < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        Ok(watch)
+    }
+
+    /// Block until an entry is created in the directory, or the timeout elapsed
+    fn wait(&self, timeout: Duration) -> Result<(), IoError> {
+        use std::{io::Read, os::unix::io::AsRawFd};
+
+        let mut pollfd = libc::pollfd {
+            fd: self.0.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        if unsafe { libc::poll(&mut pollfd, 1, timeout) } < 0 {
+            let e = IoError::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+
+        // The events are only a wake up, the path is checked again by the caller
+        let mut events = [0x00u8; 4096];
+        match (&self.0).read(&mut events) {
+            Err(e) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Directories can't be watched, the path is checked periodically
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+struct Watch;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+impl Watch {
+    fn new(_dir: &Path) -> Result<Self, IoError> {
+        Err(IoError::new(
+            io::ErrorKind::Unsupported,
+            "Directories can only be watched on Linux",
+        ))
+    }
+
+    fn wait(&self, _timeout: Duration) -> Result<(), IoError> {
+        Ok(())
+    }
+}
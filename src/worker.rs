@@ -1,73 +1,734 @@
-use crate::{Message, Task, TaskProvider};
+use crate::{
+    close::{self, Drain, Notify},
+    fd::{self, SocketId},
+    fdpass,
+    listener::{ListenerInfo, ServerInfo},
+    queue::{Event, Queue},
+    reaper::Reaper,
+    reqlog::Recorder,
+    revalidate::Revalidator,
+    routes::Routes,
+    scratch::{Scratch, ScratchGuard},
+    stats::Stats,
+    trace, Budget, CompletionFn, ConnectionInfo, ConnectionState, DrainClass, DrainPolicy,
+    IpcStream, LostWaker, MemoryBudget, Message, PeerCreds, RequestLog, ServerEvent,
+    ShutdownReason, Task, TaskProvider,
+};
 
-use std::sync::{mpsc, Arc, Mutex};
+use std::{
+    any::Any,
+    collections::{HashMap, VecDeque},
+    io::{self, Error as IoError},
+    os::unix::io::AsRawFd,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
 
-use futures::executor::block_on;
+use futures::task::{self, ArcWake};
 
-/// This function will panic if the task channel is broken.
+/// Worker behavior taken from [`crate::Options`]
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerSettings {
+    pub max_concurrent: usize,
+    pub check_fd_leaks: bool,
+    pub connection_scratch: bool,
+    pub memory_budget: Option<MemoryBudget>,
+    pub linger: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    pub drain_on_close: Option<Duration>,
+    pub server: ServerInfo,
+    pub lost_waker: LostWaker,
+    pub poll_budget: Option<Duration>,
+    pub drain_policy: Option<DrainPolicy>,
+    pub request_log: Option<RequestLog>,
+    pub on_complete: Option<CompletionFn>,
+    /// Idle time after which the worker leaves an elastic pool
+    pub retire_after: Option<Duration>,
+}
+
+/// In-progress provider future, owned by a worker
+struct Connection<T> {
+    future: Pin<Box<T>>,
+    waker: Waker,
+    wake: Arc<ConnectionWaker>,
+    started: Instant,
+    traced: Option<u64>,
+    age: Option<u64>,
+    revalidated: Option<u64>,
+    /// Credentials of the peer, fetched once when the socket is taken
+    peer: Option<PeerCreds>,
+    /// Traffic of the connection, for the request log
+    recorder: Option<Arc<Recorder>>,
+    /// Listener the connection was accepted on, kept for the request log and the completion hook
+    listener: Option<ListenerInfo>,
+    socket: Option<SocketId>,
+    /// Inode of the socket, released from the quota of the peer user once finished
+    quota: Option<libc::ino_t>,
+    /// Identifier of the connection in the traffic ledger, closed once finished
+    traffic: Option<u64>,
+    drain: Option<Drain>,
+    drain_class: DrainClass,
+    notify: Option<Notify>,
+    /// Set once the worker is asked to quit, according to the drain policy
+    deadline: Option<Instant>,
+    /// Set when the last poll exceeded the poll budget
+    yielded: bool,
+    budget: Option<Budget>,
+    // Dropped after the future, so the provider releases its handles first
+    scratch: Option<ScratchGuard>,
+}
+
+impl<T: TaskProvider> Connection<T> {
+    /// Apply the drain policy, once the worker is asked to quit
+    fn quit(&mut self, policy: &DrainPolicy, now: Instant) {
+        self.deadline = policy.deadline(self.drain_class).map(|d| now + d);
+
+        if let Some(n) = self.notify.take() {
+            n.notify();
+        }
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        self.deadline.is_some_and(|d| d <= now)
+    }
+
+    /// Whether the connection is to be closed for exceeding its memory budget
+    fn over_budget(&self) -> bool {
+        self.budget.as_ref().is_some_and(|b| b.is_exceeded())
+    }
+
+    /// Poll the future, reporting whether it returned [`Poll::Pending`] with no way to be woken:
+    /// no clone of the waker outlived the poll, and it wasn't woken during the poll
+    fn poll(&mut self) -> (Poll<Message>, bool) {
+        self.wake.woken.store(false, Ordering::Relaxed);
+
+        let mut cx = Context::from_waker(&self.waker);
+        let poll = self.future.as_mut().poll(&mut cx);
+
+        // One reference is held by the waker and the other one by the connection, plus the clone
+        // kept by the memory budget, if any
+        let held = 2 + self
+            .budget
+            .as_ref()
+            .map_or(0, |b| usize::from(b.holds_waker()));
+        let lost = poll.is_pending()
+            && Arc::strong_count(&self.wake) <= held
+            && !self.wake.woken.load(Ordering::Relaxed);
+
+        (poll, lost)
+    }
+
+    /// Replace the resolved future with the provider the socket is handed over to. The socket
+    /// keeps its identity, age and scratch, so it remains a single connection.
+    fn handoff(
+        &mut self,
+        routes: &Routes<T>,
+        target: &str,
+        server: ServerInfo,
+    ) -> Result<(), IoError> {
+        let mut p = routes.named(target).cloned().ok_or_else(|| {
+            IoError::new(
+                io::ErrorKind::NotFound,
+                format!("No provider registered as {}", target),
+            )
+        })?;
+
+        let stream = self
+            .future
+            .as_mut()
+            .take_socket()
+            .ok_or_else(|| IoError::other("The provider didn't give the socket back"))?;
+
+        if let Some(scratch) = self.scratch.as_ref() {
+            p.set_scratch(scratch.0.clone());
+        }
+
+        if let Some(budget) = self.budget.as_ref() {
+            p.set_budget(budget.clone());
+        }
+
+        p.set_listener(ListenerInfo::of(&stream, server));
+        if let Some(peer) = self.peer {
+            p.set_peer(peer);
+        }
+        p.set_socket(stream);
+        self.future = Box::pin(p);
+
+        Ok(())
+    }
+}
+
+/// Wake a specific future of a worker through the task queue
+struct ConnectionWaker {
+    queue: Arc<Queue>,
+    worker: usize,
+    id: u64,
+    /// Set when woken, so a wake during the poll is not mistaken for a lost waker
+    woken: AtomicBool,
+}
+
+impl ArcWake for ConnectionWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.woken.store(true, Ordering::Relaxed);
+        arc_self.queue.wake(arc_self.worker, arc_self.id);
+    }
+}
+
+/// Connections of a worker, released if it unwinds, so their slots in the queue and their quotas
+/// are not lost with the worker. Records the end of the worker either way
+struct Held<'a, T: TaskProvider> {
+    connections: HashMap<u64, Connection<T>>,
+    worker: usize,
+    queue: &'a Queue,
+    stats: &'a Stats,
+    reaper: Option<&'a Reaper>,
+    revalidator: Option<&'a Revalidator>,
+}
+
+impl<T: TaskProvider> Drop for Held<'_, T> {
+    fn drop(&mut self) {
+        let counters = &self.stats.workers[self.worker];
+        counters.set_running(false);
+
+        if !thread::panicking() {
+            return;
+        }
+
+        for (_, connection) in self.connections.drain() {
+            self.stats
+                .record(connection.traced, ConnectionState::Cancelled);
+
+            if let (Some(r), Some(age)) = (self.reaper, connection.age) {
+                r.release(age);
+            }
+
+            if let (Some(r), Some(id)) = (self.revalidator, connection.revalidated) {
+                r.release(id);
+            }
+
+            self.stats.release_quota(connection.quota);
+            self.stats.close_traffic(connection.traffic);
+
+            let elapsed = self
+                .stats
+                .clock
+                .now()
+                .saturating_duration_since(connection.started);
+            counters.finished(self.stats, true, elapsed);
+            self.queue.release(self.worker);
+        }
+    }
+}
+
+/// Message of a panic, if it was a string
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> Option<String> {
+    payload
+        .downcast_ref::<&str>()
+        .map(|m| m.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+}
+
+/// Every worker owns up to [`WorkerSettings::max_concurrent`] in-progress provider futures,
+/// polling them again whenever their waker is called. New sockets are only taken from the queue
+/// while there is room for them.
 ///
-/// There is no point in preserving the event loop in case there is no available channel.
+/// A future panicking while polled resolves as [`Message::Error`]. Any other panic unwinds the
+/// worker, after its connections were released.
+///
+/// This function will panic if the task queue is poisoned.
+///
+/// There is no point in preserving the event loop in case there is no available queue.
 ///
 /// Therefore, this function should be called from a thread.
 pub fn worker<T: TaskProvider>(
-    tx: mpsc::Sender<Task>,
-    rx: Arc<Mutex<mpsc::Receiver<Task>>>,
-    provider: T,
+    id: usize,
+    queue: Arc<Queue>,
+    mut routes: Routes<T>,
+    reaper: Option<Arc<Reaper>>,
+    revalidator: Option<Arc<Revalidator>>,
+    stats: Arc<Stats>,
+    settings: WorkerSettings,
 ) {
+    let counters = &stats.workers[id];
+    counters.set_running(true);
+
+    let mut held = Held {
+        connections: HashMap::new(),
+        worker: id,
+        queue: &queue,
+        stats: &stats,
+        reaper: reaper.as_deref(),
+        revalidator: revalidator.as_deref(),
+    };
+    let connections = &mut held.connections;
+    let mut next_id = 0u64;
+    let mut quitting = false;
+    // Set once the drain policy applies, which may be before the worker takes the ShouldQuit
+    // message if it has no room for more sockets
+    let mut draining = false;
+    // Wall time, even under a manual clock, to compare with the CPU time
+    let mut busy_since = Instant::now();
+
     loop {
-        let task = rx
-            .lock()
-            .map_err(|e| {
-                error!("Error trying to lock the task channel: {}", e);
-                e
-            })
-            .unwrap()
-            .recv()
-            .map_err(|e| {
-                error!(
-                    "Error trying to receive a task from the respective channel: {}",
-                    e
-                );
-                e
-            })
-            .unwrap();
+        if quitting && connections.is_empty() {
+            break;
+        }
+
+        // Connections past their drain deadline are handled before waiting for more work
+        let now = stats.clock.now();
+        let expired: Vec<u64> = connections
+            .iter()
+            .filter(|(_, c)| c.expired(now))
+            .map(|(c, _)| *c)
+            .collect();
+        let deadline = connections.values().filter_map(|c| c.deadline).min();
+
+        // Idle worker of an elastic pool, retired once it waited long enough for work
+        let retire = settings
+            .retire_after
+            .filter(|_| connections.is_empty() && !quitting);
+        let deadline = deadline.or_else(|| retire.map(|r| now + r));
+
+        let accept = !quitting && connections.len() < settings.max_concurrent;
+        let event = if expired.is_empty() {
+            let waiting = Instant::now();
+            let event = queue.next(id, accept, deadline);
+            let woken = Instant::now();
+
+            counters.account(
+                waiting.saturating_duration_since(busy_since),
+                woken.saturating_duration_since(waiting),
+            );
+            busy_since = woken;
+            event
+        } else {
+            Event::Woken(expired)
+        };
+
+        let mut woken = match event {
+            Event::Task(Task::Socket(stream)) => {
+                let traced = stats.traced(&stream);
+                let quota = stats
+                    .quota
+                    .as_ref()
+                    .and_then(|_| fd::inode(stream.as_raw_fd()));
+                let peer = PeerCreds::from_stream(&stream)
+                    .map_err(|e| warn!("Error fetching the peer credentials: {}", e))
+                    .ok();
+
+                let mut p = match routes.select(&stream, peer.as_ref()) {
+                    Some(p) => p.clone(),
+                    None => {
+                        // The tenant of the socket was removed while it was queued
+                        debug!("No provider to handle the UDS socket, dropping it");
+                        stats.record(traced, ConnectionState::Cancelled);
+                        stats.release_quota(quota);
+                        queue.release(id);
+                        continue;
+                    }
+                };
+
+                let socket = settings
+                    .check_fd_leaks
+                    .then(|| SocketId::of(&stream))
+                    .flatten();
+
+                let scratch = settings.connection_scratch.then(|| {
+                    let scratch = Scratch::default();
+                    p.set_scratch(scratch.clone());
+                    ScratchGuard(scratch)
+                });
+
+                let budget = settings.memory_budget.map(|b| {
+                    let budget = Budget::new(&b);
+                    p.set_budget(budget.clone());
+                    budget
+                });
+
+                if let Some(timeout) = settings.linger {
+                    close::set_linger(&stream, timeout).unwrap_or_else(|e| {
+                        error!("Error setting the linger timeout of the socket: {}", e);
+                    });
+                }
+
+                if let Err(e) = stream
+                    .set_read_timeout(settings.read_timeout)
+                    .and_then(|_| stream.set_write_timeout(settings.write_timeout))
+                {
+                    error!("Error setting the timeouts of the socket: {}", e);
+                }
+
+                let drain = settings
+                    .drain_on_close
+                    .and_then(|_| Drain::new(&stream, &stats));
+                let age = reaper.as_ref().and_then(|r| r.register(&stream));
+                let revalidated = revalidator
+                    .as_ref()
+                    .zip(peer.as_ref())
+                    .and_then(|(r, creds)| r.register(&stream, creds));
+                let listener = ListenerInfo::of(&stream, settings.server);
+
+                let drain_class = settings
+                    .drain_policy
+                    .zip(peer.as_ref())
+                    .map(|(policy, creds)| (policy.classify)(creds, &listener))
+                    .unwrap_or(DrainClass::Finish);
+                let notify = (DrainClass::Notify == drain_class)
+                    .then(|| Notify::new(&stream, &stats))
+                    .flatten();
 
-        match task {
-            Task::Socket(stream) => {
-                let mut p = provider.clone();
+                let recorder = settings
+                    .request_log
+                    .map(|log| Arc::new(Recorder::new(&log)));
+                let traffic = stats.traffic.as_ref().map(|ledger| {
+                    let id = trace::connection_id(&stream);
+                    (id, ledger.open(id, peer.as_ref().map(|p| p.uid)))
+                });
+                let mut stream = match recorder.as_ref() {
+                    Some(r) => IpcStream::recorded(stream, Arc::clone(r)),
+                    None => IpcStream::from(stream),
+                };
+                let traffic = traffic.map(|(id, counters)| {
+                    stream.set_traffic(counters);
+                    id
+                });
 
+                let kept = (settings.request_log.is_some() || settings.on_complete.is_some())
+                    .then(|| listener.clone());
+                p.set_listener(listener);
+                if let Some(peer) = peer {
+                    p.set_peer(peer);
+                }
                 p.set_socket(stream);
 
-                // TODO - Naive implementation, will not reschedule if the poll returns pending
-                let message = block_on(p);
+                let wake = Arc::new(ConnectionWaker {
+                    queue: Arc::clone(&queue),
+                    worker: id,
+                    id: next_id,
+                    woken: AtomicBool::new(false),
+                });
+                let waker = task::waker(Arc::clone(&wake));
+                if let Some(b) = budget.as_ref() {
+                    b.set_waker(&waker);
+                }
+
+                connections.insert(
+                    next_id,
+                    Connection {
+                        future: Box::pin(p),
+                        waker,
+                        wake,
+                        started: stats.clock.now(),
+                        traced,
+                        age,
+                        revalidated,
+                        peer,
+                        recorder,
+                        listener: kept,
+                        socket,
+                        quota,
+                        traffic,
+                        drain,
+                        drain_class,
+                        notify,
+                        deadline: None,
+                        yielded: false,
+                        budget,
+                        scratch,
+                    },
+                );
+
+                counters.started();
+
+                // Taken from ahead of the ShouldQuit message, after the drain started
+                if let (true, Some(policy)) = (draining, settings.drain_policy.as_ref()) {
+                    let now = stats.clock.now();
+                    if let Some(c) = connections.get_mut(&next_id) {
+                        c.quit(policy, now);
+                    }
+                }
+
+                next_id += 1;
+                VecDeque::from([next_id - 1])
+            }
+
+            Event::Task(Task::Message(Message::ShouldQuit)) => {
+                // Keep the message in the queue so every other worker receives it. Sent as a plain
+                // message, the shutdown was requested by the application
+                queue.quit(ShutdownReason::Admin).unwrap_or_else(|e| {
+                    error!(
+                        "Error trying to send a ShouldQuit message to the task queue: {}",
+                        e
+                    );
+                });
+
+                quitting = true;
+
+                if let (false, Some(policy)) = (draining, settings.drain_policy.as_ref()) {
+                    let now = stats.clock.now();
+                    connections.values_mut().for_each(|c| c.quit(policy, now));
+                }
+                draining = true;
+
+                VecDeque::new()
+            }
+
+            // Received even without room for more sockets, so the drain policy applies to the
+            // connections of a busy worker. Polled right away, so the providers can answer their
+            // clients, and the ones past their deadline are cut
+            Event::Shutdown(reason) => {
+                if let (false, Some(policy)) = (draining, settings.drain_policy.as_ref()) {
+                    let now = stats.clock.now();
+                    connections.values_mut().for_each(|c| c.quit(policy, now));
+                }
+                draining = true;
+
+                connections
+                    .iter_mut()
+                    .map(|(c, connection)| {
+                        connection.future.as_mut().shutting_down(&reason);
+                        *c
+                    })
+                    .collect()
+            }
+
+            Event::Task(Task::Message(m)) => {
+                routes.handle_message(&m);
+                VecDeque::new()
+            }
+
+            // Futures that exceeded the poll budget yield to the other woken ones
+            Event::Woken(ids) => {
+                // Sorted in place, reusing the buffer of the queue
+                let mut woken = VecDeque::from(ids);
+                woken.make_contiguous().sort_unstable_by_key(|c| {
+                    let yielded = connections
+                        .get(c)
+                        .is_some_and(|connection| connection.yielded);
+                    (yielded, *c)
+                });
+
+                woken
+            }
+            Event::Timeout => match retire {
+                Some(idle) if queue.retire(id) => {
+                    info!("Worker {} idle for {:?}, retired", id, idle);
+
+                    // Before the event, so its subscribers see the worker stopped in the stats
+                    counters.set_running(false);
+                    stats
+                        .events
+                        .emit(|| ServerEvent::WorkerRetired { worker: id });
+                    break;
+                }
+
+                _ => VecDeque::new(),
+            },
+        };
+
+        while let Some(c) = woken.pop_front() {
+            let over_budget = connections
+                .get(&c)
+                .is_some_and(|connection| connection.over_budget());
+            let cut = over_budget
+                || connections
+                    .get(&c)
+                    .is_some_and(|connection| connection.expired(stats.clock.now()));
+
+            let message = match connections.get_mut(&c) {
+                Some(_) if over_budget => {
+                    counters.over_budget();
+                    warn!("Connection closed for exceeding its memory budget");
+                    Message::Error
+                }
+
+                Some(_) if cut => {
+                    debug!("Connection cut by the drain policy");
+                    Message::Error
+                }
+
+                Some(connection) => {
+                    stats.record(connection.traced, ConnectionState::Polling);
 
-                if Message::ShouldQuit == message {
-                    tx.send(Task::Message(Message::ShouldQuit))
-                        .map_err(|e| {
+                    let polled = stats.clock.now();
+                    let poll = panic::catch_unwind(AssertUnwindSafe(|| connection.poll()))
+                        .unwrap_or_else(|payload| {
+                            let message = panic_message(payload.as_ref());
                             error!(
-                                "Error trying to send a ShouldQuit message to the task channel: {}",
-                                e
+                                "Provider future panicked: {}",
+                                message.as_deref().unwrap_or("no message")
                             );
-                            e
-                        })
-                        .unwrap();
+
+                            counters.panicked();
+                            stats.events.emit(|| ServerEvent::ProviderPanicked {
+                                worker: id,
+                                message,
+                            });
+
+                            (Poll::Ready(Message::Error), false)
+                        });
+
+                    let elapsed = stats.clock.now().saturating_duration_since(polled);
+                    connection.yielded = settings.poll_budget.is_some_and(|b| elapsed > b);
+                    if connection.yielded {
+                        counters.slow_poll();
+                        warn!(
+                            "Poll of a provider future took {:?}, over the budget of {:?}",
+                            elapsed,
+                            settings.poll_budget.unwrap_or_default()
+                        );
+                    }
+
+                    match poll {
+                        (Poll::Ready(m), _) => m,
+
+                        (Poll::Pending, true) => {
+                            warn!(
+                                "Provider future returned Pending without keeping its waker, it \
+                                 will never be woken"
+                            );
+                            stats.record(connection.traced, ConnectionState::Pending);
+
+                            match settings.lost_waker {
+                                LostWaker::Park => continue,
+                                LostWaker::Cancel => Message::Error,
+                            }
+                        }
+
+                        (Poll::Pending, false) => {
+                            stats.record(connection.traced, ConnectionState::Pending);
+                            continue;
+                        }
+                    }
+                }
+
+                // Stale wake of a finished future
+                None => continue,
+            };
+
+            // The new provider is polled right away, in place of the resolved one
+            let message = match (message, connections.get_mut(&c)) {
+                (Message::Handoff(target), Some(connection)) => {
+                    match connection.handoff(&routes, &target, settings.server) {
+                        Ok(()) => {
+                            woken.push_back(c);
+                            continue;
+                        }
+
+                        Err(e) => {
+                            error!("Error handing the socket over to {}: {}", target, e);
+                            Message::Error
+                        }
+                    }
+                }
+
+                (Message::Handover(fd), _) => {
+                    match fdpass::handover(fd, queue.group(id), &stats, &queue) {
+                        Ok(()) => Message::Handover(fd),
+                        Err(e) => {
+                            error!("Error handing the descriptor over to the workers: {}", e);
+                            Message::Error
+                        }
+                    }
+                }
+
+                (m, _) => m,
+            };
+
+            let elapsed = connections
+                .get(&c)
+                .map(|connection| {
+                    let state = if cut {
+                        ConnectionState::Cancelled
+                    } else {
+                        ConnectionState::Finished
+                    };
+
+                    stats.record(connection.traced, state);
+                    let elapsed = stats
+                        .clock
+                        .now()
+                        .saturating_duration_since(connection.started);
+
+                    if let (Some(log), Some(r), Some(listener)) = (
+                        settings.request_log.as_ref(),
+                        connection.recorder.as_ref(),
+                        connection.listener.as_ref(),
+                    ) {
+                        r.log(log, listener, connection.peer.as_ref(), &message, elapsed);
+                    }
+
+                    if let (Some(hook), Some(listener)) =
+                        (settings.on_complete, connection.listener.as_ref())
+                    {
+                        let info = ConnectionInfo {
+                            listener: listener.clone(),
+                            peer: connection.peer,
+                        };
+                        hook(&info, &message, elapsed);
+                    }
+
+                    stats.events.emit(|| ServerEvent::HandlerFinished {
+                        outcome: message.clone(),
+                        elapsed,
+                    });
+
+                    elapsed
+                })
+                .unwrap_or_default();
+            counters.finished(&stats, Message::Error == message, elapsed);
+            queue.release(id);
+
+            if let Some(connection) = connections.remove(&c) {
+                if let (Some(r), Some(age)) = (reaper.as_ref(), connection.age) {
+                    r.release(age);
+                }
+
+                if let (Some(r), Some(id)) = (revalidator.as_ref(), connection.revalidated) {
+                    r.release(id);
+                }
+
+                stats.release_quota(connection.quota);
+                stats.close_traffic(connection.traffic);
+
+                let detached = matches!(message, Message::Detached | Message::Handover(_));
+                let socket = connection.socket.filter(|_| !detached);
+                let drain = connection.drain.filter(|_| !detached);
+                drop(connection.future);
+
+                if let (Some(d), Some(timeout)) = (drain, settings.drain_on_close) {
+                    d.close(timeout);
+                }
+
+                if socket.is_some_and(|s| s.is_open()) {
+                    counters.leaked();
+                    error!(
+                        "Socket {:?} still open after its provider was dropped",
+                        socket
+                    );
                 }
             }
 
-            Task::Message(m) if m == Message::ShouldQuit => {
-                tx.send(Task::Message(Message::ShouldQuit))
-                    .map_err(|e| {
-                        error!(
-                            "Error trying to send a ShouldQuit message to the task channel: {}",
-                            e
-                        );
+            if Message::ShouldQuit == message && !quitting {
+                queue.quit(ShutdownReason::Provider).unwrap_or_else(|e| {
+                    error!(
+                        "Error trying to send a ShouldQuit message to the task queue: {}",
                         e
-                    })
-                    .unwrap();
-                break;
+                    );
+                });
             }
+        }
 
-            _ => (),
+        if woken.capacity() > 0 {
+            queue.recycle(id, woken.into());
         }
     }
 }
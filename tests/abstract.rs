@@ -0,0 +1,30 @@
+#![cfg(any(target_os = "linux", target_os = "android"))]
+
+use dusk_uds::*;
+
+use std::{process, time::Duration};
+
+fn echo(request: Vec<u8>, _peer: &PeerCreds) -> Vec<u8> {
+    request
+}
+
+#[test]
+fn abstract_names_need_not_be_utf8() {
+    let mut name = b"dusk-uds-\xff\xfe-".to_vec();
+    name.extend_from_slice(process::id().to_string().as_bytes());
+    let address = Address::Abstract(name);
+    assert!(address.to_string().starts_with("@dusk-uds-"));
+
+    let handle = UnixDomainSocket::new(address.clone(), None, oneshot_handler(echo))
+        .start()
+        .unwrap();
+
+    let client = UnixDomainClient::new(address).timeout(Duration::from_secs(10));
+    assert_eq!(client.request(b"bytes").unwrap(), b"bytes");
+
+    handle
+        .task_sender()
+        .send(Task::Message(Message::ShouldQuit))
+        .unwrap();
+    handle.join().unwrap();
+}
@@ -0,0 +1,276 @@
+use dusk_uds::{
+    codec::{self, ErrorCode, ErrorFrame},
+    UnixDomainClient,
+};
+
+use std::{
+    io,
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()))
+}
+
+/// Answer every frame with the same payload, or with an error frame for "fail". "close" closes the
+/// connection without a response
+fn echo(mut stream: UnixStream) {
+    while let Ok(payload) = codec::read_frame(&mut stream, 1024) {
+        match &payload[..] {
+            b"close" => return,
+            b"fail" => {
+                let error = ErrorFrame::new(ErrorCode(7), "failed on purpose");
+                codec::write_error(&mut stream, &error).unwrap();
+            }
+            _ => codec::write_frame(&mut stream, &payload).unwrap(),
+        }
+    }
+}
+
+/// Bind the path and echo on every connection, counting them
+fn serve(path: PathBuf) -> Arc<AtomicUsize> {
+    std::fs::remove_file(path.as_path()).unwrap_or_default();
+    let listener = UnixListener::bind(path).unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let accepted = Arc::clone(&connections);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            thread::spawn(move || echo(stream.unwrap()));
+        }
+    });
+
+    connections
+}
+
+#[test]
+fn connections_are_reused_across_calls() {
+    let path = socket_path("client-reuse");
+    let connections = serve(path.clone());
+    let client = UnixDomainClient::new(path.clone()).timeout(Duration::from_secs(10));
+
+    assert!(!client.is_connected());
+    assert_eq!(client.request(b"one").unwrap(), b"one");
+    assert_eq!(client.clone().request(b"two").unwrap(), b"two");
+    assert!(client.is_connected());
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+    client.close();
+    assert!(!client.is_connected());
+    assert_eq!(client.request(b"three").unwrap(), b"three");
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn failed_connections_are_dropped_and_opened_again() {
+    let path = socket_path("client-reconnect");
+    let connections = serve(path.clone());
+    let client = UnixDomainClient::new(path.clone()).timeout(Duration::from_secs(10));
+
+    let e = client.request(b"close").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(!client.is_connected());
+
+    assert_eq!(client.request(b"again").unwrap(), b"again");
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn error_frames_are_returned_as_errors() {
+    let path = socket_path("client-error");
+    serve(path.clone());
+    let client = UnixDomainClient::new(path.clone()).timeout(Duration::from_secs(10));
+
+    let e = client.request(b"fail").unwrap_err();
+    let error = ErrorFrame::from_io(&e).expect("Not an error frame");
+    assert_eq!(error.code, ErrorCode(7));
+    assert_eq!(error.message, "failed on purpose");
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn connecting_is_retried_until_the_socket_is_bound() {
+    let path = socket_path("client-retries");
+    std::fs::remove_file(path.as_path()).unwrap_or_default();
+
+    let missing = UnixDomainClient::new(path.clone());
+    let e = missing.connect().unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+
+    let client = UnixDomainClient::new(path.clone())
+        .connect_timeout(Duration::from_secs(1))
+        .retries(100, Duration::from_millis(5), Duration::from_millis(50));
+
+    let server_path = path.clone();
+    let server = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        serve(server_path);
+    });
+
+    assert_eq!(client.request(b"late").unwrap(), b"late");
+    server.join().unwrap();
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn connections_wait_for_the_socket_to_be_created() {
+    let path = socket_path("client-available");
+    std::fs::remove_file(path.as_path()).unwrap_or_default();
+    let client = UnixDomainClient::new(path.clone());
+
+    let server_path = path.clone();
+    let server = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        serve(server_path);
+    });
+
+    client
+        .connect_when_available(Duration::from_secs(10))
+        .unwrap();
+    assert!(client.is_connected());
+    assert_eq!(client.request(b"up").unwrap(), b"up");
+    server.join().unwrap();
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn stale_socket_files_are_attempted_until_accepting() {
+    let path = socket_path("client-stale");
+    std::fs::remove_file(path.as_path()).unwrap_or_default();
+
+    // Left behind by a listener that is gone, refusing the connections
+    drop(UnixListener::bind(path.as_path()).unwrap());
+    let client = UnixDomainClient::new(path.clone());
+
+    let server_path = path.clone();
+    let server = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        serve(server_path);
+    });
+
+    client
+        .connect_when_available(Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(client.request(b"fresh").unwrap(), b"fresh");
+    server.join().unwrap();
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn waiting_for_the_socket_times_out() {
+    let path = socket_path("client-unavailable");
+    std::fs::remove_file(path.as_path()).unwrap_or_default();
+    let client = UnixDomainClient::new(path);
+
+    let start = Instant::now();
+    let e = client
+        .connect_when_available(Duration::from_millis(100))
+        .unwrap_err();
+
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(!client.is_connected());
+}
+
+#[test]
+fn async_calls_resolve_to_the_response() {
+    let path = socket_path("client-async");
+    serve(path.clone());
+    let client = UnixDomainClient::new(path.clone()).timeout(Duration::from_secs(10));
+
+    futures::executor::block_on(async {
+        assert_eq!(client.request_async(b"async").await.unwrap(), b"async");
+
+        // The echo of the sent frame is read by the next request on the connection
+        client.send_async(b"sent").await.unwrap();
+        assert_eq!(client.request_async(b"next").await.unwrap(), b"sent");
+        assert_eq!(client.request_async(b"last").await.unwrap(), b"next");
+        client.close();
+
+        let e = client.request_async(b"fail").await.unwrap_err();
+        assert!(ErrorFrame::from_io(&e).is_some());
+    });
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn async_calls_time_out() {
+    let path = socket_path("client-async-timeout");
+    std::fs::remove_file(path.as_path()).unwrap_or_default();
+
+    // Accepts without ever answering
+    let listener = UnixListener::bind(path.as_path()).unwrap();
+    let silent = thread::spawn(move || listener.accept().unwrap());
+
+    let client = UnixDomainClient::new(path.clone()).timeout(Duration::from_millis(100));
+    let start = Instant::now();
+    let e = futures::executor::block_on(client.request_async(b"hello")).unwrap_err();
+
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(!client.is_connected());
+
+    silent.join().unwrap();
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn async_calls_retry_connecting_until_the_socket_is_bound() {
+    let path = socket_path("client-async-retries");
+    std::fs::remove_file(path.as_path()).unwrap_or_default();
+
+    let client = UnixDomainClient::new(path.clone())
+        .timeout(Duration::from_secs(10))
+        .retries(100, Duration::from_millis(5), Duration::from_millis(50));
+
+    let server_path = path.clone();
+    let server = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        serve(server_path);
+    });
+
+    let call = client.request_async(b"late");
+    assert_eq!(futures::executor::block_on(call).unwrap(), b"late");
+    server.join().unwrap();
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn concurrent_async_calls_take_turns_on_the_connection() {
+    let path = socket_path("client-async-turns");
+    let connections = serve(path.clone());
+    let client = UnixDomainClient::new(path.clone()).timeout(Duration::from_secs(10));
+
+    let calls = (0..8).map(|i| {
+        let payload = format!("call {}", i);
+        let call = client.clone().request_async(payload.as_bytes());
+        async move { (call.await.unwrap(), payload) }
+    });
+
+    for (response, payload) in futures::executor::block_on(futures::future::join_all(calls)) {
+        assert_eq!(response, payload.as_bytes());
+    }
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,147 @@
+use dusk_uds::codec::{self, FrameReader};
+
+use std::{
+    io::{self, Cursor, Write},
+    os::unix::net::UnixStream,
+    thread,
+};
+
+#[test]
+fn frames_round_trip() {
+    let (mut tx, mut rx) = UnixStream::pair().unwrap();
+
+    let writer = thread::spawn(move || {
+        for payload in [&b""[..], b"x", &[0x5au8; 70_000]] {
+            codec::write_frame(&mut tx, payload).unwrap();
+        }
+    });
+
+    assert_eq!(codec::read_frame(&mut rx, 1 << 20).unwrap(), b"");
+    assert_eq!(codec::read_frame(&mut rx, 1 << 20).unwrap(), b"x");
+    assert_eq!(
+        codec::read_frame(&mut rx, 1 << 20).unwrap(),
+        vec![0x5au8; 70_000]
+    );
+
+    writer.join().unwrap();
+}
+
+#[test]
+fn frames_are_prefixed_by_their_big_endian_length() {
+    let mut buffer = vec![];
+    codec::write_frame(&mut buffer, b"abc").unwrap();
+
+    assert_eq!(buffer, [0, 0, 0, 3, b'a', b'b', b'c']);
+}
+
+#[test]
+fn frames_over_the_maximum_are_rejected() {
+    let mut buffer = vec![];
+    codec::write_frame(&mut buffer, &[0x00u8; 16]).unwrap();
+
+    let e = codec::read_frame(&mut Cursor::new(&buffer), 15).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+    let payload = codec::read_frame(&mut Cursor::new(&buffer), 16).unwrap();
+    assert_eq!(payload.len(), 16);
+}
+
+#[test]
+fn truncated_frames_are_unexpected_eof() {
+    let (mut tx, mut rx) = UnixStream::pair().unwrap();
+
+    // Announces 8 bytes, but only sends 3 of them
+    tx.write_all(&[0, 0, 0, 8, 1, 2, 3]).unwrap();
+    drop(tx);
+
+    let e = codec::read_frame(&mut rx, 1024).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn frame_readers_read_the_frames_in_order() {
+    let (mut tx, rx) = UnixStream::pair().unwrap();
+    for payload in [&b"one"[..], b"two", b""] {
+        codec::write_frame(&mut tx, payload).unwrap();
+    }
+    drop(tx);
+
+    let mut reader = FrameReader::new(rx, 1024);
+    assert_eq!(reader.read_frame().unwrap(), b"one");
+    assert_eq!(reader.read_frame().unwrap(), b"two");
+    assert_eq!(reader.read_frame().unwrap(), b"");
+
+    let e = reader.read_frame().unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn read_ahead_keeps_pipelined_frames_in_order() {
+    let (mut tx, rx) = UnixStream::pair().unwrap();
+    let mut reader = FrameReader::read_ahead(&rx, 1024, 2).unwrap();
+
+    // Pipelined before the first one is requested, more than the read-ahead depth
+    for i in 0..8u8 {
+        codec::write_frame(&mut tx, &[i; 3]).unwrap();
+    }
+
+    for i in 0..8u8 {
+        assert_eq!(reader.read_frame().unwrap(), [i; 3]);
+    }
+
+    // Frames sent after the reader caught up are still received
+    codec::write_frame(&mut tx, b"late").unwrap();
+    assert_eq!(reader.read_frame().unwrap(), b"late");
+}
+
+#[test]
+fn read_ahead_returns_errors_after_the_frames_before_them() {
+    let (mut tx, rx) = UnixStream::pair().unwrap();
+    codec::write_frame(&mut tx, b"ok").unwrap();
+    codec::write_frame(&mut tx, &[0x00u8; 32]).unwrap();
+
+    let mut reader = FrameReader::read_ahead(&rx, 16, 4).unwrap();
+    assert_eq!(reader.read_frame().unwrap(), b"ok");
+
+    let e = reader.read_frame().unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn closed_streams_are_unexpected_eof() {
+    let (tx, mut rx) = UnixStream::pair().unwrap();
+    drop(tx);
+
+    let e = codec::read_frame(&mut rx, 1024).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn read_ahead_leaves_the_socket_open_once_dropped() {
+    let (mut tx, mut rx) = UnixStream::pair().unwrap();
+    codec::write_frame(&mut tx, b"first").unwrap();
+
+    let mut reader = FrameReader::read_ahead(&rx, 1024, 4).unwrap();
+    assert_eq!(reader.read_frame().unwrap(), b"first");
+    drop(reader);
+
+    // The caller keeps reading the socket it shares with the reader
+    codec::write_frame(&mut tx, b"second").unwrap();
+    assert_eq!(codec::read_frame(&mut rx, 1024).unwrap(), b"second");
+}
+
+#[test]
+fn read_ahead_returns_truncated_frames_after_the_whole_ones() {
+    let (mut tx, rx) = UnixStream::pair().unwrap();
+    codec::write_frame(&mut tx, b"whole").unwrap();
+    tx.write_all(&[0x00, 0x00, 0x00, 0x08, b'h', b'a']).unwrap();
+    drop(tx);
+
+    let mut reader = FrameReader::read_ahead(&rx, 1024, 4).unwrap();
+    assert_eq!(reader.read_frame().unwrap(), b"whole");
+
+    let e = reader.read_frame().unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    let e = reader.read_frame().unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+}
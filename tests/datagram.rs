@@ -0,0 +1,460 @@
+use dusk_uds::*;
+
+use std::{
+    future::Future,
+    io::{Read, Write},
+    mem,
+    os::unix::{
+        ffi::OsStrExt,
+        io::FromRawFd,
+        net::{SocketAddr, UnixDatagram, UnixStream},
+    },
+    path::{Path, PathBuf},
+    pin::Pin,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()))
+}
+
+/// Reply to every packet with its bytes reversed, counting them
+struct Reverse(&'static AtomicUsize);
+
+impl DatagramProvider for Reverse {
+    fn handle(&self, mut packet: Vec<u8>, _from: &SocketAddr) -> Option<Vec<u8>> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        packet.reverse();
+        Some(packet)
+    }
+}
+
+/// Client socket bound to a path of its own, so it can be replied to
+fn bound_client(name: &str) -> (UnixDatagram, PathBuf) {
+    let path = socket_path(name);
+    std::fs::remove_file(path.as_path()).unwrap_or_default();
+
+    let client = UnixDatagram::bind(path.as_path()).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    (client, path)
+}
+
+#[test]
+fn packets_are_replied_to_their_sender() {
+    static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+    let path = socket_path("datagram-reply");
+    let handle = DatagramSocket::new(path.clone(), None, Reverse(&HANDLED))
+        .start()
+        .unwrap();
+
+    let (client, client_path) = bound_client("datagram-reply-client");
+    let mut buffer = [0x00u8; 64];
+
+    for packet in [&b"abc"[..], b"hello"] {
+        client.send_to(packet, path.as_path()).unwrap();
+
+        let (n, from) = client.recv_from(&mut buffer).unwrap();
+        let expected: Vec<u8> = packet.iter().rev().copied().collect();
+        assert_eq!(&buffer[..n], &expected[..]);
+        assert_eq!(from.as_pathname(), Some(path.as_path()));
+    }
+
+    handle.shutdown();
+    handle.join().unwrap();
+    assert!(!path.exists());
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 2);
+
+    std::fs::remove_file(client_path).unwrap();
+}
+
+#[test]
+fn packets_over_the_maximum_are_dropped() {
+    static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+    let path = socket_path("datagram-max");
+    let handle = DatagramSocket::new(path.clone(), None, Reverse(&HANDLED))
+        .max_packet(4)
+        .start()
+        .unwrap();
+
+    let (client, client_path) = bound_client("datagram-max-client");
+    client.send_to(b"too long", path.as_path()).unwrap();
+    client.send_to(b"ok", path.as_path()).unwrap();
+
+    // Only the second packet is replied to
+    let mut buffer = [0x00u8; 64];
+    let n = client.recv(&mut buffer).unwrap();
+    assert_eq!(&buffer[..n], b"ko");
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 1);
+
+    handle.shutdown();
+    handle.join().unwrap();
+    std::fs::remove_file(client_path).unwrap();
+}
+
+/// Reply to every packet with its bytes reversed, after a while for the packets saying `slow`,
+/// and never to the packets saying `silent`
+struct Delayed;
+
+impl DatagramProvider for Delayed {
+    fn handle(&self, mut packet: Vec<u8>, _from: &SocketAddr) -> Option<Vec<u8>> {
+        match &packet[..] {
+            b"silent" => return None,
+            b"slow" => std::thread::sleep(Duration::from_millis(200)),
+            _ => (),
+        }
+
+        packet.reverse();
+        Some(packet)
+    }
+}
+
+#[test]
+fn requests_are_replied_to_autobound_clients() {
+    let path = socket_path("datagram-autobind");
+    let handle = DatagramSocket::new(path.clone(), None, Delayed)
+        .start()
+        .unwrap();
+
+    let client = DatagramClient::connect(path.as_path()).unwrap();
+    client.set_timeout(Some(Duration::from_secs(10))).unwrap();
+    assert!(!client.local_addr().unwrap().is_unnamed());
+
+    for packet in [&b"abc"[..], b"hello"] {
+        let expected: Vec<u8> = packet.iter().rev().copied().collect();
+        assert_eq!(client.request(packet).unwrap(), expected);
+    }
+
+    let bound = client.local_addr().unwrap();
+    drop(client);
+    if let Some(p) = bound.as_pathname() {
+        assert!(!p.exists());
+    }
+
+    handle.shutdown();
+    handle.join().unwrap();
+}
+
+#[test]
+fn requests_are_replied_to_the_provided_return_path() {
+    let path = socket_path("datagram-return");
+    let client_path = socket_path("datagram-return-client");
+    let handle = DatagramSocket::new(path.clone(), None, Delayed)
+        .start()
+        .unwrap();
+
+    let client = DatagramClient::connect_from(path.as_path(), client_path.as_path()).unwrap();
+    client.set_timeout(Some(Duration::from_secs(10))).unwrap();
+    assert_eq!(
+        client.local_addr().unwrap().as_pathname(),
+        Some(client_path.as_path())
+    );
+    assert_eq!(client.request(b"abc").unwrap(), b"cba");
+
+    drop(client);
+    assert!(!client_path.exists());
+
+    handle.shutdown();
+    handle.join().unwrap();
+}
+
+#[test]
+fn late_replies_are_not_taken_for_the_next_ones() {
+    let path = socket_path("datagram-late");
+    let handle = DatagramSocket::new(path.clone(), None, Delayed)
+        .start()
+        .unwrap();
+
+    let client = DatagramClient::connect(path.as_path()).unwrap();
+    client.set_timeout(Some(Duration::from_millis(50))).unwrap();
+
+    let e = client.request(b"silent").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+    let e = client.request(b"slow").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+
+    // The reply to the slow packet arrives meanwhile
+    std::thread::sleep(Duration::from_millis(300));
+    client.set_timeout(Some(Duration::from_secs(10))).unwrap();
+    assert_eq!(client.request(b"abc").unwrap(), b"cba");
+
+    handle.shutdown();
+    handle.join().unwrap();
+}
+
+/// Provider answering every record of a `SOCK_SEQPACKET` connection with its bytes reversed
+#[derive(Default)]
+struct Records {
+    socket: Option<IpcStream>,
+}
+
+impl Clone for Records {
+    fn clone(&self) -> Self {
+        Records::default()
+    }
+}
+
+impl TaskProvider for Records {
+    fn set_socket(&mut self, socket: IpcStream) {
+        self.socket.replace(socket);
+    }
+}
+
+impl Future for Records {
+    type Output = Message;
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Message> {
+        let socket = self.socket.as_mut().unwrap();
+        let mut buffer = [0x00u8; 1024];
+
+        loop {
+            match socket.read(&mut buffer) {
+                Ok(0) => return Poll::Ready(Message::Success),
+                Ok(n) => {
+                    buffer[..n].reverse();
+                    socket.write_all(&buffer[..n]).unwrap();
+                }
+                Err(_) => return Poll::Ready(Message::Error),
+            }
+        }
+    }
+}
+
+fn connect_seqpacket(path: &Path) -> UnixStream {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (d, s) in addr.sun_path.iter_mut().zip(path.as_os_str().as_bytes()) {
+        *d = *s as libc::c_char;
+    }
+
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0) };
+    assert!(fd >= 0);
+    let stream = unsafe { UnixStream::from_raw_fd(fd) };
+
+    let ret = unsafe {
+        libc::connect(
+            fd,
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(ret, 0, "{}", std::io::Error::last_os_error());
+
+    stream
+}
+
+#[test]
+fn seqpacket_connections_keep_the_record_boundaries() {
+    let path = socket_path("seqpacket-records");
+    let options = Options {
+        socket_kind: SocketKind::SeqPacket,
+        ..Default::default()
+    };
+    let handle = UnixDomainSocket::new(path.clone(), Some(options), Records::default())
+        .start()
+        .unwrap();
+
+    let mut client = connect_seqpacket(path.as_path());
+    client
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    // Every read returns a whole record, never more
+    client.write_all(b"abc").unwrap();
+    client.write_all(b"de").unwrap();
+
+    let mut buffer = [0x00u8; 64];
+    let n = client.read(&mut buffer).unwrap();
+    assert_eq!(&buffer[..n], b"cba");
+    let n = client.read(&mut buffer).unwrap();
+    assert_eq!(&buffer[..n], b"ed");
+
+    drop(client);
+    handle
+        .task_sender()
+        .send(Task::Message(Message::ShouldQuit))
+        .unwrap();
+    handle.join().unwrap();
+}
+
+/// Provider of a `SOCK_SEQPACKET` connection running the exchange until it returns false
+#[derive(Clone)]
+struct Exchange {
+    socket: Option<Arc<IpcStream>>,
+    exchange: fn(&IpcStream) -> bool,
+}
+
+impl TaskProvider for Exchange {
+    fn set_socket(&mut self, socket: IpcStream) {
+        self.socket.replace(Arc::new(socket));
+    }
+}
+
+impl Future for Exchange {
+    type Output = Message;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Message> {
+        let socket = self.socket.as_ref().unwrap();
+        while (self.exchange)(socket) {}
+
+        Poll::Ready(Message::Success)
+    }
+}
+
+/// Serve the exchange on a `SOCK_SEQPACKET` socket, returning a connected client
+fn serve_seqpacket(name: &str, exchange: fn(&IpcStream) -> bool) -> (ServerHandle, UnixStream) {
+    let path = socket_path(name);
+    let options = Options {
+        socket_kind: SocketKind::SeqPacket,
+        ..Default::default()
+    };
+    let provider = Exchange {
+        socket: None,
+        exchange,
+    };
+    let handle = UnixDomainSocket::new(path.clone(), Some(options), provider)
+        .start()
+        .unwrap();
+
+    let client = connect_seqpacket(path.as_path());
+    client
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    (handle, client)
+}
+
+fn stop(handle: ServerHandle) {
+    handle
+        .task_sender()
+        .send(Task::Message(Message::ShouldQuit))
+        .unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn seqpacket_messages_longer_than_the_reads_are_not_truncated() {
+    // Reads a byte at a time, answering with every byte
+    let (handle, mut client) = serve_seqpacket("seqpacket-short-reads", |mut socket| {
+        let mut byte = [0x00u8; 1];
+        match socket.read(&mut byte) {
+            Ok(1) => socket.write_all(&byte).is_ok(),
+            _ => false,
+        }
+    });
+
+    client.write_all(b"abc").unwrap();
+    client.write_all(b"de").unwrap();
+
+    let mut buffer = [0x00u8; 64];
+    for expected in [b"a", b"b", b"c", b"d", b"e"] {
+        let n = client.read(&mut buffer).unwrap();
+        assert_eq!(&buffer[..n], expected);
+    }
+
+    drop(client);
+    stop(handle);
+}
+
+#[test]
+fn seqpacket_messages_are_received_whole() {
+    let (handle, mut client) = serve_seqpacket("seqpacket-messages", |mut socket| {
+        match socket.recv_message() {
+            Ok(m) if !m.is_empty() => socket.write_all(&m.len().to_be_bytes()).is_ok(),
+            _ => false,
+        }
+    });
+
+    let mut buffer = [0x00u8; 8];
+    for len in [1, 4096, 50_000] {
+        client.write_all(&vec![0x5au8; len]).unwrap();
+
+        client.read_exact(&mut buffer).unwrap();
+        assert_eq!(usize::from_be_bytes(buffer), len);
+    }
+
+    drop(client);
+    stop(handle);
+}
+
+#[test]
+fn seqpacket_connections_speak_the_codec() {
+    let (handle, client) =
+        serve_seqpacket("seqpacket-codec", |mut socket| {
+            match codec::read_frame(&mut socket, 1 << 20) {
+                Ok(frame) => codec::write_frame(&mut socket, &frame).is_ok(),
+                _ => false,
+            }
+        });
+
+    // Read through an IpcStream too, so the client doesn't truncate the messages either
+    let mut client = IpcStream::from(client);
+    for payload in [&b"x"[..], &[0x5au8; 70_000]] {
+        codec::write_frame(&mut client, payload).unwrap();
+        assert_eq!(codec::read_frame(&mut client, 1 << 20).unwrap(), payload);
+    }
+
+    drop(client);
+    stop(handle);
+}
+
+#[test]
+fn messages_are_only_received_from_seqpacket_sockets() {
+    let (stream, _peer) = UnixStream::pair().unwrap();
+
+    let e = IpcStream::from(stream).recv_message().unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+fn seqpacket_pair() -> (UnixStream, UnixStream) {
+    let mut fds = [0; 2];
+    let ret = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) };
+    assert_eq!(ret, 0, "{}", std::io::Error::last_os_error());
+
+    unsafe {
+        (
+            UnixStream::from_raw_fd(fds[0]),
+            UnixStream::from_raw_fd(fds[1]),
+        )
+    }
+}
+
+#[test]
+fn seqpacket_messages_are_read_without_blocking() {
+    let (socket, mut peer) = seqpacket_pair();
+    let mut socket = IpcStream::from(socket);
+
+    let writer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        peer.write_all(b"abcd").unwrap();
+        peer.write_all(b"message").unwrap();
+        peer
+    });
+
+    let mut buffer = [0x00u8; 2];
+    futures::executor::block_on(async {
+        for expected in [b"ab", b"cd"] {
+            let n = futures::AsyncReadExt::read(&mut socket, &mut buffer)
+                .await
+                .unwrap();
+            assert_eq!(&buffer[..n], expected);
+        }
+
+        let message = std::future::poll_fn(|cx| socket.poll_recv_message(cx))
+            .await
+            .unwrap();
+        assert_eq!(message, b"message");
+    });
+
+    writer.join().unwrap();
+}
@@ -0,0 +1,161 @@
+use dusk_uds::*;
+
+use std::{
+    future::Future,
+    io::Read,
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    pin::Pin,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Provider holding its socket until dropped, keeping its waker so it is never reported lost
+#[derive(Default)]
+struct Hold {
+    socket: Option<IpcStream>,
+    waker: Option<Waker>,
+    polled: Option<&'static AtomicUsize>,
+}
+
+impl Clone for Hold {
+    fn clone(&self) -> Self {
+        Hold {
+            polled: self.polled,
+            ..Default::default()
+        }
+    }
+}
+
+impl TaskProvider for Hold {
+    fn set_socket(&mut self, socket: IpcStream) {
+        self.socket.replace(socket);
+    }
+}
+
+impl Future for Hold {
+    type Output = Message;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Message> {
+        if self.waker.is_none() {
+            if let Some(p) = self.polled {
+                p.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        self.waker.replace(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()))
+}
+
+/// Start a UDS of a single worker taking one socket at a time, holding `connections` sockets in
+/// progress once it returns
+fn start_busy(
+    name: &str,
+    policy: DrainPolicy,
+    polled: &'static AtomicUsize,
+    connections: usize,
+) -> (ShutdownHandle, Vec<UnixStream>) {
+    let path = socket_path(name);
+    let options = Options::builder()
+        .workers(1)
+        .max_concurrent_per_worker(1)
+        .drain_policy(policy)
+        .build()
+        .unwrap();
+
+    let provider = Hold {
+        polled: Some(polled),
+        ..Default::default()
+    };
+    let shutdown = UnixDomainSocket::new(path.clone(), Some(options), provider)
+        .bind_with_handle()
+        .unwrap();
+
+    let clients: Vec<_> = (0..connections)
+        .map(|_| UnixStream::connect(&path).unwrap())
+        .collect();
+
+    let started = Instant::now();
+    while polled.load(Ordering::SeqCst) == 0 {
+        assert!(started.elapsed() < Duration::from_secs(5), "never polled");
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    (shutdown, clients)
+}
+
+fn read_eof(client: &mut UnixStream, timeout: Duration) -> bool {
+    client.set_read_timeout(Some(timeout)).unwrap();
+    matches!(client.read(&mut [0u8; 16]), Ok(0))
+}
+
+#[test]
+fn cut_connections_of_a_busy_worker() {
+    static POLLED: AtomicUsize = AtomicUsize::new(0);
+
+    let policy = DrainPolicy {
+        classify: |_, _| DrainClass::Cut,
+        finish_deadline: None,
+        notify_deadline: Duration::from_secs(60),
+    };
+    let (shutdown, mut clients) = start_busy("drain-cut", policy, &POLLED, 1);
+
+    let started = Instant::now();
+    shutdown.shutdown().unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(read_eof(&mut clients[0], Duration::from_secs(1)));
+}
+
+#[test]
+fn notify_then_cut_connections_of_a_busy_worker() {
+    static POLLED: AtomicUsize = AtomicUsize::new(0);
+
+    let deadline = Duration::from_millis(300);
+    let policy = DrainPolicy {
+        classify: |_, _| DrainClass::Notify,
+        finish_deadline: None,
+        notify_deadline: deadline,
+    };
+    let (shutdown, mut clients) = start_busy("drain-notify", policy, &POLLED, 1);
+
+    let started = Instant::now();
+    shutdown.request();
+
+    // The peer observes end of stream while the provider is still in progress
+    assert!(read_eof(&mut clients[0], Duration::from_secs(1)));
+    assert!(!shutdown.is_finished());
+
+    shutdown.wait().unwrap();
+    let elapsed = started.elapsed();
+    assert!(elapsed >= deadline, "cut after {:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(5), "cut after {:?}", elapsed);
+}
+
+#[test]
+fn finish_deadline_covers_the_queued_sockets() {
+    static POLLED: AtomicUsize = AtomicUsize::new(0);
+
+    let policy = DrainPolicy {
+        classify: |_, _| DrainClass::Finish,
+        finish_deadline: Some(Duration::from_millis(100)),
+        notify_deadline: Duration::from_secs(60),
+    };
+    let (shutdown, mut clients) = start_busy("drain-finish", policy, &POLLED, 2);
+
+    let started = Instant::now();
+    shutdown.shutdown().unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(5));
+    clients
+        .iter_mut()
+        .for_each(|c| assert!(read_eof(c, Duration::from_secs(1))));
+}
@@ -0,0 +1,103 @@
+use dusk_uds::*;
+
+use std::{
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process, thread,
+    time::{Duration, Instant},
+};
+
+/// Handler holding every connection for a while, so the sockets queue up behind it
+struct Slow;
+
+impl BlockingHandler for Slow {
+    fn handle(&self, _stream: IpcStream) -> Message {
+        thread::sleep(Duration::from_millis(200));
+        Message::Success
+    }
+}
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()))
+}
+
+/// Wait for the first event matching the predicate
+fn wait_for<F: Fn(&ServerEvent) -> bool>(events: &Events, matches: F) -> ServerEvent {
+    let deadline = Instant::now() + Duration::from_secs(10);
+
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match events.next_timeout(left) {
+            Some(e) if matches(&e) => return e,
+            Some(_) => (),
+            None => panic!("no matching event"),
+        }
+    }
+}
+
+/// Let the pool shrink to its minimum, then queue sockets until a worker is spawned again
+fn shrink_and_grow(name: &str, options: Options) -> ServerStats {
+    let path = socket_path(name);
+    let handle = UnixDomainSocket::new(path.clone(), Some(options), blocking_handler(Slow, 8))
+        .start()
+        .unwrap();
+    let events = handle.events();
+
+    wait_for(&events, |e| matches!(e, ServerEvent::WorkerRetired { .. }));
+    assert_eq!(
+        handle.stats().workers.iter().filter(|w| w.running).count(),
+        1
+    );
+
+    let clients: Vec<_> = (0..6)
+        .map(|_| UnixStream::connect(&path).unwrap())
+        .collect();
+    wait_for(&events, |e| matches!(e, ServerEvent::WorkerSpawned { .. }));
+    let stats = handle.stats();
+
+    drop(clients);
+    handle
+        .task_sender()
+        .send(Task::Message(Message::ShouldQuit))
+        .unwrap();
+    handle.join().unwrap();
+
+    stats
+}
+
+fn scaling() -> ScalingPolicy {
+    ScalingPolicy {
+        queue_depth: 0,
+        scale_up_after: Duration::from_millis(20),
+        scale_down_after: Duration::from_millis(100),
+    }
+}
+
+#[test]
+fn pool_without_maximum_grows_back() {
+    let options = Options::builder()
+        .workers(2)
+        .min_workers(1)
+        .scaling(scaling())
+        .build()
+        .unwrap();
+
+    let stats = shrink_and_grow("elastic-min", options);
+    assert_eq!(stats.workers.len(), 2);
+    assert!(stats.workers.iter().filter(|w| w.running).count() >= 2);
+}
+
+#[test]
+fn pool_grows_past_its_initial_size() {
+    let options = Options::builder()
+        .workers(2)
+        .min_workers(1)
+        .max_workers(4)
+        .scaling(scaling())
+        .build()
+        .unwrap();
+
+    let stats = shrink_and_grow("elastic-max", options);
+    assert_eq!(stats.workers.len(), 4);
+    assert!(stats.workers.iter().filter(|w| w.running).count() >= 2);
+}
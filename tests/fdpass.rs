@@ -0,0 +1,171 @@
+use dusk_uds::*;
+
+use std::{
+    fs::File,
+    future::Future,
+    io::{self, Read, Write},
+    os::unix::{
+        io::{AsFd, AsRawFd, FromRawFd, IntoRawFd, OwnedFd},
+        net::UnixStream,
+    },
+    path::PathBuf,
+    pin::Pin,
+    process,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()))
+}
+
+fn stop(handle: ServerHandle) {
+    handle
+        .task_sender()
+        .send(Task::Message(Message::ShouldQuit))
+        .unwrap();
+    handle.join().unwrap();
+}
+
+fn pipe() -> (File, File) {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+}
+
+#[test]
+fn descriptors_are_passed_with_the_bytes() {
+    let (tx, rx) = UnixStream::pair().unwrap();
+    let (mut read_end, write_end) = pipe();
+
+    assert_eq!(send_fds(&tx, b"pipe", &[write_end.as_fd()]).unwrap(), 4);
+    drop(write_end);
+
+    let mut buffer = [0x00u8; 16];
+    let mut fds = vec![];
+    let n = recv_fds(&rx, &mut buffer, &mut fds).unwrap();
+    assert_eq!(&buffer[..n], b"pipe");
+    assert_eq!(fds.len(), 1);
+
+    // Received close-on-exec, and still the same pipe
+    if cfg!(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd"
+    )) {
+        let flags = unsafe { libc::fcntl(fds[0].as_raw_fd(), libc::F_GETFD) };
+        assert_ne!(flags & libc::FD_CLOEXEC, 0);
+    }
+
+    File::from(fds.pop().unwrap())
+        .write_all(b"through")
+        .unwrap();
+    let mut passed = String::new();
+    read_end.read_to_string(&mut passed).unwrap();
+    assert_eq!(passed, "through");
+}
+
+#[test]
+fn descriptors_need_at_least_one_byte() {
+    let (tx, _rx) = UnixStream::pair().unwrap();
+    let (_read_end, write_end) = pipe();
+
+    let e = send_fds(&tx, b"", &[write_end.as_fd()]).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+}
+
+/// Answer with the metadata and the number of descriptors received with it
+fn count(metadata: Vec<u8>, fds: Vec<OwnedFd>, _peer: &PeerCreds) -> Option<Vec<u8>> {
+    let mut response = metadata;
+    response.extend_from_slice(format!(" {}", fds.len()).as_bytes());
+    Some(response)
+}
+
+#[test]
+fn fd_receivers_hand_the_descriptors_to_the_handler() {
+    let path = socket_path("fdpass-receiver");
+    let handle = UnixDomainSocket::new(path.clone(), None, fd_handler(count))
+        .start()
+        .unwrap();
+
+    let mut client = UnixStream::connect(path.as_path()).unwrap();
+    let (_read_end, write_end) = pipe();
+
+    // The descriptors are passed with the bytes of the frame
+    let mut frame = vec![];
+    codec::write_frame(&mut frame, b"two").unwrap();
+    send_fds(&client, &frame, &[write_end.as_fd(), write_end.as_fd()]).unwrap();
+    assert_eq!(codec::read_frame(&mut client, 1024).unwrap(), b"two 2");
+
+    codec::write_frame(&mut client, b"none").unwrap();
+    assert_eq!(codec::read_frame(&mut client, 1024).unwrap(), b"none 0");
+
+    drop(client);
+    stop(handle);
+}
+
+/// Provider handing over the socket passed on its connection, and greeting the connections
+/// that pass no socket
+#[derive(Default)]
+struct Broker {
+    socket: Option<IpcStream>,
+}
+
+impl Clone for Broker {
+    fn clone(&self) -> Self {
+        Broker::default()
+    }
+}
+
+impl TaskProvider for Broker {
+    fn set_socket(&mut self, socket: IpcStream) {
+        self.socket.replace(socket);
+    }
+}
+
+impl Future for Broker {
+    type Output = Message;
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Message> {
+        let mut socket = self.socket.take().unwrap();
+        let mut buffer = [0x00u8; 64];
+        let mut fds = vec![];
+
+        let n = recv_fds(&socket, &mut buffer, &mut fds).unwrap();
+        if let Some(fd) = fds.pop() {
+            return Poll::Ready(Message::Handover(fd.into_raw_fd()));
+        }
+
+        let mut response = b"served ".to_vec();
+        response.extend_from_slice(&buffer[..n]);
+        socket.write_all(&response).unwrap();
+
+        Poll::Ready(Message::Success)
+    }
+}
+
+#[test]
+fn handed_over_sockets_are_served_as_new_connections() {
+    let path = socket_path("fdpass-handover");
+    let handle = UnixDomainSocket::new(path.clone(), None, Broker::default())
+        .start()
+        .unwrap();
+
+    let (mut local, remote) = UnixStream::pair().unwrap();
+    local
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    let broker = UnixStream::connect(path.as_path()).unwrap();
+    send_fds(&broker, b"x", &[remote.as_fd()]).unwrap();
+    drop(remote);
+
+    local.write_all(b"ping").unwrap();
+    let mut response = vec![];
+    local.read_to_end(&mut response).unwrap();
+    assert_eq!(response, b"served ping");
+
+    drop(broker);
+    stop(handle);
+}
@@ -0,0 +1,193 @@
+use dusk_uds::http::{Request, Response};
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::net::UnixListener,
+    path::PathBuf,
+    process,
+    thread::{self, JoinHandle},
+};
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()))
+}
+
+/// Accept a single connection, answer it with the canned response and return the request head
+fn serve(name: &str, response: &'static [u8]) -> (PathBuf, JoinHandle<String>) {
+    let path = socket_path(name);
+    std::fs::remove_file(path.as_path()).unwrap_or_default();
+    let listener = UnixListener::bind(path.as_path()).unwrap();
+
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+
+        let mut head = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                length = value.trim().parse().unwrap();
+            }
+            head.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+
+        let mut body = vec![0x00u8; length];
+        reader.read_exact(&mut body).unwrap();
+        head.push_str(&String::from_utf8(body).unwrap());
+
+        reader.get_mut().write_all(response).unwrap();
+        head
+    });
+
+    (path, server)
+}
+
+fn send(name: &str, request: Request, response: &'static [u8]) -> io::Result<Response> {
+    let (path, server) = serve(name, response);
+    let response = request.send(path.as_path());
+
+    server.join().unwrap();
+    std::fs::remove_file(path.as_path()).unwrap();
+    response
+}
+
+#[test]
+fn requests_are_written_with_their_headers_and_body() {
+    let (path, server) = serve("http-request", b"HTTP/1.1 204 No Content\r\n\r\n");
+
+    let response = Request::post("/items", "{}")
+        .header("Content-Type", "application/json")
+        .send(path.as_path())
+        .unwrap();
+    assert_eq!(response.status, 204);
+    assert!(response.is_success());
+
+    assert_eq!(
+        server.join().unwrap(),
+        "POST /items HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+         Content-Type: application/json\r\nContent-Length: 2\r\n\r\n{}"
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn bodies_are_read_up_to_the_content_length() {
+    let response = send(
+        "http-length",
+        Request::get("/"),
+        b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-Id: 7\r\n\r\nhello",
+    )
+    .unwrap();
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.reason, "OK");
+    assert_eq!(response.header("x-id"), Some("7"));
+    assert_eq!(response.body, b"hello");
+}
+
+#[test]
+fn chunked_bodies_are_decoded() {
+    let response = send(
+        "http-chunked",
+        Request::get("/"),
+        b"HTTP/1.1 100 Continue\r\n\r\n\
+          HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+          5;ext=1\r\nhello\r\n1\r\n \r\n5\r\nworld\r\n0\r\nX-Trailer: 1\r\n\r\n",
+    )
+    .unwrap();
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"hello world");
+}
+
+#[test]
+fn head_responses_have_no_body() {
+    let response = send(
+        "http-head",
+        Request::new("HEAD", "/"),
+        b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n",
+    )
+    .unwrap();
+
+    assert_eq!(response.header("Content-Length"), Some("5"));
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn malformed_responses_are_rejected() {
+    let e = send(
+        "http-truncated",
+        Request::get("/"),
+        b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort",
+    )
+    .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+    let e = send(
+        "http-chunk-size",
+        Request::get("/"),
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+    )
+    .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+    let e = send("http-status", Request::get("/"), b"SSH-2.0-OpenSSH\r\n\r\n").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn chunk_sizes_are_not_trusted() {
+    // Announces the largest chunk, but sends a few bytes of it
+    let e = send(
+        "http-chunk-huge",
+        Request::get("/"),
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+          ffffffffffffffff\r\ntruncated",
+    )
+    .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+    let e = send(
+        "http-chunk-overflow",
+        Request::get("/"),
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+          1\r\na\r\nffffffffffffffff\r\n",
+    )
+    .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+    let e = send(
+        "http-chunk-wide",
+        Request::get("/"),
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+          10000000000000000\r\n",
+    )
+    .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn headers_cannot_inject_lines() {
+    let path = socket_path("http-inject");
+    std::fs::remove_file(path.as_path()).unwrap_or_default();
+    let listener = UnixListener::bind(path.as_path()).unwrap();
+
+    let e = Request::get("/")
+        .header("X-Evil", "1\r\nX-Injected: 1")
+        .send(path.as_path())
+        .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+    // Nothing was written to the server
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut head = vec![];
+    stream.read_to_end(&mut head).unwrap();
+    assert!(head.is_empty());
+
+    std::fs::remove_file(path).unwrap();
+}
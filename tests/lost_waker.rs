@@ -0,0 +1,75 @@
+use dusk_uds::*;
+
+use std::{
+    future::Future,
+    io::Read,
+    os::unix::net::UnixStream,
+    pin::Pin,
+    process,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// Provider returning Pending without keeping its waker, so it can never be woken
+#[derive(Default)]
+struct Forgetful {
+    socket: Option<IpcStream>,
+}
+
+impl Clone for Forgetful {
+    fn clone(&self) -> Self {
+        Forgetful::default()
+    }
+}
+
+impl TaskProvider for Forgetful {
+    fn set_socket(&mut self, socket: IpcStream) {
+        self.socket.replace(socket);
+    }
+}
+
+impl Future for Forgetful {
+    type Output = Message;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Message> {
+        Poll::Pending
+    }
+}
+
+fn cancelled(name: &str, options: OptionsBuilder) {
+    let path = std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()));
+    let options = options.lost_waker(LostWaker::Cancel).build().unwrap();
+    let handle = UnixDomainSocket::new(path.clone(), Some(options), Forgetful::default())
+        .start()
+        .unwrap();
+
+    let mut client = UnixStream::connect(&path).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(client.read(&mut [0u8; 16]).unwrap(), 0);
+
+    handle
+        .task_sender()
+        .send(Task::Message(Message::ShouldQuit))
+        .unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn lost_waker_is_cancelled() {
+    cancelled("lost-waker", Options::builder());
+}
+
+#[test]
+fn lost_waker_is_cancelled_with_a_memory_budget() {
+    let budget = MemoryBudget {
+        limit: 1024,
+        on_exceeded: BudgetAction::Close,
+    };
+
+    cancelled(
+        "lost-waker-budget",
+        Options::builder().memory_budget(budget),
+    );
+}
@@ -0,0 +1,83 @@
+#![cfg(any(target_os = "linux", target_os = "android"))]
+
+use dusk_uds::{codec, SharedBuffer};
+
+use std::{io, os::unix::net::UnixStream, thread};
+
+const THRESHOLD: usize = 4096;
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn frames_below_the_threshold_are_inline() {
+    let (mut tx, mut rx) = UnixStream::pair().unwrap();
+    let sent = payload(THRESHOLD - 1);
+
+    codec::write_frame_shared(&mut tx, &sent, THRESHOLD).unwrap();
+    let received = codec::read_frame_shared(&mut rx, usize::MAX).unwrap();
+
+    assert!(!received.is_mapped());
+    assert_eq!(&*received, sent.as_slice());
+}
+
+#[test]
+fn frames_from_the_threshold_are_mapped() {
+    let (mut tx, mut rx) = UnixStream::pair().unwrap();
+
+    for len in [THRESHOLD, 1024 * 1024] {
+        let sent = payload(len);
+
+        codec::write_frame_shared(&mut tx, &sent, THRESHOLD).unwrap();
+        let received = codec::read_frame_shared(&mut rx, usize::MAX).unwrap();
+
+        assert!(received.is_mapped());
+        assert_eq!(&*received, sent.as_slice());
+        assert_eq!(received.into_vec(), sent);
+    }
+}
+
+#[test]
+fn buffers_written_in_place_are_mapped() {
+    let (mut tx, mut rx) = UnixStream::pair().unwrap();
+    let sent = payload(64 * 1024);
+
+    let mut buffer = SharedBuffer::new(sent.len()).unwrap();
+    buffer.copy_from_slice(&sent);
+    codec::write_shared(&mut tx, buffer).unwrap();
+
+    let received = codec::read_frame_shared(&mut rx, usize::MAX).unwrap();
+    assert!(received.is_mapped());
+    assert_eq!(&*received, sent.as_slice());
+}
+
+#[test]
+fn inline_and_mapped_frames_interleave() {
+    let (mut tx, mut rx) = UnixStream::pair().unwrap();
+    let lens = [1, THRESHOLD * 4, 0, THRESHOLD - 1, THRESHOLD];
+
+    let writer = thread::spawn(move || {
+        for len in lens {
+            codec::write_frame_shared(&mut tx, &payload(len), THRESHOLD).unwrap();
+        }
+    });
+
+    for len in lens {
+        let received = codec::read_frame_shared(&mut rx, usize::MAX).unwrap();
+        assert_eq!(received.is_mapped(), len >= THRESHOLD);
+        assert_eq!(&*received, payload(len).as_slice());
+    }
+
+    writer.join().unwrap();
+}
+
+#[test]
+fn mapped_frames_over_the_maximum_are_rejected() {
+    let (mut tx, mut rx) = UnixStream::pair().unwrap();
+
+    codec::write_frame_shared(&mut tx, &payload(THRESHOLD * 2), THRESHOLD).unwrap();
+    let e = codec::read_frame_shared(&mut rx, THRESHOLD).unwrap_err();
+
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,64 @@
+use dusk_uds::*;
+
+use std::{os::unix::net::UnixStream, path::PathBuf, process};
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()))
+}
+
+/// Answer with the request reversed, followed by the uid of the peer
+fn reverse(mut request: Vec<u8>, peer: &PeerCreds) -> Vec<u8> {
+    request.reverse();
+    request.extend_from_slice(&peer.uid.to_be_bytes());
+    request
+}
+
+fn stop(handle: ServerHandle) {
+    handle
+        .task_sender()
+        .send(Task::Message(Message::ShouldQuit))
+        .unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn requests_are_answered_once() {
+    let path = socket_path("oneshot-answer");
+    let handle = UnixDomainSocket::new(path.clone(), None, oneshot_handler(reverse))
+        .start()
+        .unwrap();
+
+    for request in [&b"abc"[..], b""] {
+        let mut client = UnixStream::connect(&path).unwrap();
+        codec::write_frame(&mut client, request).unwrap();
+
+        let mut expected: Vec<u8> = request.iter().rev().copied().collect();
+        expected.extend_from_slice(&unsafe { libc::getuid() }.to_be_bytes());
+        assert_eq!(codec::read_frame(&mut client, 1024).unwrap(), expected);
+
+        // The connection is closed after the response
+        assert!(codec::read_frame(&mut client, 1024).is_err());
+    }
+
+    stop(handle);
+}
+
+#[test]
+fn requests_over_the_maximum_are_not_answered() {
+    let path = socket_path("oneshot-max");
+    let provider = oneshot_handler(reverse).max_frame(4);
+    let handle = UnixDomainSocket::new(path.clone(), None, provider)
+        .start()
+        .unwrap();
+
+    let mut client = UnixStream::connect(&path).unwrap();
+    codec::write_frame(&mut client, b"too long").unwrap();
+    assert!(codec::read_frame(&mut client, 1024).is_err());
+
+    // The provider keeps answering the next clients
+    let mut client = UnixStream::connect(&path).unwrap();
+    codec::write_frame(&mut client, b"ok").unwrap();
+    assert_eq!(&codec::read_frame(&mut client, 1024).unwrap()[..2], b"ko");
+
+    stop(handle);
+}
@@ -0,0 +1,129 @@
+use dusk_uds::{codec::ErrorCode, *};
+
+use std::{
+    io,
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Handler answering every connection with a single frame
+struct Greet;
+
+impl BlockingHandler for Greet {
+    fn handle(&self, mut stream: IpcStream) -> Message {
+        codec::write_frame(&mut stream, b"hello").unwrap_or_default();
+        Message::Success
+    }
+}
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()))
+}
+
+/// Wait for the first event matching the predicate
+fn wait_for<F: Fn(&ServerEvent) -> bool>(events: &Events, matches: F) -> ServerEvent {
+    let deadline = Instant::now() + Duration::from_secs(10);
+
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match events.next_timeout(left) {
+            Some(e) if matches(&e) => return e,
+            Some(_) => (),
+            None => panic!("no matching event"),
+        }
+    }
+}
+
+/// Read the single frame answered to a new client
+fn connect(path: &PathBuf) -> Result<Vec<u8>, io::Error> {
+    let mut client = UnixStream::connect(path)?;
+    client.set_read_timeout(Some(Duration::from_secs(10)))?;
+
+    codec::read_frame(&mut client, codec::MAX_FRAME_SIZE)
+}
+
+/// Start a UDS accepting one socket per second after a burst of two. The clock is only advanced
+/// by the test, so no token is refilled meanwhile
+fn start(name: &str, policy: BackpressurePolicy) -> (ServerHandle, Arc<ManualClock>, PathBuf) {
+    let path = socket_path(name);
+    let clock = Arc::new(ManualClock::new());
+    let options = Options::builder()
+        .workers(1)
+        .max_accept_rate(1)
+        .accept_burst(2)
+        .accept_rate_policy(policy)
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    let handle = UnixDomainSocket::new(path.clone(), Some(options), blocking_handler(Greet, 1))
+        .start()
+        .unwrap();
+
+    (handle, clock, path)
+}
+
+fn stop(handle: ServerHandle) {
+    handle
+        .task_sender()
+        .send(Task::Message(Message::ShouldQuit))
+        .unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn sockets_past_the_rate_are_rejected_with_an_error_frame() {
+    let (handle, clock, path) = start("rate-reject", BackpressurePolicy::RejectWithShutdown);
+    let events = handle.events();
+
+    for _ in 0..2 {
+        assert_eq!(connect(&path).unwrap(), b"hello");
+    }
+
+    let e = connect(&path).unwrap_err();
+    let error = codec::ErrorFrame::from_io(&e).unwrap();
+    assert_eq!(error.code, ErrorCode::OVERLOADED);
+    assert_eq!(error.message, "Too many connections per second");
+    assert_eq!(error.retry_after, Some(Duration::from_secs(1)));
+
+    wait_for(&events, |e| {
+        matches!(e, ServerEvent::Rejected(RejectReason::RateLimited))
+    });
+
+    // A token is refilled once the clock moved on
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(connect(&path).unwrap(), b"hello");
+
+    let stats = handle.stats();
+    assert_eq!(stats.accepted, 3);
+    assert_eq!(stats.rejected, 1);
+
+    stop(handle);
+}
+
+#[test]
+fn sockets_past_the_rate_are_dropped() {
+    let (handle, _clock, path) = start("rate-drop", BackpressurePolicy::DropNewest);
+    let events = handle.events();
+
+    for _ in 0..2 {
+        assert_eq!(connect(&path).unwrap(), b"hello");
+    }
+
+    for _ in 0..3 {
+        let e = connect(&path).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        wait_for(&events, |e| {
+            matches!(e, ServerEvent::Rejected(RejectReason::RateLimited))
+        });
+    }
+
+    let stats = handle.stats();
+    assert_eq!(stats.accepted, 2);
+    assert_eq!(stats.rejected, 3);
+
+    stop(handle);
+}
@@ -0,0 +1,149 @@
+use dusk_uds::*;
+
+use futures::{
+    executor,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+};
+
+use std::{
+    future::Future,
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    pin::Pin,
+    process,
+    task::{Context, Poll},
+    thread,
+    time::Duration,
+};
+
+/// Provider echoing every read, waiting for its socket without blocking the worker
+#[derive(Default)]
+struct Echo {
+    socket: Option<IpcStream>,
+}
+
+impl Clone for Echo {
+    fn clone(&self) -> Self {
+        Echo::default()
+    }
+}
+
+impl TaskProvider for Echo {
+    fn set_socket(&mut self, socket: IpcStream) {
+        self.socket.replace(socket);
+    }
+}
+
+impl Future for Echo {
+    type Output = Message;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Message> {
+        let socket = match self.socket.as_mut() {
+            Some(s) => s,
+            None => return Poll::Ready(Message::Error),
+        };
+
+        let mut buf = [0x00u8; 64];
+        loop {
+            match Pin::new(&mut *socket).poll_read(cx, &mut buf) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(0)) => return Poll::Ready(Message::Success),
+                Poll::Ready(Ok(n)) => {
+                    if Write::write_all(socket, &buf[..n]).is_err() {
+                        return Poll::Ready(Message::Error);
+                    }
+                }
+                Poll::Ready(Err(_)) => return Poll::Ready(Message::Error),
+            }
+        }
+    }
+}
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()))
+}
+
+fn echo(client: &mut UnixStream, payload: &[u8]) -> Vec<u8> {
+    client.write_all(payload).unwrap();
+
+    let mut answer = vec![0x00u8; payload.len()];
+    client.read_exact(&mut answer).unwrap();
+    answer
+}
+
+#[test]
+fn idle_connections_do_not_hold_the_worker() {
+    let path = socket_path("readiness-idle");
+    let options = Options::builder()
+        .workers(1)
+        .max_concurrent_per_worker(8)
+        .build()
+        .unwrap();
+
+    let shutdown = UnixDomainSocket::new(path.clone(), Some(options), Echo::default())
+        .bind_with_handle()
+        .unwrap();
+
+    let mut clients: Vec<_> = (0..4)
+        .map(|_| {
+            let client = UnixStream::connect(&path).unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            client
+        })
+        .collect();
+
+    // The last client is answered while the others are idle, then the others in reverse order
+    for (i, client) in clients.iter_mut().enumerate().rev() {
+        let payload = format!("ping {}", i);
+        assert_eq!(echo(client, payload.as_bytes()), payload.as_bytes());
+    }
+
+    drop(clients);
+    shutdown.shutdown().unwrap();
+}
+
+#[test]
+fn writes_wait_for_the_peer_to_read() {
+    let (stream, mut peer) = UnixStream::pair().unwrap();
+    let stream = IpcStream::from(stream);
+
+    // Larger than the socket buffers, so the writes have to wait for the reader
+    let payload: Vec<u8> = (0..8 * 1024 * 1024).map(|i| i as u8).collect();
+    let expected = payload.clone();
+
+    let reader = thread::spawn(move || {
+        let mut received = vec![];
+        peer.read_to_end(&mut received).unwrap();
+        received
+    });
+
+    executor::block_on(async {
+        let mut writer = &stream;
+        AsyncWriteExt::write_all(&mut writer, &payload)
+            .await
+            .unwrap();
+        writer.close().await.unwrap();
+    });
+
+    assert!(reader.join().unwrap() == expected);
+}
+
+#[test]
+fn reads_wait_for_the_peer_to_write() {
+    let (stream, mut peer) = UnixStream::pair().unwrap();
+    let mut stream = IpcStream::from(stream);
+
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        peer.write_all(b"ready").unwrap();
+    });
+
+    let mut buf = [0x00u8; 5];
+    executor::block_on(AsyncReadExt::read_exact(&mut stream, &mut buf)).unwrap();
+    assert_eq!(&buf, b"ready");
+
+    writer.join().unwrap();
+}
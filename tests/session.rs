@@ -0,0 +1,143 @@
+use dusk_uds::*;
+
+use std::{
+    io,
+    os::unix::net::UnixListener,
+    path::PathBuf,
+    process,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()))
+}
+
+/// Serve the sessions of the store, writing "1", "2" and "3" to every new session and then
+/// echoing the frames received. The token of a session is sent once its connection ends
+fn serve(path: PathBuf, store: SessionStore) -> Receiver<u64> {
+    std::fs::remove_file(path.as_path()).unwrap_or_default();
+    let listener = UnixListener::bind(path).unwrap();
+    let (tx, ended) = mpsc::channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let store = store.clone();
+            let tx = tx.clone();
+
+            thread::spawn(move || {
+                let mut session = match store.accept(IpcStream::from(stream.unwrap())) {
+                    Ok(s) => s,
+                    Err(_) => return,
+                };
+
+                if !session.is_resumed() {
+                    // Kept for replay even if the client is already gone
+                    for payload in [b"1", b"2", b"3"] {
+                        session.send(payload).unwrap_or_default();
+                    }
+                }
+
+                while let Ok(mut payload) = session.recv() {
+                    payload.splice(..0, b"echo ".iter().copied());
+                    if session.send(&payload).is_err() {
+                        break;
+                    }
+                }
+
+                let token = session.token();
+                drop(session);
+                tx.send(token).unwrap();
+            });
+        }
+    });
+
+    ended
+}
+
+fn client(path: PathBuf) -> UnixDomainClient {
+    UnixDomainClient::new(path).timeout(Duration::from_secs(10))
+}
+
+#[test]
+fn frames_are_delivered_once_across_reconnections() {
+    let path = socket_path("session-resume");
+    let store = SessionStore::new(8);
+    let ended = serve(path.clone(), store.clone());
+
+    let mut session = client(path.clone()).session(8);
+    assert_eq!(session.token(), 0);
+
+    session.send(b"hi").unwrap();
+    let token = session.token();
+    assert_ne!(token, 0);
+    assert_eq!(session.recv().unwrap(), b"1");
+
+    // Frames 2, 3 and the echo are written again from the new connection, once each
+    session.close();
+    assert_eq!(ended.recv().unwrap(), token);
+
+    assert_eq!(session.recv().unwrap(), b"2");
+    assert_eq!(session.recv().unwrap(), b"3");
+    assert_eq!(session.recv().unwrap(), b"echo hi");
+    assert_eq!(session.token(), token);
+
+    // Frames sent while disconnected are written once resumed
+    session.close();
+    assert_eq!(ended.recv().unwrap(), token);
+    session.send(b"again").unwrap();
+    assert_eq!(session.recv().unwrap(), b"echo again");
+
+    assert_eq!(session.token(), token);
+    assert_eq!(store.sessions(), 1);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn frames_are_acknowledged_by_the_peer() {
+    let path = socket_path("session-ack");
+    let store = SessionStore::new(8).replay_capacity(2);
+    serve(path.clone(), store);
+
+    let mut session = client(path.clone()).session(2);
+    for i in 0..8u8 {
+        session.send(&[i]).unwrap();
+        assert!(session.unacknowledged() <= 2);
+    }
+
+    for expected in [&b"1"[..], b"2", b"3"] {
+        assert_eq!(session.recv().unwrap(), expected);
+    }
+    for i in 0..8u8 {
+        assert_eq!(session.recv().unwrap(), [&b"echo "[..], &[i]].concat());
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn forgotten_sessions_are_reported_and_started_again() {
+    let path = socket_path("session-forgotten");
+    let ended = serve(path.clone(), SessionStore::new(1));
+
+    let mut first = client(path.clone()).session(8);
+    first.send(b"first").unwrap();
+    let token = first.token();
+    first.close();
+    assert_eq!(ended.recv().unwrap(), token);
+
+    // Takes the only place, forgetting the detached session
+    let mut second = client(path.clone()).session(8);
+    second.send(b"second").unwrap();
+    assert_ne!(second.token(), token);
+    second.close();
+    assert_eq!(ended.recv().unwrap(), second.token());
+
+    let e = first.recv().unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+    assert_ne!(first.token(), token);
+    assert_eq!(first.recv().unwrap(), b"1");
+
+    std::fs::remove_file(path).unwrap();
+}
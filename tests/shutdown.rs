@@ -0,0 +1,50 @@
+use dusk_uds::*;
+
+use std::{fs, io, path::PathBuf, process, thread};
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()))
+}
+
+fn echo(request: Vec<u8>, _peer: &PeerCreds) -> Vec<u8> {
+    request
+}
+
+#[test]
+fn shutdown_removes_the_path() {
+    let path = socket_path("shutdown-clean");
+    let shutdown = UnixDomainSocket::new(path.clone(), None, oneshot_handler(echo))
+        .bind_with_handle()
+        .unwrap();
+
+    assert!(path.exists());
+    shutdown.shutdown().unwrap();
+
+    assert!(shutdown.is_finished());
+    assert!(!path.exists());
+}
+
+#[test]
+fn every_waiter_gets_the_error() {
+    let path = socket_path("shutdown-error");
+    let shutdown = UnixDomainSocket::new(path.clone(), None, oneshot_handler(echo))
+        .bind_with_handle()
+        .unwrap();
+
+    // A directory in place of the socket file can't be removed as a file
+    fs::remove_file(path.as_path()).unwrap();
+    fs::create_dir(path.as_path()).unwrap();
+
+    let waiter = shutdown.clone();
+    let waiting = thread::spawn(move || waiter.wait());
+
+    let e = shutdown.shutdown().unwrap_err();
+    assert!(matches!(e, UdsError::Io(_)), "{:?}", e);
+    assert_ne!(e.kind(), io::ErrorKind::NotFound);
+
+    let other = waiting.join().unwrap().unwrap_err();
+    assert_eq!(other.kind(), e.kind());
+    assert_eq!(other.to_string(), e.to_string());
+
+    fs::remove_dir(path).unwrap();
+}
@@ -0,0 +1,93 @@
+use dusk_uds::*;
+
+use std::{
+    future::Future,
+    pin::Pin,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    task::{Context, Poll},
+    thread,
+    time::Duration,
+};
+
+/// Set once the logger may let the signal thread go on
+static RELEASED: AtomicBool = AtomicBool::new(false);
+
+/// Logger stalling the signal thread on the first signal, so a burst fills the socket buffer
+struct Stall;
+
+impl log::Log for Stall {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        if record.args().to_string().starts_with("Stop signal") {
+            while !RELEASED.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[derive(Default, Clone)]
+struct Close;
+
+impl TaskProvider for Close {
+    fn set_socket(&mut self, _socket: IpcStream) {}
+}
+
+impl Future for Close {
+    type Output = Message;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Message> {
+        Poll::Ready(Message::Success)
+    }
+}
+
+#[test]
+fn signal_bursts_neither_block_nor_clobber_errno() {
+    log::set_logger(&Stall).unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+
+    let path = std::env::temp_dir().join(format!("dusk-uds-signals-{}.sock", process::id()));
+    let options = Options::builder().handle_signals(true).build().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let p = path.clone();
+    thread::spawn(move || {
+        let bound = UnixDomainSocket::new(p, Some(options), Close).bind();
+        tx.send(bound.map_err(|e| e.to_string())).unwrap();
+    });
+
+    UnixDomainClient::new(path)
+        .connect_when_available(Duration::from_secs(5))
+        .unwrap();
+
+    // Raised on a thread of its own, so the handler interrupts it, and a blocked write is
+    // reported instead of hanging the test
+    let (done, burst) = mpsc::channel();
+    thread::spawn(move || {
+        for _ in 0..10_000 {
+            unsafe {
+                *libc::__errno_location() = libc::ENOENT;
+                assert_eq!(libc::raise(libc::SIGTERM), 0);
+                assert_eq!(*libc::__errno_location(), libc::ENOENT);
+            }
+        }
+
+        done.send(()).unwrap();
+    });
+
+    let raised = burst.recv_timeout(Duration::from_secs(10));
+    RELEASED.store(true, Ordering::SeqCst);
+    raised.expect("The signal handler blocked or clobbered errno");
+
+    let bound = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(bound, Ok(()));
+}
@@ -0,0 +1,91 @@
+use dusk_uds::*;
+
+use std::{io, os::unix::net::UnixStream, path::PathBuf, process};
+
+fn tenants_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}", name, process::id()))
+}
+
+fn alpha(_request: Vec<u8>, _peer: &PeerCreds) -> Vec<u8> {
+    b"alpha".to_vec()
+}
+
+fn beta(_request: Vec<u8>, _peer: &PeerCreds) -> Vec<u8> {
+    b"beta".to_vec()
+}
+
+fn request(manager: &TenantManager<OneShot>, id: &str) -> Vec<u8> {
+    let mut client = UnixStream::connect(manager.tenant_path(id)).unwrap();
+    codec::write_frame(&mut client, b"who").unwrap();
+    codec::read_frame(&mut client, 1024).unwrap()
+}
+
+fn stop(manager: TenantManager<OneShot>) {
+    manager
+        .handle()
+        .task_sender()
+        .send(Task::Message(Message::ShouldQuit))
+        .unwrap();
+    manager.join().unwrap();
+}
+
+#[test]
+fn every_tenant_has_its_own_provider() {
+    let manager = TenantManager::start(tenants_dir("tenants-own"), None).unwrap();
+    manager.add_tenant("a", oneshot_handler(alpha)).unwrap();
+    manager.add_tenant("b", oneshot_handler(beta)).unwrap();
+
+    let mut tenants = manager.tenants();
+    tenants.sort();
+    assert_eq!(tenants, ["a", "b"]);
+
+    assert_eq!(request(&manager, "a"), b"alpha");
+    assert_eq!(request(&manager, "b"), b"beta");
+    assert_eq!(request(&manager, "a"), b"alpha");
+
+    let paths = [manager.tenant_path("a"), manager.tenant_path("b")];
+    stop(manager);
+
+    // Joining closes the listeners of the remaining tenants
+    assert!(paths.iter().all(|path| !path.exists()));
+}
+
+#[test]
+fn tenants_are_added_and_removed_at_runtime() {
+    let manager = TenantManager::start(tenants_dir("tenants-runtime"), None).unwrap();
+    manager.add_tenant("a", oneshot_handler(alpha)).unwrap();
+    assert_eq!(request(&manager, "a"), b"alpha");
+
+    let path = manager.tenant_path("a");
+    manager.remove_tenant("a").unwrap();
+    assert!(manager.tenants().is_empty());
+    assert!(!path.exists());
+    assert!(UnixStream::connect(path.as_path()).is_err());
+
+    let e = manager.remove_tenant("a").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+
+    // The same id can be taken again, with a new provider
+    manager.add_tenant("a", oneshot_handler(beta)).unwrap();
+    assert_eq!(request(&manager, "a"), b"beta");
+
+    stop(manager);
+}
+
+#[test]
+fn duplicated_and_invalid_ids_are_rejected() {
+    let manager = TenantManager::start(tenants_dir("tenants-ids"), None).unwrap();
+    manager.add_tenant("a", oneshot_handler(alpha)).unwrap();
+
+    let e = manager.add_tenant("a", oneshot_handler(beta)).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(request(&manager, "a"), b"alpha");
+
+    for id in ["", ".", "..", "../escape", "nul\0"] {
+        let e = manager.add_tenant(id, oneshot_handler(beta)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{:?}", id);
+    }
+
+    assert_eq!(manager.tenants(), ["a"]);
+    stop(manager);
+}
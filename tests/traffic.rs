@@ -0,0 +1,157 @@
+use dusk_uds::*;
+
+use std::{
+    future::Future,
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    pin::Pin,
+    process,
+    task::{Context, Poll},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Provider answering a greeting, then waiting for the peer to hang up
+#[derive(Default)]
+struct Greeter {
+    socket: Option<IpcStream>,
+}
+
+impl Clone for Greeter {
+    fn clone(&self) -> Self {
+        Greeter::default()
+    }
+}
+
+impl TaskProvider for Greeter {
+    fn set_socket(&mut self, socket: IpcStream) {
+        self.socket.replace(socket);
+    }
+}
+
+impl Future for Greeter {
+    type Output = Message;
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Message> {
+        let socket = match self.socket.as_mut() {
+            Some(s) => s,
+            None => return Poll::Ready(Message::Error),
+        };
+
+        let mut greeting = [0x00u8; 5];
+        let mut rest = vec![];
+        let answered = socket
+            .read_exact(&mut greeting)
+            .and_then(|_| socket.write_all(b"welcome"))
+            .and_then(|_| socket.read_to_end(&mut rest));
+
+        match answered {
+            Ok(_) => Poll::Ready(Message::Success),
+            Err(_) => Poll::Ready(Message::Error),
+        }
+    }
+}
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dusk-uds-{}-{}.sock", name, process::id()))
+}
+
+fn greet(path: &PathBuf) -> UnixStream {
+    let mut client = UnixStream::connect(path).unwrap();
+    client.write_all(b"hello").unwrap();
+
+    let mut answer = [0x00u8; 7];
+    client.read_exact(&mut answer).unwrap();
+    assert_eq!(&answer, b"welcome");
+
+    client
+}
+
+/// Snapshot of the counters once they satisfy the condition, or after a few seconds. The bytes
+/// written by the workers are counted once their writes return, after the peer may have read them
+fn stats_once<F: Fn(&ServerStats) -> bool>(handle: &ServerHandle, condition: F) -> ServerStats {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let stats = handle.stats();
+        if condition(&stats) || Instant::now() > deadline {
+            return stats;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn stop(handle: ServerHandle) {
+    handle
+        .task_sender()
+        .send(Task::Message(Message::ShouldQuit))
+        .unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn traffic_is_counted_per_connection_and_peer_user() {
+    let path = socket_path("traffic-counted");
+    let options = Options::builder()
+        .workers(2)
+        .account_traffic(true)
+        .build()
+        .unwrap();
+
+    let handle = UnixDomainSocket::new(path.clone(), Some(options), Greeter::default())
+        .start()
+        .unwrap();
+    let uid = unsafe { libc::getuid() };
+
+    let client = greet(&path);
+    let stats = stats_once(&handle, |s| s.connections.iter().any(|c| c.sent == 7));
+    assert_eq!(stats.connections.len(), 1);
+    assert_eq!(stats.connections[0].uid, Some(uid));
+    assert_eq!(stats.connections[0].received, 5);
+    assert_eq!(stats.connections[0].sent, 7);
+
+    drop(client);
+    let stats = stats_once(&handle, |s| s.connections.is_empty());
+    assert!(stats.connections.is_empty());
+    assert_eq!(
+        stats.peers,
+        vec![PeerTraffic {
+            uid,
+            connections: 1,
+            received: 5,
+            sent: 7,
+        }]
+    );
+
+    // The totals of the user include its connections in progress
+    let client = greet(&path);
+    let stats = stats_once(&handle, |s| s.peers[0].sent == 14);
+    assert_eq!(stats.peers[0].connections, 2);
+    assert_eq!(stats.peers[0].received, 10);
+    assert_eq!(stats.peers[0].sent, 14);
+    assert!(stats.to_prometheus().contains(&format!(
+        "dusk_uds_peer_sent_bytes_total{{uid=\"{}\"}} 14\n",
+        uid
+    )));
+
+    drop(client);
+    stop(handle);
+}
+
+#[test]
+fn traffic_is_not_counted_by_default() {
+    let path = socket_path("traffic-default");
+    let handle = UnixDomainSocket::new(path.clone(), None, Greeter::default())
+        .start()
+        .unwrap();
+
+    let client = greet(&path);
+    let stats = handle.stats();
+    assert!(stats.connections.is_empty());
+    assert!(stats.peers.is_empty());
+    assert!(!stats.to_prometheus().contains("peer_"));
+
+    drop(client);
+    stop(handle);
+}